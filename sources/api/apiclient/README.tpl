@@ -132,6 +132,12 @@ apiclient exec admin cat /file > file
 This works OK because apiclient detects if you have a TTY by checking if stdout and stdin are connected to TTYs.
 If that doesn't work for your use case, you can pass `-t`/`--tty` to specifically request a TTY, or `-T`/`--no-tty` to request no TTY.
 
+To set environment variables for the command, pass `-e`/`--env NAME=VALUE` before the container name, once for each variable.
+The server only accepts variable names in its allowlist, which includes `TERM`, `LANG`, `LC_ALL`, and `TZ` by default:
+```shell
+apiclient exec -e LANG=C.UTF-8 admin bash
+```
+
 See the [exec documentation](../api-exec.md) for more detail on how this feature works.
 
 ### Raw mode
@@ -16,11 +16,12 @@
 use futures::{Future, FutureExt, Stream, StreamExt, TryStream, TryStreamExt};
 use futures_channel::{mpsc, oneshot};
 use libc::{ioctl, winsize as WinSize, STDOUT_FILENO, TIOCGWINSZ as GetWinSize};
-use log::{debug, error, trace, warn};
+use log::{debug, error, info, trace, warn};
 use model::exec::{ClientMessage, Initialize, ServerMessage, Size};
 use retry_read::RetryRead;
 use signal_hook::{consts::signal, iterator::Signals};
 use snafu::{OptionExt, ResultExt};
+use std::collections::BTreeMap;
 use std::ffi::OsString;
 use std::io::Read;
 use std::os::unix::io::RawFd;
@@ -57,6 +58,7 @@ pub async fn exec<P>(
     command: Vec<OsString>,
     target: String,
     tty: Option<bool>,
+    env: BTreeMap<String, String>,
 ) -> Result<()>
 where
     P: AsRef<Path>,
@@ -101,6 +103,7 @@ where
         command,
         target,
         tty: terminal.tty().clone(),
+        env: Some(env).filter(|env| !env.is_empty()),
     };
     // Control messages go to the server in a text channel, so we serialize to JSON before sending.
     let msg =
@@ -182,8 +185,13 @@ where
             // The connection is closing normally, we expect the process exit code in the reason message.
             CloseCode::Normal => {
                 if !ret.reason.is_empty() {
-                    // This is the normal case where the server gives us the exit code of the process.
-                    if let Ok(exit_code) = ret.reason.parse::<u16>() {
+                    // This is the normal case where the server gives us the exit code of the
+                    // process, followed by the path to the transcript if the session was recorded.
+                    let mut parts = ret.reason.splitn(2, ' ');
+                    if let Some(Ok(exit_code)) = parts.next().map(str::parse::<u16>) {
+                        if let Some(transcript) = parts.next() {
+                            info!("Session transcript recorded to {}", transcript);
+                        }
                         process::exit(i32::from(exit_code))
                     }
                 }
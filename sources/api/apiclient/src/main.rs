@@ -14,6 +14,7 @@ use simplelog::{
     ColorChoice, ConfigBuilder as LogConfigBuilder, LevelFilter, TermLogger, TerminalMode,
 };
 use snafu::ResultExt;
+use std::collections::BTreeMap;
 use std::env;
 use std::ffi::OsString;
 use std::iter::Peekable;
@@ -66,6 +67,7 @@ struct ExecArgs {
     command: Vec<OsString>,
     target: String,
     tty: Option<bool>,
+    env: BTreeMap<String, String>,
 }
 
 /// Stores user-supplied arguments for the 'get' subcommand.
@@ -249,6 +251,8 @@ fn usage() -> ! {
         exec options:
             -t, --tty                  Force the server to run the program in a pseudoterminal.
             -T, --no-tty               Force the server not to run the program in a pseudoterminal.
+            -e, --env NAME=VALUE       Set an environment variable for the program; can be given
+                                       multiple times.  The server only allows some names.
 
             TARGET                     Required; the name of the container in which to run the command.
             COMMAND                    Required; the command to run.
@@ -426,8 +430,10 @@ fn parse_exec_args(args: Vec<String>) -> Subcommand {
     let mut command = vec![];
     let mut target = None;
     let mut tty = None;
+    let mut env = BTreeMap::new();
 
-    for arg in args.into_iter() {
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
         match arg.as_ref() {
             // Check for our own arguments, but stop once we start to see the user's command; we
             // don't want to intercept its own arguments.
@@ -437,6 +443,18 @@ fn parse_exec_args(args: Vec<String>) -> Subcommand {
             "-T" | "--no-tty" if command.is_empty() => {
                 tty = Some(false);
             }
+            "-e" | "--env" if command.is_empty() => {
+                let pair = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to -e | --env"));
+                let (name, value) = pair.split_once('=').unwrap_or_else(|| {
+                    usage_msg(format!(
+                        "Invalid argument to -e | --env, expected NAME=VALUE: '{}'",
+                        pair
+                    ))
+                });
+                env.insert(name.to_string(), value.to_string());
+            }
             x if x.starts_with('-') && command.is_empty() => {
                 usage_msg(format!("Unknown argument '{}'", x))
             }
@@ -458,6 +476,7 @@ fn parse_exec_args(args: Vec<String>) -> Subcommand {
         command,
         target,
         tty,
+        env,
     })
 }
 
@@ -929,9 +948,15 @@ async fn run() -> Result<()> {
         }
 
         Subcommand::Exec(exec) => {
-            exec::exec(&args.socket_path, exec.command, exec.target, exec.tty)
-                .await
-                .context(error::ExecSnafu)?;
+            exec::exec(
+                &args.socket_path,
+                exec.command,
+                exec.target,
+                exec.tty,
+                exec.env,
+            )
+            .await
+            .context(error::ExecSnafu)?;
         }
 
         Subcommand::Get(get) => {
@@ -7,18 +7,30 @@ use libc::gid_t;
 use nix::unistd::Gid;
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
 use snafu::{ensure, ResultExt};
+use std::collections::HashSet;
 use std::env;
-use std::path::Path;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
+use std::time::Duration;
 
 use apiserver::serve;
+use apiserver::server::{
+    BindAddress, ExecConfig, ReportConfig, ServerConfig, CTR_PATH, DEFAULT_ALLOWED_ENV,
+};
 
 /// By default, this is where we create the Unix-domain socket that exposes our API.
 const DEFAULT_BIND_PATH: &str = "/run/api.sock";
 /// By default, when the user requests that we run a process via /exec, we run the process through
 /// this containerd socket.
 const DEFAULT_EXEC_SOCKET: &str = "/run/host-containerd/containerd.sock";
+/// By default, this is the directory bootstrapping tools place settings files in for /tx/seed;
+/// files elsewhere on the host can't be seeded from.
+const DEFAULT_SEED_DIR: &str = "/var/lib/bottlerocket/seed";
+/// By default, this is the program we run after changes to render config files and restart
+/// services.
+const DEFAULT_CONFIG_APPLIER: &str = "/usr/bin/thar-be-settings";
 
 type Result<T> = std::result::Result<T, error::Error>;
 
@@ -46,23 +58,74 @@ struct Args {
     socket_gid: Option<Gid>,
     socket_path: String,
     exec_socket_path: String,
+    exec_transcript_dir: Option<PathBuf>,
+    exec_max_duration: Option<Duration>,
+    exec_idle_timeout: Option<Duration>,
+    exec_allowed_commands: Option<HashSet<OsString>>,
+    exec_allowed_env: Option<HashSet<String>>,
+    report_config: ReportConfig,
+    max_transactions: Option<usize>,
+    audit_log: Option<PathBuf>,
+    seed_dir: PathBuf,
+    config_applier: PathBuf,
+    read_only: bool,
 }
 
 /// Informs the user about proper usage of the program and exits.
 fn usage() -> ! {
     let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    let default_reports = ReportConfig::default();
     eprintln!(
         r"Usage: {}
             --datastore-path PATH
             [ --socket-path PATH ]
             [ --socket-gid GROUP_ID ]
             [ --exec-socket-path PATH ]
+            [ --exec-transcript-dir PATH ]
+            [ --exec-max-duration SECONDS ]
+            [ --exec-idle-timeout SECONDS ]
+            [ --exec-allowed-command COMMAND ... ]
+            [ --exec-allowed-env NAME ... ]
+            [ --bloodhound-bin PATH ]
+            [ --bloodhound-k8s-checks PATH ]
+            [ --bloodhound-fips-checks PATH ]
+            [ --max-transactions COUNT ]
+            [ --audit-log PATH ]
+            [ --seed-dir PATH ]
+            [ --config-applier PATH ]
+            [ --read-only ]
             [ --no-color ]
             [ --log-level trace|debug|info|warn|error ]
 
     --socket-path defaults to {}
-    --exec-socket-path (for apiclient exec) defaults to {}",
-        program_name, DEFAULT_BIND_PATH, DEFAULT_EXEC_SOCKET
+    --exec-socket-path (for apiclient exec) defaults to {}
+    --exec-transcript-dir is where exec sessions are recorded if requested; by default,
+      recording is disabled
+    --exec-max-duration and --exec-idle-timeout close exec sessions that run too long or
+      receive no input for too long; by default, there's no limit
+    --exec-allowed-command can be given multiple times to restrict exec to those commands;
+      by default, any command can be run
+    --exec-allowed-env can be given multiple times to set which environment variables exec
+      clients may set; by default, {}
+    --bloodhound-bin, --bloodhound-k8s-checks, and --bloodhound-fips-checks override the
+      paths used to generate reports; they default to {}, {}, and {}
+    --max-transactions limits how many transactions can be pending at once; by default
+      there's no limit
+    --audit-log appends a line to the given file for each commit, recording the time, the
+      transaction, and the changed keys; by default, commits aren't audited
+    --seed-dir is the only directory /tx/seed reads settings files from; it defaults to {}
+    --config-applier is run after changes to render config files and restart services; it
+      defaults to {}
+    --read-only only serves requests that can't change anything, for recovery or debugging",
+        program_name,
+        DEFAULT_BIND_PATH,
+        DEFAULT_EXEC_SOCKET,
+        DEFAULT_ALLOWED_ENV.join(", "),
+        default_reports.bloodhound_bin.display(),
+        default_reports.k8s_checks.display(),
+        default_reports.fips_checks.display(),
+        DEFAULT_SEED_DIR,
+        DEFAULT_CONFIG_APPLIER,
     );
     process::exit(2);
 }
@@ -80,6 +143,17 @@ fn parse_args(args: env::Args) -> Args {
     let mut socket_gid = None;
     let mut socket_path = None;
     let mut exec_socket_path = None;
+    let mut exec_transcript_dir = None;
+    let mut exec_max_duration = None;
+    let mut exec_idle_timeout = None;
+    let mut exec_allowed_commands: Option<HashSet<OsString>> = None;
+    let mut exec_allowed_env: Option<HashSet<String>> = None;
+    let mut report_config = ReportConfig::default();
+    let mut max_transactions = None;
+    let mut audit_log = None;
+    let mut seed_dir = None;
+    let mut config_applier = None;
+    let mut read_only = false;
 
     let mut iter = args.skip(1);
     while let Some(arg) = iter.next() {
@@ -127,6 +201,92 @@ fn parse_args(args: env::Args) -> Args {
                     }))
             }
 
+            "--exec-transcript-dir" => {
+                exec_transcript_dir = Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                    usage_msg("Did not give argument to --exec-transcript-dir")
+                })))
+            }
+
+            "--exec-max-duration" => {
+                exec_max_duration = Some(parse_seconds(iter.next(), "--exec-max-duration"))
+            }
+
+            "--exec-idle-timeout" => {
+                exec_idle_timeout = Some(parse_seconds(iter.next(), "--exec-idle-timeout"))
+            }
+
+            "--exec-allowed-command" => {
+                let command = iter.next().unwrap_or_else(|| {
+                    usage_msg("Did not give argument to --exec-allowed-command")
+                });
+                exec_allowed_commands
+                    .get_or_insert_with(HashSet::new)
+                    .insert(command.into());
+            }
+
+            "--exec-allowed-env" => {
+                let name = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --exec-allowed-env"));
+                exec_allowed_env
+                    .get_or_insert_with(HashSet::new)
+                    .insert(name);
+            }
+
+            "--bloodhound-bin" => {
+                report_config.bloodhound_bin = PathBuf::from(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --bloodhound-bin")),
+                )
+            }
+
+            "--bloodhound-k8s-checks" => {
+                report_config.k8s_checks = PathBuf::from(iter.next().unwrap_or_else(|| {
+                    usage_msg("Did not give argument to --bloodhound-k8s-checks")
+                }))
+            }
+
+            "--bloodhound-fips-checks" => {
+                report_config.fips_checks = PathBuf::from(iter.next().unwrap_or_else(|| {
+                    usage_msg("Did not give argument to --bloodhound-fips-checks")
+                }))
+            }
+
+            "--max-transactions" => {
+                let max_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --max-transactions"));
+                max_transactions = Some(max_str.parse::<usize>().unwrap_or_else(|e| {
+                    usage_msg(format!(
+                        "Invalid count '{}' given to --max-transactions: {}",
+                        max_str, e
+                    ))
+                }));
+            }
+
+            "--audit-log" => {
+                audit_log =
+                    Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --audit-log")
+                    })))
+            }
+
+            "--seed-dir" => {
+                seed_dir =
+                    Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --seed-dir")
+                    })))
+            }
+
+            "--config-applier" => {
+                config_applier =
+                    Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --config-applier")
+                    })))
+            }
+
+            "--read-only" => read_only = true,
+
             _ => usage(),
         }
     }
@@ -137,9 +297,33 @@ fn parse_args(args: env::Args) -> Args {
         log_level: log_level.unwrap_or(LevelFilter::Info),
         socket_path: socket_path.unwrap_or_else(|| DEFAULT_BIND_PATH.to_string()),
         exec_socket_path: exec_socket_path.unwrap_or_else(|| DEFAULT_EXEC_SOCKET.to_string()),
+        exec_transcript_dir,
+        exec_max_duration,
+        exec_idle_timeout,
+        exec_allowed_commands,
+        exec_allowed_env,
+        report_config,
+        max_transactions,
+        audit_log,
+        seed_dir: seed_dir.unwrap_or_else(|| DEFAULT_SEED_DIR.into()),
+        config_applier: config_applier.unwrap_or_else(|| DEFAULT_CONFIG_APPLIER.into()),
+        read_only,
     }
 }
 
+/// Parses the given argument value as a number of seconds, exiting through usage() if it's
+/// missing or invalid.
+fn parse_seconds(arg: Option<String>, flag: &str) -> Duration {
+    let secs_str = arg.unwrap_or_else(|| usage_msg(format!("Did not give argument to {}", flag)));
+    let secs = secs_str.parse::<u64>().unwrap_or_else(|e| {
+        usage_msg(format!(
+            "Invalid seconds '{}' given to {}: {}",
+            secs_str, flag, e
+        ))
+    });
+    Duration::from_secs(secs)
+}
+
 /// Starts a web server to accept user requests, dispatching those requests to the controller.
 async fn run() -> Result<()> {
     let args = parse_args(env::args());
@@ -168,13 +352,31 @@ async fn run() -> Result<()> {
         &args.socket_path, threads, threads_suffix, &args.datastore_path,
     );
 
-    serve(
-        &args.socket_path,
-        &args.datastore_path,
+    serve(ServerConfig {
+        bind: BindAddress::Unix {
+            path: args.socket_path.into(),
+            gid: args.socket_gid,
+        },
+        datastore_path: args.datastore_path.into(),
         threads,
-        args.socket_gid,
-        args.exec_socket_path,
-    )
+        exec_config: ExecConfig {
+            ctr_path: CTR_PATH.into(),
+            socket_path: args.exec_socket_path.into(),
+            transcript_dir: args.exec_transcript_dir,
+            max_session_duration: args.exec_max_duration,
+            idle_timeout: args.exec_idle_timeout,
+            allowed_commands: args.exec_allowed_commands,
+            allowed_env: args
+                .exec_allowed_env
+                .unwrap_or_else(|| DEFAULT_ALLOWED_ENV.iter().map(|s| s.to_string()).collect()),
+        },
+        report_config: args.report_config,
+        max_transactions: args.max_transactions,
+        audit_log: args.audit_log,
+        seed_dir: args.seed_dir,
+        config_applier: args.config_applier,
+        read_only: args.read_only,
+    })
     .await
     .context(error::ServerSnafu)
 }
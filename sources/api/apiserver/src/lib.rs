@@ -5,6 +5,7 @@ This library provides an API server intended for use in an OS that is primarily
 It's intended to be the primary way to read and modify OS settings, to update services based on those settings, and more generally to learn about and change the state of the system.
 
 The server listens to HTTP requests on a Unix-domain socket.
+(For integration tests and local development, the `ServerConfig` given to `serve` can instead bind to a loopback TCP address.)
 There is no built-in authentication - local access to the socket should be limited to processes and containers that should be able to configure the system.
 Remote access should only be allowed through an authenticated control channel such as SSH or SSM.
 
@@ -1,20 +1,38 @@
 //! The controller module maps between the datastore and the API interface, similar to the
 //! controller in the MVC model.
 
-use bottlerocket_release::BottlerocketRelease;
+use bottlerocket_release::{BottlerocketRelease, PartialBottlerocketRelease};
+use chrono::{DateTime, Utc};
+use semver::Version;
 use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use snafu::{ensure, OptionExt, ResultExt};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::OpenOptions;
 use std::io::Write;
+use std::path::{Component, Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Instant, SystemTime};
 
 use crate::server::error::{self, Result};
 use actix_web::HttpResponse;
 use datastore::deserialization::{from_map, from_map_with_prefix};
+use datastore::filesystem::SymlinkCheck;
+use datastore::memory::MemoryDataStore;
 use datastore::serialization::to_pairs_with_prefix;
-use datastore::{deserialize_scalar, Committed, DataStore, Key, KeyType, ScalarError, Value};
+use datastore::{
+    deserialize_scalar, serialize_scalar, Committed, DataStore, FilesystemDataStore, Key, KeyType,
+    ScalarError, Value, EXPIRES_AT_METADATA_KEY, STRENGTH_METADATA_KEY, STRONG_STRENGTH,
+    WEAK_STRENGTH,
+};
+use model::generator::RawSettingsGenerator;
+use model::report::{CheckStatus, CisReport};
 use model::{ConfigurationFiles, Services, Settings};
 use num::FromPrimitive;
+use schnauzer::v2::Template;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::process::ExitStatusExt;
 use thar_be_updates::error::TbuErrorStatus;
 
@@ -30,6 +48,86 @@ where
         })
 }
 
+/// The metadata key holding a free-text note about a setting.
+pub(crate) const DESCRIPTION_METADATA_KEY: &str = "description";
+/// The metadata key that marks settings whose changes only take effect after a reboot, when true.
+pub(crate) const REBOOT_REQUIRED_METADATA_KEY: &str = "reboot-required";
+
+/// The update dispatcher, which refreshes, prepares, and activates updates.
+pub(crate) const UPDATE_DISPATCHER_PATH: &str = "/usr/bin/thar-be-updates";
+/// systemctl, used to reboot the host.
+pub(crate) const SYSTEMCTL_PATH: &str = "/usr/bin/systemctl";
+
+/// Informational details about a transaction, like who opened it.  Annotations don't affect
+/// how the transaction is committed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct TransactionAnnotation {
+    author: Option<String>,
+    description: Option<String>,
+    /// When the transaction was first annotated; clients that annotate when they first change
+    /// settings in a transaction get its creation time.
+    created_at: DateTime<Utc>,
+}
+
+/// Returns the annotation for the given transaction, if it has one.
+pub(crate) fn get_transaction_annotation<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+) -> Result<Option<TransactionAnnotation>> {
+    let annotation_str =
+        datastore
+            .get_transaction_annotation(transaction)
+            .context(error::DataStoreSnafu {
+                op: "get_transaction_annotation",
+            })?;
+    annotation_str
+        .map(|s| deserialize_scalar::<_, ScalarError>(&s))
+        .transpose()
+        .context(error::AnnotationJsonSnafu { transaction })
+}
+
+/// Annotates the given transaction with its author and description.  Fields that aren't given
+/// keep any value they already had.
+pub(crate) fn annotate_transaction<D: DataStore>(
+    datastore: &mut D,
+    transaction: &str,
+    author: Option<&str>,
+    description: Option<&str>,
+) -> Result<()> {
+    let existing = get_transaction_annotation(datastore, transaction)?;
+    let (old_author, old_description, created_at) = match existing {
+        Some(a) => (a.author, a.description, a.created_at),
+        None => (None, None, Utc::now()),
+    };
+    let annotation = TransactionAnnotation {
+        author: author.map(str::to_string).or(old_author),
+        description: description.map(str::to_string).or(old_description),
+        created_at,
+    };
+
+    let annotation_str = serialize_scalar::<_, ScalarError>(&annotation)
+        .context(error::AnnotationJsonSnafu { transaction })?;
+    let exists = datastore
+        .set_transaction_annotation(transaction, annotation_str)
+        .context(error::DataStoreSnafu {
+            op: "set_transaction_annotation",
+        })?;
+    ensure!(exists, error::MissingTransactionSnafu { transaction });
+    Ok(())
+}
+
+/// Lists the open transactions from the data store along with their annotations, if any.
+pub(crate) fn list_annotated_transactions<D: DataStore>(
+    datastore: &D,
+) -> Result<BTreeMap<String, Option<TransactionAnnotation>>> {
+    let mut result = BTreeMap::new();
+    for transaction in list_transactions(datastore)? {
+        let annotation = get_transaction_annotation(datastore, &transaction)?;
+        result.insert(transaction, annotation);
+    }
+    Ok(result)
+}
+
 /// Build a Settings based on pending data in the datastore; the Settings will be empty if there
 /// are no pending settings.
 pub(crate) fn get_transaction<D, S>(datastore: &D, transaction: S) -> Result<Settings>
@@ -44,6 +142,132 @@ where
         .map(|maybe_settings| maybe_settings.unwrap_or_default())
 }
 
+/// Build a Settings showing what the live settings would be if the given transaction were
+/// committed: the live settings, overlaid with the transaction's pending settings, including any
+/// nulls, just as a commit would write them.  Nothing is changed.
+pub(crate) fn get_effective_settings<D, S>(datastore: &D, transaction: S) -> Result<Settings>
+where
+    D: DataStore,
+    S: Into<String>,
+{
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    let mut data = datastore
+        .get_prefix("settings.", &Committed::Live)
+        .context(error::DataStoreSnafu {
+            op: "get_prefix 'settings.' for Live",
+        })?;
+    let pending_data = datastore
+        .get_prefix("settings.", &pending)
+        .with_context(|_| error::DataStoreSnafu {
+            op: format!("get_prefix 'settings.' for {:?}", pending),
+        })?;
+
+    data.extend(pending_data);
+
+    if data.is_empty() {
+        return Ok(Settings::default());
+    }
+    from_map_with_prefix(None, &data).context(error::DeserializationSnafu { given: "settings." })
+}
+
+/// The live and pending values of a setting whose value would change if a transaction were
+/// committed.  `live` is None if the setting isn't set in the live data store.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct SettingDiff {
+    pub(crate) live: Option<Value>,
+    pub(crate) pending: Value,
+}
+
+/// Mapping of setting name to the change that would be made to it by a transaction.
+pub(crate) type TransactionDiff = BTreeMap<String, SettingDiff>;
+
+/// Compares the pending settings in the given transaction to the live settings, returning the
+/// settings whose values would change if the transaction were committed.
+pub(crate) fn get_transaction_diff<D, S>(datastore: &D, transaction: S) -> Result<TransactionDiff>
+where
+    D: DataStore,
+    S: Into<String>,
+{
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    let pending_data = datastore
+        .get_prefix("settings.", &pending)
+        .with_context(|_| error::DataStoreSnafu {
+            op: format!("get_prefix 'settings.' for {:?}", pending),
+        })?;
+
+    let mut diff = BTreeMap::new();
+    for (key, pending_str) in pending_data {
+        let live_str = datastore
+            .get_key(&key, &Committed::Live)
+            .context(error::DataStoreSnafu { op: "get_key" })?;
+        if live_str.as_ref() == Some(&pending_str) {
+            continue;
+        }
+
+        let live = live_str
+            .map(|s| deserialize_scalar::<Value, ScalarError>(&s))
+            .transpose()
+            .context(error::InvalidValueSnafu { key: key.name() })?;
+        let pending = deserialize_scalar::<Value, ScalarError>(&pending_str)
+            .context(error::InvalidValueSnafu { key: key.name() })?;
+        diff.insert(key.name().clone(), SettingDiff { live, pending });
+    }
+    Ok(diff)
+}
+
+/// What committing a transaction would do: the settings that would change, and the services
+/// and restart commands affected by those changes.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct CommitPlan {
+    changes: TransactionDiff,
+    impact: ChangeImpact,
+}
+
+/// Determines what committing the given transaction would change, without changing anything.
+pub(crate) fn plan_commit<D: DataStore>(datastore: &D, transaction: &str) -> Result<CommitPlan> {
+    let changes = get_transaction_diff(datastore, transaction)?;
+    let changed_keys: HashSet<&str> = changes.keys().map(String::as_str).collect();
+    let impact = get_change_impact(datastore, &changed_keys)?;
+    Ok(CommitPlan { changes, impact })
+}
+
+/// Builds the diff against live settings for each open transaction, in order of transaction
+/// name.  Skips the first `offset` transactions and returns at most `limit`, so the response
+/// stays bounded no matter how many transactions are open.
+pub(crate) fn get_transaction_diffs<D>(
+    datastore: &D,
+    offset: usize,
+    limit: usize,
+) -> Result<BTreeMap<String, TransactionDiff>>
+where
+    D: DataStore,
+{
+    let mut transactions: Vec<String> = list_transactions(datastore)?.into_iter().collect();
+    transactions.sort();
+
+    let mut result = BTreeMap::new();
+    for transaction in transactions.into_iter().skip(offset).take(limit) {
+        let diff = get_transaction_diff(datastore, transaction.as_str())?;
+        result.insert(transaction, diff);
+    }
+    Ok(result)
+}
+
+/// Marks the transaction as in use, so it isn't expired, without changing its settings.
+pub(crate) fn touch_transaction<D: DataStore>(datastore: &mut D, transaction: &str) -> Result<()> {
+    let exists = datastore
+        .touch_transaction(transaction, SystemTime::now())
+        .context(error::DataStoreSnafu {
+            op: "touch_transaction",
+        })?;
+    ensure!(exists, error::MissingTransactionSnafu { transaction });
+    Ok(())
+}
+
 /// Deletes the transaction from the data store, removing any uncommitted settings under that
 /// transaction name.
 pub(crate) fn delete_transaction<D: DataStore>(
@@ -57,6 +281,24 @@ pub(crate) fn delete_transaction<D: DataStore>(
         })
 }
 
+/// Deletes each of the given transactions, returning the keys deleted from each, or why they
+/// couldn't be.  Transactions that don't exist are reported with no deleted keys, and a failure
+/// doesn't stop the rest from being deleted.
+pub(crate) fn delete_transactions<D: DataStore>(
+    datastore: &mut D,
+    transactions: &HashSet<&str>,
+) -> BTreeMap<String, Result<HashSet<Key>>> {
+    transactions
+        .iter()
+        .map(|transaction| {
+            (
+                transaction.to_string(),
+                delete_transaction(datastore, transaction),
+            )
+        })
+        .collect()
+}
+
 /// check_prefix is a helper for get_*_prefix functions that determines what prefix to use when
 /// checking whether settings match the prefix.  Pass in the prefix that was given in the API
 /// request, and the expected prefix of settings in the subject area (like "settings." or
@@ -80,6 +322,59 @@ fn check_prefix<'a>(given: &'a str, expected: &'static str) -> Option<&'a str> {
     None
 }
 
+/// Returns the names of the top-level sections of the live settings, like "ntp" for
+/// "settings.ntp.time-servers", so clients don't have to fetch every key to find them.
+pub(crate) fn list_setting_sections<D: DataStore>(datastore: &D) -> Result<HashSet<String>> {
+    let keys = datastore
+        .list_populated_keys("settings.", &Committed::Live)
+        .context(error::DataStoreSnafu {
+            op: "list_populated_keys",
+        })?;
+
+    Ok(keys
+        .iter()
+        .filter_map(|key| key.segments().get(1).cloned())
+        .collect())
+}
+
+/// The variant's default settings, as given to storewolf when it creates the data store.
+pub(crate) const DEFAULTS_TOML_PATH: &str = "/etc/storewolf/defaults.toml";
+
+/// Reads the default settings from the given defaults file into a data store, so they can be
+/// queried like live settings.  Only modeled settings from the file's 'settings' table are
+/// included; settings with no default aren't in the data store.  A missing file means there are
+/// no defaults.
+pub(crate) fn read_setting_defaults<P: AsRef<Path>>(path: P) -> Result<MemoryDataStore> {
+    let path = path.as_ref();
+    let mut datastore = MemoryDataStore::new();
+    let defaults_str = match std::fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(datastore),
+        Err(e) => return Err(e).context(error::DefaultsReadSnafu { path }),
+    };
+    let defaults: toml::Value =
+        toml::from_str(&defaults_str).context(error::DefaultsFormatSnafu { path })?;
+    let settings_val = match defaults.get("settings") {
+        Some(v) => v,
+        None => return Ok(datastore),
+    };
+
+    // Going through the model checks the defaults and drops anything it doesn't know about.
+    let settings: Settings = serde_json::to_value(settings_val)
+        .and_then(serde_json::from_value)
+        .context(error::DefaultsModelSnafu { path })?;
+    let settings_json = serde_json::to_value(&settings).context(error::SettingsToJsonSnafu)?;
+    let pairs = to_pairs_with_prefix("settings", &settings_json).context(
+        error::DataStoreSerializationSnafu {
+            given: "default settings",
+        },
+    )?;
+    datastore
+        .set_keys(&pairs, &Committed::Live)
+        .context(error::DataStoreSnafu { op: "set_keys" })?;
+    Ok(datastore)
+}
+
 /// Build a Settings based on the data in the datastore.  Errors if no settings are found.
 pub(crate) fn get_settings<D: DataStore>(datastore: &D, committed: &Committed) -> Result<Settings> {
     get_prefix(datastore, committed, "settings.", None)
@@ -116,42 +411,293 @@ pub(crate) fn get_os_info() -> Result<BottlerocketRelease> {
 /// Build a BottlerocketRelease using the bottlerocket-release library, returning only the fields
 /// that start with the given prefix.  If the prefix was meant for another structure, we return
 /// None, making it easier to decide whether to include an empty structure in API results.
-pub(crate) fn get_os_prefix<S>(prefix: S) -> Result<Option<serde_json::Value>>
+pub(crate) fn get_os_prefix<S>(prefix: S) -> Result<Option<PartialBottlerocketRelease>>
 where
     S: AsRef<str>,
 {
-    let prefix = prefix.as_ref();
+    // Return early if the prefix can't match os data, so we don't read the release file for
+    // nothing.  (This is important because get_model checks all of our model types using the same
+    // given prefix.)
+    if check_prefix(prefix.as_ref(), "os.").is_none() {
+        return Ok(None);
+    }
+    let os = BottlerocketRelease::new().context(error::ReleaseDataSnafu)?;
+    Ok(filter_os_prefix(os, prefix))
+}
 
-    // Return early if the prefix can't match os data.  (This is important because get_model checks
-    // all of our model types using the same given prefix.)
-    let prefix = match check_prefix(prefix, "os.") {
-        Some(prefix) => prefix,
-        None => return Ok(None),
-    };
+/// Returns the fields of the given BottlerocketRelease that start with the given prefix, or None
+/// if the prefix was meant for another structure.
+pub(crate) fn filter_os_prefix<S>(
+    os: BottlerocketRelease,
+    prefix: S,
+) -> Option<PartialBottlerocketRelease>
+where
+    S: AsRef<str>,
+{
+    let prefix = check_prefix(prefix.as_ref(), "os.")?;
 
     // We're not using the data store here, there are no dotted keys, we're just matching against
     // field names.  Strip off the structure-level prefix.
     let field_prefix = prefix.trim_start_matches("os.");
 
-    let os = BottlerocketRelease::new().context(error::ReleaseDataSnafu)?;
+    Some(os.with_field_prefix(field_prefix))
+}
+
+/// Returns the names of the fields of the given BottlerocketRelease, as they appear in API
+/// responses, sorted.
+pub(crate) fn os_field_names(os: &BottlerocketRelease) -> Vec<String> {
+    let mut names: Vec<String> = match serde_json::to_value(os).expect("struct to value can't fail")
+    {
+        Value::Object(fields) => fields.into_iter().map(|(name, _)| name).collect(),
+        _ => Vec::new(),
+    };
+    names.sort();
+    names
+}
+
+/// Finds the version of the data store at the given path.  The versioned symlinks that point to
+/// the data store live alongside it, in its parent directory.
+pub(crate) fn get_datastore_version<P: AsRef<Path>>(datastore_path: P) -> Result<Version> {
+    let datastore_path = datastore_path.as_ref();
+    let datastore_dir = datastore_path.parent().context(error::DataStoreDirSnafu {
+        path: datastore_path,
+    })?;
+    datastore::current_version(datastore_dir).context(error::DataStoreSnafu {
+        op: "current_version",
+    })
+}
+
+/// Build a Settings from the live data of an older data store version, e.g. from before an
+/// upgrade.  Older versions are kept alongside the data store at the given path.
+pub(crate) fn get_settings_at_version<P: AsRef<Path>>(
+    datastore_path: P,
+    version: &Version,
+) -> Result<Settings> {
+    let datastore_path = datastore_path.as_ref();
+    let datastore_dir = datastore_path.parent().context(error::DataStoreDirSnafu {
+        path: datastore_path,
+    })?;
+    let datastore = FilesystemDataStore::at_version(datastore_dir, version).context(
+        error::DataStoreVersionSnafu {
+            version: version.clone(),
+        },
+    )?;
+    get_settings(&datastore, &Committed::Live)
+}
+
+/// Removes stale copies of the data store at the given path, keeping the given number of older
+/// versions for rollback.  Returns the removed paths.
+pub(crate) fn compact_datastore<P: AsRef<Path>>(
+    datastore_path: P,
+    keep_versions: usize,
+) -> Result<Vec<PathBuf>> {
+    let datastore_path = datastore_path.as_ref();
+    let datastore_dir = datastore_path.parent().context(error::DataStoreDirSnafu {
+        path: datastore_path,
+    })?;
+    FilesystemDataStore::compact(datastore_dir, keep_versions)
+        .context(error::DataStoreSnafu { op: "compact" })
+}
+
+/// What's in the data store, and roughly how much space its live data takes, for capacity
+/// planning.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct DatastoreStats {
+    /// The number of populated live data keys.
+    live_keys: usize,
+    /// The number of transactions with pending changes.
+    pending_transactions: usize,
+    /// The number of live metadata values, across all data keys.
+    metadata_entries: usize,
+    /// The approximate size of the live data and metadata, in bytes.
+    live_bytes: u64,
+}
+
+/// Returns the generation of the live data, which changes with every live write.
+pub(crate) fn get_generation<D: DataStore>(datastore: &D) -> Result<u64> {
+    datastore
+        .generation()
+        .context(error::DataStoreSnafu { op: "generation" })
+}
+
+/// Gathers counts and the approximate size of the data store.
+pub(crate) fn get_datastore_stats<D: DataStore>(datastore: &D) -> Result<DatastoreStats> {
+    let live_keys = datastore
+        .count_prefix("", &Committed::Live)
+        .context(error::DataStoreSnafu { op: "count_prefix" })?;
+    let pending_transactions = datastore
+        .list_transactions()
+        .context(error::DataStoreSnafu {
+            op: "list_transactions",
+        })?
+        .len();
+    let metadata_entries = datastore
+        .list_populated_metadata("", &None::<&str>)
+        .context(error::DataStoreSnafu {
+            op: "list_populated_metadata",
+        })?
+        .values()
+        .map(HashSet::len)
+        .sum();
+    let live_bytes = datastore
+        .live_size()
+        .context(error::DataStoreSnafu { op: "live_size" })?;
+    Ok(DatastoreStats {
+        live_keys,
+        pending_transactions,
+        metadata_entries,
+        live_bytes,
+    })
+}
+
+/// Checks the version symlink chain that leads to the data store at the given path.
+pub(crate) fn check_datastore_symlinks<P: AsRef<Path>>(datastore_path: P) -> Result<SymlinkCheck> {
+    let datastore_path = datastore_path.as_ref();
+    let datastore_dir = datastore_path.parent().context(error::DataStoreDirSnafu {
+        path: datastore_path,
+    })?;
+    FilesystemDataStore::check_symlinks(datastore_dir).context(error::DataStoreSnafu {
+        op: "check_symlinks",
+    })
+}
+
+/// Repairs the version symlink chain that leads to the data store at the given path, if it's
+/// broken, by pointing it at the newest data store copy.  Returns the resulting chain.
+pub(crate) fn repair_datastore_symlinks<P: AsRef<Path>>(datastore_path: P) -> Result<SymlinkCheck> {
+    let datastore_path = datastore_path.as_ref();
+    let datastore_dir = datastore_path.parent().context(error::DataStoreDirSnafu {
+        path: datastore_path,
+    })?;
+    if let Some(version) =
+        FilesystemDataStore::repair_symlinks(datastore_dir).context(error::DataStoreSnafu {
+            op: "repair_symlinks",
+        })?
+    {
+        info!("Repaired data store version links to point to {}", version);
+    }
+    check_datastore_symlinks(datastore_path)
+}
+
+/// Gets the value of a single data key exactly as it's stored, without deserializing it, for
+/// debugging.  Returns Err if the key isn't set.
+pub(crate) fn get_raw_key<D: DataStore>(
+    datastore: &D,
+    key_str: &str,
+    committed: &Committed,
+) -> Result<String> {
+    let key = Key::new(KeyType::Data, key_str).context(error::NewKeySnafu {
+        key_type: "data",
+        name: key_str,
+    })?;
+    datastore
+        .get_key(&key, committed)
+        .context(error::DataStoreSnafu { op: "get_key" })?
+        .context(error::MissingDataSnafu { prefix: key_str })
+}
 
-    // Turn into a serde Value we can manipulate.
-    let val = serde_json::to_value(os).expect("struct to value can't fail");
+/// The live and pending values of a data key, side by side; either is None if it isn't set.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct KeyComparison {
+    pub(crate) live: Option<Value>,
+    pub(crate) pending: Option<Value>,
+}
 
-    // Structs are Objects in serde_json, which have a map of field -> value inside.  We
-    // destructure to get it by value, instead of as_object() which gives references.
-    let map = match val {
-        Value::Object(map) => map,
-        _ => panic!("structs are always objects"),
+/// Gets the live value of the given data key along with its value in the given transaction, to
+/// see what committing the transaction would change.
+pub(crate) fn compare_key<D: DataStore>(
+    datastore: &D,
+    key_str: &str,
+    transaction: &str,
+) -> Result<KeyComparison> {
+    let key = Key::new(KeyType::Data, key_str).context(error::NewKeySnafu {
+        key_type: "data",
+        name: key_str,
+    })?;
+    let get_value = |committed: &Committed| -> Result<Option<Value>> {
+        datastore
+            .get_key(&key, committed)
+            .context(error::DataStoreSnafu { op: "get_key" })?
+            .map(|value_str| {
+                deserialize_scalar::<_, ScalarError>(&value_str)
+                    .context(error::InvalidValueSnafu { key: key_str })
+            })
+            .transpose()
     };
+    Ok(KeyComparison {
+        live: get_value(&Committed::Live)?,
+        pending: get_value(&Committed::Pending {
+            tx: transaction.into(),
+        })?,
+    })
+}
 
-    // Keep the fields whose names match the requested prefix.
-    let filtered = map
-        .into_iter()
-        .filter(|(field_name, _val)| field_name.starts_with(field_prefix))
-        .collect();
+/// Gets the names of the pending transactions that stage the given data key.
+pub(crate) fn get_transactions_for_key<D: DataStore>(
+    datastore: &D,
+    key_str: &str,
+) -> Result<HashSet<String>> {
+    let key = Key::new(KeyType::Data, key_str).context(error::NewKeySnafu {
+        key_type: "data",
+        name: key_str,
+    })?;
+    datastore
+        .transactions_for_key(&key)
+        .context(error::DataStoreSnafu {
+            op: "transactions_for_key",
+        })
+}
+
+/// Gets whether each of the requested data keys is populated in the given data set.
+pub(crate) fn get_keys_exist<D: DataStore>(
+    datastore: &D,
+    data_key_strs: &HashSet<&str>,
+    committed: &Committed,
+) -> Result<BTreeMap<String, bool>> {
+    let mut result = BTreeMap::new();
+    for data_key_str in data_key_strs {
+        let data_key = Key::new(KeyType::Data, data_key_str).context(error::NewKeySnafu {
+            key_type: "data",
+            name: *data_key_str,
+        })?;
+        let populated =
+            datastore
+                .key_populated(&data_key, committed)
+                .context(error::DataStoreSnafu {
+                    op: "key_populated",
+                })?;
+        result.insert(data_key.to_string(), populated);
+    }
+    Ok(result)
+}
+
+/// Returns the number of populated data keys starting with the given prefix, without fetching
+/// their values.
+pub(crate) fn count_settings_prefix<D: DataStore, S: AsRef<str>>(
+    datastore: &D,
+    prefix: S,
+    committed: &Committed,
+) -> Result<usize> {
+    datastore
+        .count_prefix(prefix, committed)
+        .context(error::DataStoreSnafu { op: "count_prefix" })
+}
 
-    Ok(Some(filtered))
+/// Returns the data keys whose strength metadata is weak, meaning they're removed on reboot,
+/// optionally only those starting with the given prefix.  Strength is inherited, so a weak key
+/// also covers any settings below it.
+pub(crate) fn get_weak_settings<D: DataStore>(
+    datastore: &D,
+    prefix: Option<&str>,
+) -> Result<BTreeSet<String>> {
+    let strengths = get_metadata_for_all_data_keys(datastore, STRENGTH_METADATA_KEY)?;
+    Ok(strengths
+        .into_iter()
+        .filter(|(key, strength)| {
+            strength.as_str() == Some(WEAK_STRENGTH)
+                && prefix.is_none_or(|prefix| key.starts_with(prefix))
+        })
+        .map(|(key, _)| key)
+        .collect())
 }
 
 /// Build a Services based on the data in the datastore.
@@ -321,6 +867,96 @@ pub(crate) fn get_configuration_files_names<D: DataStore>(
     )
 }
 
+/// A problem found with a configuration file entry by `validate_configuration_files`.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct ConfigurationFileProblem {
+    /// The name of the configuration file entry.
+    name: String,
+    problem: String,
+}
+
+/// Checks each configuration file entry in the data store for problems that would otherwise only
+/// show up when the file is rendered: a template that doesn't exist on disk, or an output path
+/// that couldn't reasonably be written.  Returns the problems found, sorted by entry name; an
+/// empty list means everything looks OK.
+pub(crate) fn validate_configuration_files<D: DataStore>(
+    datastore: &D,
+) -> Result<Vec<ConfigurationFileProblem>> {
+    let configuration_files: BTreeMap<_, _> =
+        get_configuration_files(datastore)?.into_iter().collect();
+
+    let mut problems = Vec::new();
+    for (name, configuration_file) in configuration_files {
+        let mut problem = |problem: String| {
+            problems.push(ConfigurationFileProblem {
+                name: name.clone(),
+                problem,
+            })
+        };
+
+        let template_path = Path::new(configuration_file.template_path.as_ref());
+        if !template_path.is_file() {
+            problem(format!(
+                "template-path '{}' does not exist",
+                template_path.display()
+            ));
+        }
+
+        let path = Path::new(configuration_file.path.as_ref());
+        if !path.is_absolute() {
+            problem(format!("path '{}' is not absolute", path.display()));
+        }
+        if path.components().any(|c| c == Component::ParentDir) {
+            problem(format!("path '{}' contains '..'", path.display()));
+        }
+        if path.file_name().is_none() || configuration_file.path.ends_with('/') {
+            problem(format!("path '{}' does not name a file", path.display()));
+        }
+    }
+    Ok(problems)
+}
+
+/// Builds the context the named configuration file's template is rendered with: the live settings
+/// of each extension required by the template's frontmatter, under 'settings', and the given OS
+/// release, under 'os'.  This matches how the template renderer narrows the model, so it's exactly
+/// what the template can refer to.
+pub(crate) fn get_template_context<D: DataStore>(
+    datastore: &D,
+    name: &str,
+    os: &BottlerocketRelease,
+) -> Result<Value> {
+    let configuration_file = get_configuration_files(datastore)?
+        .remove(name)
+        .context(error::NoConfigurationFileSnafu { name })?;
+    let path = Path::new(configuration_file.template_path.as_ref());
+    let template = std::fs::read_to_string(path).context(error::TemplateReadSnafu { path })?;
+    let extensions = template_extensions(&template, path)?;
+
+    let settings = serde_json::to_value(get_settings(datastore, &Committed::Live)?)
+        .context(error::SettingsToJsonSnafu)?;
+    // Extensions without settings, like the helpers in 'std', are left out.
+    let settings: serde_json::Map<String, Value> = extensions
+        .into_iter()
+        .filter_map(|extension| {
+            let value = settings.get(&extension)?.clone();
+            Some((extension, value))
+        })
+        .collect();
+    let os = serde_json::to_value(os).context(error::SerializeSnafu)?;
+    Ok(serde_json::json!({ "settings": settings, "os": os }))
+}
+
+/// Returns the names of the extensions required by a template's frontmatter, parsing it as the
+/// template renderer does.
+fn template_extensions(template: &str, path: &Path) -> Result<Vec<String>> {
+    let template = Template::from_str(template).context(error::TemplateParseSnafu { path })?;
+    Ok(template
+        .frontmatter
+        .extension_requirements()
+        .map(|requirement| requirement.name)
+        .collect())
+}
+
 /// Helper to get data from the datastore for a collection of requested items under a given prefix.  For
 /// example, a collection of Service items under "services" that have the requested names.
 /// Returns Err if we couldn't pull expected data, including the case where a name was specified
@@ -359,6 +995,121 @@ where
     Ok(result)
 }
 
+/// Renders the given settings as an environment file, one NAME=VALUE line per setting, sorted by
+/// name, for host containers that consume one.
+///
+/// Names are built from the setting's key, including the "settings" prefix: each segment is
+/// uppercased, any character other than an ASCII letter or digit (like '-', or the '.' and '/' in
+/// a quoted segment) becomes '_', and segments are joined with '_'.  For example,
+/// settings.ntp.time-servers becomes SETTINGS_NTP_TIME_SERVERS.  Since this can map different
+/// keys to the same name, like a-b and a_b, a collision is reported as an error rather than
+/// silently dropping one of them.
+///
+/// String values are given as-is and other values, like lists, as JSON.  Values are wrapped in
+/// single quotes if they contain anything but letters, digits, and the characters in
+/// `_-.,:/+@%=`, with any single quote written as '\'' so the file can be sourced by a shell.
+pub(crate) fn settings_env_file<S: Serialize>(settings: &S) -> Result<String> {
+    let settings_json = serde_json::to_value(settings).context(error::SettingsToJsonSnafu)?;
+    let pairs = to_pairs_with_prefix("settings", &settings_json)
+        .context(error::DataStoreSerializationSnafu { given: "Settings" })?;
+
+    let mut lines: BTreeMap<String, (String, String)> = BTreeMap::new();
+    for (key, value) in pairs {
+        let name = key
+            .segments()
+            .iter()
+            .map(|segment| {
+                segment
+                    .chars()
+                    .map(|c| {
+                        if c.is_ascii_alphanumeric() {
+                            c.to_ascii_uppercase()
+                        } else {
+                            '_'
+                        }
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("_");
+        // We just serialized the value, so it's valid JSON.
+        let value: serde_json::Value =
+            serde_json::from_str(&value).expect("serialized setting is JSON");
+        let value = match value {
+            serde_json::Value::String(s) => s,
+            other => other.to_string(),
+        };
+        if let Some((first, _)) = lines.get(&name) {
+            return error::EnvNameCollisionSnafu {
+                name,
+                first: first.clone(),
+                second: key.to_string(),
+            }
+            .fail();
+        }
+        lines.insert(name, (key.to_string(), shell_quote(&value)));
+    }
+
+    Ok(lines
+        .into_iter()
+        .map(|(name, (_, value))| format!("{}={}\n", name, value))
+        .collect())
+}
+
+/// Quotes the given value for a shell, if needed; see settings_env_file.
+fn shell_quote(value: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-.,:/+@%=".contains(c);
+    if !value.is_empty() && value.chars().all(safe) {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Reads settings from a file in the seed directory, as JSON or TOML based on its extension, and
+/// checks them against the model.  The file holds settings like a PATCH of /settings, or the same
+/// under a top-level 'settings' key, like user data.  The path must be under the seed directory as
+/// given, without '..' components, so whether files exist elsewhere isn't revealed; it's then
+/// resolved and checked again, so symlinks can't lead out of the seed directory.
+pub(crate) fn read_seed_settings<P1, P2>(seed_dir: P1, path: P2) -> Result<Settings>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let (seed_dir, path) = (seed_dir.as_ref(), path.as_ref());
+    let not_allowed = || error::SeedPathNotAllowedSnafu {
+        path,
+        dir: seed_dir,
+    };
+    ensure!(
+        path.starts_with(seed_dir) && !path.components().any(|c| c == Component::ParentDir),
+        not_allowed()
+    );
+    let resolved = std::fs::canonicalize(path).context(error::SeedReadSnafu { path })?;
+    // If the seed directory doesn't exist, nothing can be in it.
+    let seed_dir_resolved = std::fs::canonicalize(seed_dir)
+        .ok()
+        .context(not_allowed())?;
+    ensure!(resolved.starts_with(&seed_dir_resolved), not_allowed());
+
+    let contents = std::fs::read_to_string(&resolved).context(error::SeedReadSnafu { path })?;
+    let value: Value = match resolved.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&contents).context(error::SeedJsonSnafu { path })?,
+        Some("toml") => {
+            let value: toml::Value =
+                toml::from_str(&contents).context(error::SeedTomlSnafu { path })?;
+            serde_json::to_value(value).context(error::SettingsToJsonSnafu)?
+        }
+        _ => return error::SeedFileTypeSnafu { path }.fail(),
+    };
+    let settings = match value {
+        Value::Object(mut map) if map.contains_key("settings") => {
+            map.remove("settings").unwrap_or_default()
+        }
+        value => value,
+    };
+    serde_json::from_value(settings).context(error::SeedModelSnafu { path })
+}
+
 /// Given a Settings, takes any Some values and updates them in the datastore.
 pub(crate) fn set_settings<D: DataStore>(
     datastore: &mut D,
@@ -377,6 +1128,57 @@ pub(crate) fn set_settings<D: DataStore>(
         .context(error::DataStoreSnafu { op: "set_keys" })
 }
 
+/// Returns the names of the given input keys that don't correspond to a field in the Settings
+/// model, sorted.  `settings` should be the result of deserializing the input; keys that were
+/// dropped along the way, for example because of a typo, are the ones returned.
+pub(crate) fn find_unmodeled_keys(
+    input: &HashMap<Key, String>,
+    settings: &Settings,
+) -> Result<Vec<String>> {
+    let settings_json = serde_json::to_value(settings).context(error::SettingsToJsonSnafu)?;
+    let modeled = to_pairs_with_prefix("settings", &settings_json)
+        .context(error::DataStoreSerializationSnafu { given: "Settings" })?;
+
+    let mut unmodeled: Vec<String> = input
+        .iter()
+        // An explicit null deserializes to an unset field, so it doesn't round-trip; it's still
+        // a modeled key, though, or deserialization would have failed.
+        .filter(|(key, value)| value.as_str() != "null" && !modeled.contains_key(key))
+        .map(|(key, _)| key.name().clone())
+        .collect();
+    unmodeled.sort();
+    Ok(unmodeled)
+}
+
+/// Returns the names of the keys that would be written by the given settings and that are managed
+/// by a setting generator, sorted.  A key is managed if it, or a key above it, has
+/// setting-generator metadata.
+pub(crate) fn find_generated_keys<D: DataStore>(
+    datastore: &D,
+    settings: &Settings,
+) -> Result<Vec<String>> {
+    let settings_json = serde_json::to_value(settings).context(error::SettingsToJsonSnafu)?;
+    let written = to_pairs_with_prefix("settings", &settings_json)
+        .context(error::DataStoreSerializationSnafu { given: "Settings" })?;
+    let generated = get_metadata_for_all_data_keys(datastore, "setting-generator")?;
+
+    let mut managed: Vec<String> = written
+        .keys()
+        .map(|key| key.name())
+        .filter(|name| {
+            generated.keys().any(|generated| {
+                name.as_str() == generated
+                    || name
+                        .strip_prefix(generated.as_str())
+                        .is_some_and(|rest| rest.starts_with('.'))
+            })
+        })
+        .cloned()
+        .collect();
+    managed.sort();
+    Ok(managed)
+}
+
 // This is not as nice as get_settings, which uses Serializer/Deserializer to properly use the
 // data model and check types.
 /// Gets the value of a metadata key for the requested list of data keys.
@@ -386,37 +1188,228 @@ pub(crate) fn get_metadata_for_data_keys<D: DataStore, S: AsRef<str>>(
     data_key_strs: &HashSet<&str>,
 ) -> Result<HashMap<String, Value>> {
     trace!("Getting metadata '{}'", md_key_str.as_ref());
-    let md_key = Key::new(KeyType::Meta, md_key_str.as_ref()).context(error::NewKeySnafu {
-        key_type: "meta",
-        name: md_key_str.as_ref(),
-    })?;
-
     let mut result = HashMap::new();
     for data_key_str in data_key_strs {
         trace!("Pulling metadata from datastore for key: {}", data_key_str);
-        let data_key = Key::new(KeyType::Data, data_key_str).context(error::NewKeySnafu {
-            key_type: "data",
-            name: *data_key_str,
-        })?;
-        let value_str = match datastore.get_metadata(&md_key, &data_key) {
+        let value = match get_effective_metadata(datastore, data_key_str, md_key_str.as_ref()) {
             Ok(Some(v)) => v,
             // TODO: confirm we want to skip requested keys if not populated, or error
             Ok(None) => continue,
             // May want to make it possible to receive an error if a metadata key doesn't
             // exist, but to start, we expect to request metadata for multiple keys and not all
             // of them will necessarily have the metadata.
-            Err(_) => continue,
+            Err(error::Error::DataStore { .. }) => continue,
+            Err(e) => return Err(e),
         };
-        trace!("Deserializing scalar from metadata");
-        let value: Value = deserialize_scalar::<_, ScalarError>(&value_str)
-            .context(error::InvalidMetadataSnafu { key: md_key.name() })?;
-        result.insert(data_key.to_string(), value);
+        result.insert(data_key_str.to_string(), value);
     }
 
     Ok(result)
 }
 
-/// Gets the value of a metadata key everywhere it's found in the data store.  Returns a mapping
+/// Gets the effective live value of the named metadata for a data key: the value set on the key
+/// itself, or else the value set on its nearest ancestor, so a child overrides its parent.  For
+/// example, strength set on 'settings.kubernetes' applies to 'settings.kubernetes.cluster-name'
+/// unless that key has a strength of its own.  Returns None if neither the key nor any of its
+/// ancestors has the metadata.
+pub(crate) fn get_effective_metadata<D, S1, S2>(
+    datastore: &D,
+    data_key_str: S1,
+    md_key_str: S2,
+) -> Result<Option<Value>>
+where
+    D: DataStore,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    let data_key = Key::new(KeyType::Data, data_key_str.as_ref()).context(error::NewKeySnafu {
+        key_type: "data",
+        name: data_key_str.as_ref(),
+    })?;
+    let md_key = Key::new(KeyType::Meta, md_key_str.as_ref()).context(error::NewKeySnafu {
+        key_type: "meta",
+        name: md_key_str.as_ref(),
+    })?;
+    // The data store checks the key and each of its ancestors, keeping the nearest value.
+    let value_str = datastore
+        .get_metadata(&md_key, &data_key, &Committed::Live)
+        .context(error::DataStoreSnafu { op: "get_metadata" })?;
+    value_str
+        .map(|value_str| {
+            trace!("Deserializing scalar from metadata");
+            deserialize_scalar::<_, ScalarError>(&value_str)
+                .context(error::InvalidMetadataSnafu { key: md_key.name() })
+        })
+        .transpose()
+}
+
+/// Writes the given metadata into a pending transaction, so it can be committed along with, or
+/// without, settings changes.  Metadata is given as a map of data key to a map of metadata key to
+/// value, e.g. {"settings.motd": {"strength": "weak"}}.
+pub(crate) fn set_metadata<D: DataStore>(
+    datastore: &mut D,
+    metadata: &HashMap<String, HashMap<String, Value>>,
+    transaction: &str,
+) -> Result<()> {
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    for (data_key_str, meta_map) in metadata {
+        let data_key = Key::new(KeyType::Data, data_key_str).context(error::NewKeySnafu {
+            key_type: "data",
+            name: data_key_str,
+        })?;
+        for (md_key_str, value) in meta_map {
+            let md_key = Key::new(KeyType::Meta, md_key_str).context(error::NewKeySnafu {
+                key_type: "meta",
+                name: md_key_str,
+            })?;
+            datastore
+                .set_metadata_typed(&md_key, &data_key, value, &pending)
+                .context(error::DataStoreSnafu { op: "set_metadata" })?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets the description metadata of each given data key, a free-text note explaining the
+/// setting.  Descriptions are set directly in the live data store, so they're kept across
+/// commits.  Every key is checked before any description is set, so an invalid key leaves all
+/// descriptions as they were.
+pub(crate) fn set_descriptions<D: DataStore>(
+    datastore: &mut D,
+    descriptions: &HashMap<String, String>,
+) -> Result<()> {
+    let md_key = Key::new(KeyType::Meta, DESCRIPTION_METADATA_KEY).context(error::NewKeySnafu {
+        key_type: "meta",
+        name: DESCRIPTION_METADATA_KEY,
+    })?;
+
+    let descriptions = descriptions
+        .iter()
+        .map(|(data_key_str, description)| {
+            Key::new(KeyType::Data, data_key_str)
+                .context(error::NewKeySnafu {
+                    key_type: "data",
+                    name: data_key_str,
+                })
+                .map(|data_key| (data_key, description))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for (data_key, description) in descriptions {
+        datastore
+            .set_metadata_typed(&md_key, &data_key, description, &Committed::Live)
+            .context(error::DataStoreSnafu { op: "set_metadata" })?;
+    }
+    Ok(())
+}
+
+/// Everything that's affected by changing a set of settings, gathered in one response so
+/// clients don't need a request for each.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct ChangeImpact {
+    /// The affected-services metadata of each requested key.
+    affected_services: HashMap<String, Value>,
+    /// The template metadata of each requested key.
+    templates: HashMap<String, Value>,
+    /// The restart commands of each affected service.
+    restart_commands: HashMap<String, Vec<String>>,
+}
+
+/// Gets the affected services, templates, and restart commands for the requested list of data
+/// keys.
+pub(crate) fn get_change_impact<D: DataStore>(
+    datastore: &D,
+    data_key_strs: &HashSet<&str>,
+) -> Result<ChangeImpact> {
+    let affected_services =
+        get_metadata_for_data_keys(datastore, "affected-services", data_key_strs)?;
+    let templates = get_metadata_for_data_keys(datastore, "template", data_key_strs)?;
+
+    // affected-services metadata is a list of service names for each key.
+    let service_names: HashSet<&str> = affected_services
+        .values()
+        .filter_map(Value::as_array)
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    let restart_commands = get_services_names(datastore, &service_names, &Committed::Live)?
+        .into_iter()
+        .map(|(name, service)| (name, service.restart_commands))
+        .collect();
+
+    Ok(ChangeImpact {
+        affected_services,
+        templates,
+        restart_commands,
+    })
+}
+
+/// Returns whether committing changes to the given keys requires a reboot for them to take
+/// effect: that is, whether any of them, or a key above one of them, has reboot-required metadata
+/// of true.  Restarting affected services is enough for other changes.
+pub(crate) fn commit_requires_reboot<D: DataStore>(
+    datastore: &D,
+    changed_keys: &HashSet<Key>,
+) -> Result<bool> {
+    for key in changed_keys {
+        let reboot_required =
+            get_effective_metadata(datastore, key.name(), REBOOT_REQUIRED_METADATA_KEY)?;
+        if reboot_required == Some(Value::Bool(true)) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Where a setting's value could have come from, to help explain why it's set.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct SettingProvenance {
+    /// Whether a setting generator is defined for the setting, meaning its value may have been
+    /// generated rather than set by a user.
+    has_generator: bool,
+    /// Whether the setting has a live value.
+    populated: bool,
+    /// The strength of the setting; settings without strength metadata are strong.
+    strength: Value,
+}
+
+/// Gets the provenance of each of the requested data keys, combining setting-generator and
+/// strength metadata with whether the key is populated.
+pub(crate) fn get_setting_provenance<D: DataStore>(
+    datastore: &D,
+    data_key_strs: &HashSet<&str>,
+) -> Result<HashMap<String, SettingProvenance>> {
+    let generators = get_metadata_for_data_keys(datastore, "setting-generator", data_key_strs)?;
+    let mut strengths =
+        get_metadata_for_data_keys(datastore, STRENGTH_METADATA_KEY, data_key_strs)?;
+
+    let mut result = HashMap::new();
+    for data_key_str in data_key_strs {
+        let data_key = Key::new(KeyType::Data, data_key_str).context(error::NewKeySnafu {
+            key_type: "data",
+            name: *data_key_str,
+        })?;
+        let populated = datastore
+            .key_populated(&data_key, &Committed::Live)
+            .context(error::DataStoreSnafu {
+                op: "key_populated",
+            })?;
+        let name = data_key.to_string();
+        let provenance = SettingProvenance {
+            has_generator: generators.contains_key(&name),
+            populated,
+            strength: strengths
+                .remove(&name)
+                .unwrap_or_else(|| STRONG_STRENGTH.into()),
+        };
+        result.insert(name, provenance);
+    }
+    Ok(result)
+}
+
+/// Gets the value of a metadata key everywhere it's found in the data store.  Returns a mapping
 /// of data key to the metadata value associated with the requested key.
 pub(crate) fn get_metadata_for_all_data_keys<D: DataStore, S: AsRef<str>>(
     datastore: &D,
@@ -444,24 +1437,409 @@ pub(crate) fn get_metadata_for_all_data_keys<D: DataStore, S: AsRef<str>>(
     Ok(result)
 }
 
-/// Makes live any pending settings in the datastore, returning the changed keys.
-pub(crate) fn commit_transaction<D>(datastore: &mut D, transaction: &str) -> Result<HashSet<Key>>
+/// Gets the live setting generator of the given data key, if it has one.  Only a generator for
+/// the key itself is returned, not one for a key above it, since that generates the parent.
+pub(crate) fn get_setting_generator<D: DataStore, S: AsRef<str>>(
+    datastore: &D,
+    data_key_str: S,
+) -> Result<Option<RawSettingsGenerator>> {
+    let data_key_str = data_key_str.as_ref();
+    let data_key = Key::new(KeyType::Data, data_key_str).context(error::NewKeySnafu {
+        key_type: "data",
+        name: data_key_str,
+    })?;
+    let md_key = Key::new(KeyType::Meta, "setting-generator").context(error::NewKeySnafu {
+        key_type: "meta",
+        name: "setting-generator",
+    })?;
+    let value_str = datastore
+        .get_metadata_raw(&md_key, &data_key, &Committed::Live)
+        .context(error::DataStoreSnafu {
+            op: "get_metadata_raw",
+        })?;
+    value_str
+        .map(|value_str| {
+            deserialize_scalar::<_, ScalarError>(&value_str)
+                .context(error::InvalidMetadataSnafu { key: md_key.name() })
+        })
+        .transpose()
+}
+
+/// Gets every metadata value attached directly to one data key, as a mapping of metadata key to
+/// value.  The data store can only list live metadata, so for a transaction this returns the live
+/// metadata of the key with any values staged in the transaction in their place.
+pub(crate) fn get_metadata_for_key<D: DataStore, S: AsRef<str>>(
+    datastore: &D,
+    data_key_str: S,
+    committed: &Committed,
+) -> Result<HashMap<String, Value>> {
+    let data_key_str = data_key_str.as_ref();
+    trace!("Getting all metadata for '{}'", data_key_str);
+    let data_key = Key::new(KeyType::Data, data_key_str).context(error::NewKeySnafu {
+        key_type: "data",
+        name: data_key_str,
+    })?;
+    let mut meta_map = datastore
+        .get_metadata_prefix(data_key_str, &None::<&str>)
+        .context(error::DataStoreSnafu {
+            op: "get_metadata_prefix",
+        })?;
+    // The prefix also matches longer keys, like 'motd-extra' for 'motd', so only keep our own.
+    let metadata = meta_map.remove(&data_key).unwrap_or_default();
+
+    let mut result = HashMap::new();
+    for (meta_key, live_value) in metadata {
+        let value_str = match committed {
+            Committed::Live => live_value,
+            Committed::Pending { .. } => datastore
+                .get_metadata_raw(&meta_key, &data_key, committed)
+                .context(error::DataStoreSnafu {
+                    op: "get_metadata_raw",
+                })?
+                .unwrap_or(live_value),
+        };
+        trace!("Deserializing scalar from metadata");
+        let value: Value = deserialize_scalar::<_, ScalarError>(&value_str).context(
+            error::InvalidMetadataSnafu {
+                key: meta_key.name(),
+            },
+        )?;
+        result.insert(meta_key.name().to_string(), value);
+    }
+    Ok(result)
+}
+
+/// Parses the JSON output of bloodhound into a typed report, so the shape returned to clients is
+/// validated and stable.
+pub(crate) fn parse_report(output: &str) -> Result<CisReport> {
+    serde_json::from_str(output).context(error::ReportSchemaSnafu)
+}
+
+/// Parses a report status filter as given by the user, like "failed".
+pub(crate) fn parse_report_status(given: &str) -> Result<CheckStatus> {
+    match given {
+        "passed" => Ok(CheckStatus::Pass),
+        "failed" => Ok(CheckStatus::Fail),
+        "skipped" => Ok(CheckStatus::Skip),
+        _ => error::InvalidReportStatusSnafu { given }.fail(),
+    }
+}
+
+/// Removes any check results from the report that don't have the given status.  The summary
+/// counts still describe the full report.
+pub(crate) fn filter_report(report: &mut CisReport, status: CheckStatus) {
+    report.results.retain(|_, result| result.status == status);
+}
+
+/// Combines the output of several reports into one object keyed by report name.  If `json` is
+/// true, each output is parsed as a report, and filtered by `status` if given; otherwise each
+/// output is included as text.
+pub(crate) fn combine_reports(
+    outputs: Vec<(String, String)>,
+    json: bool,
+    status: Option<CheckStatus>,
+) -> Result<BTreeMap<String, serde_json::Value>> {
+    let mut combined = BTreeMap::new();
+    for (name, output) in outputs {
+        let value = if json {
+            let mut report = parse_report(&output)?;
+            if let Some(status) = status {
+                filter_report(&mut report, status);
+            }
+            serde_json::to_value(report).context(error::SerializeSnafu)?
+        } else {
+            serde_json::Value::String(output)
+        };
+        combined.insert(name, value);
+    }
+    Ok(combined)
+}
+
+/// A check that can be run against a transaction before it's committed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct ConstraintCheck {
+    pub(crate) name: &'static str,
+    /// What the check confirms, and which requests run it.
+    pub(crate) description: &'static str,
+}
+
+/// The checks that can be run against a transaction before it's committed.
+pub(crate) const CONSTRAINT_CHECKS: &[ConstraintCheck] = &[
+    ConstraintCheck {
+        name: "settings-model",
+        description: "Pending settings deserialize through the settings model; run by \
+            /tx/commit and /tx/commit_and_apply with 'validate=true', and by \
+            /tx/validate_commit_and_apply",
+    },
+    ConstraintCheck {
+        name: "config-render",
+        description: "Config files affected by the pending settings render in a dry run of \
+            the config applier; run by /tx/validate_commit_and_apply",
+    },
+];
+
+/// Confirms that the pending settings in the given transaction can be deserialized through the
+/// settings model, so that committing them won't leave live settings that can't be read, e.g.
+/// after a partial migration.
+pub(crate) fn validate_transaction<D: DataStore>(datastore: &D, transaction: &str) -> Result<()> {
+    let start = Instant::now();
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    let data = datastore
+        .get_prefix("settings.", &pending)
+        .context(error::DataStoreSnafu { op: "get_prefix" })?;
+    let _: Settings =
+        from_map_with_prefix(None, &data).context(error::TransactionSchemaSnafu { transaction })?;
+    debug!(
+        "Checked transaction '{}' against the settings model in {:?}",
+        transaction,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+/// The keys changed by committing a transaction.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct CommitChanges {
+    /// Settings whose values changed.  These are the changes that need to be applied.
+    pub(crate) settings: HashSet<Key>,
+    /// Settings whose metadata changed, like their strength, but whose values didn't.
+    pub(crate) metadata_only: HashSet<Key>,
+}
+
+impl CommitChanges {
+    /// Returns whether the commit changed anything at all.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.settings.is_empty() && self.metadata_only.is_empty()
+    }
+
+    /// Returns all changed keys, whether their values or only their metadata changed.
+    pub(crate) fn all(&self) -> HashSet<Key> {
+        self.settings.union(&self.metadata_only).cloned().collect()
+    }
+}
+
+/// Makes live any pending settings and metadata in the datastore, returning the changed keys.  If
+/// an expected generation is given, the commit fails unless the live data is still at that
+/// generation, i.e. nothing changed it since the caller read it.
+pub(crate) fn commit_transaction<D>(
+    datastore: &mut D,
+    transaction: &str,
+    expected_generation: Option<u64>,
+) -> Result<CommitChanges>
+where
+    D: DataStore,
+{
+    let start = Instant::now();
+    // The data store reports keys whose value or metadata changed, so we check which have pending
+    // values first, to tell them apart.
+    let pending_settings = datastore
+        .list_populated_keys(
+            "settings.",
+            &Committed::Pending {
+                tx: transaction.to_string(),
+            },
+        )
+        .context(error::DataStoreSnafu {
+            op: "list_populated_keys",
+        })?;
+    let checked = start.elapsed();
+    debug!(
+        "Checked pending settings of transaction '{}' in {:?}",
+        transaction, checked
+    );
+
+    let committed = match expected_generation {
+        Some(expected) => datastore.commit_transaction_at_generation(transaction, expected),
+        None => datastore.commit_transaction(transaction),
+    };
+    let (settings, metadata_only) = committed
+        .context(error::DataStoreSnafu { op: "commit" })?
+        .into_iter()
+        .partition(|key| pending_settings.contains(key));
+    let changes = CommitChanges {
+        settings,
+        metadata_only,
+    };
+    info!(
+        "Committed transaction '{}': {} keys changed, checks took {:?}, set_keys took {:?}",
+        transaction,
+        changes.settings.len() + changes.metadata_only.len(),
+        checked,
+        start.elapsed() - checked
+    );
+    Ok(changes)
+}
+
+/// Moves any pending settings in the given transaction into the target transaction instead of
+/// making them live, returning the changed keys.  This lets reviewed changes be gathered into a
+/// larger staging transaction that's committed later.
+pub(crate) fn promote_transaction<D>(
+    datastore: &mut D,
+    transaction: &str,
+    target: &str,
+) -> Result<HashSet<Key>>
+where
+    D: DataStore,
+{
+    ensure!(
+        transaction != target,
+        error::PromoteToSelfSnafu { transaction }
+    );
+    datastore
+        .commit_transaction_to(
+            transaction,
+            &Committed::Pending {
+                tx: target.to_string(),
+            },
+        )
+        .context(error::DataStoreSnafu { op: "promote" })
+}
+
+/// Removes all live settings starting with the given prefix, along with their strength and expiry
+/// metadata, returning the removed keys.  Other metadata, like affected services, describes the
+/// setting rather than its value, so it's left alone.
+pub(crate) fn unset_settings_prefix<D>(datastore: &mut D, prefix: &str) -> Result<HashSet<Key>>
+where
+    D: DataStore,
+{
+    ensure!(
+        prefix.starts_with("settings."),
+        error::PrefixNotSettingsSnafu { prefix }
+    );
+    let removed = datastore
+        .unset_prefix(prefix, &Committed::Live)
+        .context(error::DataStoreSnafu { op: "unset_prefix" })?;
+    for meta_name in [STRENGTH_METADATA_KEY, EXPIRES_AT_METADATA_KEY] {
+        datastore
+            .unset_metadata_prefix(prefix, &Some(meta_name), &Committed::Live)
+            .context(error::DataStoreSnafu {
+                op: "unset_metadata_prefix",
+            })?;
+    }
+    Ok(removed)
+}
+
+/// What resetting a settings subtree staged, by key name.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub(crate) struct ResetOutcome {
+    /// Keys staged with their default value.
+    pub(crate) defaults: BTreeSet<String>,
+    /// Generated keys staged as weak, so they're removed and generated again on the next boot.
+    pub(crate) regenerated: BTreeSet<String>,
+}
+
+/// Resets the settings starting with the given prefix to their defaults in the given
+/// transaction, so committing it undoes customizations.  Changes already staged under the prefix
+/// are discarded, and every default under the prefix is staged.  If 'regenerate' is true, live
+/// keys without a default that are managed by a setting generator are staged as weak, so they're
+/// generated again on the next boot.
+///
+/// A transaction can only stage values, not removals, so if any live key under the prefix has no
+/// default (and isn't regenerated), nothing is staged and the error names those keys; DELETE
+/// /settings removes them.
+pub(crate) fn reset_settings_prefix<D: DataStore>(
+    datastore: &mut D,
+    defaults: &MemoryDataStore,
+    prefix: &str,
+    transaction: &str,
+    regenerate: bool,
+) -> Result<ResetOutcome> {
+    ensure!(
+        prefix.starts_with("settings."),
+        error::PrefixNotSettingsSnafu { prefix }
+    );
+    let default_pairs = defaults
+        .get_prefix(prefix, &Committed::Live)
+        .context(error::DataStoreSnafu { op: "get_prefix" })?;
+    let live = datastore
+        .list_populated_keys(prefix, &Committed::Live)
+        .context(error::DataStoreSnafu {
+            op: "list_populated_keys",
+        })?;
+
+    // Sort out the live keys without a default before staging anything, so we don't leave a
+    // partial reset behind if some can't be reset.
+    let mut regenerated = Vec::new();
+    let mut unresettable = BTreeSet::new();
+    for key in live {
+        if default_pairs.contains_key(&key) {
+            continue;
+        }
+        let generated = regenerate
+            && get_effective_metadata(&*datastore, key.name(), "setting-generator")?.is_some();
+        if generated {
+            regenerated.push(key);
+        } else {
+            unresettable.insert(key.name().clone());
+        }
+    }
+    ensure!(
+        unresettable.is_empty(),
+        error::ResetWithoutDefaultSnafu {
+            keys: unresettable.into_iter().collect::<Vec<_>>().join(", "),
+        }
+    );
+
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    datastore
+        .unset_prefix(prefix, &pending)
+        .context(error::DataStoreSnafu { op: "unset_prefix" })?;
+    datastore
+        .set_keys(&default_pairs, &pending)
+        .context(error::DataStoreSnafu { op: "set_keys" })?;
+    let strength = Key::new(KeyType::Meta, STRENGTH_METADATA_KEY).context(error::NewKeySnafu {
+        key_type: "meta",
+        name: STRENGTH_METADATA_KEY,
+    })?;
+    for key in &regenerated {
+        datastore
+            .set_metadata_typed(&strength, key, &WEAK_STRENGTH, &pending)
+            .context(error::DataStoreSnafu { op: "set_metadata" })?;
+    }
+
+    Ok(ResetOutcome {
+        defaults: default_pairs.keys().map(|key| key.name().clone()).collect(),
+        regenerated: regenerated.iter().map(|key| key.name().clone()).collect(),
+    })
+}
+
+/// Removes weak settings that have expired from the live data store, returning the removed keys.
+pub(crate) fn remove_expired_settings<D>(datastore: &mut D) -> Result<HashSet<Key>>
+where
+    D: DataStore,
+{
+    datastore
+        .remove_expired_settings(SystemTime::now())
+        .context(error::DataStoreSnafu {
+            op: "remove_expired_settings",
+        })
+}
+
+/// Removes weak settings from the live data store, returning the removed keys.
+pub(crate) fn remove_weak_settings<D>(datastore: &mut D) -> Result<HashSet<Key>>
 where
     D: DataStore,
 {
     datastore
-        .commit_transaction(transaction)
-        .context(error::DataStoreSnafu { op: "commit" })
+        .remove_weak_settings()
+        .context(error::DataStoreSnafu {
+            op: "remove_weak_settings",
+        })
 }
 
-/// Launches the config applier to make appropriate changes to the system based on any settings
-/// that have been committed.  Can be called after a commit, with the keys that changed in that
-/// commit, or called on its own to reset configuration state with all known keys.
+/// Launches the config applier at the given path to make appropriate changes to the system based
+/// on any settings that have been committed.  Can be called after a commit, with the keys that
+/// changed in that commit, or called on its own to reset configuration state with all known keys.
 ///
 /// If `keys_limit` is Some, gives those keys to the applier so only changes relevant to those
 /// keys are made.  Otherwise, tells the applier to apply changes for all known keys.
-pub(crate) fn apply_changes<S>(keys_limit: Option<&HashSet<S>>) -> Result<()>
+pub(crate) fn apply_changes<P, S>(applier: P, keys_limit: Option<&HashSet<S>>) -> Result<()>
 where
+    P: AsRef<Path>,
     S: AsRef<str>,
 {
     if let Some(keys_limit) = keys_limit {
@@ -475,7 +1853,8 @@ where
 
         // Start config applier
         debug!("Launching thar-be-settings to apply changes");
-        let mut cmd = Command::new("/usr/bin/thar-be-settings")
+        let start = Instant::now();
+        let mut cmd = Command::new(applier.as_ref())
             // Ask it to fork itself so we don't block the API
             .arg("--daemon")
             .stdin(Stdio::piped())
@@ -506,12 +1885,18 @@ where
                     .unwrap_or_else(|| "unknown".to_string()),
             }
         );
+        info!(
+            "Config applier forked for {} keys in {:?}",
+            keys_limit.len(),
+            start.elapsed()
+        );
     } else {
         // Start config applier
         // (See comments above about daemonizing and checking the fork result; we don't need a
         // separate wait() here because we don't pass any stdin, status() does it for us.)
         debug!("Launching thar-be-settings to apply any and all changes");
-        let status = Command::new("/usr/bin/thar-be-settings")
+        let start = Instant::now();
+        let status = Command::new(applier.as_ref())
             .arg("--daemon")
             .arg("--all")
             // FIXME where to send output?
@@ -528,14 +1913,145 @@ where
                     .unwrap_or_else(|| "unknown".to_string()),
             }
         );
+        info!(
+            "Config applier forked for all keys in {:?}",
+            start.elapsed()
+        );
     }
 
     Ok(())
 }
 
-/// Dispatches an update command via `thar-be-updates`
-pub(crate) fn dispatch_update_command(args: &[&str]) -> Result<HttpResponse> {
-    let status = Command::new("/usr/bin/thar-be-updates")
+/// A line in the audit log, recording a commit, or live settings being removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct AuditEntry {
+    pub(crate) timestamp: DateTime<Utc>,
+    /// The committed transaction; removals don't go through a transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) transaction: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub(crate) changed: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub(crate) removed: BTreeSet<String>,
+}
+
+/// Appends a line to the audit log at the given path recording that the given keys were committed
+/// from the given transaction.  The file is created if it doesn't exist.
+pub(crate) fn record_commit<P: AsRef<Path>>(
+    audit_log: P,
+    transaction: &str,
+    changes: &HashSet<Key>,
+) -> Result<()> {
+    let entry = AuditEntry {
+        timestamp: Utc::now(),
+        transaction: Some(transaction.to_string()),
+        changed: changes.iter().map(|key| key.name().to_string()).collect(),
+        removed: BTreeSet::new(),
+    };
+    append_audit_entry(audit_log.as_ref(), &entry)
+}
+
+/// Appends a line to the audit log at the given path recording that the given keys were removed
+/// from the live settings.  The file is created if it doesn't exist.
+pub(crate) fn record_removal<P: AsRef<Path>>(audit_log: P, removed: &HashSet<Key>) -> Result<()> {
+    let entry = AuditEntry {
+        timestamp: Utc::now(),
+        transaction: None,
+        changed: BTreeSet::new(),
+        removed: removed.iter().map(|key| key.name().to_string()).collect(),
+    };
+    append_audit_entry(audit_log.as_ref(), &entry)
+}
+
+fn append_audit_entry(audit_log: &Path, entry: &AuditEntry) -> Result<()> {
+    let mut line = serde_json::to_string(entry).context(error::CommandSerializationSnafu {
+        given: "audit entry",
+    })?;
+    line.push('\n');
+
+    // Write the line in one call so concurrent appends can't interleave within it.  The log shows
+    // which settings changed and when, so only root can read it.
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(audit_log)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .context(error::AuditWriteSnafu { path: audit_log })
+}
+
+/// Returns the settings keys that are pending in the given transaction, with their serialized
+/// values.
+pub(crate) fn get_pending_data<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+) -> Result<HashMap<Key, String>> {
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    datastore
+        .get_prefix("settings.", &pending)
+        .context(error::DataStoreSnafu {
+            op: "get_prefix 'settings.' for pending",
+        })
+}
+
+/// Runs the config applier at the given path in dry-run mode for the given keys, so the config
+/// files affected by those keys are rendered with the settings as they'd be if the given
+/// transaction were committed, but not written, and no services are restarted.  Returns a
+/// RenderCheck error holding the applier's error output if rendering fails.
+pub(crate) fn check_rendering<P, S>(applier: P, transaction: &str, keys: &HashSet<S>) -> Result<()>
+where
+    P: AsRef<Path>,
+    S: AsRef<str>,
+{
+    let keys: Vec<&str> = keys.iter().map(|s| s.as_ref()).collect();
+    let cmd_input = serde_json::to_string(&keys).context(error::CommandSerializationSnafu {
+        given: "transaction's pending keys",
+    })?;
+
+    debug!("Launching config applier to check rendering");
+    let start = Instant::now();
+    let mut cmd = Command::new(applier.as_ref())
+        .args(["--dry-run", "--transaction", transaction])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(error::ConfigApplierStartSnafu)?;
+
+    // Write the keys from another thread while we read the applier's errors, so neither of us can
+    // block the other by filling a pipe.  Dropping stdin after writing closes it, so the applier
+    // knows it has all the keys.
+    let mut stdin = cmd.stdin.take().context(error::ConfigApplierStdinSnafu)?;
+    let writer = thread::spawn(move || stdin.write_all(cmd_input.as_bytes()));
+
+    let output = cmd
+        .wait_with_output()
+        .context(error::ConfigApplierWaitSnafu)?;
+    let written = writer.join().expect("writing to config applier panicked");
+    // An applier that fails may not read all of its input, so its errors are what matter.
+    ensure!(
+        output.status.success(),
+        error::RenderCheckSnafu {
+            errors: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+    );
+    written.context(error::ConfigApplierWriteSnafu)?;
+    info!(
+        "Config applier rendered files for {} keys in {:?}",
+        keys.len(),
+        start.elapsed()
+    );
+    Ok(())
+}
+
+/// Dispatches an update command via the update dispatcher at the given path, `thar-be-updates`
+pub(crate) fn dispatch_update_command<P: AsRef<Path>>(
+    dispatcher: P,
+    args: &[&str],
+) -> Result<HttpResponse> {
+    let status = Command::new(dispatcher.as_ref())
         .args(args)
         .status()
         .context(error::UpdateDispatcherSnafu)?;
@@ -563,10 +2079,12 @@ mod test {
     use super::*;
     use datastore::memory::MemoryDataStore;
     use datastore::{Committed, DataStore, Key, KeyType};
-    use maplit::{hashmap, hashset};
+    use maplit::{btreemap, btreeset, hashmap, hashset};
+    use model::report::{CheckMode, CheckResult};
     use model::{ConfigurationFile, Service};
     use serde::{Deserialize, Serialize};
     use std::convert::TryInto;
+    use std::sync::Mutex;
 
     #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
     struct TestSettings {
@@ -774,70 +2292,1755 @@ mod test {
     }
 
     #[test]
-    fn set_settings_works() {
-        let settings = serde_json::from_str::<model::Settings>("{\"motd\": \"tz\"}").unwrap();
+    fn validate_configuration_files_works() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let template = dir.path().join("good.template");
+        std::fs::write(&template, "{{settings.foo}}").unwrap();
+        let dangling = dir.path().join("missing.template");
 
-        // Set with helper
         let mut ds = MemoryDataStore::new();
-        let tx = "test transaction";
-        let pending = Committed::Pending { tx: tx.into() };
-        set_settings(&mut ds, &settings, tx).unwrap();
+        for (name, path, template_path) in [
+            ("good", "/etc/good.conf", &template),
+            ("bad", "/etc/bad.conf", &dangling),
+        ] {
+            ds.set_key(
+                &Key::new(KeyType::Data, format!("configuration-files.{}.path", name)).unwrap(),
+                serialize_scalar::<_, ScalarError>(&path).unwrap(),
+                &Committed::Live,
+            )
+            .unwrap();
+            ds.set_key(
+                &Key::new(
+                    KeyType::Data,
+                    format!("configuration-files.{}.template-path", name),
+                )
+                .unwrap(),
+                serialize_scalar::<_, ScalarError>(&template_path).unwrap(),
+                &Committed::Live,
+            )
+            .unwrap();
+        }
 
-        // Retrieve directly
-        let key = Key::new(KeyType::Data, "settings.motd").unwrap();
         assert_eq!(
-            Some("\"tz\"".to_string()),
-            ds.get_key(&key, &pending).unwrap()
+            validate_configuration_files(&ds).unwrap(),
+            vec![ConfigurationFileProblem {
+                name: "bad".to_string(),
+                problem: format!("template-path '{}' does not exist", dangling.display()),
+            }]
         );
     }
 
     #[test]
-    fn get_metadata_keys_works() {
+    fn get_template_context_works() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let template = dir.path().join("motd.template");
+        std::fs::write(
+            &template,
+            "[required-extensions]\nmotd = \"v1\"\nstd = { version = \"v1\", helpers = [\"default\"] }\n+++\n{{settings.motd}}\n",
+        )
+        .unwrap();
+        let bare = dir.path().join("bare.template");
+        std::fs::write(&bare, "{{settings.motd}}\n").unwrap();
+
         let mut ds = MemoryDataStore::new();
-        // Set directly with data store
-        for data_key in &["abc", "def", "ghi"] {
-            ds.set_metadata(
-                &Key::new(KeyType::Meta, "my-meta").unwrap(),
-                &Key::new(KeyType::Data, data_key).unwrap(),
-                "\"json string\"",
+        for (name, template_path) in [("motd", &template), ("bare", &bare)] {
+            ds.set_key(
+                &Key::new(KeyType::Data, format!("configuration-files.{}.path", name)).unwrap(),
+                serialize_scalar::<_, ScalarError>(&format!("/etc/{}", name)).unwrap(),
+                &Committed::Live,
+            )
+            .unwrap();
+            ds.set_key(
+                &Key::new(
+                    KeyType::Data,
+                    format!("configuration-files.{}.template-path", name),
+                )
+                .unwrap(),
+                serialize_scalar::<_, ScalarError>(&template_path).unwrap(),
+                &Committed::Live,
             )
             .unwrap();
         }
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.motd").unwrap(),
+            "\"hello\"",
+            &Committed::Live,
+        )
+        .unwrap();
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.ntp.time-servers").unwrap(),
+            "[\"pool.ntp.org\"]",
+            &Committed::Live,
+        )
+        .unwrap();
 
-        // We'll check a subset by specifying 2 of the 3 keys
-        let expected = hashmap!(
-            "abc".to_string() => "json string".into(),
-            "def".to_string() => "json string".into(),
-        );
-        // Retrieve with helper
-        let actual = get_metadata_for_data_keys(&ds, "my-meta", &hashset!("abc", "def")).unwrap();
-
-        assert_eq!(expected, actual);
+        let os = BottlerocketRelease {
+            pretty_name: "Bottlerocket OS 1.2.3 (aws-dev)".to_string(),
+            variant_id: "aws-dev".to_string(),
+            version_id: Version::new(1, 2, 3),
+            build_id: "abcdef".to_string(),
+            arch: "x86_64".to_string(),
+        };
+
+        // Only the settings the template requires are included.
+        let context = get_template_context(&ds, "motd", &os).unwrap();
+        assert_eq!(context["settings"], serde_json::json!({"motd": "hello"}));
+        assert_eq!(context["os"]["variant_id"], "aws-dev");
+
+        assert!(matches!(
+            get_template_context(&ds, "bare", &os),
+            Err(error::Error::TemplateParse { .. })
+        ));
+        assert!(matches!(
+            get_template_context(&ds, "missing", &os),
+            Err(error::Error::NoConfigurationFile { .. })
+        ));
     }
 
     #[test]
-    fn get_metadata_all_works() {
+    fn get_raw_key_works() {
+        let mut ds = MemoryDataStore::new();
+        let key = Key::new(KeyType::Data, "settings.motd").unwrap();
+        ds.set_key(&key, "\"json string\"", &Committed::Live)
+            .unwrap();
+        let pending = Committed::Pending {
+            tx: "test".to_string(),
+        };
+        ds.set_key(&key, "\"pending string\"", &pending).unwrap();
+
+        // The serialized form is returned verbatim, quotes and all
+        assert_eq!(
+            get_raw_key(&ds, "settings.motd", &Committed::Live).unwrap(),
+            "\"json string\""
+        );
+        assert_eq!(
+            get_raw_key(&ds, "settings.motd", &pending).unwrap(),
+            "\"pending string\""
+        );
+        assert!(get_raw_key(&ds, "settings.hostname", &Committed::Live).is_err());
+    }
+
+    #[test]
+    fn settings_env_file_names() {
+        let settings = serde_json::json!({
+            "motd": "hello",
+            "ntp": {"time-servers": ["a.example.com", "b.example.com"]},
+            "kubernetes": {
+                "max-pods": 110,
+                "node-labels": {"example.com/role": "worker"},
+            },
+        });
+        assert_eq!(
+            settings_env_file(&settings).unwrap(),
+            "SETTINGS_KUBERNETES_MAX_PODS=110\n\
+             SETTINGS_KUBERNETES_NODE_LABELS_EXAMPLE_COM_ROLE=worker\n\
+             SETTINGS_MOTD=hello\n\
+             SETTINGS_NTP_TIME_SERVERS='[\"a.example.com\",\"b.example.com\"]'\n"
+        );
+
+        let colliding = serde_json::json!({"a-b": "1", "a_b": "2"});
+        let err = settings_env_file(&colliding).unwrap_err();
+        assert!(
+            matches!(err, error::Error::EnvNameCollision { name, .. } if name == "SETTINGS_A_B")
+        );
+    }
+
+    #[test]
+    fn settings_env_file_quoting() {
+        let settings = serde_json::json!({
+            "motd": "it's a $HOME\nsweet home",
+            "empty": "",
+            "url": "https://example.com:8443/path",
+            "enabled": true,
+        });
+        assert_eq!(
+            settings_env_file(&settings).unwrap(),
+            "SETTINGS_EMPTY=''\n\
+             SETTINGS_ENABLED=true\n\
+             SETTINGS_MOTD='it'\\''s a $HOME\nsweet home'\n\
+             SETTINGS_URL=https://example.com:8443/path\n"
+        );
+    }
+
+    /// Stores two live settings, with three metadata values between them, and a pending setting
+    /// in each of two transactions.
+    fn populate_for_stats<D: DataStore>(ds: &mut D) {
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let hostname = Key::new(KeyType::Data, "settings.network.hostname").unwrap();
+        let strength = Key::new(KeyType::Meta, "strength").unwrap();
+        let services = Key::new(KeyType::Meta, "affected-services").unwrap();
+        ds.set_key(&motd, "\"hi\"", &Committed::Live).unwrap();
+        ds.set_key(&hostname, "\"abc\"", &Committed::Live).unwrap();
+        ds.set_metadata(&strength, &motd, "\"weak\"", &Committed::Live)
+            .unwrap();
+        ds.set_metadata(&services, &motd, "[\"motd\"]", &Committed::Live)
+            .unwrap();
+        ds.set_metadata(&strength, &hostname, "\"strong\"", &Committed::Live)
+            .unwrap();
+        for tx in ["one", "two"] {
+            let pending = Committed::Pending { tx: tx.to_string() };
+            ds.set_key(&motd, "\"bye\"", &pending).unwrap();
+        }
+    }
+
+    #[test]
+    fn get_datastore_stats_works() {
+        let expected = DatastoreStats {
+            live_keys: 2,
+            pending_transactions: 2,
+            metadata_entries: 3,
+            // Values: "hi", "abc", "weak", ["motd"], "strong"
+            live_bytes: (4 + 5 + 6 + 8 + 8) as u64,
+        };
+
+        let mut memory = MemoryDataStore::new();
+        populate_for_stats(&mut memory);
+        assert_eq!(get_datastore_stats(&memory).unwrap(), expected);
+
+        // The filesystem data store measures its files, which hold exactly the values.
+        let tmp = tempfile::TempDir::new().unwrap();
+        let mut filesystem = FilesystemDataStore::new(tmp.path());
+        populate_for_stats(&mut filesystem);
+        assert_eq!(get_datastore_stats(&filesystem).unwrap(), expected);
+    }
+
+    #[test]
+    fn get_keys_exist_works() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let hostname = Key::new(KeyType::Data, "settings.network.hostname").unwrap();
+        ds.set_key(&motd, "\"hi\"", &Committed::Live).unwrap();
+        let pending = Committed::Pending {
+            tx: "test".to_string(),
+        };
+        ds.set_key(&hostname, "\"abc\"", &pending).unwrap();
+
+        let keys = hashset!("settings.motd", "settings.network.hostname", "settings.ntp");
+        assert_eq!(
+            get_keys_exist(&ds, &keys, &Committed::Live).unwrap(),
+            BTreeMap::from([
+                ("settings.motd".to_string(), true),
+                ("settings.network.hostname".to_string(), false),
+                ("settings.ntp".to_string(), false),
+            ])
+        );
+        assert_eq!(
+            get_keys_exist(&ds, &keys, &pending).unwrap(),
+            BTreeMap::from([
+                ("settings.motd".to_string(), false),
+                ("settings.network.hostname".to_string(), true),
+                ("settings.ntp".to_string(), false),
+            ])
+        );
+
+        assert!(get_keys_exist(&ds, &hashset!("bad key"), &Committed::Live).is_err());
+    }
+
+    #[test]
+    fn get_weak_settings_works() {
+        let mut ds = MemoryDataStore::new();
+        let strength = Key::new(KeyType::Meta, STRENGTH_METADATA_KEY).unwrap();
+        for (name, value) in [
+            ("settings.motd", "\"weak\""),
+            ("settings.network.hostname", "\"strong\""),
+            ("settings.kubernetes.cluster-name", "\"weak\""),
+            ("settings.kubernetes.api-server", "\"strong\""),
+        ] {
+            let key = Key::new(KeyType::Data, name).unwrap();
+            ds.set_key(&key, "\"x\"", &Committed::Live).unwrap();
+            ds.set_metadata(&strength, &key, value, &Committed::Live)
+                .unwrap();
+        }
+
+        assert_eq!(
+            get_weak_settings(&ds, None).unwrap(),
+            btreeset!(
+                "settings.kubernetes.cluster-name".to_string(),
+                "settings.motd".to_string(),
+            )
+        );
+        assert_eq!(
+            get_weak_settings(&ds, Some("settings.kubernetes.")).unwrap(),
+            btreeset!("settings.kubernetes.cluster-name".to_string())
+        );
+        assert!(get_weak_settings(&ds, Some("settings.network."))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn compare_key_works() {
+        let mut ds = MemoryDataStore::new();
+        let pending = Committed::Pending { tx: "test".into() };
+        let both = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let live_only = Key::new(KeyType::Data, "settings.network.hostname").unwrap();
+        let pending_only = Key::new(KeyType::Data, "settings.ntp.time-servers").unwrap();
+        ds.set_key(&both, "\"old\"", &Committed::Live).unwrap();
+        ds.set_key(&both, "\"new\"", &pending).unwrap();
+        ds.set_key(&live_only, "\"host\"", &Committed::Live)
+            .unwrap();
+        ds.set_key(&pending_only, "[\"pool.ntp.org\"]", &pending)
+            .unwrap();
+
+        assert_eq!(
+            compare_key(&ds, "settings.motd", "test").unwrap(),
+            KeyComparison {
+                live: Some("old".into()),
+                pending: Some("new".into()),
+            }
+        );
+        assert_eq!(
+            compare_key(&ds, "settings.network.hostname", "test").unwrap(),
+            KeyComparison {
+                live: Some("host".into()),
+                pending: None,
+            }
+        );
+        assert_eq!(
+            compare_key(&ds, "settings.ntp.time-servers", "test").unwrap(),
+            KeyComparison {
+                live: None,
+                pending: Some(serde_json::json!(["pool.ntp.org"])),
+            }
+        );
+        // Another transaction doesn't see this one's changes.
+        assert_eq!(
+            compare_key(&ds, "settings.motd", "other").unwrap(),
+            KeyComparison {
+                live: Some("old".into()),
+                pending: None,
+            }
+        );
+    }
+
+    #[test]
+    fn get_transactions_for_key_works() {
+        // The filesystem data store finds transactions with the default implementation.
+        let tmp = tempfile::TempDir::new().unwrap();
+        let mut ds = FilesystemDataStore::new(tmp.path());
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let hostname = Key::new(KeyType::Data, "settings.network.hostname").unwrap();
+        for tx in ["tx1", "tx2"] {
+            ds.set_key(&motd, "\"x\"", &Committed::Pending { tx: tx.into() })
+                .unwrap();
+        }
+        ds.set_key(&hostname, "\"x\"", &Committed::Pending { tx: "tx3".into() })
+            .unwrap();
+
+        assert_eq!(
+            get_transactions_for_key(&ds, "settings.motd").unwrap(),
+            hashset!("tx1".to_string(), "tx2".to_string())
+        );
+        assert!(get_transactions_for_key(&ds, "settings.ntp")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn count_settings_prefix_works() {
+        // The filesystem data store counts with the default implementation, by listing keys.
+        let tmp = tempfile::TempDir::new().unwrap();
+        let mut ds = FilesystemDataStore::new(tmp.path());
+        let pending = Committed::Pending {
+            tx: "test".to_string(),
+        };
+        for name in [
+            "settings.kubernetes.cluster-name",
+            "settings.kubernetes.api-server",
+            "settings.motd",
+        ] {
+            let key = Key::new(KeyType::Data, name).unwrap();
+            ds.set_key(&key, "\"x\"", &Committed::Live).unwrap();
+        }
+        let pending_key = Key::new(KeyType::Data, "settings.kubernetes.pod-infra").unwrap();
+        ds.set_key(&pending_key, "\"x\"", &pending).unwrap();
+
+        for committed in [&Committed::Live, &pending] {
+            for prefix in ["settings.", "settings.kubernetes.", "settings.ntp."] {
+                assert_eq!(
+                    count_settings_prefix(&ds, prefix, committed).unwrap(),
+                    ds.list_populated_keys(prefix, committed).unwrap().len()
+                );
+            }
+        }
+        assert_eq!(
+            count_settings_prefix(&ds, "settings.kubernetes.", &Committed::Live).unwrap(),
+            2
+        );
+        assert_eq!(
+            count_settings_prefix(&ds, "settings.kubernetes.", &pending).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn get_settings_at_version_works() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let dir = tmp.path();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        for (copy, name, value) in [
+            ("v1.0.0_aaaa", "v1.0.0", "\"old\""),
+            ("v1.1.0_bbbb", "v1.1.0", "\"new\""),
+        ] {
+            std::fs::create_dir(dir.join(copy)).unwrap();
+            std::os::unix::fs::symlink(copy, dir.join(name)).unwrap();
+            FilesystemDataStore::new(dir.join(copy))
+                .set_key(&motd, value, &Committed::Live)
+                .unwrap();
+        }
+        std::os::unix::fs::symlink("v1.1.0", dir.join("current")).unwrap();
+        let datastore_path = dir.join("current");
+
+        let settings = get_settings_at_version(&datastore_path, &Version::new(1, 0, 0)).unwrap();
+        assert_eq!(
+            serde_json::to_value(settings).unwrap()["motd"],
+            serde_json::json!("old")
+        );
+        assert!(matches!(
+            get_settings_at_version(&datastore_path, &Version::new(0, 9, 0)),
+            Err(error::Error::DataStoreVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn unset_settings_prefix_works() {
+        let mut ds = MemoryDataStore::new();
+        let strength = Key::new(KeyType::Meta, STRENGTH_METADATA_KEY).unwrap();
+        let services = Key::new(KeyType::Meta, "affected-services").unwrap();
+        let name = Key::new(KeyType::Data, "settings.kubernetes.cluster-name").unwrap();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        for key in &[&name, &motd] {
+            ds.set_key(key, "\"value\"", &Committed::Live).unwrap();
+            ds.set_metadata(&strength, key, "\"weak\"", &Committed::Live)
+                .unwrap();
+            ds.set_metadata(&services, key, "[\"svc\"]", &Committed::Live)
+                .unwrap();
+        }
+
+        let removed = unset_settings_prefix(&mut ds, "settings.kubernetes.").unwrap();
+        assert_eq!(removed, hashset!(name.clone()));
+        assert_eq!(ds.get_key(&name, &Committed::Live).unwrap(), None);
+        assert_eq!(
+            ds.get_metadata_raw(&strength, &name, &Committed::Live)
+                .unwrap(),
+            None
+        );
+        assert!(ds
+            .get_metadata_raw(&services, &name, &Committed::Live)
+            .unwrap()
+            .is_some());
+        // Settings outside the prefix are untouched.
+        assert!(ds.get_key(&motd, &Committed::Live).unwrap().is_some());
+        assert!(ds
+            .get_metadata_raw(&strength, &motd, &Committed::Live)
+            .unwrap()
+            .is_some());
+
+        // Only settings can be removed this way.
+        assert!(unset_settings_prefix(&mut ds, "services.").is_err());
+    }
+
+    #[test]
+    fn get_setting_provenance_works() {
+        let mut ds = MemoryDataStore::new();
+        let strength = Key::new(KeyType::Meta, STRENGTH_METADATA_KEY).unwrap();
+        let generator = Key::new(KeyType::Meta, "setting-generator").unwrap();
+        // A generated value, which is weak so it's generated again on each boot.
+        let generated = Key::new(KeyType::Data, "settings.generated").unwrap();
+        ds.set_key(&generated, "\"gen\"", &Committed::Live).unwrap();
+        ds.set_metadata(&generator, &generated, "\"/bin/gen\"", &Committed::Live)
+            .unwrap();
+        ds.set_metadata(&strength, &generated, "\"weak\"", &Committed::Live)
+            .unwrap();
+        // A value set by a user, with no generator and no strength metadata.
+        let user = Key::new(KeyType::Data, "settings.user").unwrap();
+        ds.set_key(&user, "\"mine\"", &Committed::Live).unwrap();
+
+        let keys = hashset!("settings.generated", "settings.user", "settings.missing");
+        let provenance = get_setting_provenance(&ds, &keys).unwrap();
+        assert_eq!(
+            provenance,
+            hashmap!(
+                "settings.generated".to_string() => SettingProvenance {
+                    has_generator: true,
+                    populated: true,
+                    strength: "weak".into(),
+                },
+                "settings.user".to_string() => SettingProvenance {
+                    has_generator: false,
+                    populated: true,
+                    strength: "strong".into(),
+                },
+                "settings.missing".to_string() => SettingProvenance {
+                    has_generator: false,
+                    populated: false,
+                    strength: "strong".into(),
+                },
+            )
+        );
+    }
+
+    #[test]
+    fn commit_metadata_only_works() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let strength = Key::new(KeyType::Meta, STRENGTH_METADATA_KEY).unwrap();
+        ds.set_key(&motd, "\"hi\"", &Committed::Live).unwrap();
+        ds.set_metadata(&strength, &motd, "\"strong\"", &Committed::Live)
+            .unwrap();
+
+        let metadata = hashmap!(
+            "settings.motd".to_string() => hashmap!(
+                STRENGTH_METADATA_KEY.to_string() => serde_json::json!("weak")
+            )
+        );
+        set_metadata(&mut ds, &metadata, "test").unwrap();
+        // Nothing changes until the transaction is committed.
+        assert_eq!(
+            ds.get_metadata_raw(&strength, &motd, &Committed::Live)
+                .unwrap(),
+            Some("\"strong\"".to_string())
+        );
+
+        // The key is reported, but separately from settings whose values need applying.
+        assert_eq!(
+            commit_transaction(&mut ds, "test", None).unwrap(),
+            CommitChanges {
+                settings: HashSet::new(),
+                metadata_only: hashset!(motd.clone()),
+            }
+        );
+        assert_eq!(
+            ds.get_metadata_raw(&strength, &motd, &Committed::Live)
+                .unwrap(),
+            Some("\"weak\"".to_string())
+        );
+        assert_eq!(
+            ds.get_key(&motd, &Committed::Live).unwrap(),
+            Some("\"hi\"".to_string())
+        );
+    }
+
+    #[test]
+    fn reset_settings_prefix_works() {
+        let mut defaults = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        defaults
+            .set_key(&motd, "\"default motd\"", &Committed::Live)
+            .unwrap();
+
+        let mut ds = MemoryDataStore::new();
+        let hostname = Key::new(KeyType::Data, "settings.network.hostname").unwrap();
+        let servers = Key::new(KeyType::Data, "settings.ntp.time-servers").unwrap();
+        let generator = Key::new(KeyType::Meta, "setting-generator").unwrap();
+        let strength = Key::new(KeyType::Meta, STRENGTH_METADATA_KEY).unwrap();
+        ds.set_key(&motd, "\"custom\"", &Committed::Live).unwrap();
+        ds.set_key(&hostname, "\"custom\"", &Committed::Live)
+            .unwrap();
+        ds.set_metadata(&generator, &hostname, "\"/bin/hostgen\"", &Committed::Live)
+            .unwrap();
+        ds.set_key(&servers, "[\"custom\"]", &Committed::Live)
+            .unwrap();
+        let pending = Committed::Pending { tx: "test".into() };
+        ds.set_key(&motd, "\"staged\"", &pending).unwrap();
+        ds.set_key(&servers, "[\"staged\"]", &pending).unwrap();
+
+        // A live key with no default can't be reset, so nothing is staged.
+        match reset_settings_prefix(&mut ds, &defaults, "settings.", "test", true) {
+            Err(error::Error::ResetWithoutDefault { keys }) => {
+                assert_eq!(keys, "settings.ntp.time-servers")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(
+            ds.get_key(&motd, &pending).unwrap(),
+            Some("\"staged\"".to_string())
+        );
+
+        defaults
+            .set_key(&servers, "[\"default\"]", &Committed::Live)
+            .unwrap();
+        ds.set_key(&servers, "[\"custom\"]", &Committed::Live)
+            .unwrap();
+        assert_eq!(
+            reset_settings_prefix(&mut ds, &defaults, "settings.", "test", true).unwrap(),
+            ResetOutcome {
+                defaults: btreeset!(
+                    "settings.motd".to_string(),
+                    "settings.ntp.time-servers".to_string()
+                ),
+                regenerated: btreeset!("settings.network.hostname".to_string()),
+            }
+        );
+        // Staged changes are replaced by the default.
+        assert_eq!(
+            ds.get_key(&motd, &pending).unwrap(),
+            Some("\"default motd\"".to_string())
+        );
+        assert_eq!(
+            ds.get_key(&servers, &pending).unwrap(),
+            Some("[\"default\"]".to_string())
+        );
+        assert_eq!(
+            ds.get_metadata_raw(&strength, &hostname, &pending).unwrap(),
+            Some("\"weak\"".to_string())
+        );
+        // Nothing changes until the transaction is committed.
+        assert_eq!(
+            ds.get_key(&motd, &Committed::Live).unwrap(),
+            Some("\"custom\"".to_string())
+        );
+
+        // Without regenerating, generated keys can't be reset either.
+        assert!(matches!(
+            reset_settings_prefix(&mut ds, &defaults, "settings.network.", "other", false),
+            Err(error::Error::ResetWithoutDefault { .. })
+        ));
+        assert!(matches!(
+            reset_settings_prefix(&mut ds, &defaults, "services.", "test", true),
+            Err(error::Error::PrefixNotSettings { .. })
+        ));
+    }
+
+    #[test]
+    fn set_settings_works() {
+        let settings = serde_json::from_str::<model::Settings>("{\"motd\": \"tz\"}").unwrap();
+
+        // Set with helper
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        set_settings(&mut ds, &settings, tx).unwrap();
+
+        // Retrieve directly
+        let key = Key::new(KeyType::Data, "settings.motd").unwrap();
+        assert_eq!(
+            Some("\"tz\"".to_string()),
+            ds.get_key(&key, &pending).unwrap()
+        );
+    }
+
+    #[test]
+    fn find_unmodeled_keys_works() {
+        let input = serde_json::json!({"motd": "tz", "motdd": "typo"});
+        let input_pairs = to_pairs_with_prefix("settings", &input).unwrap();
+        let settings = serde_json::from_str::<model::Settings>("{\"motd\": \"tz\"}").unwrap();
+
+        assert_eq!(
+            find_unmodeled_keys(&input_pairs, &settings).unwrap(),
+            vec!["settings.motdd".to_string()]
+        );
+    }
+
+    #[test]
+    fn get_metadata_keys_works() {
         let mut ds = MemoryDataStore::new();
         // Set directly with data store
-        for data_key in &["abc", "def"] {
+        for data_key in &["abc", "def", "ghi"] {
             ds.set_metadata(
                 &Key::new(KeyType::Meta, "my-meta").unwrap(),
                 &Key::new(KeyType::Data, data_key).unwrap(),
                 "\"json string\"",
+                &Committed::Live,
             )
             .unwrap();
         }
 
+        // We'll check a subset by specifying 2 of the 3 keys
         let expected = hashmap!(
             "abc".to_string() => "json string".into(),
             "def".to_string() => "json string".into(),
         );
         // Retrieve with helper
-        let actual = get_metadata_for_all_data_keys(&ds, "my-meta").unwrap();
+        let actual = get_metadata_for_data_keys(&ds, "my-meta", &hashset!("abc", "def")).unwrap();
 
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn descriptions_work() {
+        let mut ds = MemoryDataStore::new();
+        set_descriptions(
+            &mut ds,
+            &hashmap!("settings.motd".to_string() => "Shown at login".to_string()),
+        )
+        .unwrap();
+
+        // Descriptions are live metadata, so they aren't touched by committing transactions.
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.motd").unwrap(),
+            "\"hi\"",
+            &Committed::Pending {
+                tx: "default".to_string(),
+            },
+        )
+        .unwrap();
+        commit_transaction(&mut ds, "default", None).unwrap();
+
+        let descriptions = get_metadata_for_data_keys(
+            &ds,
+            DESCRIPTION_METADATA_KEY,
+            &hashset!("settings.motd", "settings.hostname"),
+        )
+        .unwrap();
+        assert_eq!(
+            descriptions,
+            hashmap!("settings.motd".to_string() => "Shown at login".into())
+        );
+
+        // A bad key fails the whole request, without setting the valid descriptions.
+        assert!(set_descriptions(
+            &mut ds,
+            &hashmap!(
+                "bad key!".to_string() => "x".to_string(),
+                "settings.hostname".to_string() => "Set by DHCP".to_string()
+            )
+        )
+        .is_err());
+        let hostname = get_metadata_for_data_keys(
+            &ds,
+            DESCRIPTION_METADATA_KEY,
+            &hashset!("settings.hostname"),
+        )
+        .unwrap();
+        assert!(hostname.is_empty());
+    }
+
+    #[test]
+    fn plan_commit_works() {
+        let mut ds = MemoryDataStore::new();
+        let pending = Committed::Pending {
+            tx: "default".to_string(),
+        };
+        // Metadata on a parent key applies to the settings under it.
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "affected-services").unwrap(),
+            &Key::new(KeyType::Data, "settings.ntp").unwrap(),
+            "[\"chronyd\"]",
+            &Committed::Live,
+        )
+        .unwrap();
+        ds.set_key(
+            &Key::new(KeyType::Data, "services.chronyd.restart-commands").unwrap(),
+            "[\"/bin/systemctl try-restart chronyd\"]",
+            &Committed::Live,
+        )
+        .unwrap();
+        ds.set_key(
+            &Key::new(KeyType::Data, "services.chronyd.configuration-files").unwrap(),
+            "[\"chrony-conf\"]",
+            &Committed::Live,
+        )
+        .unwrap();
+        // One setting changes; the other is pending but already has the same live value.
+        let servers = Key::new(KeyType::Data, "settings.ntp.time-servers").unwrap();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        ds.set_key(&motd, "\"hi\"", &Committed::Live).unwrap();
+        ds.set_key(&motd, "\"hi\"", &pending).unwrap();
+        ds.set_key(&servers, "[\"pool.ntp.org\"]", &pending)
+            .unwrap();
+
+        let plan = plan_commit(&ds, "default").unwrap();
+        assert_eq!(
+            plan,
+            CommitPlan {
+                changes: btreemap!(
+                    "settings.ntp.time-servers".to_string() => SettingDiff {
+                        live: None,
+                        pending: vec!["pool.ntp.org"].into(),
+                    }
+                ),
+                impact: ChangeImpact {
+                    affected_services: hashmap!(
+                        "settings.ntp.time-servers".to_string() => vec!["chronyd"].into()
+                    ),
+                    templates: HashMap::new(),
+                    restart_commands: hashmap!(
+                        "chronyd".to_string() => vec!["/bin/systemctl try-restart chronyd".to_string()]
+                    ),
+                },
+            }
+        );
+
+        // Planning doesn't change anything.
+        assert_eq!(
+            list_transactions(&ds).unwrap(),
+            hashset!("default".to_string())
+        );
+        assert!(ds.get_key(&servers, &Committed::Live).unwrap().is_none());
+    }
+
+    #[test]
+    fn commit_requires_reboot_works() {
+        let mut ds = MemoryDataStore::new();
+        let reboot_required = Key::new(KeyType::Meta, REBOOT_REQUIRED_METADATA_KEY).unwrap();
+        let kernel = Key::new(KeyType::Data, "settings.kernel").unwrap();
+        let lockdown = Key::new(KeyType::Data, "settings.kernel.lockdown").unwrap();
+        let sysctl = Key::new(KeyType::Data, "settings.kernel.sysctl.vm.swappiness").unwrap();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        ds.set_metadata(&reboot_required, &kernel, "true", &Committed::Live)
+            .unwrap();
+        // A child can opt out of its parent's requirement.
+        ds.set_metadata(
+            &reboot_required,
+            &Key::new(KeyType::Data, "settings.kernel.sysctl").unwrap(),
+            "false",
+            &Committed::Live,
+        )
+        .unwrap();
+
+        assert!(commit_requires_reboot(&ds, &hashset!(lockdown.clone())).unwrap());
+        assert!(commit_requires_reboot(&ds, &hashset!(motd.clone(), lockdown)).unwrap());
+        assert!(!commit_requires_reboot(&ds, &hashset!(motd.clone())).unwrap());
+        assert!(!commit_requires_reboot(&ds, &hashset!(sysctl, motd)).unwrap());
+        assert!(!commit_requires_reboot(&ds, &HashSet::new()).unwrap());
+    }
+
+    #[test]
+    fn read_seed_settings_works() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let seed_dir = tmp.path().join("seed");
+        std::fs::create_dir(&seed_dir).unwrap();
+        let write = |name: &str, contents: &str| {
+            let path = seed_dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        };
+        let json = write("a.json", r#"{"motd": "from json"}"#);
+        let toml = write("b.toml", "[settings]\nmotd = \"from toml\"\n");
+        let text = write("c.txt", r#"{"motd": "hi"}"#);
+        let invalid = write("d.json", r#"{"motd": 42}"#);
+        let outside = tmp.path().join("outside.json");
+        std::fs::write(&outside, r#"{"motd": "hi"}"#).unwrap();
+
+        // Seed a transaction, as /tx/seed does.
+        let mut ds = MemoryDataStore::new();
+        let settings = read_seed_settings(&seed_dir, &json).unwrap();
+        set_settings(&mut ds, &settings, "seeded").unwrap();
+        let pending = get_transaction(&ds, "seeded").unwrap();
+        assert_eq!(extract!(pending.motd), Some("from json".into()));
+
+        let settings = read_seed_settings(&seed_dir, &toml).unwrap();
+        assert_eq!(extract!(settings.motd), Some("from toml".into()));
+
+        assert!(matches!(
+            read_seed_settings(&seed_dir, &text),
+            Err(error::Error::SeedFileType { .. })
+        ));
+        assert!(matches!(
+            read_seed_settings(&seed_dir, &invalid),
+            Err(error::Error::SeedModel { .. })
+        ));
+
+        // Files outside the seed directory are refused, however they're reached, and whether or
+        // not they exist.
+        let symlink = seed_dir.join("link.json");
+        std::os::unix::fs::symlink(&outside, &symlink).unwrap();
+        for path in [
+            outside.clone(),
+            tmp.path().join("missing.json"),
+            seed_dir.join("..").join("outside.json"),
+            seed_dir.join("..").join("missing.json"),
+            symlink,
+        ] {
+            assert!(
+                matches!(
+                    read_seed_settings(&seed_dir, &path),
+                    Err(error::Error::SeedPathNotAllowed { .. })
+                ),
+                "{}",
+                path.display()
+            );
+        }
+        assert!(matches!(
+            read_seed_settings(tmp.path().join("missing"), &outside),
+            Err(error::Error::SeedPathNotAllowed { .. })
+        ));
+        assert!(matches!(
+            read_seed_settings(&seed_dir, seed_dir.join("missing.json")),
+            Err(error::Error::SeedRead { .. })
+        ));
+    }
+
+    #[test]
+    fn get_change_impact_works() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "affected-services").unwrap(),
+            &motd,
+            "[\"motd\"]",
+            &Committed::Live,
+        )
+        .unwrap();
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "template").unwrap(),
+            &motd,
+            "\"{{settings.motd}}\"",
+            &Committed::Live,
+        )
+        .unwrap();
+        ds.set_key(
+            &Key::new(KeyType::Data, "services.motd.configuration-files").unwrap(),
+            "[\"motd\"]",
+            &Committed::Live,
+        )
+        .unwrap();
+        ds.set_key(
+            &Key::new(KeyType::Data, "services.motd.restart-commands").unwrap(),
+            "[\"/bin/true\"]",
+            &Committed::Live,
+        )
+        .unwrap();
+
+        let impact = get_change_impact(&ds, &hashset!("settings.motd")).unwrap();
+        assert_eq!(
+            impact,
+            ChangeImpact {
+                affected_services: hashmap!("settings.motd".to_string() => vec!["motd"].into()),
+                templates: hashmap!("settings.motd".to_string() => "{{settings.motd}}".into()),
+                restart_commands: hashmap!("motd".to_string() => vec!["/bin/true".to_string()]),
+            }
+        );
+    }
+
+    #[test]
+    fn get_metadata_all_works() {
+        let mut ds = MemoryDataStore::new();
+        // Set directly with data store
+        for data_key in &["abc", "def"] {
+            ds.set_metadata(
+                &Key::new(KeyType::Meta, "my-meta").unwrap(),
+                &Key::new(KeyType::Data, data_key).unwrap(),
+                "\"json string\"",
+                &Committed::Live,
+            )
+            .unwrap();
+        }
+
+        let expected = hashmap!(
+            "abc".to_string() => "json string".into(),
+            "def".to_string() => "json string".into(),
+        );
+        // Retrieve with helper
+        let actual = get_metadata_for_all_data_keys(&ds, "my-meta").unwrap();
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn get_metadata_for_key_works() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        // A longer key sharing the prefix shouldn't be included.
+        let motd_extra = Key::new(KeyType::Data, "settings.motd-extra").unwrap();
+        for (meta, value) in &[
+            ("affected-services", "[\"motd\"]"),
+            ("strength", "\"strong\""),
+            ("description", "\"Shown at login\""),
+        ] {
+            let meta = Key::new(KeyType::Meta, meta).unwrap();
+            ds.set_metadata(&meta, &motd, value, &Committed::Live)
+                .unwrap();
+            ds.set_metadata(&meta, &motd_extra, "\"other\"", &Committed::Live)
+                .unwrap();
+        }
+        let tx = Committed::Pending { tx: "tx".into() };
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "strength").unwrap(),
+            &motd,
+            "\"weak\"",
+            &tx,
+        )
+        .unwrap();
+
+        let expected = hashmap!(
+            "affected-services".to_string() => serde_json::json!(["motd"]),
+            "strength".to_string() => "strong".into(),
+            "description".to_string() => "Shown at login".into(),
+        );
+        let actual = get_metadata_for_key(&ds, "settings.motd", &Committed::Live).unwrap();
+        assert_eq!(expected, actual);
+
+        // Values staged in the transaction replace the live ones.
+        let actual = get_metadata_for_key(&ds, "settings.motd", &tx).unwrap();
+        assert_eq!(actual["strength"], "weak");
+        assert_eq!(actual["description"], "Shown at login");
+        assert_eq!(actual.len(), 3);
+    }
+
+    #[test]
+    fn get_effective_metadata_inherits() {
+        let mut ds = MemoryDataStore::new();
+        let strength = Key::new(KeyType::Meta, "strength").unwrap();
+        let kubernetes = Key::new(KeyType::Data, "settings.kubernetes").unwrap();
+        let node_labels = Key::new(KeyType::Data, "settings.kubernetes.node-labels").unwrap();
+        ds.set_metadata(&strength, &kubernetes, "\"weak\"", &Committed::Live)
+            .unwrap();
+
+        // Children inherit from their nearest ancestor with the metadata, at any depth.
+        for key in [
+            "settings.kubernetes",
+            "settings.kubernetes.cluster-name",
+            "settings.kubernetes.node-labels.team",
+        ] {
+            assert_eq!(
+                get_effective_metadata(&ds, key, "strength").unwrap(),
+                Some("weak".into()),
+                "{}",
+                key
+            );
+        }
+        // Keys outside the parent, including ones that only share its prefix, don't inherit.
+        for key in ["settings.motd", "settings.kubernetes-extra", "settings"] {
+            assert_eq!(get_effective_metadata(&ds, key, "strength").unwrap(), None);
+        }
+
+        // A child's own value overrides its parent's, for itself and its own children.
+        ds.set_metadata(&strength, &node_labels, "\"strong\"", &Committed::Live)
+            .unwrap();
+        assert_eq!(
+            get_effective_metadata(&ds, "settings.kubernetes.node-labels.team", "strength")
+                .unwrap(),
+            Some("strong".into())
+        );
+        assert_eq!(
+            get_effective_metadata(&ds, "settings.kubernetes.cluster-name", "strength").unwrap(),
+            Some("weak".into())
+        );
+
+        get_effective_metadata(&ds, "bad key", "strength").unwrap_err();
+    }
+
+    #[test]
+    fn find_generated_keys_works() {
+        let mut ds = MemoryDataStore::new();
+        let generator = Key::new(KeyType::Meta, "setting-generator").unwrap();
+        for data_key in &["settings.motd", "settings.ntp"] {
+            ds.set_metadata(
+                &generator,
+                &Key::new(KeyType::Data, data_key).unwrap(),
+                "\"generate\"",
+                &Committed::Live,
+            )
+            .unwrap();
+        }
+
+        // Keys below a generated key are managed too.
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "motd": "hi",
+            "ntp": {"time-servers": ["pool.ntp.org"]},
+        }))
+        .unwrap();
+        assert_eq!(
+            find_generated_keys(&ds, &settings).unwrap(),
+            vec!["settings.motd", "settings.ntp.time-servers"]
+        );
+
+        let ds = MemoryDataStore::new();
+        assert!(find_generated_keys(&ds, &settings).unwrap().is_empty());
+    }
+
+    #[test]
+    fn os_field_names_works() {
+        let os = BottlerocketRelease {
+            pretty_name: "Bottlerocket OS 1.2.3 (aws-dev)".to_string(),
+            variant_id: "aws-dev".to_string(),
+            version_id: Version::new(1, 2, 3),
+            build_id: "abcdef".to_string(),
+            arch: "x86_64".to_string(),
+        };
+        let fields = os_field_names(&os);
+        for known in [
+            "variant_id",
+            "version_id",
+            "pretty_name",
+            "build_id",
+            "arch",
+        ] {
+            assert!(fields.contains(&known.to_string()), "missing {}", known);
+        }
+        assert_eq!(fields.len(), 5);
+    }
+
+    #[test]
+    fn get_setting_generator_works() {
+        use model::generator::SettingsGenerator;
+
+        let mut ds = MemoryDataStore::new();
+        let generator = Key::new(KeyType::Meta, "setting-generator").unwrap();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        ds.set_metadata(&generator, &motd, "\"/bin/motdgen\"", &Committed::Live)
+            .unwrap();
+        let ntp = Key::new(KeyType::Data, "settings.ntp").unwrap();
+        ds.set_metadata(
+            &generator,
+            &ntp,
+            r#"{"command": "/bin/ntpgen", "strength": "weak"}"#,
+            &Committed::Live,
+        )
+        .unwrap();
+
+        assert_eq!(
+            get_setting_generator(&ds, "settings.motd").unwrap(),
+            Some(RawSettingsGenerator::Command("/bin/motdgen".to_string()))
+        );
+        assert_eq!(
+            get_setting_generator(&ds, "settings.ntp").unwrap(),
+            Some(RawSettingsGenerator::Structured(SettingsGenerator {
+                command: "/bin/ntpgen".to_string(),
+                strength: Some("weak".to_string()),
+                skip_if_populated: false,
+            }))
+        );
+        // A key below one with a generator doesn't have its own.
+        assert_eq!(
+            get_setting_generator(&ds, "settings.ntp.time-servers").unwrap(),
+            None
+        );
+        assert_eq!(get_setting_generator(&ds, "settings.other").unwrap(), None);
+    }
+
+    #[test]
+    fn list_setting_sections_works() {
+        let mut ds = MemoryDataStore::new();
+        for (name, value) in &[
+            ("settings.motd", "\"hi\""),
+            ("settings.ntp.time-servers", "[\"pool.ntp.org\"]"),
+            ("settings.kubernetes.cluster-name", "\"cluster\""),
+            ("settings.kubernetes.api-server", "\"https://example.com\""),
+            ("os.arch", "\"x86_64\""),
+        ] {
+            let key = Key::new(KeyType::Data, name).unwrap();
+            ds.set_key(&key, value, &Committed::Live).unwrap();
+        }
+        // Pending settings aren't included.
+        let pending = Committed::Pending { tx: "tx".into() };
+        let key = Key::new(KeyType::Data, "settings.host-containers.admin.enabled").unwrap();
+        ds.set_key(&key, "true", &pending).unwrap();
+
+        assert_eq!(
+            list_setting_sections(&ds).unwrap(),
+            hashset!(
+                "motd".to_string(),
+                "ntp".to_string(),
+                "kubernetes".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn get_transaction_diffs_works() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let ntp = Key::new(KeyType::Data, "settings.ntp.time-servers").unwrap();
+        ds.set_key(&motd, "\"live motd\"", &Committed::Live)
+            .unwrap();
+
+        // One transaction changes an existing setting, and also sets it to the same value.
+        let tx1 = Committed::Pending { tx: "tx1".into() };
+        ds.set_key(&motd, "\"new motd\"", &tx1).unwrap();
+        // The other adds a setting that isn't live yet, and repeats a live value.
+        let tx2 = Committed::Pending { tx: "tx2".into() };
+        ds.set_key(&ntp, "\"pool.ntp.org\"", &tx2).unwrap();
+        ds.set_key(&motd, "\"live motd\"", &tx2).unwrap();
+
+        let diffs = get_transaction_diffs(&ds, 0, 10).unwrap();
+        assert_eq!(
+            diffs,
+            btreemap!(
+                "tx1".to_string() => btreemap!(
+                    "settings.motd".to_string() => SettingDiff {
+                        live: Some("live motd".into()),
+                        pending: "new motd".into(),
+                    }
+                ),
+                "tx2".to_string() => btreemap!(
+                    "settings.ntp.time-servers".to_string() => SettingDiff {
+                        live: None,
+                        pending: "pool.ntp.org".into(),
+                    }
+                ),
+            )
+        );
+
+        // Results are bounded and ordered by transaction name.
+        let diffs = get_transaction_diffs(&ds, 1, 10).unwrap();
+        assert_eq!(diffs.keys().collect::<Vec<_>>(), vec!["tx2"]);
+        let diffs = get_transaction_diffs(&ds, 0, 1).unwrap();
+        assert_eq!(diffs.keys().collect::<Vec<_>>(), vec!["tx1"]);
+    }
+
+    /// Keeps log messages so tests can check what was logged.
+    struct CaptureLogger(Mutex<Vec<String>>);
+
+    static CAPTURE_LOGGER: CaptureLogger = CaptureLogger(Mutex::new(Vec::new()));
+
+    /// Installs the capturing logger as the global logger, once per test binary, and returns it.
+    /// Tests run in parallel, so callers should only look for messages they caused.
+    fn capture_logs() -> &'static CaptureLogger {
+        static INSTALL: std::sync::Once = std::sync::Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&CAPTURE_LOGGER).unwrap();
+            log::set_max_level(log::LevelFilter::Debug);
+        });
+        &CAPTURE_LOGGER
+    }
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn commit_transaction_logs_timing() {
+        let logger = capture_logs();
+        let mut ds = MemoryDataStore::new();
+        let tx = Committed::Pending {
+            tx: "timing-test".into(),
+        };
+        for name in ["settings.motd", "settings.ntp.time-servers"] {
+            let key = Key::new(KeyType::Data, name).unwrap();
+            ds.set_key(&key, "\"value\"", &tx).unwrap();
+        }
+        commit_transaction(&mut ds, "timing-test", None).unwrap();
+
+        let logs = logger.0.lock().unwrap();
+        let lines: Vec<_> = logs
+            .iter()
+            .filter(|line| line.contains("'timing-test'"))
+            .collect();
+        assert!(
+            lines
+                .iter()
+                .any(|line| line.starts_with("Checked pending settings")),
+            "{:?}",
+            lines
+        );
+        let line = lines
+            .iter()
+            .find(|line| line.starts_with("Committed transaction"))
+            .expect("no log line for the commit");
+        assert!(line.contains("2 keys changed, checks took "), "{}", line);
+        assert!(line.contains(", set_keys took "), "{}", line);
+    }
+
+    #[test]
+    fn setting_defaults_work() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("defaults.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [settings]
+            motd = "default motd"
+
+            [metadata.settings.motd]
+            affected-services = ["motd"]
+            "#,
+        )
+        .unwrap();
+        let defaults = read_setting_defaults(&path).unwrap();
+
+        let settings = get_settings_prefix(&defaults, "settings.", &Committed::Live)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&settings).unwrap(),
+            serde_json::json!({"motd": "default motd"})
+        );
+        // Settings without defaults are left out.
+        let keys = hashset!("settings.motd", "settings.ntp.time-servers");
+        let settings = get_settings_keys(&defaults, &keys, &Committed::Live).unwrap();
+        assert_eq!(
+            serde_json::to_value(&settings).unwrap(),
+            serde_json::json!({"motd": "default motd"})
+        );
+
+        // No file means no defaults.
+        let defaults = read_setting_defaults(tmp.path().join("missing.toml")).unwrap();
+        assert_eq!(
+            get_settings_prefix(&defaults, "settings.", &Committed::Live).unwrap(),
+            None
+        );
+
+        // Defaults must match the model.
+        std::fs::write(&path, "[settings]\nmotd = 42\n").unwrap();
+        assert!(matches!(
+            read_setting_defaults(&path),
+            Err(error::Error::DefaultsModel { .. })
+        ));
+    }
+
+    #[test]
+    fn get_effective_settings_works() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let ntp = Key::new(KeyType::Data, "settings.ntp.time-servers").unwrap();
+        let live: Settings = serde_json::from_value(
+            serde_json::json!({"motd": "live motd", "ntp": {"time-servers": ["pool.ntp.org"]}}),
+        )
+        .unwrap();
+        ds.set_keys(
+            &to_pairs_with_prefix("settings", &serde_json::to_value(&live).unwrap()).unwrap(),
+            &Committed::Live,
+        )
+        .unwrap();
+
+        // Pending values win, and null pending values read as they would once committed.
+        let tx = Committed::Pending { tx: "tx".into() };
+        ds.set_key(&motd, "\"new motd\"", &tx).unwrap();
+        ds.set_key(&ntp, "null", &tx).unwrap();
+        let effective = get_effective_settings(&ds, "tx").unwrap();
+        assert_eq!(
+            serde_json::to_value(&effective).unwrap(),
+            serde_json::json!({"motd": "new motd", "ntp": {}})
+        );
+
+        // A transaction with no changes shows the live settings.
+        let settings = get_effective_settings(&ds, "empty").unwrap();
+        assert_eq!(settings, live);
+
+        // Nothing was changed.
+        assert_eq!(
+            ds.get_key(&motd, &Committed::Live).unwrap(),
+            Some("\"live motd\"".to_string())
+        );
+        assert_eq!(ds.get_key(&ntp, &tx).unwrap(), Some("null".to_string()));
+
+        // The view matches what a commit actually makes live.
+        commit_transaction(&mut ds, "tx", None).unwrap();
+        assert_eq!(get_settings(&ds, &Committed::Live).unwrap(), effective);
+    }
+
+    #[test]
+    fn parse_report_works() {
+        let output = r#"{"level":1,"total":2,"passed":1,"skipped":0,"failed":1,"status":"FAIL","timestamp":"2024-01-01T00:00:00.000000000Z","name":"CIS Bottlerocket Benchmark","version":"v1.0.0","url":"https://www.cisecurity.org/benchmark/bottlerocket","results":{"br01010101":{"name":"br01010101","id":"1.1.1.1","level":1,"title":"Ensure mounting of udf filesystems is disabled","mode":"Automatic","status":"PASS","error":""},"br01030100":{"name":"br01030100","id":"1.3.1","level":1,"title":"Ensure AIDE is installed","mode":"Manual","status":"FAIL","error":"AIDE not found"}}}"#;
+        let report = parse_report(output).unwrap();
+        assert_eq!(report.status, CheckStatus::Fail);
+        assert_eq!((report.total, report.passed, report.failed), (2, 1, 1));
+        assert_eq!(
+            report.results["br01030100"],
+            CheckResult {
+                name: "br01030100".to_string(),
+                id: "1.3.1".to_string(),
+                level: 1,
+                title: "Ensure AIDE is installed".to_string(),
+                mode: CheckMode::Manual,
+                status: CheckStatus::Fail,
+                error: "AIDE not found".to_string(),
+            }
+        );
+
+        // Output that's missing required fields is rejected.
+        assert!(matches!(
+            parse_report(r#"{"level":1,"results":{}}"#),
+            Err(error::Error::ReportSchema { .. })
+        ));
+    }
+
+    #[test]
+    fn filter_report_works() {
+        let output = r#"{"level":1,"total":3,"passed":1,"skipped":1,"failed":1,"status":"FAIL","timestamp":"2024-01-01T00:00:00.000000000Z","results":{"br01":{"name":"br01","id":"1","level":1,"title":"One","mode":"Automatic","status":"PASS","error":""},"br02":{"name":"br02","id":"2","level":1,"title":"Two","mode":"Automatic","status":"FAIL","error":"bad"},"br03":{"name":"br03","id":"3","level":1,"title":"Three","mode":"Manual","status":"SKIP","error":"manual"}}}"#;
+        let mut report = parse_report(output).unwrap();
+        filter_report(&mut report, parse_report_status("failed").unwrap());
+        assert_eq!(
+            report.results.keys().collect::<Vec<_>>(),
+            vec![&"br02".to_string()]
+        );
+        assert_eq!(report.total, 3);
+
+        assert!(matches!(
+            parse_report_status("bogus"),
+            Err(error::Error::InvalidReportStatus { .. })
+        ));
+    }
+
+    #[test]
+    fn combine_reports_works() {
+        let cis = r#"{"level":1,"total":1,"passed":1,"skipped":0,"failed":0,"status":"PASS","timestamp":"2024-01-01T00:00:00.000000000Z","results":{"br01":{"name":"br01","id":"1","level":1,"title":"One","mode":"Automatic","status":"PASS","error":""}}}"#;
+        let fips = r#"{"level":0,"total":1,"passed":0,"skipped":0,"failed":1,"status":"FAIL","timestamp":"2024-01-01T00:00:00.000000000Z","results":{"fips01":{"name":"fips01","id":"1","level":0,"title":"FIPS one","mode":"Automatic","status":"FAIL","error":"bad"}}}"#;
+        let outputs = vec![
+            ("cis".to_string(), cis.to_string()),
+            ("fips".to_string(), fips.to_string()),
+        ];
+
+        let combined = combine_reports(outputs.clone(), true, None).unwrap();
+        assert_eq!(combined.keys().collect::<Vec<_>>(), vec!["cis", "fips"]);
+        assert_eq!(combined["cis"]["status"], "PASS");
+        assert_eq!(combined["fips"]["results"]["fips01"]["error"], "bad");
+
+        // Text output is passed through as-is.
+        let combined = combine_reports(outputs, false, None).unwrap();
+        assert_eq!(combined["fips"], fips);
+    }
+
+    #[test]
+    fn constraint_checks_listed() {
+        let names: Vec<_> = CONSTRAINT_CHECKS.iter().map(|c| c.name).collect();
+        assert!(names.contains(&"settings-model"));
+        assert!(names.contains(&"config-render"));
+        // Names identify checks, so they must be unique.
+        assert_eq!(names.iter().collect::<HashSet<_>>().len(), names.len());
+    }
+
+    #[test]
+    fn validate_transaction_works() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        ds.set_key(&motd, "\"hi\"", &Committed::Live).unwrap();
+        // motd is a string in the model, so a list can't be deserialized.
+        let bad = Committed::Pending {
+            tx: "bad".to_string(),
+        };
+        ds.set_key(&motd, "[1, 2]", &bad).unwrap();
+        let good = Committed::Pending {
+            tx: "good".to_string(),
+        };
+        ds.set_key(&motd, "\"hello\"", &good).unwrap();
+
+        assert!(matches!(
+            validate_transaction(&ds, "bad"),
+            Err(error::Error::TransactionSchema { .. })
+        ));
+        validate_transaction(&ds, "good").unwrap();
+        // An empty transaction has nothing wrong with it.
+        validate_transaction(&ds, "empty").unwrap();
+    }
+
+    /// Writes an executable shell script standing in for the config applier.
+    fn stub_applier(dir: &Path, script: &str) -> PathBuf {
+        stub_command(dir, "applier", script)
+    }
+
+    /// Writes an executable shell script with the given name, standing in for a system command.
+    fn stub_command(dir: &Path, name: &str, script: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn apply_changes_sends_keys() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        // With --all, stdin is shared with us rather than piped, so don't read it.
+        let applier = stub_applier(
+            tmp.path(),
+            &format!(
+                "echo \"$@\" >> {0}/args; [ \"$2\" = --all ] || cat > {0}/keys",
+                tmp.path().display()
+            ),
+        );
+
+        let keys = hashset!["settings.motd", "settings.ntp.time-servers"];
+        apply_changes(&applier, Some(&keys)).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("args")).unwrap(),
+            "--daemon\n"
+        );
+        let mut sent: Vec<String> =
+            serde_json::from_str(&std::fs::read_to_string(tmp.path().join("keys")).unwrap())
+                .unwrap();
+        sent.sort();
+        assert_eq!(sent, vec!["settings.motd", "settings.ntp.time-servers"]);
+
+        // Without keys, the applier is asked to apply everything.
+        apply_changes(&applier, None as Option<&HashSet<&str>>).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("args")).unwrap(),
+            "--daemon\n--daemon --all\n"
+        );
+    }
+
+    #[test]
+    fn commit_is_audited() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let audit_log = tmp.path().join("audit.log");
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let pending = Committed::Pending {
+            tx: "test".to_string(),
+        };
+        ds.set_key(&motd, "\"hi\"", &pending).unwrap();
+
+        let changes = commit_transaction(&mut ds, "test", None).unwrap().settings;
+        record_commit(&audit_log, "test", &changes).unwrap();
+        // Lines are appended.
+        record_commit(&audit_log, "other", &HashSet::new()).unwrap();
+        let removed = unset_settings_prefix(&mut ds, "settings.").unwrap();
+        record_removal(&audit_log, &removed).unwrap();
+
+        let contents = std::fs::read_to_string(&audit_log).unwrap();
+        let entries: Vec<AuditEntry> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].transaction.as_deref(), Some("test"));
+        assert_eq!(
+            entries[0].changed,
+            BTreeSet::from(["settings.motd".to_string()])
+        );
+        assert!(entries[0].removed.is_empty());
+        assert_eq!(entries[1].transaction.as_deref(), Some("other"));
+        assert!(entries[0].timestamp <= entries[1].timestamp);
+        assert_eq!(entries[2].transaction, None);
+        assert!(entries[2].changed.is_empty());
+        assert_eq!(
+            entries[2].removed,
+            BTreeSet::from(["settings.motd".to_string()])
+        );
+        let mode = std::fs::metadata(&audit_log).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let missing_dir = tmp.path().join("missing").join("audit.log");
+        let err = record_commit(&missing_dir, "test", &changes).unwrap_err();
+        assert!(matches!(err, error::Error::AuditWrite { .. }));
+    }
+
+    #[test]
+    fn update_dispatch_errors() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let succeeding = stub_command(
+            tmp.path(),
+            "succeeding",
+            &format!("echo \"$@\" > {}/args", tmp.path().display()),
+        );
+        let response = dispatch_update_command(&succeeding, &["prepare"]).unwrap();
+        assert_eq!(response.status(), http::StatusCode::NO_CONTENT);
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("args")).unwrap(),
+            "prepare\n"
+        );
+
+        // Each exit code from the dispatcher maps to its own error.
+        let error_for = |code: i32| {
+            let name = format!("exit-{}", code);
+            let failing = stub_command(tmp.path(), &name, &format!("exit {}", code));
+            dispatch_update_command(&failing, &["activate"]).unwrap_err()
+        };
+        assert!(matches!(error_for(64), error::Error::UpdateLockHeld));
+        assert!(matches!(error_for(65), error::Error::DisallowCommand));
+        assert!(matches!(error_for(66), error::Error::UpdateDoesNotExist));
+        assert!(matches!(error_for(67), error::Error::NoStagedImage));
+        assert!(matches!(error_for(1), error::Error::UpdateError));
+        assert!(matches!(error_for(99), error::Error::UpdateError));
+
+        let missing = tmp.path().join("missing");
+        let err = dispatch_update_command(&missing, &["refresh"]).unwrap_err();
+        assert!(matches!(err, error::Error::UpdateDispatcher { .. }));
+    }
+
+    #[test]
+    fn render_failure_aborts_commit() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let pending = Committed::Pending {
+            tx: "test".to_string(),
+        };
+        ds.set_key(&motd, "\"hi\"", &pending).unwrap();
+
+        // Do what validate_commit_and_apply does up to the commit, with an applier that fails.
+        let failing = stub_applier(
+            tmp.path(),
+            "cat > /dev/null; echo \"template 'motd' failed\" >&2; exit 1",
+        );
+        validate_transaction(&ds, "test").unwrap();
+        let keys: HashSet<String> = get_pending_data(&ds, "test")
+            .unwrap()
+            .keys()
+            .map(|k| k.name().to_string())
+            .collect();
+        assert_eq!(keys, hashset!["settings.motd".to_string()]);
+        match check_rendering(&failing, "test", &keys) {
+            Err(error::Error::RenderCheck { errors }) => {
+                assert_eq!(errors, "template 'motd' failed")
+            }
+            other => panic!("expected RenderCheck error, got {:?}", other),
+        }
+
+        // The transaction wasn't committed.
+        assert_eq!(ds.get_key(&motd, &Committed::Live).unwrap(), None);
+        assert_eq!(
+            ds.get_key(&motd, &pending).unwrap(),
+            Some("\"hi\"".to_string())
+        );
+
+        // An applier that renders successfully is given the dry-run flag, the transaction to
+        // render with, and the pending keys.
+        let passing = stub_applier(
+            tmp.path(),
+            &format!(
+                "echo \"$*\" > {0}/args; cat > {0}/keys",
+                tmp.path().display()
+            ),
+        );
+        check_rendering(&passing, "test", &keys).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("args")).unwrap(),
+            "--dry-run --transaction test\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("keys")).unwrap(),
+            "[\"settings.motd\"]"
+        );
+    }
+
+    #[test]
+    fn render_check_reads_errors_while_sending_keys() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        // More keys and more error output than a pipe holds, with an applier that writes all of
+        // its errors before reading any keys, so both pipes have to be serviced at once.
+        let keys: HashSet<String> = (0..10_000)
+            .map(|i| format!("settings.motd-{:05}", i))
+            .collect();
+        let applier = stub_applier(
+            tmp.path(),
+            "yes e | head -c 200000 >&2; cat > /dev/null; exit 1",
+        );
+        match check_rendering(&applier, "test", &keys) {
+            Err(error::Error::RenderCheck { errors }) => {
+                assert_eq!(errors.lines().count(), 100_000)
+            }
+            other => panic!("expected RenderCheck error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn promote_transaction_works() {
+        let mut ds = MemoryDataStore::new();
+        let staging = Committed::Pending {
+            tx: "staging".to_string(),
+        };
+        let reviewed = Committed::Pending {
+            tx: "reviewed".to_string(),
+        };
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let hostname = Key::new(KeyType::Data, "settings.hostname").unwrap();
+        ds.set_key(&motd, "\"hi\"", &staging).unwrap();
+        ds.set_key(&hostname, "\"host\"", &reviewed).unwrap();
+
+        let changed = promote_transaction(&mut ds, "reviewed", "staging").unwrap();
+        assert_eq!(changed, hashset!(hostname.clone()));
+
+        // Promoting accumulates keys in the target without touching live.
+        assert_eq!(
+            ds.get_prefix("settings.", &staging).unwrap(),
+            hashmap!(
+                motd => "\"hi\"".to_string(),
+                hostname => "\"host\"".to_string(),
+            )
+        );
+        assert!(ds
+            .get_prefix("settings.", &Committed::Live)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            list_transactions(&ds).unwrap(),
+            hashset!("staging".to_string())
+        );
+
+        assert!(matches!(
+            promote_transaction(&mut ds, "staging", "staging"),
+            Err(error::Error::PromoteToSelf { .. })
+        ));
+    }
+
+    #[test]
+    fn annotate_transaction_works() {
+        let mut ds = MemoryDataStore::new();
+        let key = Key::new(KeyType::Data, "settings.motd").unwrap();
+        for tx in &["tx1", "tx2"] {
+            ds.set_key(&key, "\"hi\"", &Committed::Pending { tx: tx.to_string() })
+                .unwrap();
+        }
+
+        annotate_transaction(&mut ds, "tx1", Some("me"), None).unwrap();
+        let first = get_transaction_annotation(&ds, "tx1").unwrap().unwrap();
+        // Later annotations keep earlier fields that aren't replaced.
+        annotate_transaction(&mut ds, "tx1", None, Some("testing")).unwrap();
+
+        let list = list_annotated_transactions(&ds).unwrap();
+        assert_eq!(
+            list,
+            btreemap!(
+                "tx1".to_string() => Some(TransactionAnnotation {
+                    author: Some("me".to_string()),
+                    description: Some("testing".to_string()),
+                    created_at: first.created_at,
+                }),
+                "tx2".to_string() => None,
+            )
+        );
+
+        assert!(matches!(
+            annotate_transaction(&mut ds, "bogus", Some("me"), None),
+            Err(error::Error::MissingTransaction { .. })
+        ));
+    }
+
+    #[test]
+    fn touch_transaction_works() {
+        let mut ds = MemoryDataStore::new();
+        let key = Key::new(KeyType::Data, "settings.motd").unwrap();
+        ds.set_key(&key, "\"hi\"", &Committed::Pending { tx: "tx".into() })
+            .unwrap();
+
+        touch_transaction(&mut ds, "tx").unwrap();
+        assert!(matches!(
+            touch_transaction(&mut ds, "bogus"),
+            Err(error::Error::MissingTransaction { .. })
+        ));
+    }
+
+    #[test]
+    fn delete_transactions_works() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let hostname = Key::new(KeyType::Data, "settings.network.hostname").unwrap();
+        ds.set_key(&motd, "\"hi\"", &Committed::Pending { tx: "tx1".into() })
+            .unwrap();
+        ds.set_key(
+            &hostname,
+            "\"abc\"",
+            &Committed::Pending { tx: "tx2".into() },
+        )
+        .unwrap();
+
+        // A transaction that can't be deleted doesn't stop the others.
+        ds.set_key(&motd, "\"hi\"", &Committed::Pending { tx: "tx3".into() })
+            .unwrap();
+        ds.lock_transaction("tx3", "tool-a").unwrap();
+
+        let deleted: BTreeMap<_, _> =
+            delete_transactions(&mut ds, &hashset!("tx1", "tx2", "tx3", "bogus"))
+                .into_iter()
+                .map(|(tx, result)| (tx, result.ok()))
+                .collect();
+        assert_eq!(
+            deleted,
+            btreemap!(
+                "tx1".to_string() => Some(hashset!(motd)),
+                "tx2".to_string() => Some(hashset!(hostname)),
+                "tx3".to_string() => None,
+                "bogus".to_string() => Some(HashSet::new()),
+            )
+        );
+        assert_eq!(ds.list_transactions().unwrap(), hashset!("tx3".to_string()));
+    }
+
     #[test]
     fn commit_works() {
         // Set directly with data store
@@ -858,7 +4061,7 @@ mod test {
         get_settings(&ds, &Committed::Live).unwrap_err();
 
         // Commit, pending -> live
-        commit_transaction(&mut ds, tx).unwrap();
+        commit_transaction(&mut ds, tx, None).unwrap();
 
         // No more pending settings
         get_settings(&ds, &pending).unwrap_err();
@@ -4,6 +4,7 @@ use datastore::{self, deserialization, serialization};
 use nix::unistd::Gid;
 use snafu::Snafu;
 use std::io;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::string::String;
 
@@ -41,15 +42,32 @@ pub enum Error {
     #[snafu(display("Input '{}' cannot be empty", input))]
     EmptyInput { input: String },
 
-    #[snafu(display("Another thread poisoned the data store lock by panicking"))]
-    DataStoreLock,
+    #[snafu(display("Input '{}' must be a non-negative integer: {}", input, source))]
+    InvalidNumber {
+        input: String,
+        source: std::num::ParseIntError,
+    },
+
+    #[snafu(display("Data store is busy, gave up waiting for its lock after {:?}", waited))]
+    DataStoreBusy { waited: std::time::Duration },
 
     #[snafu(display("Unable to serialize response: {}", source))]
     ResponseSerialization { source: serde_json::Error },
 
+    #[snafu(display("Unable to serialize response to CBOR: {}", source))]
+    ResponseToCbor {
+        source: ciborium::ser::Error<io::Error>,
+    },
+
     #[snafu(display("Unable to bind to {}: {}", path.display(), source))]
     BindSocket { path: PathBuf, source: io::Error },
 
+    #[snafu(display("Unable to bind to {}: {}", addr, source))]
+    BindTcp { addr: SocketAddr, source: io::Error },
+
+    #[snafu(display("Server is read-only, refusing {} {}", method, path))]
+    ReadOnlyServer { method: String, path: String },
+
     #[snafu(display("Unable to start server: {}", source))]
     ServerStart { source: io::Error },
 
@@ -59,15 +77,100 @@ pub enum Error {
     #[snafu(display("Unable to get OS release data: {}", source))]
     ReleaseData { source: bottlerocket_release::Error },
 
+    #[snafu(display("Unable to read default settings from '{}': {}", path.display(), source))]
+    DefaultsRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Default settings in '{}' are not valid TOML: {}", path.display(), source))]
+    DefaultsFormat {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[snafu(display(
+        "Default settings in '{}' don't match the model: {}",
+        path.display(),
+        source
+    ))]
+    DefaultsModel {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("No configuration file named '{}'", name))]
+    NoConfigurationFile { name: String },
+
+    #[snafu(display("Unable to read template '{}': {}", path.display(), source))]
+    TemplateRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to parse template '{}': {}", path.display(), source))]
+    TemplateParse {
+        path: PathBuf,
+        source: schnauzer::v2::template::error::Error,
+    },
+
+    #[snafu(display(
+        "Seed file '{}' is not in the seed directory '{}'",
+        path.display(),
+        dir.display()
+    ))]
+    SeedPathNotAllowed { path: PathBuf, dir: PathBuf },
+
+    #[snafu(display("Unable to read seed file '{}': {}", path.display(), source))]
+    SeedRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display(
+        "Seed file '{}' must have a .json or .toml extension",
+        path.display()
+    ))]
+    SeedFileType { path: PathBuf },
+
+    #[snafu(display("Seed file '{}' is not valid JSON: {}", path.display(), source))]
+    SeedJson {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Seed file '{}' is not valid TOML: {}", path.display(), source))]
+    SeedToml {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[snafu(display(
+        "Settings in seed file '{}' don't match the model: {}",
+        path.display(),
+        source
+    ))]
+    SeedModel {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display(
+        "Settings are meant for variant '{}', but this is variant '{}'",
+        requested,
+        running
+    ))]
+    VariantMismatch { requested: String, running: String },
+
     // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
     // Controller errors
     #[snafu(display("Found no '{}' in datastore", prefix))]
     MissingData { prefix: String },
 
+    #[snafu(display("No setting generator for '{}'", key))]
+    NoSettingGenerator { key: String },
+
     #[snafu(display("Found no '{}' in datastore", requested))]
     ListKeys { requested: String },
 
+    #[snafu(display("Transaction '{}' does not exist", transaction))]
+    MissingTransaction { transaction: String },
+
+    #[snafu(display("Data store path '{}' has no parent directory", path.display()))]
+    DataStoreDir { path: PathBuf },
+
     #[snafu(display("Listed key '{}' not found on disk", key))]
     ListedKeyNotPresent { key: String },
 
@@ -78,12 +181,35 @@ pub enum Error {
         source: Box<datastore::Error>,
     },
 
+    #[snafu(display("Unable to open data store for version {}: {}", version, source))]
+    DataStoreVersion {
+        version: semver::Version,
+        #[snafu(source(from(datastore::Error, Box::new)))]
+        source: Box<datastore::Error>,
+    },
+
+    #[snafu(display("Invalid data store version '{}': {}", given, source))]
+    InvalidVersion {
+        given: String,
+        source: semver::Error,
+    },
+
     #[snafu(display("Error deserializing {}: {} ", given, source))]
     Deserialization {
         given: String,
         source: deserialization::Error,
     },
 
+    #[snafu(display(
+        "Settings in transaction '{}' don't match the settings model: {}",
+        transaction,
+        source
+    ))]
+    TransactionSchema {
+        transaction: String,
+        source: deserialization::Error,
+    },
+
     // This is an important error, it's shown when the user uses 'apiclient set' with the
     // key=value form and we don't have enough data to deserialize the value.  It's not the
     // user's fault and so we want to be very clear and give an alternative.
@@ -98,6 +224,23 @@ pub enum Error {
     #[snafu(display("Error serializing settings to JSON: {}", source))]
     SettingsToJson { source: serde_json::Error },
 
+    #[snafu(display("Unable to deserialize input to settings: {}", source))]
+    SettingsFromJson { source: serde_json::Error },
+
+    #[snafu(display("Unable to deserialize CBOR input: {}", source))]
+    SettingsFromCbor {
+        source: ciborium::de::Error<io::Error>,
+    },
+
+    #[snafu(display("Input contains keys not in the settings model: {}", keys.join(", ")))]
+    UnmodeledKeys { keys: Vec<String> },
+
+    #[snafu(display(
+        "Input contains keys managed by a setting generator, which would no longer be generated: {}",
+        keys.join(", ")
+    ))]
+    GeneratedKeys { keys: Vec<String> },
+
     #[snafu(display("Error serializing {}: {} ", given, source))]
     DataStoreSerialization {
         given: String,
@@ -142,9 +285,37 @@ pub enum Error {
         source: serde_json::Error,
     },
 
+    #[snafu(display("Can't promote transaction '{}' into itself", transaction))]
+    PromoteToSelf { transaction: String },
+
+    #[snafu(display(
+        "Annotation of transaction '{}' is not valid JSON: {}",
+        transaction,
+        source
+    ))]
+    AnnotationJson {
+        transaction: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Value of '{}' is not valid JSON: {}", key, source))]
+    InvalidValue {
+        key: String,
+        source: serde_json::Error,
+    },
+
     #[snafu(display("Failed to split the string: {}", input))]
     InvalidKeyPair { input: String },
 
+    #[snafu(display("Prefix '{}' must start with 'settings.'", prefix))]
+    PrefixNotSettings { prefix: String },
+
+    #[snafu(display(
+        "Unable to reset settings with no default, remove them instead: {}",
+        keys
+    ))]
+    ResetWithoutDefault { keys: String },
+
     #[snafu(display("Prefix '{}' is not a valid key: {}", prefix, source))]
     InvalidPrefix {
         prefix: String,
@@ -170,6 +341,15 @@ pub enum Error {
     #[snafu(display("Unable to send input to config applier: {}", source))]
     ConfigApplierWrite { source: io::Error },
 
+    #[snafu(display("Config files failed to render with pending settings: {}", errors))]
+    RenderCheck { errors: String },
+
+    #[snafu(display(
+        "Transaction '{}' changed while its config files were rendering",
+        transaction
+    ))]
+    TransactionChanged { transaction: String },
+
     #[snafu(display("Unable to start shutdown: {}", source))]
     Shutdown { source: io::Error },
 
@@ -186,12 +366,63 @@ pub enum Error {
     ))]
     ReportResult { exit_code: i32, stderr: String },
 
+    #[snafu(display("Report output doesn't match the expected schema: {}", source))]
+    ReportSchema { source: serde_json::Error },
+
+    #[snafu(display(
+        "Invalid report status '{}', expected 'passed', 'failed', or 'skipped'",
+        given
+    ))]
+    InvalidReportStatus { given: String },
+
+    #[snafu(display("Invalid 'committed' value '{}', expected 'live' or 'tx'", given))]
+    InvalidCommitted { given: String },
+
+    #[snafu(display("Not staging any transactions: {}", reasons))]
+    BulkStageInvalid { reasons: String },
+
+    #[snafu(display("Invalid settings format '{}', expected 'json' or 'env'", given))]
+    InvalidSettingsFormat { given: String },
+
+    #[snafu(display(
+        "Settings '{}' and '{}' both map to environment variable {}",
+        first,
+        second,
+        name
+    ))]
+    EnvNameCollision {
+        name: String,
+        first: String,
+        second: String,
+    },
+
+    #[snafu(display("Filtering reports by status requires 'format=json'"))]
+    ReportFilterFormat,
+
     #[snafu(display("Report type must be specified"))]
     ReportTypeMissing {},
 
     #[snafu(display("Report type '{}' is not supported", report_type))]
     ReportNotSupported { report_type: String },
 
+    #[snafu(display(
+        "Report type '{}' does not support format '{}', expected one of: {}",
+        report_type,
+        format,
+        allowed
+    ))]
+    ReportFormatNotSupported {
+        report_type: String,
+        format: String,
+        allowed: String,
+    },
+
+    // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+    // Audit log errors
+    #[snafu(display("Unable to write to audit log '{}': {}", path.display(), source))]
+    AuditWrite { path: PathBuf, source: io::Error },
+
     // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
     // Update related errors
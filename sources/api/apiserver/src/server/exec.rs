@@ -15,7 +15,9 @@ use actix_web::{web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws::{self, Message};
 use log::{debug, error, info};
 use model::exec::{Capacity, ClientMessage, ServerMessage};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryFrom;
+use std::ffi::OsString;
 use std::fmt::Debug;
 use std::path::PathBuf;
 use std::sync::mpsc::TrySendError;
@@ -23,8 +25,10 @@ use std::time::{Duration, Instant};
 
 mod child;
 mod stop;
+mod transcript;
 use child::ChildHandles;
 use stop::{ok_or_stop, some_or_stop, stop};
+use transcript::Transcript;
 
 /// To guard against stale connections, we send ping and pong messages through the channel
 /// regularly as a 'heartbeat'; this is how often we send them.
@@ -54,30 +58,109 @@ const MAX_MESSAGES_OUTSTANDING: u64 = 1024;
 // the client can't read and send messages for longer.  Testing didn't show huge differences in
 // performance between 64 and 512.
 const CAPACITY_UPDATE_INTERVAL: u64 = 128;
+/// How often we check whether a session has exceeded its configured time limits.
+const SESSION_LIMIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// We use ctr as a simple interface to containerd exec requests; it does bookkeeping for us that's
+/// required by containerd, and is simpler to interact with than the containerd API, at least in
+/// Rust in 2021.  This is where it's found on the host.
+pub const CTR_PATH: &str = "/usr/bin/ctr";
+
+/// The environment variables clients may set for their command unless the server is configured
+/// otherwise.
+pub const DEFAULT_ALLOWED_ENV: &[&str] = &["TERM", "LANG", "LC_ALL", "TZ"];
+
+/// ExecConfig holds the server's settings for exec sessions.
+#[derive(Debug, Clone)]
+pub struct ExecConfig {
+    /// The ctr binary used to spawn the requested process; normally CTR_PATH.
+    pub ctr_path: PathBuf,
+    /// The containerd socket we use to spawn the requested process in a container namespace.
+    pub socket_path: PathBuf,
+    /// Where sessions are recorded if the client requests it; if None, recording is disabled.
+    pub transcript_dir: Option<PathBuf>,
+    /// If set, sessions are closed and their process stopped after running this long.
+    pub max_session_duration: Option<Duration>,
+    /// If set, sessions are closed and their process stopped if the client sends no input for
+    /// this long.
+    pub idle_timeout: Option<Duration>,
+    /// If set, only these commands may be run; a request for any other command is rejected
+    /// before it's spawned.  Commands are compared to the first element of the requested command
+    /// line, so they should be given exactly as clients request them, e.g. "/usr/bin/ls".
+    pub allowed_commands: Option<HashSet<OsString>>,
+    /// The names of environment variables clients may set for their command; a request setting
+    /// any other variable is rejected before it's spawned.
+    pub allowed_env: HashSet<String>,
+}
+
+/// Returns the first requested environment variable that isn't in the allowlist, if any.
+fn disallowed_env<'a>(
+    allowed_env: &HashSet<String>,
+    env: Option<&'a BTreeMap<String, String>>,
+) -> Option<&'a str> {
+    env.into_iter()
+        .flat_map(BTreeMap::keys)
+        .find(|name| !allowed_env.contains(*name))
+        .map(String::as_str)
+}
+
+/// Returns whether the given command line may be run according to the allowlist, if any.
+fn command_allowed(allowed_commands: Option<&HashSet<OsString>>, command: &[OsString]) -> bool {
+    match (allowed_commands, command.first()) {
+        (None, _) => true,
+        (Some(allowed), Some(program)) => allowed.contains(program),
+        (Some(_), None) => false,
+    }
+}
 
-/// Starts the WebSocket, handing control of the message stream to our WsExec actor.
+/// Starts the WebSocket, handing control of the message stream to our WsExec actor.  If the
+/// 'record' query parameter is "true", the session is recorded to a transcript file in the
+/// configured transcript directory.
 pub(crate) async fn ws_exec(
     r: HttpRequest,
     stream: web::Payload,
+    query: web::Query<HashMap<String, String>>,
     data: web::Data<crate::server::SharedData>,
 ) -> Result<HttpResponse, Error> {
+    let id = super::request_id(&r);
     info!(
-        "Received exec request to {}:{}",
+        "[{}] Received exec request to {}:{}",
+        id,
         r.connection_info().host(),
         r.path()
     );
 
-    ws::start(WsExec::new(data.exec_socket_path.clone()), &r, stream)
+    let mut transcript = None;
+    if query.get("record").map(String::as_str) == Some("true") {
+        let dir = match &data.exec_config.transcript_dir {
+            Some(dir) => dir,
+            None => {
+                return Ok(HttpResponse::BadRequest()
+                    .body("exec recording requested but no transcript directory is configured"))
+            }
+        };
+        match Transcript::new(dir) {
+            Ok(t) => transcript = Some(t),
+            Err(e) => {
+                error!("[{}] {}", id, e);
+                return Ok(HttpResponse::InternalServerError().body(e.to_string()));
+            }
+        }
+    }
+
+    ws::start(WsExec::new(&data.exec_config, transcript, id), &r, stream)
 }
 
 /// WsExec is an actor that represents the WebSocket connection to the client.  All messages to and
 /// from the client must pass through WsExec.  For example, the 'child' module holds the Addr
 /// (address) of WsExec so it can send us actor messages that we can turn into WebSocket
 /// communication.
-// If the exec feature sees much use, it may be worthwhile including a generated session ID in this
-// struct, and including that in log output to distinguish exec requests.
 #[derive(Debug)]
 pub(crate) struct WsExec {
+    /// The ID of the request that started the session, included in log output to distinguish exec
+    /// sessions.
+    request_id: String,
+
     /// This tracks the last time we heard from the client; if it's been too long, we consider the
     /// connection stale and terminate it.
     heartbeat: Instant,
@@ -86,20 +169,59 @@ pub(crate) struct WsExec {
     /// because we don't spawn the process until we get an Initialize message with request details.
     child_handles: Option<ChildHandles>,
 
+    /// The ctr binary we use to spawn the requested process.
+    ctr_path: PathBuf,
+
     /// This represents the path to the containerd socket that we use to spawn the requested
     /// process in a container namespace.
     exec_socket_path: PathBuf,
+
+    /// If set, only these commands may be run.
+    allowed_commands: Option<HashSet<OsString>>,
+
+    /// Only these environment variables may be set for the command.
+    allowed_env: HashSet<String>,
+
+    /// If the client asked for the session to be recorded, this is where we record its input and
+    /// output.
+    transcript: Option<Transcript>,
+
+    /// This tracks how long the session has run and been idle, so we can close it if it exceeds
+    /// the configured limits.
+    limits: SessionLimits,
 }
 
 impl WsExec {
-    fn new(exec_socket_path: PathBuf) -> Self {
+    fn new(config: &ExecConfig, transcript: Option<Transcript>, request_id: String) -> Self {
         Self {
+            request_id,
             heartbeat: Instant::now(),
             child_handles: None,
-            exec_socket_path,
+            ctr_path: config.ctr_path.clone(),
+            exec_socket_path: config.socket_path.clone(),
+            allowed_commands: config.allowed_commands.clone(),
+            allowed_env: config.allowed_env.clone(),
+            transcript,
+            limits: SessionLimits::new(config.max_session_duration, config.idle_timeout),
         }
     }
 
+    /// This starts a task that closes the session and stops the child process if the session has
+    /// run too long or the client has been idle too long.  A forgotten session would otherwise
+    /// keep its process, like a shell, alive indefinitely.
+    fn enforce_limits(&self, ctx: &mut <Self as Actor>::Context) {
+        if !self.limits.enabled() {
+            return;
+        }
+        ctx.run_interval(SESSION_LIMIT_INTERVAL, |actor, ctx| {
+            if let Some(reason) = actor.limits.exceeded(Instant::now()) {
+                info!("[{}] Closing exec session: {}", actor.request_id, reason);
+                // Stopping the actor stops the child, too.
+                stop(ctx, &actor.request_id, Some(reason), ws::CloseCode::Policy);
+            }
+        });
+    }
+
     /// This starts a task that's responsible for confirming that our connection to the client
     /// isn't stale.  We ping the client regularly so it knows we're alive, and we confirm that the
     /// client has pinged us recently so we know it's alive.
@@ -107,12 +229,18 @@ impl WsExec {
         ctx.run_interval(HEARTBEAT_INTERVAL, |actor, ctx| {
             // If we don't hear from the client in a while, consider it stale and terminate.
             if Instant::now().duration_since(actor.heartbeat) > CLIENT_TIMEOUT {
-                info!("exec client heartbeat failed, disconnecting");
+                info!(
+                    "[{}] exec client heartbeat failed, disconnecting",
+                    actor.request_id
+                );
                 ctx.stop();
                 return;
             }
 
-            debug!("exec client heartbeat ok, sending ping");
+            debug!(
+                "[{}] exec client heartbeat ok, sending ping",
+                actor.request_id
+            );
             ctx.ping(b"");
         });
     }
@@ -126,8 +254,12 @@ impl Actor for WsExec {
     /// When the actor is first started, we set up long-running processes like the heartbeat, and
     /// send an initial capacity update so the client can start reading and sending input.
     fn started(&mut self, ctx: &mut Self::Context) {
-        debug!("Starting heartbeat and sending initial capacity update");
+        debug!(
+            "[{}] Starting heartbeat and sending initial capacity update",
+            self.request_id
+        );
         self.heartbeat(ctx);
+        self.enforce_limits(ctx);
 
         let capacity = Capacity {
             max_messages_outstanding: MAX_MESSAGES_OUTSTANDING,
@@ -135,6 +267,15 @@ impl Actor for WsExec {
         };
         ctx.notify(message::CapacityUpdate(capacity));
     }
+
+    /// However the session ends - the client closing or disappearing, a stale heartbeat, or a
+    /// limit being exceeded - we stop the child process so it doesn't run forever with no one
+    /// watching.  The child's waiter thread reaps it once it exits.
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Some(child_handles) = &self.child_handles {
+            child_handles.stop();
+        }
+    }
 }
 
 impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
@@ -147,25 +288,34 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
             // Respond to Ping with Pong so the client knows we're alive, and record that we've
             // heard from them.
             Ok(Message::Ping(msg)) => {
-                debug!("Received ping, updating heartbeat and responding");
+                debug!(
+                    "[{}] Received ping, updating heartbeat and responding",
+                    self.request_id
+                );
                 self.heartbeat = Instant::now();
                 ctx.pong(&msg);
             }
 
             // When the client responds to our Ping with a Pong, record that we've heard from them.
             Ok(Message::Pong(_)) => {
-                debug!("Received pong, updating heartbeat");
+                debug!("[{}] Received pong, updating heartbeat", self.request_id);
                 self.heartbeat = Instant::now();
             }
 
             // Binary means process input, which we write directly to the child process.
             Ok(Message::Binary(data)) => {
-                trace!("Received {} bytes of input from client", data.len());
+                trace!(
+                    "[{}] Received {} bytes of input from client",
+                    self.request_id,
+                    data.len()
+                );
+                self.limits.record_input(Instant::now());
 
                 // Confirm we have a child, i.e. the client didn't send messages out of order.
                 let child_handles = some_or_stop!(
                     &self.child_handles,
                     ctx,
+                    &self.request_id,
                     Some("process data sent before initialization"),
                     ws::CloseCode::Policy,
                 );
@@ -174,6 +324,16 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
                 // drop this when the client sends a ContentComplete; they shouldn't send anything
                 // after that, but if they do, we can just ignore it.
                 if let Some(write_tx) = &child_handles.write_tx {
+                    if let Some(transcript) = self.transcript.as_mut() {
+                        ok_or_stop!(
+                            transcript.record(&data),
+                            ctx,
+                            &self.request_id,
+                            "failed to record input to transcript",
+                            ws::CloseCode::Error
+                        );
+                    }
+
                     // This is where we check that the client is actually obeying the capacity
                     // updates we're sending them.  The write_tx channel is bounded, and if we fail
                     // to write to it because it's full, we can righteously yell at the client.
@@ -186,7 +346,10 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
                         // block the whole actor, meaning nothing gets done; heartbeats would fail,
                         // output wouldn't get sent, etc.
                         Err(TrySendError::Full(_data)) => {
-                            info!("Client not obeying capacity updates, closing connection");
+                            info!(
+                                "[{}] Client not obeying capacity updates, closing connection",
+                                self.request_id
+                            );
                             let msg = "write buffer full; obey capacity updates".to_string();
                             ctx.close(Some(ws::CloseReason {
                                 code: ws::CloseCode::Size,
@@ -203,7 +366,12 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
                         // child process failed and we can no longer write to it safely; tell the
                         // client to stop.
                         Err(TrySendError::Disconnected(_data)) => {
-                            stop(ctx, Some("writing to process failed"), ws::CloseCode::Error);
+                            stop(
+                                ctx,
+                                &self.request_id,
+                                Some("writing to process failed"),
+                                ws::CloseCode::Error,
+                            );
                         }
                     }
                 }
@@ -212,9 +380,11 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
             // A Text message is a multiplexed control message giving us some control information
             // from the client.  We deserialize it to figure out what they want.
             Ok(Message::Text(msg)) => {
+                self.limits.record_input(Instant::now());
                 let msg = ok_or_stop!(
                     serde_json::from_str(&msg),
                     ctx,
+                    &self.request_id,
                     "invalid JSON in client message",
                     ws::CloseCode::Invalid
                 );
@@ -224,14 +394,49 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
                     // request body so we don't worry as much about ordering, but not all clients
                     // support that.)
                     ClientMessage::Initialize(init) => {
-                        debug!("Client initialized for target container '{}' and command {:?} with tty: {}",
+                        debug!("[{}] Client initialized for target container '{}' and command {:?} with tty: {}",
+                               self.request_id,
                                init.target,
                                init.command,
                                init.tty.is_some());
+                        // Reject commands that aren't allowed before spawning anything.
+                        if !command_allowed(self.allowed_commands.as_ref(), &init.command) {
+                            info!(
+                                "[{}] Rejecting exec of command not in allowlist: {:?}",
+                                self.request_id, init.command
+                            );
+                            let msg = format!(
+                                "command {:?} is not in the list of allowed commands",
+                                init.command
+                                    .first()
+                                    .map(|c| c.to_string_lossy())
+                                    .unwrap_or_default()
+                            );
+                            stop(ctx, &self.request_id, Some(msg), ws::CloseCode::Policy);
+                            return;
+                        }
+                        if let Some(name) = disallowed_env(&self.allowed_env, init.env.as_ref()) {
+                            info!(
+                                "[{}] Rejecting exec setting variable not in allowlist: {}",
+                                self.request_id, name
+                            );
+                            let msg = format!(
+                                "environment variable '{}' is not in the list of allowed variables",
+                                name
+                            );
+                            stop(ctx, &self.request_id, Some(msg), ws::CloseCode::Policy);
+                            return;
+                        }
                         // Spawn the process, getting back handles that let us interact with it.
                         let child_handles = ok_or_stop!(
-                            ChildHandles::new(init, &self.exec_socket_path, ctx.address()),
+                            ChildHandles::new(
+                                init,
+                                &self.ctr_path,
+                                &self.exec_socket_path,
+                                ctx.address()
+                            ),
                             ctx,
+                            &self.request_id,
                             "failed to spawn process",
                             ws::CloseCode::Error
                         );
@@ -241,12 +446,16 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
                     // This means the client is done reading input from the user and we can close
                     // the write channel to the process, closing its stdin.
                     ClientMessage::ContentComplete => {
-                        debug!("Received client content complete, dropping write handle");
+                        debug!(
+                            "[{}] Received client content complete, dropping write handle",
+                            self.request_id
+                        );
                         // Confirm we have a child, i.e. the client didn't send messages out of
                         // order.
                         let child_handles = some_or_stop!(
                             &mut self.child_handles,
                             ctx,
+                            &self.request_id,
                             Some("ContentComplete sent before initialization"),
                             ws::CloseCode::Policy
                         );
@@ -261,7 +470,10 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
                         if let Some(child_handles) = self.child_handles.as_mut() {
                             child_handles.set_winsize(size);
                         } else {
-                            debug!("Received client winch before child was spawned");
+                            debug!(
+                                "[{}] Received client winch before child was spawned",
+                                self.request_id
+                            );
                         }
                     }
                 }
@@ -269,7 +481,10 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
 
             // This means the client is done with us; stop the actor.
             Ok(Message::Close(reason)) => {
-                info!("Client closed exec connection with reason: {:?}", reason);
+                info!(
+                    "[{}] Client closed exec connection with reason: {:?}",
+                    self.request_id, reason
+                );
                 ctx.close(reason);
                 ctx.stop();
             }
@@ -278,27 +493,26 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
             // messages.
             Ok(Message::Continuation(_)) => {
                 let msg = "Continuation messages not supported";
-                stop(ctx, Some(msg), ws::CloseCode::Unsupported);
+                stop(ctx, &self.request_id, Some(msg), ws::CloseCode::Unsupported);
             }
 
             // no-op
             Ok(Message::Nop) => {}
 
             Err(e) => {
-                error!("Stopping after receiving error message: {}", e);
+                error!(
+                    "[{}] Stopping after receiving error message: {}",
+                    self.request_id, e
+                );
                 ctx.stop();
             }
         }
     }
 
     /// We hit finished() as soon as the client closes the channel or exits, so it's our first
-    /// indication that we're done with the child process.  We want to stop it so it doesn't run
-    /// forever with no one watching.
+    /// indication that we're done with the child process.  Stopping the actor stops the child.
     fn finished(&mut self, ctx: &mut Self::Context) {
-        info!("exec client disconnected");
-        if let Some(child_handles) = &self.child_handles {
-            child_handles.stop();
-        }
+        info!("[{}] exec client disconnected", self.request_id);
 
         // Note: stopping the actor prevents the ProcessReturn message being received and the
         // return code being logged, but the client is gone and doesn't care about the return code,
@@ -307,6 +521,53 @@ impl StreamHandler<Result<Message, ws::ProtocolError>> for WsExec {
     }
 }
 
+/// SessionLimits tracks the time limits of an exec session.
+#[derive(Debug)]
+struct SessionLimits {
+    started: Instant,
+    last_input: Instant,
+    max_duration: Option<Duration>,
+    idle_timeout: Option<Duration>,
+}
+
+impl SessionLimits {
+    fn new(max_duration: Option<Duration>, idle_timeout: Option<Duration>) -> Self {
+        let now = Instant::now();
+        Self {
+            started: now,
+            last_input: now,
+            max_duration,
+            idle_timeout,
+        }
+    }
+
+    /// Returns whether any limit is configured.
+    fn enabled(&self) -> bool {
+        self.max_duration.is_some() || self.idle_timeout.is_some()
+    }
+
+    /// Records that the client sent input, resetting the idle timer.
+    fn record_input(&mut self, now: Instant) {
+        self.last_input = now;
+    }
+
+    /// Returns a reason suitable for the client if the session has exceeded a limit as of the
+    /// given time.
+    fn exceeded(&self, now: Instant) -> Option<&'static str> {
+        if let Some(max) = self.max_duration {
+            if now.duration_since(self.started) > max {
+                return Some("session exceeded maximum duration");
+            }
+        }
+        if let Some(idle) = self.idle_timeout {
+            if now.duration_since(self.last_input) > idle {
+                return Some("session idle too long");
+            }
+        }
+        None
+    }
+}
+
 /// The 'message' module contains the non-WebSocket messages that our WebSocket actor can handle;
 /// they're how our child process code talks to the actor so data can be sent to the client.
 mod message {
@@ -338,9 +599,19 @@ impl Handler<message::ProcessOutput> for WsExec {
     /// Messages are always sent in order.
     fn handle(&mut self, msg: message::ProcessOutput, ctx: &mut Self::Context) -> Self::Result {
         trace!(
-            "Sending {} bytes of process output to client",
+            "[{}] Sending {} bytes of process output to client",
+            self.request_id,
             msg.output.len()
         );
+        if let Some(transcript) = self.transcript.as_mut() {
+            ok_or_stop!(
+                transcript.record(&msg.output),
+                ctx,
+                &self.request_id,
+                "failed to record output to transcript",
+                ws::CloseCode::Error
+            );
+        }
         ctx.binary(msg.output)
     }
 }
@@ -350,13 +621,21 @@ impl Handler<message::ProcessReturn> for WsExec {
 
     /// Sends the process return code to the client inside a Close message.
     fn handle(&mut self, msg: message::ProcessReturn, ctx: &mut Self::Context) -> Self::Result {
-        info!("exec process returned {}", msg.code);
+        info!("[{}] exec process returned {}", self.request_id, msg.code);
+        // The child has exited and been reaped, so there's nothing left to stop, and its pid
+        // could be reused.
+        self.child_handles = None;
         // nix deals with i32 (c_int) return codes, but we know they're never negative; really,
         // they're just a u8.  If that assumption breaks for some reason, we don't have a
         // reasonable code to send to the user, so just give a 0.
         let code = u16::try_from(msg.code).unwrap_or(0);
-        // We send the process return code in the closing frame's reason message.
-        stop(ctx, Some(code.to_string()), ws::CloseCode::Normal);
+        // We send the process return code in the closing frame's reason message, followed by the
+        // transcript path if the session was recorded.
+        let reason = match &self.transcript {
+            Some(transcript) => format!("{} {}", code, transcript.path().display()),
+            None => code.to_string(),
+        };
+        stop(ctx, &self.request_id, Some(reason), ws::CloseCode::Normal);
     }
 }
 
@@ -366,15 +645,88 @@ impl Handler<message::CapacityUpdate> for WsExec {
     /// Sends a capacity update to the client, multiplexed into a ServerMessage.
     fn handle(&mut self, msg: message::CapacityUpdate, ctx: &mut Self::Context) -> Self::Result {
         debug!(
-            "Sending capacity update; {} max outstanding, {} written",
-            msg.0.max_messages_outstanding, msg.0.messages_written
+            "[{}] Sending capacity update; {} max outstanding, {} written",
+            self.request_id, msg.0.max_messages_outstanding, msg.0.messages_written
         );
         let msg = ok_or_stop!(
             serde_json::to_string(&ServerMessage::Capacity(msg.0)),
             ctx,
+            &self.request_id,
             "failed to send capacity update",
             ws::CloseCode::Error,
         );
         ctx.text(msg);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn allowlist() {
+        let allowed = HashSet::from([OsString::from("/usr/bin/ls")]);
+        let allowed_cmd = vec![OsString::from("/usr/bin/ls"), OsString::from("-l")];
+        let denied_cmd = vec![OsString::from("/usr/bin/bash")];
+
+        assert!(command_allowed(Some(&allowed), &allowed_cmd));
+        assert!(!command_allowed(Some(&allowed), &denied_cmd));
+        assert!(!command_allowed(Some(&allowed), &[]));
+
+        // Without an allowlist, anything goes.
+        assert!(command_allowed(None, &denied_cmd));
+    }
+
+    #[test]
+    fn env_allowlist() {
+        let allowed = DEFAULT_ALLOWED_ENV.iter().map(|s| s.to_string()).collect();
+        let env = BTreeMap::from([("TERM".to_string(), "xterm".to_string())]);
+        assert_eq!(disallowed_env(&allowed, Some(&env)), None);
+        assert_eq!(disallowed_env(&allowed, None), None);
+
+        let env = BTreeMap::from([
+            ("LANG".to_string(), "C.UTF-8".to_string()),
+            ("LD_PRELOAD".to_string(), "/tmp/evil.so".to_string()),
+        ]);
+        assert_eq!(disallowed_env(&allowed, Some(&env)), Some("LD_PRELOAD"));
+    }
+
+    #[test]
+    fn no_limits() {
+        let limits = SessionLimits::new(None, None);
+        assert!(!limits.enabled());
+        let later = limits.started + Duration::from_secs(86400);
+        assert_eq!(limits.exceeded(later), None);
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let mut limits = SessionLimits::new(None, Some(Duration::from_secs(10)));
+        let start = limits.started;
+        assert_eq!(limits.exceeded(start + Duration::from_secs(5)), None);
+
+        // Input resets the idle timer.
+        limits.record_input(start + Duration::from_secs(8));
+        assert_eq!(limits.exceeded(start + Duration::from_secs(15)), None);
+
+        assert_eq!(
+            limits.exceeded(start + Duration::from_secs(19)),
+            Some("session idle too long")
+        );
+    }
+
+    #[test]
+    fn max_duration() {
+        let mut limits =
+            SessionLimits::new(Some(Duration::from_secs(60)), Some(Duration::from_secs(10)));
+        let start = limits.started;
+        // Staying active doesn't extend the maximum duration.
+        for secs in (5..=65).step_by(5) {
+            limits.record_input(start + Duration::from_secs(secs));
+        }
+        assert_eq!(
+            limits.exceeded(start + Duration::from_secs(65)),
+            Some("session exceeded maximum duration")
+        );
+    }
+}
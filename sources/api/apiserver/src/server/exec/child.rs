@@ -71,12 +71,15 @@ impl ChildHandles {
     /// * init: The initialization parameters for the process, meaning the target container, the
     ///   command, and any TTY settings.
     ///
+    /// * ctr_path: The ctr binary we use to ask containerd to start the process.
+    ///
     /// * exec_socket_path: The containerd socket we'll use to start the process in the desired
     ///   container's namespace.
     ///
     /// * ws_addr: The address of the WebSocket actor, for sending messages back.
     pub(crate) fn new(
         init: Initialize,
+        ctr_path: impl AsRef<OsStr>,
         exec_socket_path: impl AsRef<OsStr>,
         ws_addr: Addr<WsExec>,
     ) -> Result<Self> {
@@ -90,39 +93,12 @@ impl ChildHandles {
                 .collect::<String>()
         );
 
-        // We use ctr as a simple interface to containerd exec requests; it does bookkeeping for us
-        // that's required by containerd, and is simpler to interact with than the containerd API,
-        // at least in Rust in 2021.
-        let mut command = Command::new("/usr/bin/ctr");
-
-        // Point it at the requested containerd socket; changes are useful for local testing.
-        command.arg("-a");
-        // (The path is a different type, OsStr, so it's passed separately.)
-        command.arg(exec_socket_path.as_ref());
-
-        // Ask ctr to exec into an existing task, with a TTY if requested by the user.
-        command.args(["task", "exec", "--exec-id", &exec_id]);
-        if init.tty.is_some() {
-            command.arg("--tty");
-        }
-        // Pass the target container (task) and the requested command.
-        command.arg(init.target);
-        command.args(init.command);
-
-        // ctr sets up a basic environment for spawned processes; there's no reason to inherit
-        // anything from apiserver.
-        command.env_clear();
+        let tty = init.tty.clone();
+        let mut command = ctr_command(ctr_path, exec_socket_path, &exec_id, init);
 
         // Get read and write file descriptors, configured appropriately for the requested TTY
         // setup.  (Sometimes we'll also have a fd to close because PTYs are finicky.)
-        let child_fds = ChildFds::new(&mut command, &init.tty)?;
-
-        // We don't want to pass through a "real" TERM value because TUI programs will query for
-        // terminal capabilities, like cursor position and color support, and we don't feed that
-        // back to a terminal, just stdin of the requested program.  Use TERM=screen because it's
-        // widely understood and doesn't emulate a real terminal in ways that are awkward for us,
-        // meaning no ANSI escapes are passed back and forth.
-        command.env("TERM", "screen");
+        let child_fds = ChildFds::new(&mut command, &tty)?;
 
         debug!("Spawning command for exec request: {:?}", command);
         let mut child = command.spawn().context(error::SpawnSnafu)?;
@@ -134,9 +110,6 @@ impl ChildHandles {
         let pid = Pid::from_raw(pid_raw);
         debug!("Spawned child has pid {}", pid);
 
-        // Work around partial move of 'init' into closure; not needed in Rust 2021?
-        let tty = init.tty;
-
         // At this point we've spawned a child process but still have some configuration to do.  If
         // any of it fails, we want to return failure, but we want to make sure we kill the child
         // process too, or it'd stick around forever.  Perform the rest of initialization in a
@@ -194,7 +167,7 @@ impl ChildHandles {
         })()
         // If anything went wrong when configuring the child process, kill it and return the
         // original error.
-        .inspect(|_| Self::stop_impl(pid))
+        .inspect_err(|_| Self::stop_impl(pid))
     }
 
     /// Terminates the child process.
@@ -236,9 +209,22 @@ impl ChildHandles {
             size.cols, size.rows
         );
 
-        let mut winsize = WinSize::from(size);
-        unsafe { ioctl(self.pty_fd, SetWinSize, &mut winsize) };
+        if let Err(e) = set_pty_winsize(self.pty_fd, size) {
+            error!("Failed to update window size: {}", e);
+        }
+    }
+}
+
+/// Sets the window size of the PTY with the given file descriptor; the process on the other end
+/// of the PTY is sent SIGWINCH and sees the new size.
+fn set_pty_winsize(pty_fd: RawFd, size: Size) -> io::Result<()> {
+    let winsize = WinSize::from(size);
+    // Safety: TIOCSWINSZ only reads the winsize struct, which lives for the duration of the call.
+    let ret = unsafe { ioctl(pty_fd, SetWinSize, &winsize) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
     }
+    Ok(())
 }
 
 /// ChildFds sets up read and write file descriptors for a Command (before it's spawned) based on
@@ -616,3 +602,123 @@ mod error {
     }
 }
 type Result<T> = std::result::Result<T, error::Error>;
+
+/// Builds the ctr command that runs the requested command in the target container, with the
+/// requested environment.  The caller still has to set up the command's file descriptors.
+fn ctr_command(
+    ctr: impl AsRef<OsStr>,
+    exec_socket_path: impl AsRef<OsStr>,
+    exec_id: &str,
+    init: Initialize,
+) -> Command {
+    let mut command = Command::new(ctr);
+
+    // Point it at the requested containerd socket; changes are useful for local testing.
+    command.arg("-a");
+    // (The path is a different type, OsStr, so it's passed separately.)
+    command.arg(exec_socket_path.as_ref());
+
+    // Ask ctr to exec into an existing task, with a TTY if requested by the user.
+    command.args(["task", "exec", "--exec-id", exec_id]);
+    if init.tty.is_some() {
+        command.arg("--tty");
+    }
+    // Add the variables requested by the client, which were already checked against the
+    // allowlist.  They're for the process in the container, not ctr, so they're passed as ctr
+    // arguments rather than in ctr's environment.
+    for (name, value) in init.env.into_iter().flatten() {
+        command.arg("--env");
+        command.arg(format!("{}={}", name, value));
+    }
+    // Pass the target container (task) and the requested command.
+    command.arg(init.target);
+    command.args(init.command);
+
+    // ctr sets up a basic environment for spawned processes; there's no reason to inherit
+    // anything from apiserver.
+    command.env_clear();
+
+    // We don't want to pass through a "real" TERM value because TUI programs will query for
+    // terminal capabilities, like cursor position and color support, and we don't feed that
+    // back to a terminal, just stdin of the requested program.  Use TERM=screen because it's
+    // widely understood and doesn't emulate a real terminal in ways that are awkward for us,
+    // meaning no ANSI escapes are passed back and forth.
+    command.env("TERM", "screen");
+
+    command
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libc::TIOCGWINSZ as GetWinSize;
+
+    #[test]
+    fn resize_updates_pty() {
+        let pty = openpty(None, None).unwrap();
+        let master = pty.master;
+        let slave = pty.slave;
+
+        set_pty_winsize(
+            master,
+            Size {
+                rows: 42,
+                cols: 120,
+            },
+        )
+        .unwrap();
+
+        // The child sees the PTY from the "slave" end, so check the size from there.
+        let mut winsize: WinSize = unsafe { std::mem::zeroed() };
+        assert_eq!(unsafe { ioctl(slave, GetWinSize, &mut winsize) }, 0);
+        let size = Size::from(winsize);
+        assert_eq!((size.rows, size.cols), (42, 120));
+
+        close(master).unwrap();
+        close(slave).unwrap();
+    }
+
+    #[test]
+    fn env_passed_as_ctr_args() {
+        use std::collections::BTreeMap;
+
+        let init = Initialize {
+            command: vec!["/usr/bin/bash".into(), "-l".into()],
+            target: "admin".to_string(),
+            tty: None,
+            env: Some(BTreeMap::from([
+                ("LANG".to_string(), "C.UTF-8".to_string()),
+                ("TZ".to_string(), "UTC".to_string()),
+            ])),
+        };
+        let command = ctr_command("ctr", "/run/containerd.sock", "apiexec-test", init);
+
+        // The variables are given to ctr for the exec'd process, before the target and command.
+        let args: Vec<_> = command
+            .get_args()
+            .map(|arg| arg.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            args,
+            vec![
+                "-a",
+                "/run/containerd.sock",
+                "task",
+                "exec",
+                "--exec-id",
+                "apiexec-test",
+                "--env",
+                "LANG=C.UTF-8",
+                "--env",
+                "TZ=UTC",
+                "admin",
+                "/usr/bin/bash",
+                "-l",
+            ]
+        );
+
+        // ctr itself only gets our TERM.
+        let envs: Vec<_> = command.get_envs().collect();
+        assert_eq!(envs, vec![(OsStr::new("TERM"), Some(OsStr::new("screen")))]);
+    }
+}
@@ -10,14 +10,14 @@ use std::fmt::Debug;
 /// If the given Option is Some, this evaluates to the inner value.  If it's None, this stops the
 /// WebSocket and returns from the current function.
 ///
-/// Pass in the actor context so we're able to call stop, and the desired message and CloseCode to
-/// be sent to the client in the case of None.
+/// Pass in the actor context so we're able to call stop, the request ID for logging, and the
+/// desired message and CloseCode to be sent to the client in the case of None.
 macro_rules! some_or_stop {
-    ($option:expr, $context:expr, $message:expr, $closecode:expr $(,)?) => {
+    ($option:expr, $context:expr, $id:expr, $message:expr, $closecode:expr $(,)?) => {
         match $option {
             Some(inner) => inner,
             None => {
-                stop($context, $message, $closecode);
+                stop($context, $id, $message, $closecode);
                 return;
             }
         }
@@ -28,15 +28,20 @@ pub(crate) use some_or_stop;
 /// If the given Result is Ok, this evaluates to the inner value.  If it's Err, this stops the
 /// WebSocket and returns from the current function.
 ///
-/// Pass in the actor context so we're able to call stop, and the desired message and CloseCode to
-/// be sent to the client in the case of Err.  The Error inside the Err is appended to your
-/// message.
+/// Pass in the actor context so we're able to call stop, the request ID for logging, and the
+/// desired message and CloseCode to be sent to the client in the case of Err.  The Error inside
+/// the Err is appended to your message.
 macro_rules! ok_or_stop {
-    ($result:expr, $context:expr, $message:expr, $closecode:expr $(,)?) => {
+    ($result:expr, $context:expr, $id:expr, $message:expr, $closecode:expr $(,)?) => {
         match $result {
             Ok(inner) => inner,
             Err(e) => {
-                stop($context, Some(format!("{}: {}", $message, e)), $closecode);
+                stop(
+                    $context,
+                    $id,
+                    Some(format!("{}: {}", $message, e)),
+                    $closecode,
+                );
                 return;
             }
         }
@@ -44,16 +49,19 @@ macro_rules! ok_or_stop {
 }
 pub(crate) use ok_or_stop;
 
-/// Sends the given Close message (if any) and CloseCode to the client and stops the WebSocket.
+/// Sends the given Close message (if any) and CloseCode to the client and stops the WebSocket.  The
+/// ID of the exec request is included in the log line.
 pub(crate) fn stop<S>(
     ctx: &mut ws::WebsocketContext<WsExec>,
+    id: &str,
     message: Option<S>,
     closecode: ws::CloseCode,
 ) where
     S: Into<String> + Debug,
 {
     info!(
-        "Closing exec connection{}",
+        "[{}] Closing exec connection{}",
+        id,
         if let Some(message) = &message {
             format!("; message: {:?}", message)
         } else {
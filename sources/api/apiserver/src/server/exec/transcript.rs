@@ -0,0 +1,99 @@
+//! The 'transcript' module records the input and output of an exec session to a file, for
+//! auditing.
+//!
+//! Input and output are written to the file in the order they pass through the WebSocket, so the
+//! transcript reads like the user's terminal, plus any input that wasn't echoed.
+
+use chrono::Utc;
+use log::info;
+use snafu::ResultExt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Transcript represents an open transcript file for an exec session.
+#[derive(Debug)]
+pub(crate) struct Transcript {
+    file: File,
+    path: PathBuf,
+}
+
+impl Transcript {
+    /// Creates a new transcript file in the given directory, named for the current time.
+    pub(crate) fn new(dir: &Path) -> Result<Self> {
+        let name = format!(
+            "exec-{}.transcript",
+            Utc::now().format("%Y%m%dT%H%M%S%.6fZ")
+        );
+        let path = dir.join(name);
+        // create_new makes sure we don't append to the transcript of another session.  Sessions
+        // can include secrets, so only root can read the transcript.
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .context(error::CreateSnafu { path: &path })?;
+        info!("Recording exec session to {}", path.display());
+        Ok(Self { file, path })
+    }
+
+    /// Returns the path to the transcript file.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Adds the given data to the transcript.  Input and output are recorded the same way.
+    pub(crate) fn record(&mut self, data: &[u8]) -> Result<()> {
+        self.file
+            .write_all(data)
+            .context(error::WriteSnafu { path: &self.path })
+    }
+}
+
+mod error {
+    use snafu::Snafu;
+    use std::io;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub(crate) enum Error {
+        #[snafu(display("Unable to create transcript file '{}': {}", path.display(), source))]
+        Create { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Unable to write to transcript file '{}': {}", path.display(), source))]
+        Write { path: PathBuf, source: io::Error },
+    }
+}
+type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    #[test]
+    fn records_session() {
+        let dir = TempDir::new().unwrap();
+        let mut transcript = Transcript::new(dir.path()).unwrap();
+        // A short scripted session: the user runs a command and sees its output.
+        transcript.record(b"echo hi\n").unwrap();
+        transcript.record(b"hi\n").unwrap();
+        transcript.record(b"exit\n").unwrap();
+
+        assert_eq!(transcript.path().parent(), Some(dir.path()));
+        assert_eq!(
+            fs::read_to_string(transcript.path()).unwrap(),
+            "echo hi\nhi\nexit\n"
+        );
+        let mode = fs::metadata(transcript.path())
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -7,49 +7,169 @@ mod error;
 mod exec;
 
 pub use error::Error;
+pub use exec::{ExecConfig, CTR_PATH, DEFAULT_ALLOWED_ENV};
 
+use actix_web::dev::Service;
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, CONTENT_TYPE};
+use actix_web::http::Method;
 use actix_web::{
-    body::BoxBody, error::ResponseError, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+    body::BoxBody, error::ResponseError, web, App, HttpMessage, HttpRequest, HttpResponse,
+    HttpServer, Responder,
+};
+use bottlerocket_release::PartialBottlerocketRelease;
+use datastore::filesystem::SymlinkCheck;
+use datastore::serialization::to_pairs_with_prefix;
+use datastore::{
+    Committed, DatastoreValue, FilesystemDataStore, Key, KeyType, TransactionLocks, Value,
 };
-use datastore::{serialize_scalar, Committed, FilesystemDataStore, Key, KeyType, Value};
 use error::Result;
 use fs2::FileExt;
+use futures_util::{future, stream, StreamExt};
 use http::StatusCode;
-use log::info;
+use log::{debug, error, info, warn};
 use model::ephemeral_storage::{Bind, Init};
-use model::{ConfigurationFiles, Model, Report, Services, Settings};
+use model::generator::RawSettingsGenerator;
+use model::revision::ModelRevision;
+use model::{ConfigurationFiles, Report, Services, Settings};
 use nix::unistd::{chown, Gid};
+use rand::{thread_rng, Rng};
+use semver::Version;
 use serde::{Deserialize, Serialize};
 use snafu::{ensure, OptionExt, ResultExt};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
+use std::ffi::OsStr;
 use std::fs::{set_permissions, File, Permissions};
+use std::net::SocketAddr;
 use std::os::unix::fs::PermissionsExt;
 use std::os::unix::process::ExitStatusExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, ExitStatus, Output, Stdio};
 use std::sync;
+use std::thread;
+use std::time::Duration;
 use thar_be_updates::status::{UpdateStatus, UPDATE_LOCKFILE};
+use tokio::io::AsyncReadExt;
 use tokio::process::Command as AsyncCommand;
+use tokio_util::io::ReaderStream;
 
 const BLOODHOUND_BIN: &str = "/usr/bin/bloodhound";
 const BLOODHOUND_K8S_CHECKS: &str = "/usr/libexec/cis-checks/kubernetes";
 const BLOODHOUND_FIPS_CHECKS: &str = "/usr/libexec/fips-checks/bottlerocket";
 
+/// ReportConfig holds the paths the server uses to generate reports with bloodhound.
+#[derive(Debug, Clone)]
+pub struct ReportConfig {
+    /// The bloodhound binary that runs checks and produces reports.
+    pub bloodhound_bin: PathBuf,
+    /// The checks run for the Kubernetes CIS report.
+    pub k8s_checks: PathBuf,
+    /// The checks run for the FIPS report.
+    pub fips_checks: PathBuf,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        Self {
+            bloodhound_bin: BLOODHOUND_BIN.into(),
+            k8s_checks: BLOODHOUND_K8S_CHECKS.into(),
+            fips_checks: BLOODHOUND_FIPS_CHECKS.into(),
+        }
+    }
+}
+
+/// A report the server can generate with bloodhound.
+struct ReportType {
+    name: &'static str,
+    description: &'static str,
+    /// The values bloodhound accepts for this report's '-f' argument; the first is the default.
+    formats: &'static [&'static str],
+}
+
+/// The reports supported by this host.  Add each report here when adding a new handler.
+const REPORT_TYPES: &[ReportType] = &[
+    ReportType {
+        name: "cis",
+        description: "CIS Bottlerocket Benchmark",
+        formats: &["text", "json"],
+    },
+    ReportType {
+        name: "fips",
+        description: "FIPS Security Policy",
+        formats: &["text", "json"],
+    },
+];
+
+/// The number of older data store versions kept for rollback by /datastore/compact, unless the
+/// caller asks otherwise.
+const DEFAULT_COMPACT_KEEP: usize = 1;
+
+/// The maximum number of transactions whose diffs we'll return in one response; callers can page
+/// through the rest with the 'offset' query parameter.
+const MAX_TRANSACTION_DIFFS: usize = 50;
+
+/// The program we use to tell systemd the API socket is ready.
+const SYSTEMD_NOTIFY: &str = "systemd-notify";
+
+/// How many times we try socket setup steps that can fail transiently, like notifying systemd.
+const SETUP_ATTEMPTS: u32 = 5;
+
+/// How long we wait before retrying a failed socket setup step the first time.
+const SETUP_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The media type for settings encoded as CBOR, for constrained clients that prefer it to JSON.
+const CBOR_CONTENT_TYPE: &str = "application/cbor";
+
+/// The largest settings body we'll read, matching actix-web's default limit for JSON bodies.
+const SETTINGS_PAYLOAD_LIMIT: usize = 2 * 1024 * 1024;
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
+/// Runs the given socket setup step, retrying with backoff if it fails, in case the failure is
+/// transient, e.g. systemd being briefly busy.  Each delay is doubled from the last, plus some
+/// jitter.  This blocks the thread, so it's only meant for setup before the server starts.
+fn retry_setup<T, F>(description: &str, mut step: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let mut delay = SETUP_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match step() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < SETUP_ATTEMPTS => {
+                warn!(
+                    "Failed to {} (attempt {} of {}), retrying: {}",
+                    description, attempt, SETUP_ATTEMPTS, e
+                );
+                let jitter = thread_rng().gen_range(0..=delay.as_millis() as u64 / 2);
+                thread::sleep(delay + Duration::from_millis(jitter));
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Runs the given systemd-notify program to tell systemd we're ready.
+fn systemd_notify<P: AsRef<OsStr>>(program: P) -> Result<()> {
+    ensure!(
+        Command::new(program)
+            .arg("--ready")
+            .arg("--no-block")
+            .status()
+            .context(error::SystemdNotifySnafu)?
+            .success(),
+        error::SystemdNotifyStatusSnafu
+    );
+    Ok(())
+}
+
 // sd_notify helper
 fn notify_unix_socket_ready() -> Result<()> {
     if env::var_os("NOTIFY_SOCKET").is_some() {
-        ensure!(
-            Command::new("systemd-notify")
-                .arg("--ready")
-                .arg("--no-block")
-                .status()
-                .context(error::SystemdNotifySnafu)?
-                .success(),
-            error::SystemdNotifyStatusSnafu
-        );
+        retry_setup("notify systemd", || systemd_notify(SYSTEMD_NOTIFY))?;
         env::remove_var("NOTIFY_SOCKET");
     } else {
         info!("NOTIFY_SOCKET not set, not calling systemd-notify");
@@ -57,29 +177,133 @@ fn notify_unix_socket_ready() -> Result<()> {
     Ok(())
 }
 
+// Request IDs
+
+/// The header clients can use to pass an ID for their request, so it can be correlated with our
+/// log lines.  We generate an ID if they don't, and return it in the same response header.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The header in commit responses saying whether any of the committed settings need a reboot to
+/// take effect, "true" or "false".
+const REBOOT_REQUIRED_HEADER: &str = "x-reboot-required";
+
+/// The longest request ID we'll accept from a client; longer IDs are replaced with our own.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The ID of the current request, stored in the request's extensions by the request ID middleware
+/// so handlers can include it in their log lines.
+#[derive(Debug, Clone)]
+struct RequestId(String);
+
+/// Returns the ID the client gave in the request ID header, if it's reasonable to log, or a new
+/// random ID otherwise.
+fn incoming_or_new_request_id(headers: &HeaderMap) -> String {
+    let incoming = headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.chars().all(|c| c.is_ascii_graphic())
+        });
+    match incoming {
+        Some(id) => id.to_string(),
+        None => format!("{:016x}", thread_rng().gen::<u64>()),
+    }
+}
+
+/// Returns the ID of the given request for use in log lines.
+fn request_id(req: &HttpRequest) -> String {
+    req.extensions()
+        .get::<RequestId>()
+        .map(|id| id.0.clone())
+        .unwrap_or_default()
+}
+
 // Router
 
+/// Where the server listens for requests.
+#[derive(Debug, Clone)]
+pub enum BindAddress {
+    /// A Unix-domain socket at the given path.  If a group is given, the socket's group owner is
+    /// set to it so its members can use the API.  This is what's used in production.
+    Unix { path: PathBuf, gid: Option<Gid> },
+    /// A TCP address, for integration tests and local development.  There's no access control,
+    /// so this should only be a loopback address.
+    Tcp(SocketAddr),
+}
+
+/// ServerConfig holds everything serve needs to start the server.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Where the server listens for requests.
+    pub bind: BindAddress,
+    /// The data store directory, e.g. /var/lib/bottlerocket/datastore/current.
+    pub datastore_path: PathBuf,
+    /// The number of worker threads handling requests.
+    pub threads: usize,
+    pub exec_config: ExecConfig,
+    pub report_config: ReportConfig,
+    /// If set, writes that would start more than this many pending transactions are refused.
+    pub max_transactions: Option<usize>,
+    /// If set, each commit is recorded in this file as a line of JSON.
+    pub audit_log: Option<PathBuf>,
+    /// The only directory /tx/seed reads settings files from.
+    pub seed_dir: PathBuf,
+    /// Run to render config files and restart services for changed settings.
+    pub config_applier: PathBuf,
+    /// If true, requests that could change the system are refused, and the data store is opened
+    /// read-only.
+    pub read_only: bool,
+}
+
+/// Returns true if the given request could change the system, so it should be refused by a
+/// read-only server.  Only GET and HEAD requests are safe, along with value validation, which
+/// needs a body but changes nothing; /exec is refused regardless since the command it runs could
+/// do anything.
+fn is_mutating(method: &Method, path: &str) -> bool {
+    let safe = matches!(*method, Method::GET | Method::HEAD) || path == "/settings/validate-value";
+    !safe || path == "/exec"
+}
+
 /// This is the primary interface of the module.  It defines the server and application that actix
 /// spawns for requests.  It creates a shared datastore handle that can be used by handler methods
-/// to interface with the controller.
-pub async fn serve<P1, P2, P3>(
-    socket_path: P1,
-    datastore_path: P2,
-    threads: usize,
-    socket_gid: Option<Gid>,
-    exec_socket_path: P3,
-) -> Result<()>
-where
-    P1: AsRef<Path>,
-    P2: AsRef<Path>,
-    P3: Into<PathBuf>,
-{
+/// to interface with the controller.  See ServerConfig for what can be configured.
+pub async fn serve(config: ServerConfig) -> Result<()> {
+    let ServerConfig {
+        bind,
+        datastore_path,
+        threads,
+        exec_config,
+        report_config,
+        max_transactions,
+        audit_log,
+        seed_dir,
+        config_applier,
+        read_only,
+    } = config;
+
     // SharedData gives us a convenient way to make data available to handler methods when it
     // doesn't come from the request itself.  It's easier than the ownership tricks required to
     // pass parameters to the handler methods.
+    let mut datastore = FilesystemDataStore::new(&datastore_path);
+    if let Some(max) = max_transactions {
+        datastore = datastore.with_max_transactions(max);
+    }
+    if read_only {
+        datastore = datastore.read_only();
+    }
     let shared_data = web::Data::new(SharedData {
-        ds: sync::RwLock::new(FilesystemDataStore::new(datastore_path)),
-        exec_socket_path: exec_socket_path.into(),
+        ds: sync::RwLock::new(datastore),
+        datastore_path,
+        exec_config,
+        report_config,
+        tx_locks: sync::Mutex::new(TransactionLocks::new()),
+        config_applier,
+        update_dispatcher: controller::UPDATE_DISPATCHER_PATH.into(),
+        systemctl: controller::SYSTEMCTL_PATH.into(),
+        audit_log,
+        seed_dir,
     });
 
     let http_server = HttpServer::new(move || {
@@ -87,13 +311,75 @@ where
             // This makes the data store available to API methods merely by having a Data
             // parameter.
             .app_data(shared_data.clone())
+            // In read-only mode, refuse anything that could make changes before it gets to a
+            // handler.
+            .wrap_fn(move |req, srv| {
+                let response = if read_only && is_mutating(req.method(), req.path()) {
+                    Err(req)
+                } else {
+                    Ok(srv.call(req))
+                };
+                async move {
+                    match response {
+                        Ok(response) => response.await,
+                        Err(req) => {
+                            let error = error::ReadOnlyServerSnafu {
+                                method: req.method().as_str(),
+                                path: req.path(),
+                            }
+                            .build();
+                            warn!("[{}] {}", request_id(req.request()), error);
+                            Ok(req.into_response(error.error_response()))
+                        }
+                    }
+                }
+            })
+            // Give each request an ID, which handlers can include in log lines, and which is
+            // returned to the client so it can correlate its requests with our logs.
+            .wrap_fn(|req, srv| {
+                let id = incoming_or_new_request_id(req.headers());
+                debug!("[{}] {} {}", id, req.method(), req.path());
+                req.extensions_mut().insert(RequestId(id.clone()));
+                let response = srv.call(req);
+                async move {
+                    let mut response = response.await?;
+                    // Handlers return most failures rather than logging them, so log them here,
+                    // where we know which request they belong to.
+                    if let Some(e) = response.response().error() {
+                        info!("[{}] Request failed with {}: {}", id, response.status(), e);
+                    }
+                    debug!("[{}] Responding with {}", id, response.status());
+                    // The ID was either a valid header value given to us, or generated as hex.
+                    if let Ok(value) = HeaderValue::from_str(&id) {
+                        response
+                            .headers_mut()
+                            .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                    }
+                    Ok(response)
+                }
+            })
             // Retrieve the full API model; not all data is writable, so we only support GET.
             .route("/", web::get().to(get_model))
             .service(
                 web::scope("/settings")
+                    .app_data(web::PayloadConfig::new(SETTINGS_PAYLOAD_LIMIT))
                     .route("", web::get().to(get_settings))
                     .route("", web::patch().to(patch_settings))
-                    .route("/keypair", web::patch().to(patch_settings_key_pair)),
+                    .route("", web::delete().to(delete_settings))
+                    .route("/keypair", web::patch().to(patch_settings_key_pair))
+                    .route("/effective", web::get().to(get_effective_settings))
+                    .route("/defaults", web::get().to(get_setting_defaults))
+                    .route("/provenance", web::get().to(get_setting_provenance))
+                    .route("/exists", web::get().to(get_keys_exist))
+                    .route("/count", web::get().to(get_settings_count))
+                    .route("/weak", web::get().to(get_weak_settings))
+                    .route("/reset", web::post().to(reset_settings))
+                    .route("/key/transactions", web::get().to(get_transactions_for_key))
+                    .route("/key/compare", web::get().to(compare_key))
+                    .route("/schema", web::get().to(get_settings_schema))
+                    .route("/model-version", web::get().to(get_model_revision))
+                    .route("/validate-value", web::post().to(validate_setting_value))
+                    .route("/sections", web::get().to(get_setting_sections)),
             )
             .service(
                 // Transaction support
@@ -102,27 +388,77 @@ where
                     .route("", web::get().to(get_transaction))
                     .route("", web::delete().to(delete_transaction))
                     .route("/commit", web::post().to(commit_transaction))
+                    .route("/plan", web::get().to(plan_commit))
+                    .route("/constraints", web::get().to(get_constraint_checks))
+                    .route("/touch", web::post().to(touch_transaction))
+                    .route("/bulk", web::post().to(bulk_stage_transactions))
+                    .route("/bulk", web::delete().to(bulk_delete_transactions))
+                    .route("/seed", web::post().to(seed_transaction))
+                    .route("/lock", web::post().to(lock_transaction))
+                    .route("/unlock", web::post().to(unlock_transaction))
+                    .route("/annotate", web::post().to(annotate_transaction))
                     .route("/apply", web::post().to(apply_changes))
                     .route(
                         "/commit_and_apply",
                         web::post().to(commit_transaction_and_apply),
+                    )
+                    .route(
+                        "/validate_commit_and_apply",
+                        web::post().to(validate_commit_and_apply),
                     ),
             )
-            .service(web::scope("/os").route("", web::get().to(get_os_info)))
+            .service(
+                web::scope("/v2")
+                    .route("/tx/list", web::get().to(get_annotated_transaction_list))
+                    .route("/tx/diffs", web::get().to(get_transaction_diffs))
+                    .route(
+                        "/metadata/setting-generators/key",
+                        web::get().to(get_setting_generator),
+                    ),
+            )
+            .service(
+                web::scope("/os")
+                    .route("", web::get().to(get_os_info))
+                    .route("/fields", web::get().to(get_os_fields)),
+            )
+            .service(
+                web::scope("/datastore")
+                    .route("/version", web::get().to(get_datastore_version))
+                    .route(
+                        "/version/{version}/settings",
+                        web::get().to(get_settings_at_version),
+                    )
+                    .route("/key", web::get().to(get_raw_key))
+                    .route("/compact", web::post().to(compact_datastore))
+                    .route("/stats", web::get().to(get_datastore_stats))
+                    .route("/generation", web::get().to(get_datastore_generation))
+                    .route("/symlinks", web::get().to(check_datastore_symlinks))
+                    .route("/symlinks", web::post().to(repair_datastore_symlinks)),
+            )
             .service(
                 web::scope("/metadata")
+                    .route("", web::patch().to(patch_metadata))
                     .route("/affected-services", web::get().to(get_affected_services))
+                    .route("/impact", web::get().to(get_change_impact))
+                    .route("/description", web::get().to(get_descriptions))
+                    .route("/description", web::patch().to(set_descriptions))
+                    .route("/export", web::get().to(export_metadata))
+                    .route("/key", web::get().to(get_key_metadata))
                     .route("/setting-generators", web::get().to(get_setting_generators))
                     .route("/templates", web::get().to(get_templates)),
             )
             .service(web::scope("/services").route("", web::get().to(get_services)))
             .service(
                 web::scope("/configuration-files")
-                    .route("", web::get().to(get_configuration_files)),
+                    .route("", web::get().to(get_configuration_files))
+                    .route("/validate", web::get().to(validate_configuration_files))
+                    .route("/{name}/context", web::get().to(get_template_context)),
             )
             .service(
                 web::scope("/actions")
                     .route("/reboot", web::post().to(reboot))
+                    .route("/sweep-weak", web::post().to(sweep_weak_settings))
+                    .route("/sweep-expired", web::post().to(sweep_expired_settings))
                     .route("/refresh-updates", web::post().to(refresh_updates))
                     .route("/prepare-update", web::post().to(prepare_update))
                     .route("/activate-update", web::post().to(activate_update))
@@ -149,25 +485,38 @@ where
             .service(
                 web::scope("/report")
                     .route("", web::get().to(list_reports))
+                    .route("", web::post().to(get_combined_report))
                     .route("/cis", web::get().to(get_cis_report))
                     .route("/fips", web::get().to(get_fips_report)),
             )
     })
-    .workers(threads)
-    .bind_uds(socket_path.as_ref())
-    .context(error::BindSocketSnafu {
-        path: socket_path.as_ref(),
-    })?;
-
-    // If the socket needs to be chowned to a group to grant further access, that can be passed
-    // as a parameter.
-    if let Some(gid) = socket_gid {
-        chown(socket_path.as_ref(), None, Some(gid)).context(error::SetGroupSnafu { gid })?;
-    }
+    .workers(threads);
+
+    let http_server = match bind {
+        BindAddress::Unix { path, gid } => {
+            let http_server = http_server
+                .bind_uds(&path)
+                .context(error::BindSocketSnafu { path: &path })?;
+
+            // If the socket needs to be chowned to a group to grant further access, that can be
+            // passed as a parameter.
+            if let Some(gid) = gid {
+                retry_setup("set group owner on the API socket", || {
+                    chown(&path, None, Some(gid)).context(error::SetGroupSnafu { gid })
+                })?;
+            }
 
-    let mode = 0o0660;
-    let perms = Permissions::from_mode(mode);
-    set_permissions(socket_path.as_ref(), perms).context(error::SetPermissionsSnafu { mode })?;
+            let mode = 0o0660;
+            retry_setup("set permissions on the API socket", || {
+                let perms = Permissions::from_mode(mode);
+                set_permissions(&path, perms).context(error::SetPermissionsSnafu { mode })
+            })?;
+            http_server
+        }
+        BindAddress::Tcp(addr) => http_server
+            .bind(addr)
+            .context(error::BindTcpSnafu { addr })?,
+    };
 
     // Notify system manager the UNIX socket has been initialized, so other service units can proceed
     notify_unix_socket_ready()?;
@@ -181,8 +530,8 @@ where
 
 /// Returns all data in the API model.  If you pass a 'prefix' query string, only field names
 /// starting with that prefix will be included.  For example, a prefix of "settings." only returns
-/// settings.  Returns a ModelResponse, which contains a serde_json Value instead of a Model so
-/// that we can include only matched fields; this is necessary because the 'os' field contains a
+/// settings.  Returns a ModelResponse, which contains a PartialModel instead of a Model so that
+/// we can include only matched fields; this is necessary because the 'os' field contains a
 /// BottlerocketRelease whose fields aren't optional.  (Its other users depend on those fields.)
 async fn get_model(
     query: web::Query<HashMap<String, String>>,
@@ -197,25 +546,25 @@ async fn get_model(
         return get_model_prefix(data, prefix).await;
     }
 
-    let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+    let datastore = data.read_datastore().await?;
 
-    // Fetch all the data and build a Model.
-    let settings = Some(controller::get_settings(&*datastore, &Committed::Live)?);
+    // Fetch all the data and build a Model.  If we're given a transaction, the settings are what
+    // they'd be if it were committed, so config files can be rendered with them before a commit.
+    let settings = match query.get("tx") {
+        Some(transaction) => controller::get_effective_settings(&*datastore, transaction)?,
+        None => controller::get_settings(&*datastore, &Committed::Live)?,
+    };
+    let settings = Some(settings);
     let services = Some(controller::get_services(&*datastore)?);
     let configuration_files = Some(controller::get_configuration_files(&*datastore)?);
-    let os = Some(controller::get_os_info()?);
+    let os = Some(controller::get_os_info()?.into());
 
-    let model = Model {
+    Ok(ModelResponse(PartialModel {
         settings,
         services,
         configuration_files,
         os,
-    };
-
-    // Turn the Model into a Value so we can match the type used when fetching by prefix.
-    let val = serde_json::to_value(model).expect("struct to value can't fail");
-
-    Ok(ModelResponse(val))
+    }))
 }
 
 /// Helper for get_model that handles the case of matching a user-specified prefix.
@@ -224,7 +573,7 @@ async fn get_model_prefix(data: web::Data<SharedData>, prefix: &str) -> Result<M
         return error::EmptyInputSnafu { input: "prefix" }.fail();
     }
 
-    let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+    let datastore = data.read_datastore().await?;
 
     // Fetch all the data.
     // Note that we don't add a prefix (for example "settings.") to the given prefix before passing
@@ -234,154 +583,600 @@ async fn get_model_prefix(data: web::Data<SharedData>, prefix: &str) -> Result<M
     let services = controller::get_services_prefix(&*datastore, prefix)?;
     let configuration_files = controller::get_configuration_files_prefix(&*datastore, prefix)?;
 
-    // Build a Model, but exclude 'os' for now.  BottlerocketRelease's fields aren't Option (for
-    // good reason - its other users rely on them) so we can't make a BottlerocketRelease with only
-    // some fields based on a prefix match.
-    let model = Model {
+    // If the user gave a prefix unrelated to os, this will return None and so we'll leave 'os' out
+    // of the model.  Otherwise it'll give us back a PartialBottlerocketRelease with only the
+    // fields matching the prefix.
+    let os = controller::get_os_prefix(prefix)?;
+
+    Ok(ModelResponse(PartialModel {
         settings,
         services,
         configuration_files,
-        os: None,
-    };
-
-    // Turn the Model into a Value so we can insert an "os" value with filtered fields.
-    let mut val = serde_json::to_value(model).expect("struct to value can't fail");
-
-    // If the user gave a prefix unrelated to os, this will return None and so we'll leave the None
-    // in the model.  Otherwise it'll give us back a Value that's like a BottlerocketRelease but
-    // with only the fields matching the prefix.
-    if let Some(os) = controller::get_os_prefix(prefix)? {
-        // Structs are Objects in serde_json, which have a map of field -> value inside.  We
-        // destructure to get it by value, instead of as_object() which gives references.
-        let mut map = match val {
-            Value::Object(map) => map,
-            _ => panic!("structs are always objects"),
-        };
-        // Insert the filtered result and turn the map back into a Value.
-        map.insert("os".to_string(), os);
-        val = map.into();
-    }
-
-    Ok(ModelResponse(val))
+        os,
+    }))
 }
 
 // actix-web doesn't support Query for enums, so we use a HashMap and check for the expected keys
 // ourselves.
 /// Return the live settings from the data store; if 'keys' or 'prefix' are specified in query
-/// parameters, return the subset of matching settings.
+/// parameters, return the subset of matching settings.  If 'format' is 'env', the settings are
+/// returned as an environment file rather than JSON; see controller::settings_env_file.
 async fn get_settings(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
-) -> Result<SettingsResponse> {
-    let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+) -> Result<HttpResponse> {
+    let env = match query.get("format").map(String::as_str) {
+        None | Some("json") => false,
+        Some("env") => true,
+        Some(given) => return error::InvalidSettingsFormatSnafu { given }.fail(),
+    };
+    let datastore = data.read_datastore().await?;
 
     let settings = if let Some(keys_str) = query.get("keys") {
         let keys = comma_separated("keys", keys_str)?;
         controller::get_settings_keys(&*datastore, &keys, &Committed::Live)
-    } else if let Some(mut prefix) = query.get("prefix") {
-        if prefix.is_empty() {
-            return error::EmptyInputSnafu { input: "prefix" }.fail();
-        }
-        // When retrieving from /settings, the settings prefix is implied, so we add it if it
-        // wasn't given.
-        let with_prefix = format!("settings.{}", prefix);
-        if !prefix.starts_with("settings") {
-            prefix = &with_prefix;
-        }
+    } else if let Some(prefix) = settings_prefix(&query)? {
         controller::get_settings_prefix(&*datastore, prefix, &Committed::Live)
             .map(|opt| opt.unwrap_or_default())
     } else {
         controller::get_settings(&*datastore, &Committed::Live)
     }?;
 
+    if env {
+        let env_file = controller::settings_env_file(&settings)?;
+        return Ok(HttpResponse::Ok().content_type("text/plain").body(env_file));
+    }
+    Ok(SettingsResponse(settings).respond_to(&req))
+}
+
+/// Return the variant's default settings, optionally limited to the given 'keys' or 'prefix', like
+/// GET /settings.  Settings that have no default aren't included.
+async fn get_setting_defaults(
+    query: web::Query<HashMap<String, String>>,
+) -> Result<SettingsResponse> {
+    let defaults = controller::read_setting_defaults(controller::DEFAULTS_TOML_PATH)?;
+
+    let settings = if let Some(keys_str) = query.get("keys") {
+        let keys = comma_separated("keys", keys_str)?;
+        controller::get_settings_keys(&defaults, &keys, &Committed::Live)?
+    } else {
+        let prefix = settings_prefix(&query)?.unwrap_or_else(|| "settings.".to_string());
+        controller::get_settings_prefix(&defaults, prefix, &Committed::Live)?.unwrap_or_default()
+    };
+
     Ok(SettingsResponse(settings))
 }
 
-/// Apply the requested settings to the pending data store
+/// Apply the requested settings to the pending data store.  If the 'variant' query parameter is
+/// given, the settings are only applied if it matches the running variant.  If 'protect_generated'
+/// is "true", the settings are refused if they'd write over keys managed by a setting generator.
+/// The settings are read as CBOR if the Content-Type is application/cbor, and JSON otherwise.
 async fn patch_settings(
-    settings: web::Json<Settings>,
+    req: HttpRequest,
+    body: web::Bytes,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
 ) -> Result<HttpResponse> {
+    let input: serde_json::Value = if is_cbor(req.headers()) {
+        ciborium::de::from_reader(&body[..]).context(error::SettingsFromCborSnafu)?
+    } else {
+        serde_json::from_slice(&body).context(error::SettingsFromJsonSnafu)?
+    };
+    let settings = serde_json::from_value(input.clone()).context(error::SettingsFromJsonSnafu)?;
+    if let Some(variant) = query.get("variant") {
+        check_variant(variant, &controller::get_os_info()?.variant_id)?;
+    }
+    if strict(&query) {
+        let input_pairs = to_pairs_with_prefix("settings", &input)
+            .context(error::DataStoreSerializationSnafu { given: "input" })?;
+        check_modeled_keys(&input_pairs, &settings)?;
+    }
     let transaction = transaction_name(&query);
-    let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
+    let mut datastore = data.write_datastore().await?;
+    data.check_transaction_lock(transaction, &query)?;
+    if query.get("protect_generated").map(String::as_str) == Some("true") {
+        let keys = controller::find_generated_keys(&*datastore, &settings)?;
+        ensure!(keys.is_empty(), error::GeneratedKeysSnafu { keys });
+    }
     controller::set_settings(&mut *datastore, &settings, transaction)?;
+    annotate_from_query(&mut datastore, transaction, &query)?;
     Ok(HttpResponse::NoContent().finish()) // 204
 }
 
-// Apply the requested settings in Key Value pair.
+// Apply the requested settings in Key Value pair.  By default, no settings are staged if any pair
+// is invalid.  If the 'partial' query parameter is "true", each pair is checked on its own, the
+// valid ones are staged, and the result for each pair is returned.
 async fn patch_settings_key_pair(
+    req: HttpRequest,
     settings: web::Json<SetKeyPairSettings>,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
 ) -> Result<HttpResponse> {
-    // Convert to a Map of Key Value pairs.
-    let settings_key_pair_map = construct_key_pair_map(&settings.request_payload)?;
     let transaction = transaction_name(&query);
-    let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
-    // We massage the values in the input key pair map.
-    // The data store deserialization code understands how to turn the key names
-    // (a.b.c) and serialized values into the nested Settings structure.
-    let settings_model = datastore::deserialization::from_map(&settings_key_pair_map)
-        .context(error::DeserializeMapSnafu)?;
-    controller::set_settings(&mut *datastore, &settings_model, transaction)?;
-    Ok(HttpResponse::NoContent().finish()) // 204
+    let mut datastore = data.write_datastore().await?;
+    data.check_transaction_lock(transaction, &query)?;
+    let response = if query.get("partial").map(String::as_str) == Some("true") {
+        let results = stage_key_pairs_partial(
+            &mut *datastore,
+            &settings.request_payload,
+            transaction,
+            strict(&query),
+        )?;
+        KeyPairResultsResponse(results).respond_to(&req)
+    } else {
+        stage_key_pairs(
+            &mut *datastore,
+            &settings.request_payload,
+            transaction,
+            strict(&query),
+        )?;
+        HttpResponse::NoContent().finish() // 204
+    };
+    annotate_from_query(&mut datastore, transaction, &query)?;
+    Ok(response)
+}
+
+/// Get a JSON Schema of the API model, including settings, for tooling and editors.
+async fn get_settings_schema() -> Result<SchemaResponse> {
+    Ok(SchemaResponse(model::schema::model_schema()))
+}
+
+/// Get the revision of the API model, so clients can tell when it changes, e.g. to invalidate a
+/// cached schema after the settings plugin is updated.
+async fn get_model_revision() -> Result<ModelRevisionResponse> {
+    let os = controller::get_os_info()?;
+    Ok(ModelRevisionResponse(ModelRevision::for_release(&os)))
+}
+
+/// Checks whether the given value could be set for the given key, by deserializing it into the
+/// settings model.  Nothing is changed; the response says whether the value is valid, and why not.
+async fn validate_setting_value(
+    input: web::Json<ValidateValueRequest>,
+) -> Result<ValueValidationResponse> {
+    let outcome = match check_setting_value(&input.key, &input.value) {
+        Ok(()) => KeyPairOutcome::Ok,
+        Err(e) => KeyPairOutcome::Error {
+            reason: e.to_string(),
+        },
+    };
+    Ok(ValueValidationResponse(outcome))
+}
+
+/// Removes all live settings starting with the given 'prefix', like "settings.kubernetes.",
+/// returning the removed keys.  Removals can't be staged in a transaction, so this changes live
+/// settings directly, like a commit would; the removal is audited, and configuration files and
+/// services affected by the removed keys are updated.
+async fn delete_settings(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<ChangedKeysResponse> {
+    let id = request_id(&req);
+    let prefix = match query.get("prefix") {
+        Some(prefix) if !prefix.is_empty() => prefix,
+        Some(_) => return error::EmptyInputSnafu { input: "prefix" }.fail(),
+        None => return error::MissingInputSnafu { input: "prefix" }.fail(),
+    };
+    let mut datastore = data.write_datastore().await?;
+    let removed = controller::unset_settings_prefix(&mut *datastore, prefix)?;
+    data.audit_removal(&id, &removed);
+
+    if !removed.is_empty() {
+        info!(
+            "[{}] Removed {} keys under '{}', applying changes",
+            id,
+            removed.len(),
+            prefix
+        );
+        let key_names = removed.iter().map(|k| k.name()).collect();
+        controller::apply_changes(&data.config_applier, Some(&key_names))?;
+    }
+    Ok(ChangedKeysResponse(removed))
+}
+
+/// Resets the settings starting with the given 'prefix' to their defaults in the transaction named
+/// by 'tx', or "default", so committing it undoes customizations.  If 'regenerate' is "true",
+/// generated settings without a default are staged as weak, so they're generated again on the
+/// next boot.  Returns what was staged for each key.  If any settings under the prefix have no
+/// default, nothing is staged and the error names them.
+async fn reset_settings(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<ResetOutcomeResponse> {
+    let prefix = match query.get("prefix") {
+        Some(prefix) if !prefix.is_empty() => prefix,
+        Some(_) => return error::EmptyInputSnafu { input: "prefix" }.fail(),
+        None => return error::MissingInputSnafu { input: "prefix" }.fail(),
+    };
+    let transaction = transaction_name(&query);
+    let regenerate = query.get("regenerate").map(String::as_str) == Some("true");
+    let defaults = controller::read_setting_defaults(controller::DEFAULTS_TOML_PATH)?;
+
+    let mut datastore = data.write_datastore().await?;
+    data.check_transaction_lock(transaction, &query)?;
+    let outcome = controller::reset_settings_prefix(
+        &mut *datastore,
+        &defaults,
+        prefix,
+        transaction,
+        regenerate,
+    )?;
+    Ok(ResetOutcomeResponse(outcome))
+}
+
+/// Return the names of the top-level sections of the live settings.
+async fn get_setting_sections(data: web::Data<SharedData>) -> Result<SettingSectionsResponse> {
+    let datastore = data.read_datastore().await?;
+    let sections = controller::list_setting_sections(&*datastore)?;
+    Ok(SettingSectionsResponse(sections))
 }
 
 async fn get_transaction_list(data: web::Data<SharedData>) -> Result<TransactionListResponse> {
-    let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+    let datastore = data.read_datastore().await?;
     let data = controller::list_transactions(&*datastore)?;
     Ok(TransactionListResponse(data))
 }
 
+/// Lists the checks that can be run against a transaction before it's committed.
+async fn get_constraint_checks() -> ConstraintChecksResponse {
+    ConstraintChecksResponse(controller::CONSTRAINT_CHECKS)
+}
+
+/// Get the open transactions along with their annotations, if any.
+async fn get_annotated_transaction_list(
+    data: web::Data<SharedData>,
+) -> Result<AnnotatedTransactionListResponse> {
+    let datastore = data.read_datastore().await?;
+    let data = controller::list_annotated_transactions(&*datastore)?;
+    Ok(AnnotatedTransactionListResponse(data))
+}
+
+/// Annotate the given transaction, or the "default" transaction if unspecified, with the
+/// 'author' and 'description' query parameters.
+async fn annotate_transaction(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<HttpResponse> {
+    let transaction = transaction_name(&query);
+    let mut datastore = data.write_datastore().await?;
+    data.check_transaction_lock(transaction, &query)?;
+    controller::annotate_transaction(
+        &mut *datastore,
+        transaction,
+        query.get("author").map(String::as_str),
+        query.get("description").map(String::as_str),
+    )?;
+    Ok(HttpResponse::NoContent().finish()) // 204
+}
+
+/// Get the changes each open transaction would make to the live settings.  Transactions are
+/// returned in order of name; pass 'limit' (at most MAX_TRANSACTION_DIFFS, the default) and
+/// 'offset' query parameters to page through them.
+async fn get_transaction_diffs(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<TransactionDiffsResponse> {
+    let offset = optional_number(&query, "offset")?.unwrap_or(0);
+    let limit = optional_number(&query, "limit")?
+        .unwrap_or(MAX_TRANSACTION_DIFFS)
+        .min(MAX_TRANSACTION_DIFFS);
+
+    let datastore = data.read_datastore().await?;
+    let diffs = controller::get_transaction_diffs(&*datastore, offset, limit)?;
+    Ok(TransactionDiffsResponse(diffs))
+}
+
+/// Get the settings that committing the given transaction, or the "default" transaction if
+/// unspecified, would change, and the services and restart commands affected.  Nothing is changed.
+async fn plan_commit(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<CommitPlanResponse> {
+    let transaction = transaction_name(&query);
+    let datastore = data.read_datastore().await?;
+    let plan = controller::plan_commit(&*datastore, transaction)?;
+    Ok(CommitPlanResponse(plan))
+}
+
 /// Get any pending settings in the given transaction, or the "default" transaction if unspecified.
 async fn get_transaction(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
 ) -> Result<SettingsResponse> {
     let transaction = transaction_name(&query);
-    let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+    let datastore = data.read_datastore().await?;
     let data = controller::get_transaction(&*datastore, transaction)?;
     Ok(SettingsResponse(data))
 }
 
+/// Get what the live settings would be if the given transaction, or the "default" transaction if
+/// unspecified, were committed.  Nothing is changed.
+async fn get_effective_settings(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<SettingsResponse> {
+    let transaction = transaction_name(&query);
+    let datastore = data.read_datastore().await?;
+    let settings = controller::get_effective_settings(&*datastore, transaction)?;
+    Ok(SettingsResponse(settings))
+}
+
 /// Delete the given transaction, or the "default" transaction if unspecified.
 async fn delete_transaction(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
 ) -> Result<ChangedKeysResponse> {
     let transaction = transaction_name(&query);
-    let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
+    let mut datastore = data.write_datastore().await?;
+    data.check_transaction_lock(transaction, &query)?;
     let deleted = controller::delete_transaction(&mut *datastore, transaction)?;
+    data.transaction_locks().clear(transaction);
     Ok(ChangedKeysResponse(deleted))
 }
 
+/// Handles DELETE /tx/bulk, which deletes each of the transactions named in the comma-separated
+/// 'tx' query parameter, returning the outcome for each: the keys deleted from it, or why it
+/// couldn't be deleted.  Transactions that don't exist are reported with no keys.  If any
+/// transaction is locked by another holder, nothing is deleted.
+async fn bulk_delete_transactions(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<TransactionsDeletedResponse> {
+    let id = request_id(&req);
+    let transactions = query
+        .get("tx")
+        .context(error::MissingInputSnafu { input: "tx" })?;
+    let transactions = comma_separated("tx", transactions)?;
+    let mut datastore = data.write_datastore().await?;
+    for transaction in &transactions {
+        data.check_transaction_lock(transaction, &query)?;
+    }
+    let outcomes = controller::delete_transactions(&mut *datastore, &transactions)
+        .into_iter()
+        .map(|(transaction, result)| {
+            let outcome = match result {
+                Ok(deleted) => {
+                    data.transaction_locks().clear(&transaction);
+                    BulkDeleteOutcome::Ok {
+                        deleted: deleted.iter().map(|k| k.name().to_string()).collect(),
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        "[{}] Failed to delete transaction '{}': {}",
+                        id, transaction, e
+                    );
+                    BulkDeleteOutcome::Error {
+                        reason: e.to_string(),
+                    }
+                }
+            };
+            (transaction, outcome)
+        })
+        .collect();
+    Ok(TransactionsDeletedResponse(outcomes))
+}
+
+/// Stages settings into several transactions at once, given as a map of transaction name to
+/// settings, like the body of PATCH /settings.  Each transaction is checked and staged on its own,
+/// and the result for each is returned: the keys staged, or why it failed.  If the 'atomic' query
+/// parameter is "true", nothing is staged unless every transaction's settings are valid.
+/// 'strict' and 'holder' apply to every transaction, like for PATCH /settings.
+async fn bulk_stage_transactions(
+    input: web::Json<BTreeMap<String, serde_json::Value>>,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<BulkStageResponse> {
+    ensure!(
+        !input.is_empty(),
+        error::EmptyInputSnafu {
+            input: "transactions"
+        }
+    );
+    let check_settings = |value: &serde_json::Value| -> Result<(Settings, BTreeSet<String>)> {
+        let settings: Settings =
+            serde_json::from_value(value.clone()).context(error::SettingsFromJsonSnafu)?;
+        let input_pairs = to_pairs_with_prefix("settings", value)
+            .context(error::DataStoreSerializationSnafu { given: "input" })?;
+        if strict(&query) {
+            check_modeled_keys(&input_pairs, &settings)?;
+        }
+        // Report the keys that will actually be staged, which are the modeled ones.
+        let staged = serde_json::to_value(&settings).context(error::SettingsToJsonSnafu)?;
+        let staged = to_pairs_with_prefix("settings", &staged)
+            .context(error::DataStoreSerializationSnafu { given: "Settings" })?
+            .into_keys()
+            .map(|key| key.to_string())
+            .collect();
+        Ok((settings, staged))
+    };
+
+    let mut datastore = data.write_datastore().await?;
+    let checked: BTreeMap<_, _> = input
+        .iter()
+        .map(|(tx, value)| {
+            let result = data
+                .check_transaction_lock(tx, &query)
+                .and_then(|()| check_settings(value));
+            (tx, result)
+        })
+        .collect();
+
+    if query.get("atomic").map(String::as_str) == Some("true") {
+        let reasons: Vec<_> = checked
+            .iter()
+            .filter_map(|(tx, result)| result.as_ref().err().map(|e| format!("{}: {}", tx, e)))
+            .collect();
+        ensure!(
+            reasons.is_empty(),
+            error::BulkStageInvalidSnafu {
+                reasons: reasons.join("; ")
+            }
+        );
+    }
+
+    let mut results = BTreeMap::new();
+    for (tx, result) in checked {
+        let outcome = match result.and_then(|(settings, staged)| {
+            controller::set_settings(&mut *datastore, &settings, tx)?;
+            Ok(staged)
+        }) {
+            Ok(changed) => BulkStageOutcome::Ok { changed },
+            Err(e) => BulkStageOutcome::Error {
+                reason: e.to_string(),
+            },
+        };
+        results.insert(tx.clone(), outcome);
+    }
+    Ok(BulkStageResponse(results))
+}
+
+/// Locks the given transaction, or the "default" transaction if unspecified, for the holder named
+/// by the 'holder' query parameter.  Until it's unlocked, committed, or deleted, requests that
+/// change the transaction are refused unless they give the same 'holder'.
+async fn lock_transaction(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<HttpResponse> {
+    let transaction = transaction_name(&query);
+    let holder = lock_holder(&query)?;
+    let _datastore = data.write_datastore().await?;
+    data.transaction_locks()
+        .acquire(transaction, holder)
+        .context(error::DataStoreSnafu {
+            op: "lock_transaction",
+        })?;
+    Ok(HttpResponse::NoContent().finish()) // 204
+}
+
+/// Unlocks the given transaction, or the "default" transaction if unspecified, if it's locked by
+/// the holder named by the 'holder' query parameter.  Unlocking a transaction that isn't locked
+/// is fine.
+async fn unlock_transaction(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<HttpResponse> {
+    let transaction = transaction_name(&query);
+    let holder = lock_holder(&query)?;
+    let _datastore = data.write_datastore().await?;
+    data.transaction_locks()
+        .release(transaction, holder)
+        .context(error::DataStoreSnafu {
+            op: "unlock_transaction",
+        })?;
+    Ok(HttpResponse::NoContent().finish()) // 204
+}
+
+/// Mark the given transaction, or the "default" transaction if unspecified, as in use, without
+/// changing its settings.
+async fn touch_transaction(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<HttpResponse> {
+    let transaction = transaction_name(&query);
+    let mut datastore = data.write_datastore().await?;
+    data.check_transaction_lock(transaction, &query)?;
+    controller::touch_transaction(&mut *datastore, transaction)?;
+    Ok(HttpResponse::NoContent().finish()) // 204
+}
+
+/// Stage the settings in the host file given by the 'path' query parameter into the given
+/// transaction, or the "default" transaction if unspecified.  The file must be in the seed
+/// directory, and is read as JSON or TOML based on its extension.
+async fn seed_transaction(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<HttpResponse> {
+    let path = query
+        .get("path")
+        .context(error::MissingInputSnafu { input: "path" })?;
+    let settings = controller::read_seed_settings(&data.seed_dir, path)?;
+    let transaction = transaction_name(&query);
+    let mut datastore = data.write_datastore().await?;
+    data.check_transaction_lock(transaction, &query)?;
+    controller::set_settings(&mut *datastore, &settings, transaction)?;
+    annotate_from_query(&mut datastore, transaction, &query)?;
+    Ok(HttpResponse::NoContent().finish()) // 204
+}
+
 /// Save settings changes from the given transaction, or the "default" transaction if unspecified,
-/// to the live data store.  Returns the list of changed keys.
+/// to the live data store.  If a 'target_tx' query parameter is given, the changes are instead
+/// moved into that pending transaction.  Returns the list of changed keys, and whether any of them
+/// need a reboot to take effect.
 async fn commit_transaction(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
-) -> Result<ChangedKeysResponse> {
+) -> Result<CommitResponse> {
+    let id = request_id(&req);
     let transaction = transaction_name(&query);
-    let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
+    let generation = expected_generation(&query)?;
+    let mut datastore = data.write_datastore().await?;
+    data.check_transaction_lock(transaction, &query)?;
+    if let Some(target) = query.get("target_tx") {
+        data.check_transaction_lock(target, &query)?;
+    }
+    if validate(&query) {
+        controller::validate_transaction(&*datastore, transaction)?;
+    }
 
-    let changes = controller::commit_transaction(&mut *datastore, transaction)?;
+    let (changes, reboot_required) = match query.get("target_tx") {
+        Some(target) => {
+            let changes = controller::promote_transaction(&mut *datastore, transaction, target)?;
+            info!(
+                "[{}] Moved {} keys from transaction '{}' to '{}'",
+                id,
+                changes.len(),
+                transaction,
+                target
+            );
+            // Changes moved into another transaction aren't live yet, so they can't need a reboot.
+            (changes, false)
+        }
+        None => {
+            let changes = controller::commit_transaction(&mut *datastore, transaction, generation)?;
+            data.audit_commit(&id, transaction, &changes.settings);
+            info!(
+                "[{}] Committed {} keys from transaction '{}'",
+                id,
+                changes.settings.len() + changes.metadata_only.len(),
+                transaction
+            );
+            // Only changed values can need a reboot, not changed metadata.
+            let reboot_required =
+                controller::commit_requires_reboot(&*datastore, &changes.settings)?;
+            (changes.all(), reboot_required)
+        }
+    };
+    data.transaction_locks().clear(transaction);
 
     if changes.is_empty() {
         return error::CommitWithNoPendingSnafu.fail();
     }
 
-    Ok(ChangedKeysResponse(changes))
+    Ok(CommitResponse {
+        changed: changes,
+        reboot_required,
+    })
 }
 
 /// Starts settings appliers for any changes that have been committed to the data store.  This
 /// updates config files, runs restart commands, etc.
-async fn apply_changes(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
+async fn apply_changes(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<HttpResponse> {
+    let id = request_id(&req);
     if let Some(keys_str) = query.get("keys") {
         let keys = comma_separated("keys", keys_str)?;
-        controller::apply_changes(Some(&keys))?;
+        info!("[{}] Applying changes to {} keys", id, keys.len());
+        controller::apply_changes(&data.config_applier, Some(&keys))?;
     } else {
-        controller::apply_changes(None as Option<&HashSet<&str>>)?;
+        info!("[{}] Applying all settings", id);
+        controller::apply_changes(&data.config_applier, None as Option<&HashSet<&str>>)?;
     }
 
     Ok(HttpResponse::NoContent().json(()))
@@ -389,24 +1184,126 @@ async fn apply_changes(query: web::Query<HashMap<String, String>>) -> Result<Htt
 
 /// Usually you want to apply settings changes you've committed, so this is a convenience method to
 /// perform both a commit and an apply.  Commits the given transaction, or the "default"
-/// transaction if unspecified.
+/// transaction if unspecified.  If 'reboot_if_required' is "true" and any of the changes need a
+/// reboot to take effect, the host is rebooted once they're applied.  The commit has already
+/// happened by then, so a failed reboot is logged rather than failing the request.
 async fn commit_transaction_and_apply(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
-) -> Result<ChangedKeysResponse> {
+) -> Result<CommitResponse> {
+    let id = request_id(&req);
     let transaction = transaction_name(&query);
-    let mut datastore = data.ds.write().ok().context(error::DataStoreLockSnafu)?;
+    let generation = expected_generation(&query)?;
+    let mut datastore = data.write_datastore().await?;
+    data.check_transaction_lock(transaction, &query)?;
+    if validate(&query) {
+        controller::validate_transaction(&*datastore, transaction)?;
+    }
 
-    let changes = controller::commit_transaction(&mut *datastore, transaction)?;
+    let changes = controller::commit_transaction(&mut *datastore, transaction, generation)?;
+    data.transaction_locks().clear(transaction);
+    data.audit_commit(&id, transaction, &changes.settings);
 
     if changes.is_empty() {
         return error::CommitWithNoPendingSnafu.fail();
     }
 
-    let key_names = changes.iter().map(|k| k.name()).collect();
-    controller::apply_changes(Some(&key_names))?;
+    let reboot_required = apply_commit(&id, transaction, &changes, &datastore, &data)?;
+    if reboot_required && query.get("reboot_if_required").map(String::as_str) == Some("true") {
+        info!("[{}] Committed changes require a reboot, rebooting", id);
+        if let Err(e) = reboot_host(&id, &data.systemctl) {
+            error!("[{}] Unable to reboot after commit: {}", id, e);
+        }
+    }
+    Ok(CommitResponse {
+        changed: changes.all(),
+        reboot_required,
+    })
+}
+
+/// Applies the settings whose values were changed by a commit, and returns whether any of them
+/// need a reboot to take effect.  Settings whose metadata changed don't need applying.
+fn apply_commit(
+    id: &str,
+    transaction: &str,
+    changes: &controller::CommitChanges,
+    datastore: &FilesystemDataStore,
+    data: &SharedData,
+) -> Result<bool> {
+    if changes.settings.is_empty() {
+        info!(
+            "[{}] Committed metadata for {} keys from transaction '{}', nothing to apply",
+            id,
+            changes.metadata_only.len(),
+            transaction
+        );
+        return Ok(false);
+    }
+    info!(
+        "[{}] Committed {} keys from transaction '{}', applying changes",
+        id,
+        changes.settings.len(),
+        transaction
+    );
+    let key_names = changes.settings.iter().map(|k| k.name()).collect();
+    controller::apply_changes(&data.config_applier, Some(&key_names))?;
+    controller::commit_requires_reboot(datastore, &changes.settings)
+}
+
+/// Like commit_and_apply, but first checks the given transaction against the settings model and
+/// has the config applier render the affected config files in a dry run, using the settings as
+/// they'd be after the commit.  If anything fails to render, the render errors are returned and
+/// the transaction is left pending.
+async fn validate_commit_and_apply(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<CommitResponse> {
+    let id = request_id(&req);
+    let transaction = transaction_name(&query);
+    let generation = expected_generation(&query)?;
+    let pending = {
+        let datastore = data.read_datastore().await?;
+        controller::validate_transaction(&*datastore, transaction)?;
+        controller::get_pending_data(&*datastore, transaction)?
+    };
+    if pending.is_empty() {
+        return error::CommitWithNoPendingSnafu.fail();
+    }
+
+    // The applier reads settings back from the API while rendering, so we can't hold the lock.
+    let key_names = pending.keys().map(|k| k.name()).collect();
+    controller::check_rendering(&data.config_applier, transaction, &key_names)?;
+    info!(
+        "[{}] Config files rendered for {} keys from transaction '{}'",
+        id,
+        pending.len(),
+        transaction
+    );
+
+    let mut datastore = data.write_datastore().await?;
+    // Check the lock here, rather than before the dry run, since it could change in between.
+    data.check_transaction_lock(transaction, &query)?;
+    // Likewise, the transaction or live settings could have changed while we rendered, so check
+    // again now that nothing else can change them, and make sure we commit what we rendered.
+    controller::validate_transaction(&*datastore, transaction)?;
+    ensure!(
+        controller::get_pending_data(&*datastore, transaction)? == pending,
+        error::TransactionChangedSnafu { transaction }
+    );
+    let changes = controller::commit_transaction(&mut *datastore, transaction, generation)?;
+    data.transaction_locks().clear(transaction);
+    data.audit_commit(&id, transaction, &changes.settings);
+    if changes.is_empty() {
+        return error::CommitWithNoPendingSnafu.fail();
+    }
 
-    Ok(ChangedKeysResponse(changes))
+    let reboot_required = apply_commit(&id, transaction, &changes, &datastore, &data)?;
+    Ok(CommitResponse {
+        changed: changes.all(),
+        reboot_required,
+    })
 }
 
 /// Returns information about the OS image, like variant and version.  If you pass a 'prefix' query
@@ -425,7 +1322,8 @@ async fn get_os_info(
         if !prefix.starts_with("os") {
             prefix = &with_prefix;
         }
-        controller::get_os_prefix(prefix)?.unwrap_or_else(|| Value::Object(serde_json::Map::new()))
+        let os = controller::get_os_prefix(prefix)?.unwrap_or_default();
+        serde_json::to_value(os).expect("struct to value can't fail")
     } else {
         let os = controller::get_os_info()?;
         serde_json::to_value(os).expect("struct to value can't fail")
@@ -434,6 +1332,111 @@ async fn get_os_info(
     Ok(BottlerocketReleaseResponse(os))
 }
 
+/// Get the names of the OS info fields, e.g. to build queries for /os with a 'prefix'.
+async fn get_os_fields() -> Result<OsFieldsResponse> {
+    let os = controller::get_os_info()?;
+    Ok(OsFieldsResponse(controller::os_field_names(&os)))
+}
+
+/// Returns the version of the live data store, based on its versioned symlinks.
+async fn get_datastore_version(data: web::Data<SharedData>) -> Result<DatastoreVersionResponse> {
+    let version = controller::get_datastore_version(&data.datastore_path)?;
+    Ok(DatastoreVersionResponse(version))
+}
+
+/// Returns the live settings of an older data store version, like the one from before the last
+/// upgrade, for comparing settings across upgrades.
+async fn get_settings_at_version(
+    version: web::Path<String>,
+    data: web::Data<SharedData>,
+) -> Result<SettingsResponse> {
+    let given = version.into_inner();
+    let version = Version::parse(given.strip_prefix('v').unwrap_or(&given))
+        .context(error::InvalidVersionSnafu { given: &given })?;
+    let settings = controller::get_settings_at_version(&data.datastore_path, &version)?;
+    Ok(SettingsResponse(settings))
+}
+
+/// Get the value of a single data key exactly as it's stored, for debugging.  Reads from the live
+/// data store unless 'committed' is 'tx', in which case it reads from the transaction named by
+/// 'tx', or "default".
+async fn get_raw_key(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<HttpResponse> {
+    let key = match query.get("key") {
+        Some(key) => key,
+        None => return error::MissingInputSnafu { input: "key" }.fail(),
+    };
+    let committed = committed_param(&query)?;
+
+    let datastore = data.read_datastore().await?;
+    let value = controller::get_raw_key(&*datastore, key, &committed)?;
+    Ok(HttpResponse::Ok().content_type("text/plain").body(value))
+}
+
+/// Removes stale copies of the data store left by migrations, keeping the number of older
+/// versions given in the 'keep' query parameter, or one if unspecified.  Fails if an update is in
+/// progress, since it may be preparing a new data store.  Returns the removed paths.
+async fn compact_datastore(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<CompactResponse> {
+    let keep = optional_number(&query, "keep")?.unwrap_or(DEFAULT_COMPACT_KEEP);
+
+    let lockfile = File::create(UPDATE_LOCKFILE).context(error::UpdateLockOpenSnafu)?;
+    lockfile
+        .try_lock_exclusive()
+        .ok()
+        .context(error::UpdateLockHeldSnafu)?;
+    // Hold the data store lock so no requests use the data store while we work.
+    let _datastore = data.write_datastore().await?;
+
+    let removed = controller::compact_datastore(&data.datastore_path, keep)?;
+    Ok(CompactResponse(removed))
+}
+
+/// Get the number of live keys, pending transactions, and metadata entries in the data store,
+/// and the approximate size of its live data in bytes.
+async fn get_datastore_stats(data: web::Data<SharedData>) -> Result<DatastoreStatsResponse> {
+    let datastore = data.read_datastore().await?;
+    let stats = controller::get_datastore_stats(&*datastore)?;
+    Ok(DatastoreStatsResponse(stats))
+}
+
+/// Returns the generation of the live data, for passing to a later commit as 'expected_generation'
+/// to make sure nothing changed the live data in between.
+async fn get_datastore_generation(
+    data: web::Data<SharedData>,
+) -> Result<DatastoreGenerationResponse> {
+    let datastore = data.read_datastore().await?;
+    let generation = controller::get_generation(&*datastore)?;
+    Ok(DatastoreGenerationResponse(generation))
+}
+
+/// Checks the chain of version symlinks that leads to the data store, reporting the first link
+/// that's missing or dangling, if any.
+async fn check_datastore_symlinks(data: web::Data<SharedData>) -> Result<SymlinkCheckResponse> {
+    let check = controller::check_datastore_symlinks(&data.datastore_path)?;
+    Ok(SymlinkCheckResponse(check))
+}
+
+/// Repairs a broken chain of version symlinks by pointing it at the newest data store copy.
+/// Fails if an update is in progress, since it may be moving the links itself.  Returns the
+/// resulting chain.
+async fn repair_datastore_symlinks(data: web::Data<SharedData>) -> Result<SymlinkCheckResponse> {
+    let lockfile = File::create(UPDATE_LOCKFILE).context(error::UpdateLockOpenSnafu)?;
+    lockfile
+        .try_lock_exclusive()
+        .ok()
+        .context(error::UpdateLockHeldSnafu)?;
+    // Hold the data store lock so no requests use the data store while we work.
+    let _datastore = data.write_datastore().await?;
+
+    let check = controller::repair_datastore_symlinks(&data.datastore_path)?;
+    Ok(SymlinkCheckResponse(check))
+}
+
 /// Get the affected services for a list of data keys
 async fn get_affected_services(
     query: web::Query<HashMap<String, String>>,
@@ -441,7 +1444,7 @@ async fn get_affected_services(
 ) -> Result<MetadataResponse> {
     if let Some(keys_str) = query.get("keys") {
         let data_keys = comma_separated("keys", keys_str)?;
-        let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+        let datastore = data.read_datastore().await?;
         let resp =
             controller::get_metadata_for_data_keys(&*datastore, "affected-services", &data_keys)?;
 
@@ -451,13 +1454,223 @@ async fn get_affected_services(
     }
 }
 
+/// Get the description metadata for a list of data keys
+async fn get_descriptions(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<MetadataResponse> {
+    if let Some(keys_str) = query.get("keys") {
+        let data_keys = comma_separated("keys", keys_str)?;
+        let datastore = data.read_datastore().await?;
+        let resp = controller::get_metadata_for_data_keys(
+            &*datastore,
+            controller::DESCRIPTION_METADATA_KEY,
+            &data_keys,
+        )?;
+
+        Ok(MetadataResponse(resp))
+    } else {
+        error::MissingInputSnafu { input: "keys" }.fail()
+    }
+}
+
+/// Stage metadata changes in a transaction, given as a map of data key to a map of metadata key to
+/// value.  The transaction can be committed like any other, even if it has no settings changes.
+async fn patch_metadata(
+    metadata: web::Json<HashMap<String, HashMap<String, Value>>>,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<HttpResponse> {
+    let transaction = transaction_name(&query);
+    let mut datastore = data.write_datastore().await?;
+    data.check_transaction_lock(transaction, &query)?;
+    controller::set_metadata(&mut *datastore, &metadata, transaction)?;
+    Ok(HttpResponse::NoContent().finish()) // 204
+}
+
+/// Set the description metadata for data keys, given as a map of data key to description
+async fn set_descriptions(
+    descriptions: web::Json<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<HttpResponse> {
+    let mut datastore = data.write_datastore().await?;
+    controller::set_descriptions(&mut *datastore, &descriptions)?;
+    Ok(HttpResponse::NoContent().finish()) // 204
+}
+
+/// Get the affected services, templates, and restart commands for a list of data keys
+async fn get_change_impact(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<ChangeImpactResponse> {
+    if let Some(keys_str) = query.get("keys") {
+        let data_keys = comma_separated("keys", keys_str)?;
+        let datastore = data.read_datastore().await?;
+        let resp = controller::get_change_impact(&*datastore, &data_keys)?;
+
+        Ok(ChangeImpactResponse(resp))
+    } else {
+        error::MissingInputSnafu { input: "keys" }.fail()
+    }
+}
+
+/// Get whether each of a list of data keys has a setting generator, is populated, and its
+/// strength, to help explain why a setting has its value.
+async fn get_setting_provenance(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<SettingProvenanceResponse> {
+    if let Some(keys_str) = query.get("keys") {
+        let data_keys = comma_separated("keys", keys_str)?;
+        let datastore = data.read_datastore().await?;
+        let resp = controller::get_setting_provenance(&*datastore, &data_keys)?;
+
+        Ok(SettingProvenanceResponse(resp))
+    } else {
+        error::MissingInputSnafu { input: "keys" }.fail()
+    }
+}
+
+/// Get whether each of the data keys in the 'keys' query parameter is populated, so clients can
+/// decide whether they're creating or updating a setting.  Checks the live data store unless
+/// 'committed' is 'tx', in which case it checks the transaction named by 'tx', or "default".
+async fn get_keys_exist(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<KeysExistResponse> {
+    let keys_str = query
+        .get("keys")
+        .context(error::MissingInputSnafu { input: "keys" })?;
+    let data_keys = comma_separated("keys", keys_str)?;
+    let committed = committed_param(&query)?;
+
+    let datastore = data.read_datastore().await?;
+    let resp = controller::get_keys_exist(&*datastore, &data_keys, &committed)?;
+    Ok(KeysExistResponse(resp))
+}
+
+/// Get the number of populated settings keys under the 'prefix' query parameter, or all settings
+/// if it's not given, without fetching them.  Counts live settings unless 'committed' is 'tx', in
+/// which case it counts the transaction named by 'tx', or "default".
+async fn get_settings_count(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<SettingsCountResponse> {
+    let prefix = settings_prefix(&query)?.unwrap_or_else(|| "settings.".to_string());
+    let committed = committed_param(&query)?;
+
+    let datastore = data.read_datastore().await?;
+    let count = controller::count_settings_prefix(&*datastore, prefix, &committed)?;
+    Ok(SettingsCountResponse(count))
+}
+
+/// Get the live value of the data key given in the 'key' query parameter alongside its value in
+/// the transaction named by 'tx', or "default".  Either is null if the key isn't set there.
+async fn compare_key(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<KeyComparisonResponse> {
+    let key = query
+        .get("key")
+        .context(error::MissingInputSnafu { input: "key" })?;
+    let transaction = transaction_name(&query);
+    let datastore = data.read_datastore().await?;
+    let comparison = controller::compare_key(&*datastore, key, transaction)?;
+    Ok(KeyComparisonResponse(comparison))
+}
+
+/// Get the names of the pending transactions that stage the data key given in the 'key' query
+/// parameter, to find transactions that would overwrite each other's changes.
+async fn get_transactions_for_key(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<TransactionListResponse> {
+    let key = query
+        .get("key")
+        .context(error::MissingInputSnafu { input: "key" })?;
+    let datastore = data.read_datastore().await?;
+    let transactions = controller::get_transactions_for_key(&*datastore, key)?;
+    Ok(TransactionListResponse(transactions))
+}
+
+/// Get the settings keys whose strength is weak, which are removed on reboot, optionally only
+/// those under the 'prefix' query parameter.
+async fn get_weak_settings(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<WeakSettingsResponse> {
+    let prefix = settings_prefix(&query)?;
+    let datastore = data.read_datastore().await?;
+    let weak = controller::get_weak_settings(&*datastore, prefix.as_deref())?;
+    Ok(WeakSettingsResponse(weak))
+}
+
+/// Get every live value of the metadata key named by the 'metadata' query parameter, for example
+/// to back up all strength metadata.
+async fn export_metadata(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<MetadataResponse> {
+    let md_key_str = match query.get("metadata") {
+        Some(name) if !name.is_empty() => name,
+        Some(_) => return error::EmptyInputSnafu { input: "metadata" }.fail(),
+        None => return error::MissingInputSnafu { input: "metadata" }.fail(),
+    };
+    // Make sure it's a valid name, so the client gets a clear error rather than nothing.
+    Key::new(KeyType::Meta, md_key_str).context(error::NewKeySnafu {
+        key_type: "meta",
+        name: md_key_str,
+    })?;
+
+    let datastore = data.read_datastore().await?;
+    let resp = controller::get_metadata_for_all_data_keys(&*datastore, md_key_str)?;
+    Ok(MetadataResponse(resp))
+}
+
+/// Get all metadata attached to the data key named by the 'key' query parameter, as a mapping of
+/// metadata key to value.  Reads from the live data store unless 'committed' is 'tx', in which
+/// case values staged in the transaction named by 'tx', or "default", take precedence.
+async fn get_key_metadata(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<MetadataResponse> {
+    let key = match query.get("key") {
+        Some(key) if !key.is_empty() => key,
+        Some(_) => return error::EmptyInputSnafu { input: "key" }.fail(),
+        None => return error::MissingInputSnafu { input: "key" }.fail(),
+    };
+    let committed = committed_param(&query)?;
+
+    let datastore = data.read_datastore().await?;
+    let resp = controller::get_metadata_for_key(&*datastore, key, &committed)?;
+    Ok(MetadataResponse(resp))
+}
+
 /// Get all settings that have setting-generator metadata
 async fn get_setting_generators(data: web::Data<SharedData>) -> Result<MetadataResponse> {
-    let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+    let datastore = data.read_datastore().await?;
     let resp = controller::get_metadata_for_all_data_keys(&*datastore, "setting-generator")?;
     Ok(MetadataResponse(resp))
 }
 
+/// Get the setting generator of the data key named by the 'key' query parameter, or 404 if it
+/// doesn't have one.
+async fn get_setting_generator(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<SettingGeneratorResponse> {
+    let key = match query.get("key") {
+        Some(key) if !key.is_empty() => key,
+        Some(_) => return error::EmptyInputSnafu { input: "key" }.fail(),
+        None => return error::MissingInputSnafu { input: "key" }.fail(),
+    };
+
+    let datastore = data.read_datastore().await?;
+    let generator = controller::get_setting_generator(&*datastore, key)?
+        .context(error::NoSettingGeneratorSnafu { key })?;
+    Ok(SettingGeneratorResponse(generator))
+}
+
 /// Get the template metadata for a list of data keys
 async fn get_templates(
     query: web::Query<HashMap<String, String>>,
@@ -465,7 +1678,7 @@ async fn get_templates(
 ) -> Result<MetadataResponse> {
     if let Some(keys_str) = query.get("keys") {
         let data_keys = comma_separated("keys", keys_str)?;
-        let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+        let datastore = data.read_datastore().await?;
         let resp = controller::get_metadata_for_data_keys(&*datastore, "template", &data_keys)?;
 
         Ok(MetadataResponse(resp))
@@ -480,7 +1693,7 @@ async fn get_services(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
 ) -> Result<ServicesResponse> {
-    let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+    let datastore = data.read_datastore().await?;
 
     let resp = if let Some(names_str) = query.get("names") {
         let names = comma_separated("names", names_str)?;
@@ -510,7 +1723,7 @@ async fn get_configuration_files(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
 ) -> Result<ConfigurationFilesResponse> {
-    let datastore = data.ds.read().ok().context(error::DataStoreLockSnafu)?;
+    let datastore = data.read_datastore().await?;
 
     let resp = if let Some(names_str) = query.get("names") {
         let names = comma_separated("names", names_str)?;
@@ -534,6 +1747,27 @@ async fn get_configuration_files(
     Ok(ConfigurationFilesResponse(resp))
 }
 
+/// Check each configuration file entry for problems, like a missing template, before it's rendered
+async fn validate_configuration_files(
+    data: web::Data<SharedData>,
+) -> Result<ConfigurationFileProblemsResponse> {
+    let datastore = data.read_datastore().await?;
+    let problems = controller::validate_configuration_files(&*datastore)?;
+    Ok(ConfigurationFileProblemsResponse(problems))
+}
+
+/// Get the context the named configuration file's template is rendered with, to help debug the
+/// template; see controller::get_template_context.
+async fn get_template_context(
+    name: web::Path<String>,
+    data: web::Data<SharedData>,
+) -> Result<TemplateContextResponse> {
+    let os = controller::get_os_info()?;
+    let datastore = data.read_datastore().await?;
+    let context = controller::get_template_context(&*datastore, &name, &os)?;
+    Ok(TemplateContextResponse(context))
+}
+
 /// Get the update status from 'thar-be-updates'
 async fn get_update_status() -> Result<UpdateStatusResponse> {
     let lockfile = File::create(UPDATE_LOCKFILE).context(error::UpdateLockOpenSnafu)?;
@@ -553,29 +1787,36 @@ async fn get_update_status() -> Result<UpdateStatusResponse> {
 }
 
 /// Refreshes the list of updates and checks if an update is available matching the configured version lock
-async fn refresh_updates() -> Result<HttpResponse> {
-    controller::dispatch_update_command(&["refresh"])
+async fn refresh_updates(data: web::Data<SharedData>) -> Result<HttpResponse> {
+    controller::dispatch_update_command(&data.update_dispatcher, &["refresh"])
 }
 
 /// Prepares update by downloading the images to the staging partition set
-async fn prepare_update() -> Result<HttpResponse> {
-    controller::dispatch_update_command(&["prepare"])
+async fn prepare_update(data: web::Data<SharedData>) -> Result<HttpResponse> {
+    controller::dispatch_update_command(&data.update_dispatcher, &["prepare"])
 }
 
 /// "Activates" an already staged update by bumping the priority bits on the staging partition set
-async fn activate_update() -> Result<HttpResponse> {
-    controller::dispatch_update_command(&["activate"])
+async fn activate_update(data: web::Data<SharedData>) -> Result<HttpResponse> {
+    controller::dispatch_update_command(&data.update_dispatcher, &["activate"])
 }
 
 /// "Deactivates" an already activated update by rolling back actions done by 'activate-update'
-async fn deactivate_update() -> Result<HttpResponse> {
-    controller::dispatch_update_command(&["deactivate"])
+async fn deactivate_update(data: web::Data<SharedData>) -> Result<HttpResponse> {
+    controller::dispatch_update_command(&data.update_dispatcher, &["deactivate"])
 }
 
 /// Reboots the machine
-async fn reboot() -> Result<HttpResponse> {
-    debug!("Rebooting now");
-    let output = Command::new("/usr/bin/systemctl")
+async fn reboot(req: HttpRequest, data: web::Data<SharedData>) -> Result<HttpResponse> {
+    reboot_host(&request_id(&req), &data.systemctl)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Asks systemd, through the given systemctl, to reboot the host, respecting any inhibitors.  The
+/// ID of the request asking for the reboot is included in log lines.
+fn reboot_host(id: &str, systemctl: &Path) -> Result<()> {
+    debug!("[{}] Rebooting now", id);
+    let output = Command::new(systemctl)
         .arg("reboot")
         .arg("--check-inhibitors=yes")
         .output()
@@ -590,80 +1831,261 @@ async fn reboot() -> Result<HttpResponse> {
             stderr: String::from_utf8_lossy(&output.stderr),
         }
     );
-    Ok(HttpResponse::NoContent().finish())
+    Ok(())
+}
+
+/// Removes any weak settings from the live data store, as is done on reboot, then applies the
+/// removals, as with DELETE /settings.  Returns the list of removed keys.
+async fn sweep_weak_settings(
+    req: HttpRequest,
+    data: web::Data<SharedData>,
+) -> Result<ChangedKeysResponse> {
+    let id = request_id(&req);
+    let mut datastore = data.write_datastore().await?;
+    let removed = controller::remove_weak_settings(&mut *datastore)?;
+    data.audit_removal(&id, &removed);
+
+    if !removed.is_empty() {
+        info!(
+            "[{}] Removed {} weak settings, applying changes",
+            id,
+            removed.len()
+        );
+        let key_names = removed.iter().map(|k| k.name()).collect();
+        controller::apply_changes(&data.config_applier, Some(&key_names))?;
+    }
+    Ok(ChangedKeysResponse(removed))
+}
+
+/// Removes any weak settings whose expiry has passed from the live data store, then applies the
+/// removals, as with DELETE /settings.  Returns the list of removed keys.
+async fn sweep_expired_settings(
+    req: HttpRequest,
+    data: web::Data<SharedData>,
+) -> Result<ChangedKeysResponse> {
+    let id = request_id(&req);
+    let mut datastore = data.write_datastore().await?;
+    let removed = controller::remove_expired_settings(&mut *datastore)?;
+    data.audit_removal(&id, &removed);
+
+    if !removed.is_empty() {
+        info!(
+            "[{}] Removed {} expired settings, applying changes",
+            id,
+            removed.len()
+        );
+        let key_names = removed.iter().map(|k| k.name()).collect();
+        controller::apply_changes(&data.config_applier, Some(&key_names))?;
+    }
+    Ok(ChangedKeysResponse(removed))
 }
 
 /// Gets the set of report types supported by this host.
 async fn list_reports() -> Result<ReportListResponse> {
-    // Add each report to list response when adding a new handler
-    let data = vec![Report {
-        name: "cis".to_string(),
-        description: "CIS Bottlerocket Benchmark".to_string(),
-    }];
+    let data = REPORT_TYPES
+        .iter()
+        .map(|report| Report {
+            name: report.name.to_string(),
+            description: report.description.to_string(),
+            formats: report.formats.iter().map(|f| f.to_string()).collect(),
+        })
+        .collect();
     Ok(ReportListResponse(data))
 }
 
-/// Gets the Bottlerocket CIS benchmark report.
-async fn get_cis_report(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
-    let mut cmd = AsyncCommand::new(BLOODHOUND_BIN);
-
-    // Check for requested level, default is 1
-    if let Some(level) = query.get("level") {
-        cmd.arg("-l").arg(level);
+/// Gets the Bottlerocket CIS benchmark report.
+async fn get_cis_report(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<HttpResponse> {
+    let cmd = report_command(&data.report_config, "cis", &query)?;
+    run_report(cmd, &query).await
+}
+
+/// Gets the FIPS Security Policy report.
+async fn get_fips_report(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<HttpResponse> {
+    let cmd = report_command(&data.report_config, "fips", &query)?;
+    run_report(cmd, &query).await
+}
+
+/// Runs several reports at once, returning an object with each report keyed by name.  The
+/// parameters, like 'format' and 'level', apply to each report that supports them.
+async fn get_combined_report(
+    request: web::Json<CombinedReportRequest>,
+    data: web::Data<SharedData>,
+) -> Result<HttpResponse> {
+    ensure!(!request.reports.is_empty(), error::ReportTypeMissingSnafu);
+    let params = request.params();
+    let json = params.get("format").map(String::as_str) == Some("json");
+    let status = params
+        .get("status")
+        .map(|s| controller::parse_report_status(s))
+        .transpose()?;
+    ensure!(status.is_none() || json, error::ReportFilterFormatSnafu);
+
+    // Start every report before waiting on any of them, so they run concurrently.  If one fails,
+    // or the client goes away, the rest are dropped unfinished, so kill them rather than leaving
+    // them running.
+    let mut children = Vec::new();
+    for name in &request.reports {
+        let mut cmd = report_command(&data.report_config, name, &params)?;
+        cmd.stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        children.push((name, cmd.spawn().context(error::ReportExecSnafu)?));
+    }
+    let mut outputs = Vec::new();
+    for (name, child) in children {
+        let output = child
+            .wait_with_output()
+            .await
+            .context(error::ReportExecSnafu)?;
+        outputs.push((name.clone(), report_stdout(output)?));
+    }
+
+    let combined = controller::combine_reports(outputs, json, status)?;
+    Ok(HttpResponse::Ok().json(combined))
+}
+
+/// Builds the bloodhound command for the given report, passing along the parameters the report
+/// supports.  Fails if the requested format isn't one the report supports.
+fn report_command(
+    config: &ReportConfig,
+    report: &str,
+    params: &HashMap<String, String>,
+) -> Result<AsyncCommand> {
+    let report_type =
+        REPORT_TYPES
+            .iter()
+            .find(|r| r.name == report)
+            .context(error::ReportNotSupportedSnafu {
+                report_type: report,
+            })?;
+    let mut cmd = AsyncCommand::new(&config.bloodhound_bin);
+
+    // Check for requested format, default is text
+    if let Some(format) = params.get("format") {
+        ensure!(
+            report_type.formats.contains(&format.as_str()),
+            error::ReportFormatNotSupportedSnafu {
+                report_type: report,
+                format,
+                allowed: report_type.formats.join(", "),
+            }
+        );
+        cmd.arg("-f").arg(format);
+    }
+
+    match report {
+        "cis" => {
+            // Check for requested level, default is 1
+            if let Some(level) = params.get("level") {
+                cmd.arg("-l").arg(level);
+            }
+            if params.get("type").map(String::as_str) == Some("kubernetes") {
+                cmd.arg("-c").arg(&config.k8s_checks);
+            }
+        }
+        "fips" => {
+            cmd.arg("-c").arg(&config.fips_checks);
+        }
+        _ => {
+            return error::ReportNotSupportedSnafu {
+                report_type: report,
+            }
+            .fail()
+        }
+    }
+    Ok(cmd)
+}
+
+/// Runs the given bloodhound command and returns its report.  If JSON format was requested, the
+/// report is parsed and re-serialized to make sure it has the expected shape, and if a 'status'
+/// query parameter was given, only checks with that status are returned.
+async fn run_report(
+    mut cmd: AsyncCommand,
+    query: &web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse> {
+    let json = query.get("format").map(String::as_str) == Some("json");
+    let status = query
+        .get("status")
+        .map(|s| controller::parse_report_status(s))
+        .transpose()?;
+    ensure!(status.is_none() || json, error::ReportFilterFormatSnafu);
+
+    if !json {
+        return stream_report(cmd).await;
     }
 
-    // Check for requested format, default is text
-    if let Some(format) = query.get("format") {
-        cmd.arg("-f").arg(format);
+    let output = cmd.output().await.context(error::ReportExecSnafu)?;
+    let stdout = report_stdout(output)?;
+    let mut report = controller::parse_report(&stdout)?;
+    if let Some(status) = status {
+        controller::filter_report(&mut report, status);
     }
+    Ok(HttpResponse::Ok().json(report))
+}
 
-    if let Some(report_type) = query.get("type") {
-        if report_type == "kubernetes" {
-            cmd.arg("-c").arg(BLOODHOUND_K8S_CHECKS);
-        }
-    }
+/// Responds with the stdout of the given bloodhound command as it's produced, so large reports
+/// don't have to be held in memory.  If bloodhound fails before writing anything, we respond with
+/// its error as usual.  If it fails partway through, the response has already started, so the
+/// body ends with the error instead, which aborts the response rather than completing it.
+async fn stream_report(mut cmd: AsyncCommand) -> Result<HttpResponse> {
+    cmd.stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = cmd.spawn().context(error::ReportExecSnafu)?;
+    let mut output = ReaderStream::new(child.stdout.take().expect("stdout is piped"));
+
+    // Collect stderr as we go so bloodhound can't block on a full pipe.
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr = actix_rt::spawn(async move {
+        let mut buf = Vec::new();
+        let _ = stderr.read_to_end(&mut buf).await;
+        buf
+    });
+    let finish = async move {
+        let status = child.wait().await.context(error::ReportExecSnafu)?;
+        check_report_status(status, &stderr.await.unwrap_or_default())
+    };
 
-    let output = cmd.output().await.context(error::ReportExecSnafu)?;
-    ensure!(
-        output.status.success(),
-        error::ReportResultSnafu {
-            exit_code: match output.status.code() {
-                Some(code) => code,
-                None => output.status.signal().unwrap_or(1),
-            },
-            stderr: String::from_utf8_lossy(&output.stderr),
+    let first = match output.next().await {
+        Some(chunk) => chunk.context(error::ReportExecSnafu)?,
+        None => {
+            finish.await?;
+            return Ok(HttpResponse::Ok().content_type("application/text").finish());
         }
-    );
+    };
+    let body = stream::once(future::ready(Ok(first)))
+        .chain(output.map(|chunk| chunk.context(error::ReportExecSnafu)))
+        .chain(stream::once(finish).filter_map(|r| future::ready(r.err().map(Err))));
     Ok(HttpResponse::Ok()
         .content_type("application/text")
-        .body(String::from_utf8_lossy(&output.stdout).to_string()))
+        .streaming(body))
 }
 
-/// Gets the FIPS Security Policy report.
-async fn get_fips_report(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
-    let mut cmd = AsyncCommand::new(BLOODHOUND_BIN);
-
-    // Check for requested format, default is text
-    if let Some(format) = query.get("format") {
-        cmd.arg("-f").arg(format);
-    }
-
-    cmd.arg("-c").arg(BLOODHOUND_FIPS_CHECKS);
+/// Returns the report from bloodhound's output, or an error with its stderr if it failed.
+fn report_stdout(output: Output) -> Result<String> {
+    check_report_status(output.status, &output.stderr)?;
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
 
-    let output = cmd.output().await.context(error::ReportExecSnafu)?;
+/// Returns an error with bloodhound's stderr if it exited unsuccessfully.
+fn check_report_status(status: ExitStatus, stderr: &[u8]) -> Result<()> {
     ensure!(
-        output.status.success(),
+        status.success(),
         error::ReportResultSnafu {
-            exit_code: match output.status.code() {
+            exit_code: match status.code() {
                 Some(code) => code,
-                None => output.status.signal().unwrap_or(1),
+                None => status.signal().unwrap_or(1),
             },
-            stderr: String::from_utf8_lossy(&output.stderr),
+            stderr: String::from_utf8_lossy(stderr),
         }
     );
-    Ok(HttpResponse::Ok()
-        .content_type("application/text")
-        .body(String::from_utf8_lossy(&output.stdout).to_string()))
+    Ok(())
 }
 
 /// Configure ephemeral storage (raid & format, or just format for single disk)
@@ -745,34 +2167,140 @@ fn comma_separated<'a>(key_name: &'static str, input: &'a str) -> Result<HashSet
     Ok(input.split(',').collect())
 }
 
+/// Returns the 'holder' query parameter, naming who a transaction lock is for.
+fn lock_holder(query: &web::Query<HashMap<String, String>>) -> Result<&str> {
+    match query.get("holder") {
+        Some(holder) if !holder.is_empty() => Ok(holder),
+        Some(_) => error::EmptyInputSnafu { input: "holder" }.fail(),
+        None => error::MissingInputSnafu { input: "holder" }.fail(),
+    }
+}
+
+/// Parses the 'committed' query parameter into the data set a request should read: the live data
+/// store if unspecified or 'live', or the transaction named by 'tx' if 'tx'.
+fn committed_param(query: &web::Query<HashMap<String, String>>) -> Result<Committed> {
+    match query.get("committed").map(String::as_str) {
+        None | Some("live") => Ok(Committed::Live),
+        Some("tx") => Ok(Committed::Pending {
+            tx: transaction_name(query).into(),
+        }),
+        Some(given) => error::InvalidCommittedSnafu { given }.fail(),
+    }
+}
+
+/// Parses the given query parameter as a number, if it was specified.
+fn optional_number(
+    query: &web::Query<HashMap<String, String>>,
+    key_name: &'static str,
+) -> Result<Option<usize>> {
+    query
+        .get(key_name)
+        .map(|s| {
+            s.parse()
+                .context(error::InvalidNumberSnafu { input: key_name })
+        })
+        .transpose()
+}
+
+/// Parses the 'expected_generation' query parameter, which makes a commit fail with a conflict if
+/// the live data changed since the client read that generation.
+fn expected_generation(query: &web::Query<HashMap<String, String>>) -> Result<Option<u64>> {
+    Ok(optional_number(query, "expected_generation")?.map(|generation| generation as u64))
+}
+
 fn transaction_name(query: &web::Query<HashMap<String, String>>) -> &str {
     query.get("tx").map(String::as_str).unwrap_or("default")
 }
 
+/// Returns true if the 'strict' query parameter is "true", meaning input keys that aren't in the
+/// settings model should be rejected rather than ignored.
+fn strict(query: &web::Query<HashMap<String, String>>) -> bool {
+    query.get("strict").map(String::as_str) == Some("true")
+}
+
+/// Returns true if the given media type, which may have parameters, is CBOR.
+fn is_cbor_media_type(media_type: &str) -> bool {
+    media_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .eq_ignore_ascii_case(CBOR_CONTENT_TYPE)
+}
+
+/// Returns true if the request body is CBOR, according to its Content-Type.
+fn is_cbor(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(is_cbor_media_type)
+}
+
+/// Returns true if the client accepts CBOR responses, according to its Accept header.
+fn accepts_cbor(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(is_cbor_media_type)
+}
+
+/// Returns the 'prefix' query parameter, if given, for requests under /settings.  The settings
+/// prefix is implied there, so we add it if it wasn't given.
+fn settings_prefix(query: &web::Query<HashMap<String, String>>) -> Result<Option<String>> {
+    match query.get("prefix") {
+        Some(prefix) if prefix.is_empty() => error::EmptyInputSnafu { input: "prefix" }.fail(),
+        Some(prefix) if prefix.starts_with("settings") => Ok(Some(prefix.clone())),
+        Some(prefix) => Ok(Some(format!("settings.{}", prefix))),
+        None => Ok(None),
+    }
+}
+
+/// Returns true if the 'validate' query parameter is "true", meaning a transaction should be
+/// checked against the settings model before it's committed.  This is opt-in because some flows
+/// intentionally stage partial data.
+fn validate(query: &web::Query<HashMap<String, String>>) -> bool {
+    query.get("validate").map(String::as_str) == Some("true")
+}
+
+/// Returns an error if settings meant for the 'requested' variant would be applied on the
+/// 'running' variant.
+fn check_variant(requested: &str, running: &str) -> Result<()> {
+    ensure!(
+        requested == running,
+        error::VariantMismatchSnafu { requested, running }
+    );
+    Ok(())
+}
+
+/// Fails with a list of the input keys that aren't in the settings model, if there are any.
+fn check_modeled_keys(input: &HashMap<Key, String>, settings: &Settings) -> Result<()> {
+    let keys = controller::find_unmodeled_keys(input, settings)?;
+    ensure!(keys.is_empty(), error::UnmodeledKeysSnafu { keys });
+    Ok(())
+}
+
+/// Annotates the transaction if the 'author' or 'description' query parameters were given.
+fn annotate_from_query(
+    datastore: &mut FilesystemDataStore,
+    transaction: &str,
+    query: &web::Query<HashMap<String, String>>,
+) -> Result<()> {
+    let author = query.get("author").map(String::as_str);
+    let description = query.get("description").map(String::as_str);
+    if author.is_none() && description.is_none() {
+        return Ok(());
+    }
+    controller::annotate_transaction(datastore, transaction, author, description)
+}
+
 // Helpers methods for the 'set' API
 
 fn construct_key_pair_map(settings_key_pair_vec: &Vec<String>) -> Result<HashMap<Key, String>> {
     let mut settings_key_pair_map = HashMap::new();
     for settings_key_pair in settings_key_pair_vec {
         // If we see an invalid key pair. We will return the result with an error immediately.
-        let (raw_key, value) =
-            settings_key_pair
-                .split_once('=')
-                .ok_or_else(|| Error::InvalidKeyPair {
-                    input: settings_key_pair.clone(),
-                })?;
-        let mut key = Key::new(KeyType::Data, raw_key)
-            .context(error::InvalidPrefixSnafu { prefix: raw_key })?;
-        // Add "settings" prefix if the user didn't give a known prefix, to ease usage
-        let key_prefix = &key.segments()[0];
-
-        if key_prefix != "settings" {
-            let mut segments = key.segments().clone();
-            segments.insert(0, "settings".to_string());
-            key = Key::from_segments(KeyType::Data, &segments)
-                .context(error::InvalidPrefixSnafu { prefix: raw_key })?;
-        }
-
+        let (key, value) = split_key_pair(settings_key_pair)?;
         settings_key_pair_map.insert(key, value.to_string());
     }
     trace!("Key=Value map: {:#?}", settings_key_pair_map);
@@ -780,39 +2308,131 @@ fn construct_key_pair_map(settings_key_pair_vec: &Vec<String>) -> Result<HashMap
     massage_set_input(settings_key_pair_map)
 }
 
+/// Splits a "key=value" pair into a data key and its raw value.  A "settings" prefix is added to
+/// the key if the user didn't give it, to ease usage.
+fn split_key_pair(settings_key_pair: &str) -> Result<(Key, &str)> {
+    let (raw_key, value) =
+        settings_key_pair
+            .split_once('=')
+            .ok_or_else(|| Error::InvalidKeyPair {
+                input: settings_key_pair.to_string(),
+            })?;
+    Ok((settings_key(raw_key)?, value))
+}
+
+/// Makes a data key from the given name, adding a "settings" prefix if the user didn't give it.
+fn settings_key(raw_key: &str) -> Result<Key> {
+    let key =
+        Key::new(KeyType::Data, raw_key).context(error::InvalidPrefixSnafu { prefix: raw_key })?;
+    if key.segments()[0] == "settings" {
+        return Ok(key);
+    }
+    let mut segments = key.segments().clone();
+    segments.insert(0, "settings".to_string());
+    Key::from_segments(KeyType::Data, &segments)
+        .context(error::InvalidPrefixSnafu { prefix: raw_key })
+}
+
+/// Checks that the given value is valid for the given setting key, meaning the key is in the
+/// settings model and the value deserializes into the modeled type.
+fn check_setting_value(raw_key: &str, value: &serde_json::Value) -> Result<()> {
+    let key = settings_key(raw_key)?;
+    let pairs = to_pairs_with_prefix(key.name(), value)
+        .context(error::DataStoreSerializationSnafu { given: "value" })?;
+    let settings: Settings =
+        datastore::deserialization::from_map(&pairs).context(error::DeserializeMapSnafu)?;
+    // Unknown keys can be dropped during deserialization rather than failing it.
+    check_modeled_keys(&pairs, &settings)
+}
+
+/// Turns key=value pairs into Settings and stages them in the given transaction.  If any pair is
+/// invalid, nothing is staged.
+fn stage_key_pairs<D: datastore::DataStore>(
+    datastore: &mut D,
+    settings_key_pair_vec: &Vec<String>,
+    transaction: &str,
+    strict: bool,
+) -> Result<()> {
+    let settings_key_pair_map = construct_key_pair_map(settings_key_pair_vec)?;
+    // The data store deserialization code understands how to turn the key names
+    // (a.b.c) and serialized values into the nested Settings structure.
+    let settings_model = datastore::deserialization::from_map(&settings_key_pair_map)
+        .context(error::DeserializeMapSnafu)?;
+    if strict {
+        check_modeled_keys(&settings_key_pair_map, &settings_model)?;
+    }
+    controller::set_settings(datastore, &settings_model, transaction)
+}
+
+/// Like stage_key_pairs, but checks each key=value pair on its own, staging the valid ones and
+/// returning the result for each pair, in input order.
+fn stage_key_pairs_partial<D: datastore::DataStore>(
+    datastore: &mut D,
+    settings_key_pair_vec: &[String],
+    transaction: &str,
+    strict: bool,
+) -> Result<Vec<KeyPairResult>> {
+    let check_pair = |settings_key_pair: &str| -> Result<HashMap<Key, String>> {
+        let (key, value) = split_key_pair(settings_key_pair)?;
+        let pair = massage_set_input(HashMap::from([(key, value.to_string())]))?;
+        let settings: Settings =
+            datastore::deserialization::from_map(&pair).context(error::DeserializeMapSnafu)?;
+        if strict {
+            check_modeled_keys(&pair, &settings)?;
+        }
+        Ok(pair)
+    };
+
+    let mut valid_pairs = HashMap::new();
+    let mut results = Vec::with_capacity(settings_key_pair_vec.len());
+    for settings_key_pair in settings_key_pair_vec {
+        let outcome = match check_pair(settings_key_pair) {
+            Ok(pair) => {
+                valid_pairs.extend(pair);
+                KeyPairOutcome::Ok
+            }
+            Err(e) => KeyPairOutcome::Error {
+                reason: e.to_string(),
+            },
+        };
+        results.push(KeyPairResult {
+            input: settings_key_pair.clone(),
+            outcome,
+        });
+    }
+
+    if !valid_pairs.is_empty() {
+        let settings_model = datastore::deserialization::from_map(&valid_pairs)
+            .context(error::DeserializeMapSnafu)?;
+        controller::set_settings(datastore, &settings_model, transaction)?;
+    }
+    Ok(results)
+}
+
 /// We want the key=val form of 'set' to be as simple as possible; we don't want users to have to
 /// annotate or structure their input too much just to tell us the data type, but unfortunately
 /// knowledge of the data type is required to deserialize with the current datastore ser/de code.
 ///
-/// To simplify usage, we use some heuristics to determine the type of each input.  We try to parse
-/// each value as a number and boolean, and if those fail, we assume a string.  (API communication
-/// is in JSON form, limiting the set of types; the API doesn't allow arrays or null, and "objects"
-/// (maps) are represented natively through our nested tree-like settings structure.)
+/// To simplify usage, DatastoreValue uses some heuristics to determine the type of each input.  It
+/// tries to parse each value as a number, boolean, JSON array, or null, and if those fail, assumes
+/// a string.  (API communication is in JSON form, limiting the set of types; "objects" (maps) are
+/// represented natively through our nested tree-like settings structure, so they're not accepted
+/// here.)
 ///
 /// If this goes wrong -- for example the user wants a string "42" -- we'll get a deserialization
 /// error, and can print a clear error and request the user use JSON input form to handle
-/// situations with more complex types.g
+/// situations with more complex types.
 ///
 /// If you have an idea for how to improve deserialization so we don't have to do this, please say!
 fn massage_set_input(input_map: HashMap<Key, String>) -> Result<HashMap<Key, String>> {
-    // Deserialize the given value into the matching Rust type.  When we find a matching type, we
-    // serialize back out to the data store format, which is required to build a Settings object
-    // through the data store deserialization code.
+    // Interpret the given value as the matching type, then serialize back out to the data store
+    // format, which is required to build a Settings object through the data store deserialization
+    // code.
     let mut massaged_map = HashMap::with_capacity(input_map.len());
     for (key, in_val) in input_map {
-        let serialized = if let Ok(b) = serde_json::from_str::<bool>(&in_val) {
-            serialize_scalar(&b).context(error::SerializeSnafu)?
-        } else if let Ok(u) = serde_json::from_str::<u64>(&in_val) {
-            trace!("Serializing scalar of type u64");
-            serialize_scalar(&u).context(error::SerializeSnafu)?
-        } else if let Ok(f) = serde_json::from_str::<f64>(&in_val) {
-            trace!("Serializing scalar of type f64");
-            serialize_scalar(&f).context(error::SerializeSnafu)?
-        } else {
-            trace!("Serializing scalar of type string");
-            // No deserialization, already a string, just serialize
-            serialize_scalar(&in_val).context(error::SerializeSnafu)?
-        };
+        let serialized = DatastoreValue::from_input(&in_val)
+            .to_scalar_string()
+            .context(error::SerializeSnafu)?;
         massaged_map.insert(key, serialized);
     }
     trace!("Massaged key=value input: {:#?}", massaged_map);
@@ -828,36 +2448,79 @@ impl ResponseError for error::Error {
             // 400 Bad Request
             MissingInput { .. } => StatusCode::BAD_REQUEST,
             EmptyInput { .. } => StatusCode::BAD_REQUEST,
+            InvalidNumber { .. } => StatusCode::BAD_REQUEST,
             NewKey { .. } => StatusCode::BAD_REQUEST,
             ReportTypeMissing { .. } => StatusCode::BAD_REQUEST,
+            InvalidReportStatus { .. } => StatusCode::BAD_REQUEST,
+            InvalidCommitted { .. } => StatusCode::BAD_REQUEST,
+            InvalidSettingsFormat { .. } => StatusCode::BAD_REQUEST,
+            BulkStageInvalid { .. } => StatusCode::BAD_REQUEST,
+            InvalidVersion { .. } => StatusCode::BAD_REQUEST,
+            ReportFilterFormat => StatusCode::BAD_REQUEST,
+            ReportFormatNotSupported { .. } => StatusCode::BAD_REQUEST,
             Serialize { .. } => StatusCode::BAD_REQUEST,
             DeserializeMap { .. } => StatusCode::BAD_REQUEST,
             InvalidPrefix { .. } => StatusCode::BAD_REQUEST,
+            TransactionSchema { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            PrefixNotSettings { .. } => StatusCode::BAD_REQUEST,
             DeserializeJson { .. } => StatusCode::BAD_REQUEST,
             InvalidKeyPair { .. } => StatusCode::BAD_REQUEST,
 
             // 404 Not Found
             MissingData { .. } => StatusCode::NOT_FOUND,
+            NoSettingGenerator { .. } => StatusCode::NOT_FOUND,
+            DataStoreVersion { .. } => StatusCode::NOT_FOUND,
+            MissingTransaction { .. } => StatusCode::NOT_FOUND,
             ListKeys { .. } => StatusCode::NOT_FOUND,
             UpdateDoesNotExist { .. } => StatusCode::NOT_FOUND,
             NoStagedImage { .. } => StatusCode::NOT_FOUND,
             UninitializedUpdateStatus { .. } => StatusCode::NOT_FOUND,
 
+            // 403 Forbidden
+            ReadOnlyServer { .. } => StatusCode::FORBIDDEN,
+            DataStore { source, .. } if matches!(**source, datastore::Error::ReadOnly { .. }) => {
+                StatusCode::FORBIDDEN
+            }
+
             // 422 Unprocessable Entity
             CommitWithNoPending => StatusCode::UNPROCESSABLE_ENTITY,
             ReportNotSupported { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            EnvNameCollision { .. } => StatusCode::UNPROCESSABLE_ENTITY,
 
             // 423 Locked
             UpdateShareLock { .. } => StatusCode::LOCKED,
             UpdateLockHeld { .. } => StatusCode::LOCKED,
+            DataStore { source, .. }
+                if matches!(**source, datastore::Error::TransactionLocked { .. }) =>
+            {
+                StatusCode::LOCKED
+            }
 
             // 409 Conflict
+            DataStore { source, .. }
+                if matches!(**source, datastore::Error::StaleGeneration { .. }) =>
+            {
+                StatusCode::CONFLICT
+            }
             DisallowCommand { .. } => StatusCode::CONFLICT,
+            VariantMismatch { .. } => StatusCode::CONFLICT,
+
+            // 429 Too Many Requests
+            DataStore { source, .. }
+                if matches!(**source, datastore::Error::TooManyTransactions { .. }) =>
+            {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+
+            // 503 Service Unavailable
+            DataStoreBusy { .. } => StatusCode::SERVICE_UNAVAILABLE,
 
             // 500 Internal Server Error
-            DataStoreLock => StatusCode::INTERNAL_SERVER_ERROR,
+            DataStoreDir { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ResponseSerialization { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ResponseToCbor { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             BindSocket { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            BindTcp { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ServerStart { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ListedKeyNotPresent { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             DataStore { .. } => StatusCode::INTERNAL_SERVER_ERROR,
@@ -868,17 +2531,40 @@ impl ResponseError for error::Error {
             EphemeralInitialize { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             EphemeralListDisks { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             InvalidMetadata { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            AuditWrite { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            InvalidValue { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            PromoteToSelf { .. } => StatusCode::BAD_REQUEST,
+            AnnotationJson { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierFork { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierStart { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierStdin {} => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierWait { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierWrite { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            RenderCheck { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            TransactionChanged { .. } => StatusCode::CONFLICT,
+            ResetWithoutDefault { .. } => StatusCode::CONFLICT,
             SystemdNotify { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             SystemdNotifyStatus {} => StatusCode::INTERNAL_SERVER_ERROR,
             SetPermissions { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             SetGroup { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             SettingsToJson { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            SettingsFromJson { .. } => StatusCode::BAD_REQUEST,
+            SettingsFromCbor { .. } => StatusCode::BAD_REQUEST,
+            UnmodeledKeys { .. } => StatusCode::BAD_REQUEST,
+            GeneratedKeys { .. } => StatusCode::CONFLICT,
             ReleaseData { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            DefaultsRead { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            DefaultsFormat { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            DefaultsModel { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            NoConfigurationFile { .. } => StatusCode::NOT_FOUND,
+            TemplateRead { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            TemplateParse { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            SeedPathNotAllowed { .. } => StatusCode::FORBIDDEN,
+            SeedRead { .. } => StatusCode::BAD_REQUEST,
+            SeedFileType { .. } => StatusCode::BAD_REQUEST,
+            SeedJson { .. } => StatusCode::BAD_REQUEST,
+            SeedToml { .. } => StatusCode::BAD_REQUEST,
+            SeedModel { .. } => StatusCode::BAD_REQUEST,
             Shutdown { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Reboot { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateDispatcher { .. } => StatusCode::INTERNAL_SERVER_ERROR,
@@ -888,6 +2574,7 @@ impl ResponseError for error::Error {
             UpdateLockOpen { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ReportExec { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ReportResult { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ReportSchema { .. } => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
         HttpResponse::build(status_code).body(self.to_string())
@@ -900,11 +2587,207 @@ struct SetKeyPairSettings {
     request_payload: Vec<String>,
 }
 
+/// The result of staging one key=value pair when the caller asked for partial success.
+#[derive(Debug, Serialize)]
+struct KeyPairResult {
+    input: String,
+    #[serde(flatten)]
+    outcome: KeyPairOutcome,
+}
+
+/// The body of a request to validate a single setting value.
+#[derive(Debug, Deserialize)]
+struct ValidateValueRequest {
+    key: String,
+    value: serde_json::Value,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum KeyPairOutcome {
+    Ok,
+    Error { reason: String },
+}
+
+/// The result of staging one transaction's settings in a bulk request: the keys staged, or why
+/// nothing was.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum BulkStageOutcome {
+    Ok { changed: BTreeSet<String> },
+    Error { reason: String },
+}
+
+/// The result of deleting one transaction in a bulk request: the keys deleted, or why it couldn't
+/// be deleted.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum BulkDeleteOutcome {
+    Ok { deleted: BTreeSet<String> },
+    Error { reason: String },
+}
+
+/// The body of a combined report request: the names of the reports to run, plus any parameters
+/// for them, like 'format'.  Parameters may be given as any JSON value, e.g. a number for 'level'.
+#[derive(Debug, Deserialize)]
+struct CombinedReportRequest {
+    reports: Vec<String>,
+    #[serde(flatten)]
+    params: HashMap<String, Value>,
+}
+
+impl CombinedReportRequest {
+    /// Returns the parameters as strings, as they'd be given in a query for a single report.
+    fn params(&self) -> HashMap<String, String> {
+        self.params
+            .iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                (name.clone(), value)
+            })
+            .collect()
+    }
+}
+
 /// SharedData is responsible for any data needed by web handlers that isn't provided by the client
 /// in the request.
 pub(crate) struct SharedData {
     ds: sync::RwLock<FilesystemDataStore>,
-    exec_socket_path: PathBuf,
+    datastore_path: PathBuf,
+    exec_config: ExecConfig,
+    report_config: ReportConfig,
+    tx_locks: sync::Mutex<TransactionLocks>,
+    /// The config applier run after commits.
+    config_applier: PathBuf,
+    /// The update dispatcher run for update actions.
+    update_dispatcher: PathBuf,
+    /// systemctl, run to reboot.
+    systemctl: PathBuf,
+    /// If set, each commit is recorded as a JSON line in this file.
+    audit_log: Option<PathBuf>,
+    /// The only directory /tx/seed reads from.
+    seed_dir: PathBuf,
+}
+
+/// How many times handlers try to take the data store lock before giving up.
+const LOCK_ATTEMPTS: u32 = 8;
+/// How long handlers wait after the first failed try to take the data store lock; the wait
+/// doubles after each failed try, up to LOCK_MAX_BACKOFF.
+const LOCK_INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+/// The longest handlers wait between tries to take the data store lock.
+const LOCK_MAX_BACKOFF: Duration = Duration::from_millis(500);
+
+impl SharedData {
+    /// Takes the data store lock for reading, retrying for a short while if it's busy.
+    async fn read_datastore(&self) -> Result<sync::RwLockReadGuard<'_, FilesystemDataStore>> {
+        let guard = retry_lock(|| self.ds.try_read()).await?;
+        self.ds.clear_poison();
+        Ok(guard)
+    }
+
+    /// Takes the data store lock for writing, retrying for a short while if it's busy.
+    async fn write_datastore(&self) -> Result<sync::RwLockWriteGuard<'_, FilesystemDataStore>> {
+        let guard = retry_lock(|| self.ds.try_write()).await?;
+        self.ds.clear_poison();
+        Ok(guard)
+    }
+
+    /// Returns the advisory transaction locks.  Callers should hold the data store write lock so
+    /// locks can't change between checking them and writing.
+    fn transaction_locks(&self) -> sync::MutexGuard<'_, TransactionLocks> {
+        // The locks are only a map of names, so there's nothing to be left inconsistent by a
+        // panic; keep using them.
+        self.tx_locks
+            .lock()
+            .unwrap_or_else(sync::PoisonError::into_inner)
+    }
+
+    /// Records a commit of the given keys from the given transaction in the audit log, if one is
+    /// configured.  Failing to write the entry is logged, but doesn't fail the commit, which has
+    /// already happened, under the ID of the request that made it.
+    fn audit_commit(&self, id: &str, transaction: &str, changes: &HashSet<Key>) {
+        if changes.is_empty() {
+            return;
+        }
+        if let Some(audit_log) = &self.audit_log {
+            if let Err(e) = controller::record_commit(audit_log, transaction, changes) {
+                error!(
+                    "[{}] Failed to audit commit of transaction '{}': {}",
+                    id, transaction, e
+                );
+            }
+        }
+    }
+
+    /// Records a removal of the given keys from the live settings in the audit log, if one is
+    /// configured.  As with commits, failing to write the entry is logged, since the removal has
+    /// already happened.
+    fn audit_removal(&self, id: &str, removed: &HashSet<Key>) {
+        if removed.is_empty() {
+            return;
+        }
+        if let Some(audit_log) = &self.audit_log {
+            if let Err(e) = controller::record_removal(audit_log, removed) {
+                error!(
+                    "[{}] Failed to audit removal of {} keys: {}",
+                    id,
+                    removed.len(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Confirms that the holder given in the 'holder' query parameter, if any, may write to the
+    /// given transaction, i.e. it's unlocked or locked by that holder.
+    fn check_transaction_lock(
+        &self,
+        transaction: &str,
+        query: &web::Query<HashMap<String, String>>,
+    ) -> Result<()> {
+        self.transaction_locks()
+            .check(transaction, query.get("holder").map(String::as_str))
+            .context(error::DataStoreSnafu {
+                op: "check_transaction_lock",
+            })
+    }
+}
+
+/// Calls `try_lock` until it returns the lock guard, backing off between tries.  The backoff is
+/// an async sleep, so the actix worker keeps serving other requests - including the one holding
+/// the lock - while we wait.  A lock that's still busy after LOCK_ATTEMPTS tries returns a
+/// DataStoreBusy error so the client can try again later.
+///
+/// A poisoned lock is recovered rather than failing every later request.  The lock only guards a
+/// handle to the data store on disk, and a request that panicked partway through leaves the data
+/// store the same as if the server had been killed, which it already has to handle.  Callers
+/// should clear the poison once they have the guard so it's only reported once.
+async fn retry_lock<G, F>(try_lock: F) -> Result<G>
+where
+    F: Fn() -> sync::TryLockResult<G>,
+{
+    let mut backoff = LOCK_INITIAL_BACKOFF;
+    let mut waited = Duration::ZERO;
+    for attempt in 1..=LOCK_ATTEMPTS {
+        match try_lock() {
+            Ok(guard) => return Ok(guard),
+            Err(sync::TryLockError::Poisoned(poisoned)) => {
+                warn!("Recovering data store lock poisoned by a request that panicked");
+                return Ok(poisoned.into_inner());
+            }
+            Err(sync::TryLockError::WouldBlock) if attempt < LOCK_ATTEMPTS => {
+                debug!("Data store lock is busy, retrying in {:?}", backoff);
+                actix_rt::time::sleep(backoff).await;
+                waited += backoff;
+                backoff = (backoff * 2).min(LOCK_MAX_BACKOFF);
+            }
+            Err(sync::TryLockError::WouldBlock) => {}
+        }
+    }
+    error::DataStoreBusySnafu { waited }.fail()
 }
 
 /// Helper macro for implementing the actix-web Responder trait for a type.
@@ -929,20 +2812,57 @@ macro_rules! impl_responder_for {
     )
 }
 
-/// This lets us respond from our handler methods with a model (or Result<model>), where "model" is
-/// a serde_json::Value corresponding to the Model struct.
-///
-/// This contains a serde_json::Value instead of a Model to support prefix queries; if the user
-/// gives a prefix that doesn't match all BottlerocketRelease fields, we can't construct a
-/// BottlerocketRelease since its fields aren't Option; using a Value lets us return the same
-/// structure, just not including fields the user doesn't want to see.  (Trying to deserialize
-/// those results into a Model/BottlerocketRelease would fail, so it's just intended for viewing.)
-struct ModelResponse(serde_json::Value);
-impl_responder_for!(ModelResponse, self, self.0);
-
-/// This lets us respond from our handler methods with a Settings (or Result<Settings>)
+/// PartialModel has the same structure as Model, but holds a PartialBottlerocketRelease so it can
+/// represent prefix queries; if the user gives a prefix that doesn't match all BottlerocketRelease
+/// fields, we can't construct a BottlerocketRelease since its fields aren't Option.  (Trying to
+/// deserialize those results into a Model/BottlerocketRelease would fail, so it's just intended
+/// for viewing.)
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct PartialModel {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    settings: Option<Settings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    services: Option<Services>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    configuration_files: Option<ConfigurationFiles>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    os: Option<PartialBottlerocketRelease>,
+}
+
+/// This lets us respond from our handler methods with a model (or Result<model>).
+struct ModelResponse(PartialModel);
+// Responses have always had their object keys sorted, like a serde_json Value gives us, and
+// Services and ConfigurationFiles are HashMaps, so we go through a Value for stable output.
+impl_responder_for!(
+    ModelResponse,
+    self,
+    serde_json::to_value(&self.0).expect("struct to value can't fail")
+);
+
+/// This lets us respond from our handler methods with a Settings (or Result<Settings>).  Settings
+/// are returned as CBOR if the client accepts application/cbor, and JSON otherwise.
 struct SettingsResponse(Settings);
-impl_responder_for!(SettingsResponse, self, self.0);
+impl Responder for SettingsResponse {
+    type Body = BoxBody;
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse {
+        if !accepts_cbor(req.headers()) {
+            return match serde_json::to_string(&self.0) {
+                Ok(body) => HttpResponse::Ok()
+                    .content_type("application/json")
+                    .body(body),
+                Err(e) => Error::ResponseSerialization { source: e }.into(),
+            };
+        }
+        let mut body = Vec::new();
+        match ciborium::ser::into_writer(&self.0, &mut body) {
+            Ok(()) => HttpResponse::Ok()
+                .content_type(CBOR_CONTENT_TYPE)
+                .body(body),
+            Err(e) => Error::ResponseToCbor { source: e }.into(),
+        }
+    }
+}
 
 /// This lets us respond from our handler methods with a release (or Result<release>), where
 /// "release" is a serde_json::Value corresponding to the BottlerocketRelease struct.
@@ -956,10 +2876,62 @@ impl_responder_for!(SettingsResponse, self, self.0);
 struct BottlerocketReleaseResponse(serde_json::Value);
 impl_responder_for!(BottlerocketReleaseResponse, self, self.0);
 
+struct OsFieldsResponse(Vec<String>);
+impl_responder_for!(OsFieldsResponse, self, self.0);
+
+/// This lets us respond from our handler methods with a data store version (or Result<version>)
+struct DatastoreVersionResponse(Version);
+impl_responder_for!(DatastoreVersionResponse, self, self.0.to_string());
+
 /// This lets us respond from our handler methods with a HashMap (or Result<HashMap>) for metadata
 struct MetadataResponse(HashMap<String, Value>);
 impl_responder_for!(MetadataResponse, self, self.0);
 
+struct SettingGeneratorResponse(RawSettingsGenerator);
+impl_responder_for!(SettingGeneratorResponse, self, self.0);
+
+struct CompactResponse(Vec<PathBuf>);
+impl_responder_for!(CompactResponse, self, self.0);
+
+struct DatastoreStatsResponse(controller::DatastoreStats);
+impl_responder_for!(DatastoreStatsResponse, self, self.0);
+
+struct DatastoreGenerationResponse(u64);
+impl_responder_for!(DatastoreGenerationResponse, self, self.0);
+
+/// This lets us respond from our handler methods with the result of a symlink check.
+struct SymlinkCheckResponse(SymlinkCheck);
+impl_responder_for!(SymlinkCheckResponse, self, self.0);
+
+struct CommitPlanResponse(controller::CommitPlan);
+impl_responder_for!(CommitPlanResponse, self, self.0);
+
+struct ChangeImpactResponse(controller::ChangeImpact);
+impl_responder_for!(ChangeImpactResponse, self, self.0);
+
+struct SettingProvenanceResponse(HashMap<String, controller::SettingProvenance>);
+impl_responder_for!(SettingProvenanceResponse, self, self.0);
+
+/// This lets us respond from our handler methods with the model revision.
+struct ModelRevisionResponse(ModelRevision);
+impl_responder_for!(ModelRevisionResponse, self, self.0);
+
+/// This lets us respond from our handler methods with whether each requested key is populated.
+struct KeysExistResponse(BTreeMap<String, bool>);
+impl_responder_for!(KeysExistResponse, self, self.0);
+
+struct SettingsCountResponse(usize);
+impl_responder_for!(SettingsCountResponse, self, self.0);
+
+struct WeakSettingsResponse(BTreeSet<String>);
+impl_responder_for!(WeakSettingsResponse, self, self.0);
+
+struct ResetOutcomeResponse(controller::ResetOutcome);
+impl_responder_for!(ResetOutcomeResponse, self, self.0);
+
+struct KeyComparisonResponse(controller::KeyComparison);
+impl_responder_for!(KeyComparisonResponse, self, self.0);
+
 /// This lets us respond from our handler methods with a Services (or Result<Services>)
 struct ServicesResponse(Services);
 impl_responder_for!(ServicesResponse, self, self.0);
@@ -973,14 +2945,493 @@ impl_responder_for!(UpdateStatusResponse, self, self.0);
 struct ConfigurationFilesResponse(ConfigurationFiles);
 impl_responder_for!(ConfigurationFilesResponse, self, self.0);
 
+struct TemplateContextResponse(serde_json::Value);
+impl_responder_for!(TemplateContextResponse, self, self.0);
+
+struct ConfigurationFileProblemsResponse(Vec<controller::ConfigurationFileProblem>);
+impl_responder_for!(ConfigurationFileProblemsResponse, self, self.0);
+
+/// The result of committing a transaction: the keys that changed, and whether any of them need a
+/// reboot to take effect.  The changed keys are the response body, like other commits, and the
+/// reboot flag is given in a header so clients expecting a list of keys aren't affected.
+#[derive(Debug)]
+struct CommitResponse {
+    changed: HashSet<Key>,
+    reboot_required: bool,
+}
+
+impl Responder for CommitResponse {
+    type Body = BoxBody;
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse {
+        let body = match serde_json::to_string(&self.changed) {
+            Ok(s) => s,
+            Err(e) => return Error::ResponseSerialization { source: e }.into(),
+        };
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .insert_header((REBOOT_REQUIRED_HEADER, self.reboot_required.to_string()))
+            .body(body)
+    }
+}
+
 struct ChangedKeysResponse(HashSet<Key>);
 impl_responder_for!(ChangedKeysResponse, self, self.0);
 
+struct KeyPairResultsResponse(Vec<KeyPairResult>);
+impl_responder_for!(KeyPairResultsResponse, self, self.0);
+
+/// This lets us respond from our handler methods with the outcome of deleting each transaction in
+/// a bulk delete.
+struct TransactionsDeletedResponse(BTreeMap<String, BulkDeleteOutcome>);
+impl_responder_for!(TransactionsDeletedResponse, self, self.0);
+
+/// This lets us respond from our handler methods with the result for each transaction in a bulk
+/// request.
+struct BulkStageResponse(BTreeMap<String, BulkStageOutcome>);
+impl_responder_for!(BulkStageResponse, self, self.0);
+
+struct SchemaResponse(serde_json::Value);
+impl_responder_for!(SchemaResponse, self, self.0);
+
+struct ValueValidationResponse(KeyPairOutcome);
+impl_responder_for!(ValueValidationResponse, self, self.0);
+
+struct ConstraintChecksResponse(&'static [controller::ConstraintCheck]);
+impl_responder_for!(ConstraintChecksResponse, self, self.0);
+
+struct SettingSectionsResponse(HashSet<String>);
+impl_responder_for!(SettingSectionsResponse, self, self.0);
+
 struct TransactionListResponse(HashSet<String>);
 impl_responder_for!(TransactionListResponse, self, self.0);
 
+struct AnnotatedTransactionListResponse(
+    BTreeMap<String, Option<controller::TransactionAnnotation>>,
+);
+impl_responder_for!(AnnotatedTransactionListResponse, self, self.0);
+
+struct TransactionDiffsResponse(BTreeMap<String, controller::TransactionDiff>);
+impl_responder_for!(TransactionDiffsResponse, self, self.0);
+
 struct ReportListResponse(Vec<Report>);
 impl_responder_for!(ReportListResponse, self, self.0);
 
 struct EphemeralListResponse(Vec<String>);
 impl_responder_for!(EphemeralListResponse, self, self.0);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use datastore::memory::MemoryDataStore;
+    use datastore::DataStore;
+    use maplit::hashset;
+    use serde_json::json;
+
+    #[test]
+    fn systemd_notify_retried() {
+        // A stand-in for systemd-notify that fails the first time it's run, then succeeds.
+        let dir = tempfile::TempDir::new().unwrap();
+        let stub = dir.path().join("systemd-notify");
+        let marker = dir.path().join("ran");
+        std::fs::write(
+            &stub,
+            format!(
+                "#!/bin/sh\nif [ -e {0} ]; then exit 0; fi\ntouch {0}\nexit 1\n",
+                marker.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&stub, Permissions::from_mode(0o755)).unwrap();
+
+        assert!(matches!(
+            systemd_notify(&stub),
+            Err(Error::SystemdNotifyStatus)
+        ));
+        std::fs::remove_file(&marker).unwrap();
+
+        let mut attempts = 0;
+        retry_setup("notify systemd", || {
+            attempts += 1;
+            systemd_notify(&stub)
+        })
+        .unwrap();
+        assert_eq!(attempts, 2);
+    }
+
+    #[test]
+    fn retry_setup_gives_up() {
+        let mut attempts = 0;
+        let result: Result<()> = retry_setup("fail", || {
+            attempts += 1;
+            error::SystemdNotifyStatusSnafu.fail()
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, SETUP_ATTEMPTS);
+    }
+
+    #[test]
+    fn report_command_uses_config() {
+        let config = ReportConfig {
+            bloodhound_bin: "/test/bin/bloodhound-stub".into(),
+            fips_checks: "/test/fips-checks".into(),
+            ..Default::default()
+        };
+        let params = HashMap::from([("format".to_string(), "json".to_string())]);
+
+        let cmd = report_command(&config, "fips", &params).unwrap();
+        let cmd = cmd.as_std();
+        assert_eq!(cmd.get_program(), "/test/bin/bloodhound-stub");
+        assert_eq!(
+            cmd.get_args().collect::<Vec<_>>(),
+            vec!["-f", "json", "-c", "/test/fips-checks"]
+        );
+
+        // Checks that weren't overridden keep their default.
+        let params = HashMap::from([("type".to_string(), "kubernetes".to_string())]);
+        let cmd = report_command(&config, "cis", &params).unwrap();
+        assert_eq!(
+            cmd.as_std().get_args().collect::<Vec<_>>(),
+            vec!["-c", BLOODHOUND_K8S_CHECKS]
+        );
+    }
+
+    #[test]
+    fn combined_report_params() {
+        let request: CombinedReportRequest =
+            serde_json::from_str(r#"{"reports": ["cis", "fips"], "format": "json", "level": 2}"#)
+                .unwrap();
+        assert_eq!(request.reports, vec!["cis", "fips"]);
+        assert_eq!(
+            request.params(),
+            HashMap::from([
+                ("format".to_string(), "json".to_string()),
+                ("level".to_string(), "2".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn report_command_rejects_unsupported_format() {
+        let config = ReportConfig::default();
+        let params = HashMap::from([("format".to_string(), "yaml".to_string())]);
+
+        let err = report_command(&config, "cis", &params).unwrap_err();
+        assert!(matches!(err, error::Error::ReportFormatNotSupported { .. }));
+        assert_eq!(
+            err.to_string(),
+            "Report type 'cis' does not support format 'yaml', expected one of: text, json"
+        );
+        assert_eq!(err.error_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn key_pair_values() {
+        let input = vec![
+            "settings.a=[1, 2]".to_string(),
+            "settings.b=null".to_string(),
+            "settings.c=true".to_string(),
+            "settings.d=42".to_string(),
+            "settings.e=hi".to_string(),
+            // Things that look a bit like arrays are still strings.
+            "settings.f=[1, 2".to_string(),
+        ];
+        let pairs = construct_key_pair_map(&input).unwrap();
+
+        // Each value survives a trip through the data store with its type intact.
+        let mut ds = MemoryDataStore::new();
+        ds.set_keys(&pairs, &Committed::Live).unwrap();
+        let get = |name: &str| -> serde_json::Value {
+            let key = Key::new(KeyType::Data, name).unwrap();
+            let raw = ds.get_key(&key, &Committed::Live).unwrap().unwrap();
+            serde_json::from_str(&raw).unwrap()
+        };
+        assert_eq!(get("settings.a"), json!([1, 2]));
+        assert_eq!(get("settings.b"), json!(null));
+        assert_eq!(get("settings.c"), json!(true));
+        assert_eq!(get("settings.d"), json!(42));
+        assert_eq!(get("settings.e"), json!("hi"));
+        assert_eq!(get("settings.f"), json!("[1, 2"));
+    }
+
+    fn mixed_key_pairs() -> Vec<String> {
+        vec![
+            "motd=hi".to_string(),
+            "no-equals-sign".to_string(),
+            // ntp is a structure, so a single string won't do.
+            "ntp=pool.ntp.org".to_string(),
+        ]
+    }
+
+    #[test]
+    fn key_pairs_all_or_nothing() {
+        let mut ds = MemoryDataStore::new();
+        assert!(stage_key_pairs(&mut ds, &mixed_key_pairs(), "tx", false).is_err());
+        assert!(ds
+            .list_populated_keys("", &Committed::Pending { tx: "tx".into() })
+            .unwrap()
+            .is_empty());
+
+        stage_key_pairs(&mut ds, &vec!["motd=hi".to_string()], "tx", false).unwrap();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        assert_eq!(
+            ds.get_key(&motd, &Committed::Pending { tx: "tx".into() })
+                .unwrap(),
+            Some("\"hi\"".to_string())
+        );
+    }
+
+    #[test]
+    fn key_pairs_partial() {
+        let mut ds = MemoryDataStore::new();
+        let results = stage_key_pairs_partial(&mut ds, &mixed_key_pairs(), "tx", false).unwrap();
+
+        let outcomes: Vec<_> = results.iter().map(|r| &r.outcome).collect();
+        assert_eq!(outcomes[0], &KeyPairOutcome::Ok);
+        assert!(matches!(outcomes[1], KeyPairOutcome::Error { .. }));
+        assert!(matches!(outcomes[2], KeyPairOutcome::Error { .. }));
+        assert_eq!(results[1].input, "no-equals-sign");
+
+        // Only the valid pair was staged.
+        let pending = Committed::Pending { tx: "tx".into() };
+        let keys = ds.list_populated_keys("", &pending).unwrap();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        assert_eq!(keys, hashset! { motd.clone() });
+        assert_eq!(
+            ds.get_key(&motd, &pending).unwrap(),
+            Some("\"hi\"".to_string())
+        );
+
+        // Each result says how it went.
+        assert_eq!(
+            serde_json::to_value(&results[0]).unwrap(),
+            json!({"input": "motd=hi", "status": "ok"})
+        );
+        assert_eq!(
+            serde_json::to_value(&results[1]).unwrap()["status"],
+            "error"
+        );
+    }
+
+    #[test]
+    fn key_pairs_partial_strict() {
+        let mut ds = MemoryDataStore::new();
+        let input = vec!["motd=hi".to_string(), "motdd=typo".to_string()];
+        let results = stage_key_pairs_partial(&mut ds, &input, "tx", true).unwrap();
+        assert_eq!(results[0].outcome, KeyPairOutcome::Ok);
+        assert!(matches!(results[1].outcome, KeyPairOutcome::Error { .. }));
+    }
+
+    #[test]
+    fn setting_value_validation() {
+        check_setting_value("settings.motd", &json!("hi")).unwrap();
+        // The prefix is optional, like for key=value input.
+        check_setting_value("motd", &json!("hi")).unwrap();
+        check_setting_value("ntp.time-servers", &json!(["pool.ntp.org"])).unwrap();
+        check_setting_value("ntp", &json!({"time-servers": ["pool.ntp.org"]})).unwrap();
+
+        // Values must match the modeled type.
+        check_setting_value("motd", &json!(42)).unwrap_err();
+        check_setting_value("ntp.time-servers", &json!("pool.ntp.org")).unwrap_err();
+        check_setting_value("ntp", &json!("pool.ntp.org")).unwrap_err();
+        // And keys must be in the model.
+        check_setting_value("motdd", &json!("hi")).unwrap_err();
+        check_setting_value("ntp.bogus", &json!("hi")).unwrap_err();
+    }
+
+    #[test]
+    fn validate_value_allowed_when_read_only() {
+        assert!(!is_mutating(&Method::POST, "/settings/validate-value"));
+        assert!(is_mutating(&Method::POST, "/settings/keypair"));
+        assert!(is_mutating(&Method::GET, "/exec"));
+    }
+
+    #[test]
+    fn variant_check() {
+        check_variant("aws-k8s-1.29", "aws-k8s-1.29").unwrap();
+
+        let err = check_variant("aws-k8s-1.29", "aws-ecs-2").unwrap_err();
+        assert!(matches!(err, Error::VariantMismatch { .. }));
+        assert_eq!(err.error_response().status(), StatusCode::CONFLICT);
+        assert_eq!(
+            err.to_string(),
+            "Settings are meant for variant 'aws-k8s-1.29', but this is variant 'aws-ecs-2'"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn poisoned_lock_recovers() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore_path = dir.path().join("current");
+        let data = SharedData {
+            ds: sync::RwLock::new(FilesystemDataStore::new(&datastore_path)),
+            datastore_path,
+            exec_config: ExecConfig {
+                ctr_path: CTR_PATH.into(),
+                socket_path: dir.path().join("containerd.sock"),
+                transcript_dir: None,
+                max_session_duration: None,
+                idle_timeout: None,
+                allowed_commands: None,
+                allowed_env: HashSet::new(),
+            },
+            report_config: ReportConfig::default(),
+            tx_locks: sync::Mutex::new(TransactionLocks::new()),
+            config_applier: "/usr/bin/thar-be-settings".into(),
+            update_dispatcher: controller::UPDATE_DISPATCHER_PATH.into(),
+            systemctl: controller::SYSTEMCTL_PATH.into(),
+            audit_log: None,
+            seed_dir: "/var/lib/bottlerocket/seed".into(),
+        };
+
+        // A handler panics while holding the write lock.
+        thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _guard = data.ds.write().unwrap();
+                    panic!("poisoning the lock");
+                })
+                .join()
+                .unwrap_err();
+        });
+        assert!(data.ds.is_poisoned());
+
+        // Later requests still get the lock, and the poison is cleared.
+        drop(data.read_datastore().await.unwrap());
+        assert!(!data.ds.is_poisoned());
+        drop(data.write_datastore().await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn lock_retries_while_busy() {
+        let lock = sync::Arc::new(sync::RwLock::new(1));
+
+        // Hold the lock briefly in another thread; we should get it once it's released.
+        let (held_tx, held_rx) = sync::mpsc::channel();
+        let holder = {
+            let lock = lock.clone();
+            thread::spawn(move || {
+                let _guard = lock.write().unwrap();
+                held_tx.send(()).unwrap();
+                thread::sleep(Duration::from_millis(50));
+            })
+        };
+        held_rx.recv().unwrap();
+        let guard = retry_lock(|| lock.try_read()).await.unwrap();
+        assert_eq!(*guard, 1);
+        drop(guard);
+        holder.join().unwrap();
+
+        // If it stays busy, we give up and tell the client to try again.
+        let _guard = lock.write().unwrap();
+        let err = retry_lock(|| lock.try_write()).await.unwrap_err();
+        assert!(matches!(err, Error::DataStoreBusy { .. }));
+        assert_eq!(
+            err.error_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[test]
+    fn prefix_model_output_unchanged() {
+        use bottlerocket_release::BottlerocketRelease;
+        use model::Model;
+
+        let mut ds = MemoryDataStore::new();
+        for (key, value) in [
+            ("settings.motd", "\"hi\""),
+            ("settings.ntp.time-servers", "[\"pool.ntp.org\"]"),
+            ("services.ntp.configuration-files", "[\"chrony-conf\"]"),
+            ("services.ntp.restart-commands", "[\"/bin/true\"]"),
+            ("services.motd.configuration-files", "[\"motd\"]"),
+            ("services.motd.restart-commands", "[]"),
+            ("configuration-files.motd.path", "\"/etc/motd\""),
+            (
+                "configuration-files.motd.template-path",
+                "\"/usr/share/templates/motd\"",
+            ),
+        ] {
+            let key = Key::new(KeyType::Data, key).unwrap();
+            ds.set_key(&key, value, &Committed::Live).unwrap();
+        }
+        let os = BottlerocketRelease {
+            pretty_name: "Bottlerocket OS 1.2.3 (aws-dev)".to_string(),
+            variant_id: "aws-dev".to_string(),
+            version_id: Version::new(1, 2, 3),
+            build_id: "abcdef".to_string(),
+            arch: "x86_64".to_string(),
+        };
+
+        for prefix in [
+            "s",
+            "se",
+            "settings.",
+            "settings.motd",
+            "services.",
+            "c",
+            "o",
+            "os.",
+            "os.v",
+            "os.arch",
+            "os.bogus",
+            "bogus",
+        ] {
+            let settings = controller::get_settings_prefix(&ds, prefix, &Committed::Live).unwrap();
+            let services = controller::get_services_prefix(&ds, prefix).unwrap();
+            let configuration_files =
+                controller::get_configuration_files_prefix(&ds, prefix).unwrap();
+
+            // This is how the model used to be built, splicing the os fields into a Value.
+            let mut old = serde_json::to_value(Model {
+                settings: settings.clone(),
+                services: services.clone(),
+                configuration_files: configuration_files.clone(),
+                os: None,
+            })
+            .unwrap();
+            if prefix.starts_with('o') {
+                let field_prefix = prefix.trim_start_matches('o').trim_start_matches("s.");
+                let fields: serde_json::Map<_, _> = serde_json::to_value(os.clone())
+                    .unwrap()
+                    .as_object()
+                    .unwrap()
+                    .clone()
+                    .into_iter()
+                    .filter(|(name, _)| name.starts_with(field_prefix))
+                    .collect();
+                old["os"] = fields.into();
+            }
+
+            let new = serde_json::to_value(PartialModel {
+                settings,
+                services,
+                configuration_files,
+                os: controller::filter_os_prefix(os.clone(), prefix),
+            })
+            .unwrap();
+            assert_eq!(
+                serde_json::to_string(&old).unwrap(),
+                serde_json::to_string(&new).unwrap(),
+                "prefix '{}'",
+                prefix
+            );
+        }
+    }
+
+    #[test]
+    fn stale_generation_conflicts() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let pending = Committed::Pending {
+            tx: "test".to_string(),
+        };
+        ds.set_key(&motd, "\"hi\"", &pending).unwrap();
+        let read = ds.generation().unwrap();
+        ds.set_key(&motd, "\"hello\"", &Committed::Live).unwrap();
+
+        let err = ds
+            .commit_transaction_at_generation("test", read)
+            .context(error::DataStoreSnafu { op: "commit" })
+            .unwrap_err();
+        assert_eq!(err.error_response().status(), StatusCode::CONFLICT);
+    }
+}
@@ -0,0 +1,803 @@
+//! Starts the API server on a loopback TCP port and makes requests against it, to test the server
+//! as a client would see it.
+
+use apiserver::serve;
+use apiserver::server::{BindAddress, ExecConfig, ReportConfig, ServerConfig};
+use datastore::{Committed, DataStore, FilesystemDataStore, Key, KeyType};
+use model::exec::{ClientMessage, Initialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Finds a free loopback port for the server to use.
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// The parts of a response we check.
+struct Response {
+    status: String,
+    headers: Vec<(String, String)>,
+    body: String,
+    raw_body: Vec<u8>,
+}
+
+impl Response {
+    /// Returns the value of the given header, if present; the name is matched case-insensitively.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Makes an HTTP request to the server, with any extra headers given, and returns the response.
+fn request(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> Response {
+    request_bytes(addr, method, path, headers, body.as_bytes())
+}
+
+/// Like request, but with a binary body.  The Content-Type is JSON unless given in the headers.
+fn request_bytes(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Response {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        body.len(),
+    );
+    if !headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+    {
+        request.push_str("Content-Type: application/json\r\n");
+    }
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    let mut request = request.into_bytes();
+    request.extend_from_slice(body);
+    stream.write_all(&request).unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+
+    let split = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap();
+    let head = String::from_utf8(response[..split].to_vec()).unwrap();
+    let raw_body = response[split + 4..].to_vec();
+    let body = String::from_utf8_lossy(&raw_body).into_owned();
+    let mut lines = head.lines();
+    let status = lines.next().unwrap().to_string();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+        .collect();
+    Response {
+        status,
+        headers,
+        body,
+        raw_body,
+    }
+}
+
+/// Decodes a chunked response body, returning None if it's missing the final empty chunk, meaning
+/// the response was cut short.
+fn dechunk(body: &str) -> Option<String> {
+    let mut decoded = String::new();
+    let mut rest = body;
+    loop {
+        let (size, after) = rest.split_once("\r\n")?;
+        let size = usize::from_str_radix(size, 16).ok()?;
+        if size == 0 {
+            return Some(decoded);
+        }
+        decoded.push_str(after.get(..size)?);
+        rest = after.get(size..)?.strip_prefix("\r\n")?;
+    }
+}
+
+/// Waits for the server to start accepting connections.
+async fn wait_for_server(addr: SocketAddr) {
+    for _ in 0..100 {
+        if TcpStream::connect(addr).is_ok() {
+            return;
+        }
+        actix_rt::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Server didn't start listening on {}", addr);
+}
+
+fn exec_config(dir: &Path) -> ExecConfig {
+    ExecConfig {
+        ctr_path: dir.join("ctr"),
+        socket_path: dir.join("containerd.sock"),
+        transcript_dir: None,
+        max_session_duration: None,
+        idle_timeout: None,
+        allowed_commands: None,
+        allowed_env: HashSet::new(),
+    }
+}
+
+/// Starts a server with a live 'motd' setting of "hello", returning its address.  The returned
+/// TempDir holds the data store and should be kept until the test is done.
+async fn start_server(read_only: bool) -> (TempDir, SocketAddr) {
+    start_server_with_reports(read_only, ReportConfig::default()).await
+}
+
+/// Like start_server, but generating reports with the given config.
+async fn start_server_with_reports(
+    read_only: bool,
+    report_config: ReportConfig,
+) -> (TempDir, SocketAddr) {
+    let dir = TempDir::new().unwrap();
+    let datastore_path = dir.path().join("current");
+    let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+    FilesystemDataStore::new(&datastore_path)
+        .set_key(&motd, "\"hello\"", &Committed::Live)
+        .unwrap();
+
+    let addr = free_addr();
+    actix_rt::spawn(serve(ServerConfig {
+        bind: BindAddress::Tcp(addr),
+        datastore_path,
+        threads: 1,
+        exec_config: exec_config(dir.path()),
+        report_config,
+        max_transactions: None,
+        audit_log: None,
+        seed_dir: dir.path().join("seed"),
+        config_applier: dir.path().join("thar-be-settings"),
+        read_only,
+    }));
+    wait_for_server(addr).await;
+    (dir, addr)
+}
+
+#[actix_rt::test]
+async fn settings_over_tcp() {
+    let (_dir, addr) = start_server(false).await;
+
+    // Requests block, so make them away from the runtime the server is using.
+    let responses = actix_rt::task::spawn_blocking(move || {
+        vec![
+            request(addr, "GET", "/settings", &[], ""),
+            request(
+                addr,
+                "PATCH",
+                "/settings?tx=test",
+                &[],
+                r#"{"motd": "changed"}"#,
+            ),
+            request(addr, "GET", "/tx?tx=test", &[], ""),
+        ]
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(responses[0].status, "HTTP/1.1 200 OK");
+    let settings: serde_json::Value = serde_json::from_str(&responses[0].body).unwrap();
+    assert_eq!(settings["motd"], "hello");
+
+    assert_eq!(responses[1].status, "HTTP/1.1 204 No Content");
+
+    assert_eq!(responses[2].status, "HTTP/1.1 200 OK");
+    let pending: serde_json::Value = serde_json::from_str(&responses[2].body).unwrap();
+    assert_eq!(pending["motd"], "changed");
+}
+
+#[actix_rt::test]
+async fn request_id_echoed() {
+    let (_dir, addr) = start_server(false).await;
+
+    let (given, generated, failed) = actix_rt::task::spawn_blocking(move || {
+        (
+            request(
+                addr,
+                "GET",
+                "/settings",
+                &[("X-Request-Id", "test-id-1")],
+                "",
+            ),
+            request(addr, "GET", "/settings", &[], ""),
+            request(
+                addr,
+                "POST",
+                "/tx/commit?tx=empty",
+                &[("X-Request-Id", "test-id-2")],
+                "",
+            ),
+        )
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(given.status, "HTTP/1.1 200 OK");
+    assert_eq!(given.header("x-request-id"), Some("test-id-1"));
+
+    // We make up an ID if the client doesn't give one.
+    let id = generated.header("x-request-id").unwrap();
+    assert!(!id.is_empty());
+
+    // Error responses include the ID too.
+    assert_ne!(failed.status, "HTTP/1.1 200 OK");
+    assert_eq!(failed.header("x-request-id"), Some("test-id-2"));
+}
+
+#[actix_rt::test]
+async fn read_only_refuses_changes() {
+    let (_dir, addr) = start_server(true).await;
+
+    let responses = actix_rt::task::spawn_blocking(move || {
+        vec![
+            request(addr, "PATCH", "/settings", &[], r#"{"motd": "changed"}"#),
+            request(addr, "POST", "/tx/commit", &[], ""),
+            request(addr, "DELETE", "/tx", &[], ""),
+            request(addr, "GET", "/exec", &[], ""),
+            request(addr, "GET", "/settings", &[], ""),
+            request(addr, "GET", "/tx", &[], ""),
+        ]
+    })
+    .await
+    .unwrap();
+
+    for response in &responses[..4] {
+        assert_eq!(response.status, "HTTP/1.1 403 Forbidden");
+    }
+    for response in &responses[4..] {
+        assert_eq!(response.status, "HTTP/1.1 200 OK");
+    }
+    let settings: serde_json::Value = serde_json::from_str(&responses[4].body).unwrap();
+    assert_eq!(settings["motd"], "hello");
+}
+
+#[actix_rt::test]
+async fn metadata_export() {
+    let (dir, addr) = start_server(false).await;
+    let mut ds = FilesystemDataStore::new(dir.path().join("current"));
+    let strength = Key::new(KeyType::Meta, "strength").unwrap();
+    for (name, value) in [
+        ("settings.motd", "\"weak\""),
+        ("settings.ntp.time-servers", "\"strong\""),
+        ("settings.host-containers.admin", "\"weak\""),
+    ] {
+        let key = Key::new(KeyType::Data, name).unwrap();
+        ds.set_metadata(&strength, &key, value, &Committed::Live)
+            .unwrap();
+    }
+    // Other metadata isn't included.
+    let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+    let services = Key::new(KeyType::Meta, "affected-services").unwrap();
+    ds.set_metadata(&services, &motd, "[\"motd\"]", &Committed::Live)
+        .unwrap();
+
+    let (export, missing) = actix_rt::task::spawn_blocking(move || {
+        (
+            request(addr, "GET", "/metadata/export?metadata=strength", &[], ""),
+            request(addr, "GET", "/metadata/export", &[], ""),
+        )
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(export.status, "HTTP/1.1 200 OK");
+    let exported: serde_json::Value = serde_json::from_str(&export.body).unwrap();
+    assert_eq!(
+        exported,
+        serde_json::json!({
+            "settings.motd": "weak",
+            "settings.ntp.time-servers": "strong",
+            "settings.host-containers.admin": "weak",
+        })
+    );
+
+    assert_eq!(missing.status, "HTTP/1.1 400 Bad Request");
+}
+
+#[actix_rt::test]
+async fn report_streams() {
+    // Stub bloodhounds: one that writes a large report, one that writes part of a report and then
+    // fails, and one that fails without writing anything.
+    let bin = TempDir::new().unwrap();
+    let report_size = 4 * 1024 * 1024;
+    let stub = |name: &str, script: &str| {
+        let path = bin.path().join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        ReportConfig {
+            bloodhound_bin: path,
+            ..Default::default()
+        }
+    };
+    let large = stub(
+        "large",
+        &format!("yes 'check passed' | head -c {}", report_size),
+    );
+    let partial = stub("partial", "yes 'check passed' | head -c 100000; exit 3");
+    let failing = stub("failing", "echo 'no checks found' >&2; exit 2");
+
+    for (config, expected) in [(large, Some(report_size)), (partial, None)] {
+        let (_dir, addr) = start_server_with_reports(false, config).await;
+        let response =
+            actix_rt::task::spawn_blocking(move || request(addr, "GET", "/report/cis", &[], ""))
+                .await
+                .unwrap();
+
+        assert_eq!(response.status, "HTTP/1.1 200 OK");
+        assert_eq!(response.header("transfer-encoding"), Some("chunked"));
+        // A failure partway through cuts the response short rather than completing it.
+        let body = dechunk(&response.body);
+        assert_eq!(body.as_ref().map(String::len), expected);
+        if let Some(body) = body {
+            // The output is cut at a byte count, so the last line may be partial.
+            assert!(body
+                .split('\n')
+                .all(|line| "check passed".starts_with(line)));
+        }
+    }
+
+    let (_dir, addr) = start_server_with_reports(false, failing).await;
+    let response =
+        actix_rt::task::spawn_blocking(move || request(addr, "GET", "/report/cis", &[], ""))
+            .await
+            .unwrap();
+    assert_eq!(response.status, "HTTP/1.1 500 Internal Server Error");
+    assert!(response.body.contains("no checks found"));
+}
+
+#[actix_rt::test]
+async fn transaction_locks() {
+    let (_dir, addr) = start_server(false).await;
+
+    let responses = actix_rt::task::spawn_blocking(move || {
+        let patch = |path: &str| request(addr, "PATCH", path, &[], r#"{"motd": "changed"}"#);
+        vec![
+            request(addr, "POST", "/tx/lock?tx=t&holder=a", &[], ""),
+            // Writes from anyone but the holder are refused.
+            patch("/settings?tx=t"),
+            patch("/settings?tx=t&holder=b"),
+            request(addr, "POST", "/tx/lock?tx=t&holder=b", &[], ""),
+            request(addr, "POST", "/tx/commit?tx=t&holder=b", &[], ""),
+            // Other transactions aren't affected.
+            patch("/settings?tx=other"),
+            // The holder can write and commit, which releases the lock.
+            patch("/settings?tx=t&holder=a"),
+            request(addr, "POST", "/tx/commit?tx=t&holder=a", &[], ""),
+            patch("/settings?tx=t"),
+            // Locks can also be released explicitly.
+            request(addr, "POST", "/tx/lock?tx=t&holder=a", &[], ""),
+            request(addr, "POST", "/tx/unlock?tx=t&holder=b", &[], ""),
+            request(addr, "POST", "/tx/unlock?tx=t&holder=a", &[], ""),
+            patch("/settings?tx=t&holder=b"),
+            request(addr, "POST", "/tx/lock?tx=t", &[], ""),
+        ]
+    })
+    .await
+    .unwrap();
+
+    let statuses: Vec<_> = responses.iter().map(|r| r.status.as_str()).collect();
+    assert_eq!(
+        statuses,
+        vec![
+            "HTTP/1.1 204 No Content",
+            "HTTP/1.1 423 Locked",
+            "HTTP/1.1 423 Locked",
+            "HTTP/1.1 423 Locked",
+            "HTTP/1.1 423 Locked",
+            "HTTP/1.1 204 No Content",
+            "HTTP/1.1 204 No Content",
+            "HTTP/1.1 200 OK",
+            "HTTP/1.1 204 No Content",
+            "HTTP/1.1 204 No Content",
+            "HTTP/1.1 423 Locked",
+            "HTTP/1.1 204 No Content",
+            "HTTP/1.1 204 No Content",
+            "HTTP/1.1 400 Bad Request",
+        ]
+    );
+    assert!(responses[1].body.contains("locked by 'a'"));
+}
+
+#[actix_rt::test]
+async fn bulk_transactions() {
+    let (_dir, addr) = start_server(false).await;
+
+    let responses = actix_rt::task::spawn_blocking(move || {
+        vec![
+            request(
+                addr,
+                "POST",
+                "/tx/bulk",
+                &[],
+                r#"{"one": {"motd": "first"}, "two": {"ntp": {"time-servers": ["a.example.com"]}}, "bad": {"motd": 5}}"#,
+            ),
+            request(addr, "GET", "/tx?tx=one", &[], ""),
+            request(addr, "GET", "/tx?tx=two", &[], ""),
+            // With 'atomic', one invalid transaction stops the rest.
+            request(
+                addr,
+                "POST",
+                "/tx/bulk?atomic=true",
+                &[],
+                r#"{"three": {"motd": "third"}, "bad": {"motd": 5}}"#,
+            ),
+            request(addr, "GET", "/tx/list", &[], ""),
+        ]
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(responses[0].status, "HTTP/1.1 200 OK");
+    let results: serde_json::Value = serde_json::from_str(&responses[0].body).unwrap();
+    assert_eq!(
+        results["one"],
+        serde_json::json!({"status": "ok", "changed": ["settings.motd"]})
+    );
+    assert_eq!(
+        results["two"],
+        serde_json::json!({"status": "ok", "changed": ["settings.ntp.time-servers"]})
+    );
+    assert_eq!(results["bad"]["status"], "error");
+
+    let one: serde_json::Value = serde_json::from_str(&responses[1].body).unwrap();
+    assert_eq!(one["motd"], "first");
+    let two: serde_json::Value = serde_json::from_str(&responses[2].body).unwrap();
+    assert_eq!(two["ntp"]["time-servers"][0], "a.example.com");
+
+    assert_eq!(responses[3].status, "HTTP/1.1 400 Bad Request");
+    assert!(responses[3].body.contains("bad: "));
+    let transactions: Vec<String> = serde_json::from_str(&responses[4].body).unwrap();
+    assert!(!transactions.contains(&"three".to_string()));
+    assert!(!transactions.contains(&"bad".to_string()));
+}
+
+#[actix_rt::test]
+async fn protect_generated_settings() {
+    let (dir, addr) = start_server(false).await;
+    let mut ds = FilesystemDataStore::new(dir.path().join("current"));
+    let generator = Key::new(KeyType::Meta, "setting-generator").unwrap();
+    let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+    ds.set_metadata(&generator, &motd, "\"motdgen\"", &Committed::Live)
+        .unwrap();
+
+    let responses = actix_rt::task::spawn_blocking(move || {
+        vec![
+            request(
+                addr,
+                "PATCH",
+                "/settings?protect_generated=true",
+                &[],
+                r#"{"motd": "mine"}"#,
+            ),
+            // Keys without a generator are still fine.
+            request(
+                addr,
+                "PATCH",
+                "/settings?protect_generated=true",
+                &[],
+                r#"{"ntp": {"time-servers": ["a.example.com"]}}"#,
+            ),
+            request(addr, "PATCH", "/settings", &[], r#"{"motd": "mine"}"#),
+        ]
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(responses[0].status, "HTTP/1.1 409 Conflict");
+    assert!(responses[0].body.contains("settings.motd"));
+    assert_eq!(responses[1].status, "HTTP/1.1 204 No Content");
+    assert_eq!(responses[2].status, "HTTP/1.1 204 No Content");
+}
+
+#[actix_rt::test]
+async fn settings_over_cbor() {
+    let (dir, addr) = start_server(false).await;
+    let settings = serde_json::json!({
+        "motd": "changed",
+        "ntp": {"time-servers": ["a.example.com", "b.example.com"]},
+    });
+    let mut cbor = Vec::new();
+    ciborium::ser::into_writer(&settings, &mut cbor).unwrap();
+    let mut invalid = Vec::new();
+    ciborium::ser::into_writer(&serde_json::json!({"motd": 42}), &mut invalid).unwrap();
+
+    let cbor_headers = [
+        ("Content-Type", "application/cbor"),
+        ("Accept", "application/cbor"),
+    ];
+    let json_body = settings.to_string();
+    let responses = actix_rt::task::spawn_blocking(move || {
+        vec![
+            request(addr, "PATCH", "/settings?tx=json", &[], &json_body),
+            request_bytes(addr, "PATCH", "/settings?tx=cbor", &cbor_headers, &cbor),
+            request_bytes(addr, "GET", "/tx?tx=cbor", &cbor_headers, &[]),
+            request_bytes(addr, "GET", "/settings", &cbor_headers, &[]),
+            // The model still checks CBOR input.
+            request_bytes(addr, "PATCH", "/settings?tx=bad", &cbor_headers, &invalid),
+        ]
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(responses[0].status, "HTTP/1.1 204 No Content");
+    assert_eq!(responses[1].status, "HTTP/1.1 204 No Content");
+
+    // Both transactions hold the same data.
+    let ds = FilesystemDataStore::new(dir.path().join("current"));
+    let pending = |tx: &str| {
+        let committed = Committed::Pending { tx: tx.to_string() };
+        ds.list_populated_keys("settings", &committed)
+            .unwrap()
+            .into_iter()
+            .map(|key| {
+                let value = ds.get_key(&key, &committed).unwrap();
+                (key.name().to_string(), value)
+            })
+            .collect::<BTreeMap<_, _>>()
+    };
+    assert!(!pending("cbor").is_empty());
+    assert_eq!(pending("cbor"), pending("json"));
+
+    assert_eq!(responses[2].status, "HTTP/1.1 200 OK");
+    assert_eq!(
+        responses[2].header("content-type"),
+        Some("application/cbor")
+    );
+    let pending: serde_json::Value = ciborium::de::from_reader(&responses[2].raw_body[..]).unwrap();
+    assert_eq!(pending, settings);
+
+    assert_eq!(responses[3].status, "HTTP/1.1 200 OK");
+    let live: serde_json::Value = ciborium::de::from_reader(&responses[3].raw_body[..]).unwrap();
+    assert_eq!(live["motd"], "hello");
+
+    assert_eq!(responses[4].status, "HTTP/1.1 400 Bad Request");
+}
+
+#[actix_rt::test]
+async fn commit_reports_reboot_required() {
+    let (dir, addr) = start_server(false).await;
+    let mut ds = FilesystemDataStore::new(dir.path().join("current"));
+    let reboot_required = Key::new(KeyType::Meta, "reboot-required").unwrap();
+    let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+    ds.set_metadata(&reboot_required, &motd, "true", &Committed::Live)
+        .unwrap();
+
+    let responses = actix_rt::task::spawn_blocking(move || {
+        vec![
+            request(addr, "PATCH", "/settings?tx=a", &[], r#"{"motd": "new"}"#),
+            request(addr, "POST", "/tx/commit?tx=a", &[], ""),
+            request(
+                addr,
+                "PATCH",
+                "/settings?tx=b",
+                &[],
+                r#"{"ntp": {"time-servers": ["a.example.com"]}}"#,
+            ),
+            request(addr, "POST", "/tx/commit?tx=b", &[], ""),
+        ]
+    })
+    .await
+    .unwrap();
+
+    // The body is still the list of changed keys; the flag is in a header.
+    assert_eq!(responses[1].status, "HTTP/1.1 200 OK");
+    let changed: serde_json::Value = serde_json::from_str(&responses[1].body).unwrap();
+    assert_eq!(changed, serde_json::json!(["settings.motd"]));
+    assert_eq!(responses[1].header("X-Reboot-Required"), Some("true"));
+
+    assert_eq!(responses[3].status, "HTTP/1.1 200 OK");
+    assert_eq!(responses[3].header("X-Reboot-Required"), Some("false"));
+}
+
+#[actix_rt::test]
+async fn commit_at_stale_generation_conflicts() {
+    let (dir, addr) = start_server(false).await;
+    let datastore_path = dir.path().join("current");
+
+    let responses = actix_rt::task::spawn_blocking(move || {
+        let read = request(addr, "GET", "/datastore/generation", &[], "");
+        let patch = request(addr, "PATCH", "/settings?tx=a", &[], r#"{"motd": "mine"}"#);
+        // Someone else changes live data after we read the generation.
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        FilesystemDataStore::new(datastore_path)
+            .set_key(&motd, "\"theirs\"", &Committed::Live)
+            .unwrap();
+        let commit = |generation: &str| {
+            let path = format!("/tx/commit?tx=a&expected_generation={}", generation);
+            request(addr, "POST", &path, &[], "")
+        };
+        let stale = commit(read.body.trim());
+        let current = request(addr, "GET", "/datastore/generation", &[], "");
+        let fresh = commit(current.body.trim());
+        vec![read, patch, stale, current, fresh]
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(responses[0].status, "HTTP/1.1 200 OK");
+    assert_eq!(responses[1].status, "HTTP/1.1 204 No Content");
+    // The commit based on the old generation is refused, and the transaction stays pending, so
+    // it can be committed once the client has caught up.
+    assert_eq!(responses[2].status, "HTTP/1.1 409 Conflict");
+    assert_ne!(responses[3].body, responses[0].body);
+    assert_eq!(responses[4].status, "HTTP/1.1 200 OK");
+    let changed: serde_json::Value = serde_json::from_str(&responses[4].body).unwrap();
+    assert_eq!(changed, serde_json::json!(["settings.motd"]));
+}
+
+/// Stands in for the config applier the server was started with, saving the keys it's asked to
+/// apply to the returned path.
+fn stub_applier(dir: &Path) -> std::path::PathBuf {
+    let applier = dir.join("thar-be-settings");
+    let applied = dir.join("applied");
+    fs::write(
+        &applier,
+        format!("#!/bin/sh\n/bin/cat > {}\n", applied.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&applier, fs::Permissions::from_mode(0o755)).unwrap();
+    applied
+}
+
+#[actix_rt::test]
+async fn sweep_expired_applies_removals() {
+    let (dir, addr) = start_server(false).await;
+    let applied = stub_applier(dir.path());
+    let mut ds = FilesystemDataStore::new(dir.path().join("current"));
+    let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+    let strength = Key::new(KeyType::Meta, "strength").unwrap();
+    let expires_at = Key::new(KeyType::Meta, "expires-at").unwrap();
+    ds.set_metadata(&strength, &motd, "\"weak\"", &Committed::Live)
+        .unwrap();
+    ds.set_metadata(&expires_at, &motd, "1", &Committed::Live)
+        .unwrap();
+
+    let response = actix_rt::task::spawn_blocking(move || {
+        request(addr, "POST", "/actions/sweep-expired", &[], "")
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(response.status, "HTTP/1.1 200 OK");
+    let removed: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(removed, serde_json::json!(["settings.motd"]));
+    assert_eq!(ds.get_key(&motd, &Committed::Live).unwrap(), None);
+    // The removal is applied like any other change.
+    assert_eq!(
+        fs::read_to_string(&applied).unwrap(),
+        r#"["settings.motd"]"#
+    );
+}
+
+#[actix_rt::test]
+async fn sweep_weak_applies_removals() {
+    let (dir, addr) = start_server(false).await;
+    let applied = stub_applier(dir.path());
+    let mut ds = FilesystemDataStore::new(dir.path().join("current"));
+    let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+    let strength = Key::new(KeyType::Meta, "strength").unwrap();
+    ds.set_metadata(&strength, &motd, "\"weak\"", &Committed::Live)
+        .unwrap();
+
+    let response = actix_rt::task::spawn_blocking(move || {
+        request(addr, "POST", "/actions/sweep-weak", &[], "")
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(response.status, "HTTP/1.1 200 OK");
+    let removed: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(removed, serde_json::json!(["settings.motd"]));
+    assert_eq!(ds.get_key(&motd, &Committed::Live).unwrap(), None);
+    assert_eq!(
+        fs::read_to_string(&applied).unwrap(),
+        r#"["settings.motd"]"#
+    );
+}
+
+/// Polls the given condition until it's true, giving up after a few seconds.
+fn wait_until(mut condition: impl FnMut() -> bool) -> bool {
+    for _ in 0..100 {
+        if condition() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+#[actix_rt::test]
+async fn exec_child_reaped_on_close() {
+    let (dir, addr) = start_server(false).await;
+
+    // Stand in for ctr with a script that records its pid and becomes a long-running process, like
+    // a shell the client walked away from.  The server clears the environment, so there's no PATH.
+    let pid_file = dir.path().join("pid");
+    let ctr = dir.path().join("ctr");
+    fs::write(
+        &ctr,
+        format!(
+            "#!/bin/sh\necho $$ > {}\nexec /bin/sleep 600\n",
+            pid_file.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&ctr, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let (pid, alive, reaped) = actix_rt::task::spawn_blocking(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"GET /exec HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                  Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                  Sec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let mut handshake = Vec::new();
+        let mut byte = [0; 1];
+        while !handshake.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            handshake.push(byte[0]);
+        }
+        assert!(handshake.starts_with(b"HTTP/1.1 101"));
+
+        let init = serde_json::to_vec(&ClientMessage::Initialize(Initialize {
+            command: vec!["/bin/sh".into()],
+            target: "admin".to_string(),
+            tty: None,
+            env: None,
+        }))
+        .unwrap();
+        // Send it in a text frame with a 16-bit length.  Clients must mask their frames; a zero
+        // mask leaves the payload as is.
+        let mut frame = vec![0x81, 0x80 | 126];
+        frame.extend_from_slice(&(init.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&[0; 4]);
+        frame.extend_from_slice(&init);
+        stream.write_all(&frame).unwrap();
+
+        let proc_dir = || {
+            let pid = fs::read_to_string(&pid_file).unwrap_or_default();
+            Path::new("/proc").join(pid.trim())
+        };
+        assert!(wait_until(|| pid_file.exists() && proc_dir().exists()));
+        let pid = proc_dir();
+        let alive = pid.exists();
+
+        // Close the session, as apiclient does when it's done; the server should stop the child
+        // and reap it, so it doesn't linger, even as a zombie.
+        stream.write_all(&[0x88, 0x80, 0, 0, 0, 0]).unwrap();
+        drop(stream);
+        let reaped = wait_until(|| !pid.exists());
+        (pid, alive, reaped)
+    })
+    .await
+    .unwrap();
+
+    assert!(alive, "{} wasn't running", pid.display());
+    assert!(reaped, "{} wasn't reaped", pid.display());
+}
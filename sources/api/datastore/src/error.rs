@@ -17,6 +17,33 @@ pub enum Error {
     #[snafu(display("Error serializing scalar {}: {} ", given, source))]
     SerializeScalar { given: String, source: ScalarError },
 
+    #[snafu(display("Error deserializing scalar {}: {} ", given, source))]
+    DeserializeScalar { given: String, source: ScalarError },
+
+    #[snafu(display(
+        "Error serializing metadata '{}' of key '{}': {} ",
+        meta_key,
+        data_key,
+        source
+    ))]
+    SerializeMetadata {
+        meta_key: String,
+        data_key: String,
+        source: ScalarError,
+    },
+
+    #[snafu(display(
+        "Error deserializing metadata '{}' of key '{}': {} ",
+        meta_key,
+        data_key,
+        source
+    ))]
+    DeserializeMetadata {
+        meta_key: String,
+        data_key: String,
+        source: ScalarError,
+    },
+
     #[snafu(display("Key would traverse outside data store: {}", name))]
     PathTraversal { name: String },
 
@@ -59,6 +86,47 @@ pub enum Error {
 
     #[snafu(display("Key name beyond maximum length {}: {}", name, max))]
     KeyTooLong { name: String, max: usize },
+
+    #[snafu(display("Failed to read symlink at {} to find version: {}", link.display(), source))]
+    LinkRead { link: PathBuf, source: io::Error },
+
+    #[snafu(display("Data store link '{}' points to /", path.display()))]
+    LinkToRoot { path: PathBuf },
+
+    #[snafu(display(
+        "Can't start transaction '{}', the limit of {} transactions has been reached",
+        tx,
+        max
+    ))]
+    TooManyTransactions { tx: String, max: usize },
+
+    #[snafu(display(
+        "Live data changed since it was read: expected generation {}, now at {}",
+        expected,
+        current
+    ))]
+    StaleGeneration { expected: u64, current: u64 },
+
+    #[snafu(display("Transaction '{}' is locked by '{}'", tx, holder))]
+    TransactionLocked { tx: String, holder: String },
+
+    #[snafu(display("No data store copy found in '{}'", path.display()))]
+    NoDataStoreCopy { path: PathBuf },
+
+    #[snafu(display("No data store for version {} at '{}'", version, path.display()))]
+    MissingVersion {
+        version: semver::Version,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Data store at '{}' is read-only", path.display()))]
+    ReadOnly { path: PathBuf },
+
+    #[snafu(display("Data store path '{}' contains invalid version: {}", path.display(), source))]
+    InvalidVersion {
+        path: PathBuf,
+        source: semver::Error,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -3,20 +3,47 @@
 //!
 //! Data is kept in files with paths resembling the keys, e.g. a/b/c for a.b.c, and metadata is
 //! kept in a suffixed file next to the data, e.g. a/b/c.meta for metadata "meta" about a.b.c
+//!
+//! Writes through one FilesystemDataStore take `&mut self`, so callers sharing a handle, like the
+//! API server, already serialize them.  Other handles to the same directory, including ones in
+//! other processes, can write at the same time.  Each key is its own file, and every file is
+//! written to a temporary file next to it and renamed into place, so concurrent writes to
+//! different keys don't interfere, concurrent writes to the same key leave one whole value (the
+//! last rename wins), and readers never see a partial value.  This doesn't need file locks.
+//! Operations that touch several keys, like commits, aren't atomic with respect to other writers.
+//!
+//! The generation of the live data is kept in a file named "generation" next to the live and
+//! pending directories, so every handle to the data store sees the same generation.  It's bumped
+//! after each write to live data, so a handle that reads it after another's write sees a new
+//! generation; concurrent writers may bump it to the same number, which is still a change.
 
 use log::{debug, error, trace};
 use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use semver::Version;
+use serde::Serialize;
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io;
 use std::path::{self, Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
 use walkdir::{DirEntry, WalkDir};
 
 use super::key::{Key, KeyType};
 use super::{error, Committed, DataStore, Result};
 
 const METADATA_KEY_PREFIX: &str = ".";
+const ANNOTATION_SUFFIX: &str = ".annotation";
+const GENERATION_FILE: &str = "generation";
+// Temporary files are named by adding this to the name of the file being written.  Path
+// components are percent-encoded and '~' isn't a valid key character, so a temporary file left
+// behind by a crash is never mistaken for a key.
+const TEMP_FILE_SUFFIX: &str = ".~tmp";
+
+// Counts temporary files written by this process, so each one has a unique name.
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 // This describes the set of characters we encode when making the filesystem path for a given key.
 // Any non-ASCII characters, plus these ones, will be encoded.
@@ -25,10 +52,52 @@ const METADATA_KEY_PREFIX: &str = ".";
 // allowed in a Key.
 const ENCODE_CHARACTERS: &AsciiSet = &NON_ALPHANUMERIC.remove(b'_').remove(b'-');
 
+/// Parses the version from the name of a data store copy, like "v1.5.2_0123456789abcdef".
+fn copy_version(name: &str) -> Option<Version> {
+    let (version, rando) = name.rsplit_once('_')?;
+    if rando.is_empty() {
+        return None;
+    }
+    Version::parse(version.strip_prefix('v').unwrap_or(version)).ok()
+}
+
+/// The names of the version links from 'current' down to the patch version link, in the order
+/// they're followed; the patch link points to the data store copy.
+const VERSION_LINK_LEVELS: [&str; 4] = ["current", "major", "minor", "patch"];
+
+/// A link in the version symlink chain of a data store directory, and where it points.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionLink {
+    pub link: PathBuf,
+    pub target: PathBuf,
+}
+
+/// The result of checking the version symlink chain of a data store directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SymlinkCheck {
+    /// The links that were followed successfully, starting from 'current'.
+    pub links: Vec<VersionLink>,
+    /// The first link in the chain that's missing, or dangling if it has a target, if any.
+    pub broken: Option<BrokenLink>,
+}
+
+/// A link in the version symlink chain that couldn't be followed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BrokenLink {
+    /// Which link in the chain this is: current, major, minor, or patch.
+    pub level: &'static str,
+    pub link: PathBuf,
+    /// Where the link points, if it exists.
+    pub target: Option<PathBuf>,
+}
+
 #[derive(Debug)]
 pub struct FilesystemDataStore {
     live_path: PathBuf,
     pending_base_path: PathBuf,
+    generation_path: PathBuf,
+    max_transactions: Option<usize>,
+    read_only: bool,
 }
 
 impl FilesystemDataStore {
@@ -36,7 +105,322 @@ impl FilesystemDataStore {
         FilesystemDataStore {
             live_path: base_path.as_ref().join("live"),
             pending_base_path: base_path.as_ref().join("pending"),
+            generation_path: base_path.as_ref().join(GENERATION_FILE),
+            max_transactions: None,
+            read_only: false,
+        }
+    }
+
+    /// Opens the data store copy for the given version in the given data store directory, as
+    /// found through the version's link, e.g. "v1.5.2".  This is useful for looking at settings
+    /// as they were before an upgrade.  The returned data store is read-only; any attempt to
+    /// write to it fails, so the previous version is still intact for a rollback.
+    pub fn at_version<P: AsRef<Path>>(
+        datastore_dir: P,
+        version: &Version,
+    ) -> Result<FilesystemDataStore> {
+        let path = datastore_dir.as_ref().join(format!("v{}", version));
+        ensure!(
+            path.is_dir(),
+            error::MissingVersionSnafu {
+                version: version.clone(),
+                path
+            }
+        );
+        Ok(Self::new(path).read_only())
+    }
+
+    /// Makes the data store read-only; any attempt to write to it fails.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Limits the number of transactions that can be pending at once; writes that would start a
+    /// new transaction beyond the limit will fail until a transaction is committed or deleted.
+    pub fn with_max_transactions(mut self, max: usize) -> Self {
+        self.max_transactions = Some(max);
+        self
+    }
+
+    /// Removes data store copies from the given data store directory that aren't used by the
+    /// live version, keeping the `keep_versions` most recent other copies so a rollback still
+    /// has somewhere to go.  Copies are directories named like "v1.5.2_0123456789abcdef", as
+    /// created by migrations; copies that a version link points to are preferred, then newer
+    /// versions.  Version links left dangling by the removal are removed too.
+    ///
+    /// Returns the removed paths.
+    pub fn compact<P: AsRef<Path>>(datastore_dir: P, keep_versions: usize) -> Result<Vec<PathBuf>> {
+        let datastore_dir = datastore_dir.as_ref();
+        // The live copy is wherever the full symlink chain from 'current' leads.
+        let current = datastore_dir.join("current");
+        let live = fs::canonicalize(&current).context(error::IoSnafu { path: &current })?;
+
+        let mut links = Vec::new();
+        let mut copies = Vec::new();
+        for entry in fs::read_dir(datastore_dir).context(error::IoSnafu {
+            path: datastore_dir,
+        })? {
+            let entry = entry.context(error::IoSnafu {
+                path: datastore_dir,
+            })?;
+            let path = entry.path();
+            let file_type = entry.file_type().context(error::IoSnafu { path: &path })?;
+            if file_type.is_symlink() {
+                links.push(path);
+                continue;
+            }
+            // Leave alone anything that doesn't look like a data store copy.
+            let version = match path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(copy_version)
+            {
+                Some(version) if file_type.is_dir() => version,
+                _ => continue,
+            };
+            let modified = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .context(error::IoSnafu { path: &path })?;
+            copies.push((path, version, modified));
+        }
+
+        // Links that don't resolve are already broken; they don't keep anything alive.
+        let linked: HashSet<PathBuf> = links
+            .iter()
+            .filter_map(|link| fs::canonicalize(link).ok())
+            .collect();
+
+        let mut candidates = Vec::new();
+        for (path, version, modified) in copies {
+            let canonical = fs::canonicalize(&path).context(error::IoSnafu { path: &path })?;
+            if canonical == live {
+                continue;
+            }
+            candidates.push((linked.contains(&canonical), version, modified, path));
+        }
+        // Most preferred first.
+        candidates.sort_by(|a, b| b.cmp(a));
+
+        let mut removed = Vec::new();
+        for (_, _, _, path) in candidates.into_iter().skip(keep_versions) {
+            debug!("Removing old data store copy {}", path.display());
+            fs::remove_dir_all(&path).context(error::IoSnafu { path: &path })?;
+            removed.push(path);
+        }
+
+        if !removed.is_empty() {
+            // Links are chained, e.g. minor -> patch -> copy, so removing one may break another;
+            // repeat until none are left dangling.
+            loop {
+                let mut removed_link = false;
+                for link in &links {
+                    if link.symlink_metadata().is_ok() && fs::metadata(link).is_err() {
+                        debug!("Removing dangling version link {}", link.display());
+                        fs::remove_file(link).context(error::IoSnafu { path: link })?;
+                        removed.push(link.clone());
+                        removed_link = true;
+                    }
+                }
+                if !removed_link {
+                    break;
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Follows the current -> major -> minor -> patch version symlink chain in the given data
+    /// store directory, and reports the first link that's missing or doesn't lead anywhere, if
+    /// any.  A chain isn't complete unless the patch link leads to a directory.
+    pub fn check_symlinks<P: AsRef<Path>>(datastore_dir: P) -> Result<SymlinkCheck> {
+        let datastore_dir = datastore_dir.as_ref();
+        let mut links = Vec::new();
+        let mut link = datastore_dir.join("current");
+        for level in VERSION_LINK_LEVELS {
+            let target = match fs::read_link(&link) {
+                Ok(target) => target,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::NotFound | io::ErrorKind::InvalidInput
+                    ) =>
+                {
+                    // Missing, or not a link at all.
+                    return Ok(SymlinkCheck {
+                        links,
+                        broken: Some(BrokenLink {
+                            level,
+                            link,
+                            target: None,
+                        }),
+                    });
+                }
+                Err(e) => return Err(e).context(error::IoSnafu { path: link }),
+            };
+            let next = datastore_dir.join(&target);
+            let leads_somewhere = if level == "patch" {
+                next.is_dir()
+            } else {
+                next.symlink_metadata().is_ok()
+            };
+            if !leads_somewhere {
+                return Ok(SymlinkCheck {
+                    links,
+                    broken: Some(BrokenLink {
+                        level,
+                        link,
+                        target: Some(target),
+                    }),
+                });
+            }
+            links.push(VersionLink { link, target });
+            link = next;
+        }
+        Ok(SymlinkCheck {
+            links,
+            broken: None,
+        })
+    }
+
+    /// If the version symlink chain in the given data store directory is broken, points it at
+    /// the newest data store copy in the directory, as found by the version in its name, e.g.
+    /// "v1.5.2_0123456789abcdef".  Each link is replaced atomically.  Returns the version the
+    /// chain now points to, or None if the chain wasn't broken.  If the chain is broken and
+    /// there's no copy to point to, returns a NoDataStoreCopy error.
+    pub fn repair_symlinks<P: AsRef<Path>>(datastore_dir: P) -> Result<Option<Version>> {
+        let datastore_dir = datastore_dir.as_ref();
+        if Self::check_symlinks(datastore_dir)?.broken.is_none() {
+            return Ok(None);
+        }
+
+        let mut newest: Option<(Version, SystemTime, String)> = None;
+        for entry in fs::read_dir(datastore_dir).context(error::IoSnafu {
+            path: datastore_dir,
+        })? {
+            let entry = entry.context(error::IoSnafu {
+                path: datastore_dir,
+            })?;
+            let path = entry.path();
+            let file_type = entry.file_type().context(error::IoSnafu { path: &path })?;
+            let name = match entry.file_name().into_string() {
+                Ok(name) if file_type.is_dir() => name,
+                _ => continue,
+            };
+            let version = match copy_version(&name) {
+                Some(version) => version,
+                None => continue,
+            };
+            let modified = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .context(error::IoSnafu { path: &path })?;
+            let candidate = (version, modified, name);
+            if newest.as_ref().is_none_or(|n| candidate > *n) {
+                newest = Some(candidate);
+            }
+        }
+        let (version, _, copy) = newest.context(error::NoDataStoreCopySnafu {
+            path: datastore_dir,
+        })?;
+
+        let patch = format!("v{}.{}.{}", version.major, version.minor, version.patch);
+        let minor = format!("v{}.{}", version.major, version.minor);
+        let major = format!("v{}", version.major);
+        debug!("Repairing version links to point to {}", copy);
+        // Link from the bottom up, so each link points at something by the time it's swapped in.
+        for (name, target) in [
+            (&patch, &copy),
+            (&minor, &patch),
+            (&major, &minor),
+            (&"current".to_string(), &major),
+        ] {
+            let link = datastore_dir.join(name);
+            let temp = datastore_dir.join(format!(".{}.repair", name));
+            // Remove any temporary link left by an earlier failed repair.
+            match fs::remove_file(&temp) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).context(error::IoSnafu { path: temp }),
+            }
+            std::os::unix::fs::symlink(target, &temp).context(error::IoSnafu { path: &temp })?;
+            fs::rename(&temp, &link).context(error::IoSnafu { path: &link })?;
+        }
+        Ok(Some(version))
+    }
+
+    /// Returns the path of the annotation file for the given transaction.  It's next to the
+    /// transaction directory rather than inside it, so it isn't mistaken for a key.  Encoded
+    /// transaction names can't contain a '.', so this can't collide with a transaction directory.
+    fn annotation_path<S: AsRef<str>>(&self, transaction: S) -> PathBuf {
+        let encoded = encode_path_component(transaction);
+        self.pending_base_path
+            .join(format!("{}{}", encoded, ANNOTATION_SUFFIX))
+    }
+
+    /// Removes the annotation file for the given pending transaction, if any.
+    fn remove_annotation(&self, committed: &Committed) -> Result<()> {
+        let path = match committed {
+            Committed::Pending { tx } => self.annotation_path(tx),
+            Committed::Live => return Ok(()),
+        };
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).context(error::IoSnafu { path }),
+        }
+    }
+
+    /// Records that the given transaction, if any, was used at the given time, by updating the
+    /// modification time of its directory.  Writes deep in the transaction don't otherwise change
+    /// it.
+    fn touch(&self, committed: &Committed, now: SystemTime) -> Result<()> {
+        if let Committed::Pending { .. } = committed {
+            let path = self.base_path(committed);
+            let dir = fs::File::open(&path).context(error::IoSnafu { path: &path })?;
+            dir.set_modified(now)
+                .context(error::IoSnafu { path: &path })?;
+        }
+        Ok(())
+    }
+
+    /// Notes a write to the given data, moving to a new generation if it was live.
+    fn bump_generation(&self, committed: &Committed) -> Result<()> {
+        if *committed == Committed::Live {
+            let generation = self.generation()? + 1;
+            write_file_mkdir(self.generation_path.clone(), generation.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Confirms that this data store may be written to, i.e. that it wasn't opened read-only.
+    fn check_writable(&self) -> Result<()> {
+        ensure!(
+            !self.read_only,
+            error::ReadOnlySnafu {
+                path: &self.live_path
+            }
+        );
+        Ok(())
+    }
+
+    /// Confirms that writing to the given transaction won't exceed our transaction limit.
+    fn check_transaction_limit(&self, committed: &Committed) -> Result<()> {
+        let (tx, max) = match (committed, self.max_transactions) {
+            (Committed::Pending { tx }, Some(max)) => (tx, max),
+            _ => return Ok(()),
+        };
+        // Writing to an existing transaction is always fine.
+        if self.base_path(committed).exists() {
+            return Ok(());
         }
+        ensure!(
+            self.list_transactions()?.len() < max,
+            error::TooManyTransactionsSnafu { tx, max }
+        );
+        Ok(())
     }
 
     /// Returns the appropriate filesystem path for pending or live data.
@@ -120,6 +504,7 @@ impl FilesystemDataStore {
     where
         P: AsRef<Path>,
     {
+        self.check_writable()?;
         let path = path.as_ref();
 
         // Remove the file.  If it doesn't exist, we're still OK.
@@ -131,6 +516,7 @@ impl FilesystemDataStore {
                 }
             }
         }
+        self.bump_generation(committed)?;
 
         // Remove the directory if it's empty, i.e. if the setting we removed was the last setting
         // in that prefix.  Continue up the tree until the base, in case it was the only thing in
@@ -217,7 +603,8 @@ fn read_file_for_key(key: &Key, path: &Path) -> Result<Option<String>> {
 }
 
 /// Helper for writing a file that makes the directory tree beforehand, so we can handle
-/// arbitrarily dotted keys without needing to create fixed structure first.
+/// arbitrarily dotted keys without needing to create fixed structure first.  The data is written
+/// to a temporary file that's renamed into place, so the write is atomic; see the module docs.
 fn write_file_mkdir<S: AsRef<str>>(path: PathBuf, data: S) -> Result<()> {
     // create key prefix directory if necessary
     let dirname = path.parent().with_context(|| error::InternalSnafu {
@@ -228,7 +615,27 @@ fn write_file_mkdir<S: AsRef<str>>(path: PathBuf, data: S) -> Result<()> {
     })?;
     fs::create_dir_all(dirname).context(error::IoSnafu { path: dirname })?;
 
-    fs::write(&path, data.as_ref().as_bytes()).context(error::IoSnafu { path: &path })
+    let mut temp = path.clone().into_os_string();
+    temp.push(format!(
+        "{}-{}-{}",
+        TEMP_FILE_SUFFIX,
+        process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp = PathBuf::from(temp);
+    fs::write(&temp, data.as_ref().as_bytes()).context(error::IoSnafu { path: &temp })?;
+    if let Err(e) = fs::rename(&temp, &path) {
+        // Don't leave the temporary file behind if we couldn't move it into place.
+        if let Err(remove_err) = fs::remove_file(&temp) {
+            error!(
+                "Failed to remove temporary file {}: {}",
+                temp.display(),
+                remove_err
+            );
+        }
+        return Err(e).context(error::IoSnafu { path });
+    }
+    Ok(())
 }
 
 /// KeyPath represents the filesystem path to a data or metadata key, relative to the base path of
@@ -451,8 +858,12 @@ impl DataStore for FilesystemDataStore {
     }
 
     fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed) -> Result<()> {
+        self.check_writable()?;
+        self.check_transaction_limit(committed)?;
         let path = self.data_path(key, committed)?;
-        write_file_mkdir(path, value)
+        write_file_mkdir(path, value)?;
+        self.bump_generation(committed)?;
+        self.touch(committed, SystemTime::now())
     }
 
     fn unset_key(&mut self, key: &Key, committed: &Committed) -> Result<()> {
@@ -460,8 +871,13 @@ impl DataStore for FilesystemDataStore {
         self.delete_key_path(path, committed)
     }
 
-    fn get_metadata_raw(&self, metadata_key: &Key, data_key: &Key) -> Result<Option<String>> {
-        let path = self.metadata_path(metadata_key, data_key, &Committed::Live)?;
+    fn get_metadata_raw(
+        &self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<Option<String>> {
+        let path = self.metadata_path(metadata_key, data_key, committed)?;
         read_file_for_key(metadata_key, &path)
     }
 
@@ -470,19 +886,108 @@ impl DataStore for FilesystemDataStore {
         metadata_key: &Key,
         data_key: &Key,
         value: S,
+        committed: &Committed,
     ) -> Result<()> {
-        let path = self.metadata_path(metadata_key, data_key, &Committed::Live)?;
-        write_file_mkdir(path, value)
+        self.check_writable()?;
+        self.check_transaction_limit(committed)?;
+        let path = self.metadata_path(metadata_key, data_key, committed)?;
+        write_file_mkdir(path, value)?;
+        self.bump_generation(committed)?;
+        self.touch(committed, SystemTime::now())
     }
 
-    fn unset_metadata(&mut self, metadata_key: &Key, data_key: &Key) -> Result<()> {
-        let path = self.metadata_path(metadata_key, data_key, &Committed::Live)?;
-        self.delete_key_path(path, &Committed::Live)
+    fn unset_metadata(
+        &mut self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<()> {
+        let path = self.metadata_path(metadata_key, data_key, committed)?;
+        self.delete_key_path(path, committed)
+    }
+
+    fn unset_metadata_prefix<S1, S2>(
+        &mut self,
+        data_prefix: S1,
+        metadata_key_name: &Option<S2>,
+        committed: &Committed,
+    ) -> Result<HashSet<(Key, Key)>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let key_paths = find_populated_key_paths(self, KeyType::Meta, data_prefix, committed)?;
+
+        let mut removed = HashSet::new();
+        for key_path in key_paths {
+            let data_key = key_path.data_key;
+            let meta_key = key_path.metadata_key.context(error::InternalSnafu {
+                msg: format!("Found meta key path with no dot: {}", data_key),
+            })?;
+
+            // If the user requested specific metadata, move to the next key unless it matches.
+            if let Some(name) = metadata_key_name {
+                if name.as_ref() != meta_key.name() {
+                    continue;
+                }
+            }
+
+            let path = self.metadata_path(&meta_key, &data_key, committed)?;
+            self.delete_key_path(path, committed)?;
+            removed.insert((data_key, meta_key));
+        }
+        Ok(removed)
+    }
+
+    fn rename_metadata_key<S1, S2>(
+        &mut self,
+        from_name: S1,
+        to_name: S2,
+        committed: &Committed,
+    ) -> Result<HashSet<Key>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let from = Key::new(KeyType::Meta, from_name)?;
+        let to = Key::new(KeyType::Meta, to_name)?;
+        let mut moved = HashSet::new();
+        if from == to {
+            return Ok(moved);
+        }
+
+        let key_paths = find_populated_key_paths(self, KeyType::Meta, "", committed)?;
+        for key_path in key_paths {
+            let data_key = key_path.data_key;
+            let meta_key = key_path.metadata_key.context(error::InternalSnafu {
+                msg: format!("Found meta key path with no dot: {}", data_key),
+            })?;
+            if meta_key != from {
+                continue;
+            }
+
+            // Already confirmed key via listing keys, so an error is more serious.
+            let value = self
+                .get_metadata_raw(&from, &data_key, committed)?
+                .context(error::ListedMetaNotPresentSnafu {
+                    meta_key: from.name(),
+                    data_key: data_key.name(),
+                })?;
+            self.set_metadata(&to, &data_key, value, committed)?;
+            let path = self.metadata_path(&from, &data_key, committed)?;
+            self.delete_key_path(path, committed)?;
+            moved.insert(data_key);
+        }
+        Ok(moved)
     }
 
-    /// We commit by copying pending keys to live, then removing pending.  Something smarter (lock,
-    /// atomic flip, etc.) will be required to make the server concurrent.
-    fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    /// We commit by copying pending keys to the target, then removing pending.  Something smarter
+    /// (lock, atomic flip, etc.) will be required to make the server concurrent.
+    fn commit_transaction_to<S>(
+        &mut self,
+        transaction: S,
+        target: &Committed,
+    ) -> Result<HashSet<Key>>
     where
         S: Into<String> + AsRef<str>,
     {
@@ -491,23 +996,43 @@ impl DataStore for FilesystemDataStore {
         };
         // Get data for changed keys
         let pending_data = self.get_prefix("settings.", &pending)?;
+        let metadata_paths = find_populated_key_paths(self, KeyType::Meta, "", &pending)?;
 
-        // Nothing to do if no keys are present in pending
-        if pending_data.is_empty() {
+        // Nothing to do if no keys or metadata are present in pending
+        if pending_data.is_empty() && metadata_paths.is_empty() {
             return Ok(Default::default());
         }
 
         // Save Keys for return value
-        let pending_keys: HashSet<Key> = pending_data.keys().cloned().collect();
+        let mut pending_keys: HashSet<Key> = pending_data.keys().cloned().collect();
 
-        // Apply changes to live
-        debug!("Writing pending keys to live");
-        self.set_keys(&pending_data, &Committed::Live)?;
+        // Apply changes to the target
+        debug!("Writing pending keys to {:?}", target);
+        self.set_keys(&pending_data, target)?;
+
+        // Apply any metadata changes to the target
+        debug!(
+            "Writing {} pending metadata to {:?}",
+            metadata_paths.len(),
+            target
+        );
+        for key_path in metadata_paths {
+            let data_key = key_path.data_key;
+            let meta_key = key_path.metadata_key.context(error::InternalSnafu {
+                msg: format!("Found meta key path with no dot: {}", data_key),
+            })?;
+            let path = self.metadata_path(&meta_key, &data_key, &pending)?;
+            if let Some(value) = read_file_for_key(&meta_key, &path)? {
+                self.set_metadata(&meta_key, &data_key, value, target)?;
+                pending_keys.insert(data_key);
+            }
+        }
 
         // Remove pending
         debug!("Removing old pending keys");
         let path = self.base_path(&pending);
         fs::remove_dir_all(&path).context(error::IoSnafu { path })?;
+        self.remove_annotation(&pending)?;
 
         Ok(pending_keys)
     }
@@ -516,6 +1041,7 @@ impl DataStore for FilesystemDataStore {
     where
         S: Into<String> + AsRef<str>,
     {
+        self.check_writable()?;
         let pending = Committed::Pending {
             tx: transaction.into(),
         };
@@ -527,6 +1053,7 @@ impl DataStore for FilesystemDataStore {
         debug!("Found pending keys: {:?}", &pending_keys);
 
         // Delete pending from the filesystem, same as a commit
+        self.remove_annotation(&pending)?;
         let path = self.base_path(&pending);
         debug!("Removing transaction directory {}", path.display());
         if let Err(e) = fs::remove_dir_all(&path) {
@@ -539,6 +1066,41 @@ impl DataStore for FilesystemDataStore {
         Ok(pending_keys)
     }
 
+    /// Reads the generation from its file; a data store that hasn't had a live write since it
+    /// started keeping a generation is at generation 0.
+    fn generation(&self) -> Result<u64> {
+        let path = &self.generation_path;
+        let generation = match fs::read_to_string(path) {
+            Ok(generation) => generation,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e).context(error::IoSnafu { path }),
+        };
+        generation
+            .trim()
+            .parse()
+            .ok()
+            .context(error::CorruptionSnafu {
+                msg: format!("invalid generation '{}'", generation.trim()),
+                path,
+            })
+    }
+
+    /// Returns the total size of the files holding live data and metadata, which is what the
+    /// live data actually takes on disk, not counting directories.
+    fn live_size(&self) -> Result<u64> {
+        let walker = WalkDir::new(&self.live_path)
+            .follow_links(false)
+            .same_file_system(true);
+        let mut size = 0;
+        for entry in walker {
+            let entry = entry.context(error::ListKeysSnafu)?;
+            if entry.file_type().is_file() {
+                size += entry.metadata().context(error::ListKeysSnafu)?.len();
+            }
+        }
+        Ok(size)
+    }
+
     /// We store transactions as subdirectories of the pending data store, so to list them we list
     /// the names of the subdirectories.
     fn list_transactions(&self) -> Result<HashSet<String>> {
@@ -581,11 +1143,468 @@ impl DataStore for FilesystemDataStore {
 
         Ok(transactions)
     }
+
+    fn get_transaction_annotation<S: AsRef<str>>(&self, transaction: S) -> Result<Option<String>> {
+        let path = self.annotation_path(transaction);
+        match fs::read_to_string(&path) {
+            Ok(annotation) => Ok(Some(annotation)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context(error::IoSnafu { path }),
+        }
+    }
+
+    fn set_transaction_annotation<S1, S2>(
+        &mut self,
+        transaction: S1,
+        annotation: S2,
+    ) -> Result<bool>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.check_writable()?;
+        let pending = Committed::Pending {
+            tx: transaction.as_ref().to_string(),
+        };
+        if !self.base_path(&pending).exists() {
+            return Ok(false);
+        }
+        let path = self.annotation_path(transaction);
+        fs::write(&path, annotation.as_ref()).context(error::IoSnafu { path })?;
+        Ok(true)
+    }
+
+    fn touch_transaction<S: AsRef<str>>(
+        &mut self,
+        transaction: S,
+        now: SystemTime,
+    ) -> Result<bool> {
+        self.check_writable()?;
+        let pending = Committed::Pending {
+            tx: transaction.as_ref().to_string(),
+        };
+        if !self.base_path(&pending).exists() {
+            return Ok(false);
+        }
+        self.touch(&pending, now)?;
+        Ok(true)
+    }
+
+    /// We use the modification time of the transaction directory, which we update on each write.
+    fn transaction_last_touched<S: AsRef<str>>(
+        &self,
+        transaction: S,
+    ) -> Result<Option<SystemTime>> {
+        let path = self.base_path(&Committed::Pending {
+            tx: transaction.as_ref().to_string(),
+        });
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context(error::IoSnafu { path }),
+        };
+        let modified = metadata.modified().context(error::IoSnafu { path })?;
+        Ok(Some(modified))
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use maplit::hashset;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn concurrent_metadata_writes() {
+        let tmp = TempDir::new().unwrap();
+        let strength = Key::new(KeyType::Meta, "strength").unwrap();
+        let services = Key::new(KeyType::Meta, "affected-services").unwrap();
+        let writers = 8;
+        let rounds = 50;
+
+        // Separate handles, like separate processes, writing metadata for different keys in the
+        // same directories, plus one key they all write.
+        let shared = Key::new(KeyType::Data, "settings.shared").unwrap();
+        let handles: Vec<_> = (0..writers)
+            .map(|writer| {
+                let path = tmp.path().to_path_buf();
+                let strength = strength.clone();
+                let services = services.clone();
+                let shared = shared.clone();
+                std::thread::spawn(move || {
+                    let mut ds = FilesystemDataStore::new(path);
+                    let key = Key::new(KeyType::Data, format!("settings.a.key{}", writer)).unwrap();
+                    for round in 0..rounds {
+                        let value = format!("\"{}-{}\"", writer, round);
+                        ds.set_metadata(&strength, &key, &value, &Committed::Live)
+                            .unwrap();
+                        ds.set_metadata(&services, &key, &value, &Committed::Live)
+                            .unwrap();
+                        ds.set_metadata(&strength, &shared, &value, &Committed::Live)
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let ds = FilesystemDataStore::new(tmp.path());
+        let last = rounds - 1;
+        for writer in 0..writers {
+            let key = Key::new(KeyType::Data, format!("settings.a.key{}", writer)).unwrap();
+            let expected = Some(format!("\"{}-{}\"", writer, last));
+            for meta in [&strength, &services] {
+                assert_eq!(
+                    ds.get_metadata_raw(meta, &key, &Committed::Live).unwrap(),
+                    expected
+                );
+            }
+        }
+        // The shared key holds one writer's whole final value.
+        let value = ds
+            .get_metadata_raw(&strength, &shared, &Committed::Live)
+            .unwrap()
+            .unwrap();
+        assert!(
+            (0..writers).any(|writer| value == format!("\"{}-{}\"", writer, last)),
+            "unexpected value {}",
+            value
+        );
+        // No temporary files are left, and nothing but the written metadata is found.
+        let metadata = ds.get_metadata_prefix("", &None as &Option<&str>).unwrap();
+        assert_eq!(metadata.len(), writers + 1);
+        assert_eq!(
+            metadata.values().map(HashMap::len).sum::<usize>(),
+            writers * 2 + 1
+        );
+        for entry in WalkDir::new(tmp.path()) {
+            let entry = entry.unwrap();
+            let name = entry.file_name().to_string_lossy();
+            assert!(!name.contains(TEMP_FILE_SUFFIX), "left behind {}", name);
+        }
+    }
+
+    #[test]
+    fn temp_files_are_not_keys() {
+        let tmp = TempDir::new().unwrap();
+        let mut ds = FilesystemDataStore::new(tmp.path());
+        let key = Key::new(KeyType::Data, "settings.a").unwrap();
+        ds.set_key(&key, "\"x\"", &Committed::Live).unwrap();
+        // A temporary file left by a crash partway through a write.
+        let path = ds.data_path(&key, &Committed::Live).unwrap();
+        let mut temp = path.into_os_string();
+        temp.push(format!("{}-1-1", TEMP_FILE_SUFFIX));
+        fs::write(temp, "\"partial").unwrap();
+
+        assert_eq!(
+            ds.list_populated_keys("", &Committed::Live).unwrap(),
+            hashset!(key)
+        );
+        assert!(ds
+            .get_metadata_prefix("", &None as &Option<&str>)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn compact_removes_stale_copies() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path();
+        let link = |target: &str, name: &str| std::os::unix::fs::symlink(target, dir.join(name));
+
+        // The live version and its symlink chain.
+        fs::create_dir(dir.join("v1.5.2_aaaa")).unwrap();
+        link("v1.5.2_aaaa", "v1.5.2").unwrap();
+        link("v1.5.2", "v1.5").unwrap();
+        link("v1.5", "v1").unwrap();
+        link("v1", "current").unwrap();
+        // Older versions left by previous migrations.
+        fs::create_dir(dir.join("v1.5.1_bbbb")).unwrap();
+        link("v1.5.1_bbbb", "v1.5.1").unwrap();
+        fs::create_dir(dir.join("v1.4.0_cccc")).unwrap();
+        link("v1.4.0_cccc", "v1.4.0").unwrap();
+        link("v1.4.0", "v1.4").unwrap();
+        // Orphaned intermediate copies that nothing points to.
+        fs::create_dir(dir.join("v1.5.2_dddd")).unwrap();
+        fs::create_dir(dir.join("v1.3.0_eeee")).unwrap();
+        // Something that isn't a data store copy.
+        fs::create_dir(dir.join("other")).unwrap();
+
+        let removed: HashSet<_> = FilesystemDataStore::compact(dir, 1)
+            .unwrap()
+            .into_iter()
+            .collect();
+        let expected: HashSet<_> = [
+            "v1.4.0_cccc",
+            "v1.4.0",
+            "v1.4",
+            "v1.5.2_dddd",
+            "v1.3.0_eeee",
+        ]
+        .iter()
+        .map(|name| dir.join(name))
+        .collect();
+        assert_eq!(removed, expected);
+
+        let remaining: HashSet<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(
+            remaining,
+            hashset!(
+                "current",
+                "v1",
+                "v1.5",
+                "v1.5.2",
+                "v1.5.2_aaaa",
+                "v1.5.1",
+                "v1.5.1_bbbb",
+                "other"
+            )
+            .into_iter()
+            .map(String::from)
+            .collect()
+        );
+        assert_eq!(crate::current_version(dir).unwrap(), Version::new(1, 5, 2));
+    }
+
+    /// Builds a complete version link chain for v1.5.2 in the given directory.
+    fn link_chain(dir: &Path) {
+        let link = |target: &str, name: &str| std::os::unix::fs::symlink(target, dir.join(name));
+        fs::create_dir(dir.join("v1.5.2_aaaa")).unwrap();
+        link("v1.5.2_aaaa", "v1.5.2").unwrap();
+        link("v1.5.2", "v1.5").unwrap();
+        link("v1.5", "v1").unwrap();
+        link("v1", "current").unwrap();
+    }
+
+    #[test]
+    fn check_symlinks_complete() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path();
+        link_chain(dir);
+
+        let check = FilesystemDataStore::check_symlinks(dir).unwrap();
+        assert_eq!(check.broken, None);
+        assert_eq!(
+            check.links.last().unwrap(),
+            &VersionLink {
+                link: dir.join("v1.5.2"),
+                target: PathBuf::from("v1.5.2_aaaa"),
+            }
+        );
+        assert_eq!(check.links.len(), 4);
+        assert_eq!(FilesystemDataStore::repair_symlinks(dir).unwrap(), None);
+    }
+
+    #[test]
+    fn check_symlinks_broken() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path();
+        link_chain(dir);
+
+        // The minor link points at a patch link that's gone.
+        fs::remove_file(dir.join("v1.5.2")).unwrap();
+        let check = FilesystemDataStore::check_symlinks(dir).unwrap();
+        assert_eq!(check.links.len(), 2);
+        assert_eq!(
+            check.broken,
+            Some(BrokenLink {
+                level: "minor",
+                link: dir.join("v1.5"),
+                target: Some(PathBuf::from("v1.5.2")),
+            })
+        );
+
+        // The patch link exists, but its data store copy doesn't.
+        std::os::unix::fs::symlink("v1.5.2_gone", dir.join("v1.5.2")).unwrap();
+        let check = FilesystemDataStore::check_symlinks(dir).unwrap();
+        assert_eq!(check.broken.unwrap().level, "patch");
+
+        // No links at all.
+        let empty = TempDir::new().unwrap();
+        let check = FilesystemDataStore::check_symlinks(empty.path()).unwrap();
+        assert_eq!(
+            check.broken,
+            Some(BrokenLink {
+                level: "current",
+                link: empty.path().join("current"),
+                target: None,
+            })
+        );
+        FilesystemDataStore::repair_symlinks(empty.path()).unwrap_err();
+    }
+
+    #[test]
+    fn repair_symlinks_points_to_newest_copy() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path();
+        link_chain(dir);
+        // A newer copy, e.g. left by a migration that finished but didn't get to flip the links,
+        // and an older one.
+        fs::create_dir(dir.join("v1.6.0_bbbb")).unwrap();
+        fs::create_dir(dir.join("v1.4.0_cccc")).unwrap();
+        fs::remove_file(dir.join("v1")).unwrap();
+        assert!(FilesystemDataStore::check_symlinks(dir)
+            .unwrap()
+            .broken
+            .is_some());
+
+        assert_eq!(
+            FilesystemDataStore::repair_symlinks(dir).unwrap(),
+            Some(Version::new(1, 6, 0))
+        );
+        assert_eq!(
+            FilesystemDataStore::check_symlinks(dir).unwrap().broken,
+            None
+        );
+        assert_eq!(crate::current_version(dir).unwrap(), Version::new(1, 6, 0));
+        assert_eq!(
+            fs::canonicalize(dir.join("current")).unwrap(),
+            fs::canonicalize(dir.join("v1.6.0_bbbb")).unwrap()
+        );
+    }
+
+    #[test]
+    fn at_version_reads_previous_version() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path();
+        let link = |target: &str, name: &str| std::os::unix::fs::symlink(target, dir.join(name));
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+
+        // Two versions, each with its own live settings.
+        for (copy, name, value) in [
+            ("v1.5.1_aaaa", "v1.5.1", "\"old\""),
+            ("v1.5.2_bbbb", "v1.5.2", "\"new\""),
+        ] {
+            fs::create_dir(dir.join(copy)).unwrap();
+            link(copy, name).unwrap();
+            FilesystemDataStore::new(dir.join(copy))
+                .set_key(&motd, value, &Committed::Live)
+                .unwrap();
+        }
+        link("v1.5.2", "current").unwrap();
+
+        let mut old = FilesystemDataStore::at_version(dir, &Version::new(1, 5, 1)).unwrap();
+        assert_eq!(
+            old.get_key(&motd, &Committed::Live).unwrap(),
+            Some("\"old\"".to_string())
+        );
+
+        // Writes fail, and the old version is left as it was.
+        assert!(matches!(
+            old.set_key(&motd, "\"changed\"", &Committed::Live),
+            Err(error::Error::ReadOnly { .. })
+        ));
+        assert!(matches!(
+            old.unset_key(&motd, &Committed::Live),
+            Err(error::Error::ReadOnly { .. })
+        ));
+        assert_eq!(
+            old.get_key(&motd, &Committed::Live).unwrap(),
+            Some("\"old\"".to_string())
+        );
+
+        // A version we don't have is an error.
+        assert!(matches!(
+            FilesystemDataStore::at_version(dir, &Version::new(1, 4, 0)),
+            Err(error::Error::MissingVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn generation_shared_between_handles() {
+        let tmp = TempDir::new().unwrap();
+        let mut a = FilesystemDataStore::new(tmp.path());
+        let mut b = FilesystemDataStore::new(tmp.path());
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let pending = Committed::Pending {
+            tx: "test".to_string(),
+        };
+
+        // Pending writes don't change the generation, live writes do.
+        assert_eq!(a.generation().unwrap(), 0);
+        a.set_key(&k, "\"pending\"", &pending).unwrap();
+        assert_eq!(a.generation().unwrap(), 0);
+        let read = a.generation().unwrap();
+
+        // A live write through another handle is seen, so a commit based on the old read fails.
+        b.set_key(&k, "\"other\"", &Committed::Live).unwrap();
+        let err = a
+            .commit_transaction_at_generation("test", read)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            error::Error::StaleGeneration {
+                expected: 0,
+                current: 1
+            }
+        ));
+        assert_eq!(
+            a.get_key(&k, &pending).unwrap(),
+            Some("\"pending\"".to_string())
+        );
+
+        // Committing at the current generation works, and moves to a new one.
+        let read = a.generation().unwrap();
+        assert_eq!(
+            a.commit_transaction_at_generation("test", read).unwrap(),
+            hashset!(k.clone())
+        );
+        assert!(b.generation().unwrap() > read);
+    }
+
+    #[test]
+    fn commit_metadata_only() {
+        let tmp = TempDir::new().unwrap();
+        let mut f = FilesystemDataStore::new(tmp.path());
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let strength = Key::new(KeyType::Meta, "strength").unwrap();
+        let pending = Committed::Pending {
+            tx: "test".to_string(),
+        };
+        f.set_key(&k, "1", &Committed::Live).unwrap();
+        f.set_metadata(&strength, &k, "\"weak\"", &pending).unwrap();
+
+        assert_eq!(f.commit_transaction("test").unwrap(), hashset!(k.clone()));
+        assert_eq!(
+            f.get_metadata_raw(&strength, &k, &Committed::Live).unwrap(),
+            Some("\"weak\"".to_string())
+        );
+        assert_eq!(
+            f.get_key(&k, &Committed::Live).unwrap(),
+            Some("1".to_string())
+        );
+        assert!(f.list_transactions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn touch_prevents_expiry() {
+        let tmp = TempDir::new().unwrap();
+        let mut f = FilesystemDataStore::new(tmp.path());
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        for tx in &["tx1", "tx2"] {
+            f.set_key(&k, "1", &Committed::Pending { tx: tx.to_string() })
+                .unwrap();
+        }
+        // Anything not used in the last minute will expire, as of a few minutes from now.
+        let now = SystemTime::now() + Duration::from_secs(300);
+        let cutoff = now - Duration::from_secs(60);
+
+        assert!(f.touch_transaction("tx1", now).unwrap());
+        assert!(!f.touch_transaction("bogus", now).unwrap());
+
+        assert_eq!(
+            f.expire_transactions(cutoff).unwrap(),
+            hashset!("tx2".into())
+        );
+        assert_eq!(f.list_transactions().unwrap(), hashset!("tx1".into()));
+        assert_eq!(f.transaction_last_touched("tx2").unwrap(), None);
+    }
 
     #[test]
     fn data_path() {
@@ -20,6 +20,15 @@ The `serialization` module provides code to serialize Rust types into a mapping
 
 The `deserialization` module provides code to deserialize datastore-acceptable keys (a.b.c) and values into Rust types.
 
+# Versions
+
+The `version` module provides code to find the version of a data store on disk, based on the symlinks that point to it.
+
+# Transaction locks
+
+The `tx_lock` module provides advisory locks on transactions, so tools sharing a transaction name can keep others from writing to it.
+`MemoryDataStore` checks them itself; other users, like apiserver, keep a `TransactionLocks` and check it before writing.
+
 # Current limitations
 
 * The user (e.g. apiserver) needs to handle locking.
@@ -33,15 +42,35 @@ pub mod filesystem;
 pub mod key;
 pub mod memory;
 pub mod serialization;
+pub mod tx_lock;
+pub mod version;
 
 pub use error::{Error, Result};
 pub use filesystem::FilesystemDataStore;
 pub use key::{Key, KeyType, KEY_SEPARATOR, KEY_SEPARATOR_STR};
+pub use tx_lock::TransactionLocks;
+pub use version::{current_version, version_from_path};
 
-use log::{info, trace};
+use log::{debug, info, trace, warn};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use snafu::OptionExt;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Name of the metadata key that records the strength of a setting.
+pub const STRENGTH_METADATA_KEY: &str = "strength";
+/// Strength value for settings that should be removed on reboot.
+pub const WEAK_STRENGTH: &str = "weak";
+/// Strength value for settings that persist until they're changed; this is the default.
+pub const STRONG_STRENGTH: &str = "strong";
+/// Name of the metadata key that records when a weak setting expires, in seconds since the Unix
+/// epoch.  Weak settings without it are only removed on reboot.
+pub const EXPIRES_AT_METADATA_KEY: &str = "expires-at";
+
+/// Iterator over keys and their values, as returned by DataStore::iter_prefix.
+pub type PrefixIter<'a> = Box<dyn Iterator<Item = (Cow<'a, Key>, Cow<'a, str>)> + 'a>;
 
 /// Committed represents whether we want to look at pending (uncommitted) or live (committed) data
 /// in the datastore.
@@ -89,7 +118,12 @@ pub trait DataStore {
 
     /// Retrieve the value for a single metadata key from the datastore.  Values will inherit from
     /// earlier in the tree, if more specific values are not found later.
-    fn get_metadata(&self, metadata_key: &Key, data_key: &Key) -> Result<Option<String>> {
+    fn get_metadata(
+        &self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<Option<String>> {
         let mut result = Ok(None);
         let mut current_path = Vec::new();
 
@@ -101,7 +135,7 @@ pub trait DataStore {
                 unreachable!("Prefix of Key failed to make Key: {:?}", current_path)
             });
 
-            if let Some(md) = self.get_metadata_raw(metadata_key, &data_key)? {
+            if let Some(md) = self.get_metadata_raw(metadata_key, &data_key, committed)? {
                 result = Ok(Some(md));
             }
         }
@@ -110,22 +144,138 @@ pub trait DataStore {
 
     /// Retrieve the value for a single metadata key from the datastore, without taking into
     /// account inheritance of metadata from earlier in the tree.
-    fn get_metadata_raw(&self, metadata_key: &Key, data_key: &Key) -> Result<Option<String>>;
+    fn get_metadata_raw(
+        &self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<Option<String>>;
     /// Set the value of a single metadata key in the datastore.
     fn set_metadata<S: AsRef<str>>(
         &mut self,
         metadata_key: &Key,
         data_key: &Key,
         value: S,
+        committed: &Committed,
     ) -> Result<()>;
+
+    /// Retrieve the value for a single metadata key from the datastore, with the same inheritance
+    /// as get_metadata, and deserialize it into the requested type.
+    fn get_metadata_typed<T: DeserializeOwned>(
+        &self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<Option<T>> {
+        self.get_metadata(metadata_key, data_key, committed)?
+            .map(|value| {
+                deserialize_scalar::<_, ScalarError>(&value).context(
+                    error::DeserializeMetadataSnafu {
+                        meta_key: metadata_key.name(),
+                        data_key: data_key.name(),
+                    },
+                )
+            })
+            .transpose()
+    }
+
+    /// Serialize the given value and set it as the value of a single metadata key in the
+    /// datastore.
+    fn set_metadata_typed<T: Serialize>(
+        &mut self,
+        metadata_key: &Key,
+        data_key: &Key,
+        value: &T,
+        committed: &Committed,
+    ) -> Result<()> {
+        let value =
+            serialize_scalar::<_, ScalarError>(value).context(error::SerializeMetadataSnafu {
+                meta_key: metadata_key.name(),
+                data_key: data_key.name(),
+            })?;
+        self.set_metadata(metadata_key, data_key, value, committed)
+    }
+
     /// Removes the given metadata key from the given data key in the datastore.  If we
     /// succeeded, we return Ok(()); if the data or metadata key didn't exist, we also return
     /// Ok(()); we return Err only if we failed to check or remove the key.
-    fn unset_metadata(&mut self, metadata_key: &Key, data_key: &Key) -> Result<()>;
+    fn unset_metadata(
+        &mut self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<()>;
+    /// Removes all metadata for data keys starting with the given prefix.  If you specify
+    /// metadata_key_name, only metadata keys with that name will be removed.
+    ///
+    /// Returns the set of (data key, metadata key) pairs that were removed.
+    fn unset_metadata_prefix<S1, S2>(
+        &mut self,
+        data_prefix: S1,
+        metadata_key_name: &Option<S2>,
+        committed: &Committed,
+    ) -> Result<HashSet<(Key, Key)>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>;
+
+    /// Moves all metadata stored under the metadata key `from_name` to the metadata key
+    /// `to_name`, for every data key, replacing any existing value under `to_name`.  This is
+    /// meant for migrations that normalize metadata naming.
+    ///
+    /// Returns the set of data keys whose metadata was moved.
+    fn rename_metadata_key<S1, S2>(
+        &mut self,
+        from_name: S1,
+        to_name: S2,
+        committed: &Committed,
+    ) -> Result<HashSet<Key>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>;
 
     /// Applies pending changes from the given transaction to the live datastore.  Returns the
-    /// list of changed keys.
+    /// list of data keys whose value or metadata changed.
     fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        self.commit_transaction_to(transaction, &Committed::Live)
+    }
+
+    /// Returns the generation of the live data, a counter that increases each time live data or
+    /// metadata is written.  Callers can save it when they read, then use
+    /// commit_transaction_at_generation to make sure nothing changed before they commit.
+    fn generation(&self) -> Result<u64>;
+
+    /// Commits the given transaction to live, like commit_transaction, but only if the live data
+    /// is still at the expected generation; otherwise returns a StaleGeneration error and leaves
+    /// the transaction pending.
+    fn commit_transaction_at_generation<S>(
+        &mut self,
+        transaction: S,
+        expected: u64,
+    ) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        let current = self.generation()?;
+        ensure!(
+            current == expected,
+            error::StaleGenerationSnafu { expected, current }
+        );
+        self.commit_transaction(transaction)
+    }
+
+    /// Applies pending changes from the given transaction to the given target, which can be the
+    /// live datastore or another pending transaction, then removes the given transaction.
+    /// Returns the list of data keys whose value or metadata changed; a transaction can change
+    /// only metadata, like a setting's strength.
+    fn commit_transaction_to<S>(
+        &mut self,
+        transaction: S,
+        target: &Committed,
+    ) -> Result<HashSet<Key>>
     where
         S: Into<String> + AsRef<str>;
 
@@ -138,6 +288,52 @@ pub trait DataStore {
     /// Returns a list of the names of any pending transactions in the data store.
     fn list_transactions(&self) -> Result<HashSet<String>>;
 
+    /// Returns the annotation stored for the given transaction, if any.  Annotations are
+    /// informational, like who opened the transaction, and are removed along with the
+    /// transaction when it's committed or deleted.
+    fn get_transaction_annotation<S: AsRef<str>>(&self, transaction: S) -> Result<Option<String>>;
+
+    /// Stores an annotation for the given transaction, replacing any existing annotation.
+    /// Returns false if the transaction doesn't exist.
+    fn set_transaction_annotation<S1, S2>(
+        &mut self,
+        transaction: S1,
+        annotation: S2,
+    ) -> Result<bool>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>;
+
+    /// Marks the given transaction as used at the given time, normally now, without changing its
+    /// contents, so it isn't removed by expire_transactions.  Returns false if the transaction
+    /// doesn't exist.
+    fn touch_transaction<S: AsRef<str>>(&mut self, transaction: S, now: SystemTime)
+        -> Result<bool>;
+
+    /// Returns when the given transaction was last written or touched, or None if the
+    /// transaction doesn't exist.
+    fn transaction_last_touched<S: AsRef<str>>(&self, transaction: S)
+        -> Result<Option<SystemTime>>;
+
+    /// Removes any pending transactions that haven't been written or touched since the given
+    /// time.  Returns the names of the removed transactions.
+    fn expire_transactions(&mut self, older_than: SystemTime) -> Result<HashSet<String>> {
+        let mut expired = HashSet::new();
+        for transaction in self.list_transactions()? {
+            let last_touched = match self.transaction_last_touched(&transaction)? {
+                Some(last_touched) => last_touched,
+                // Removed since we listed it; nothing to do.
+                None => continue,
+            };
+            if last_touched < older_than {
+                debug!("Expiring transaction '{}'", transaction);
+                self.delete_transaction(transaction.as_str())?;
+                expired.insert(transaction);
+            }
+        }
+        Ok(expired)
+    }
+
     /// Set multiple data keys at once in the data store.
     ///
     /// Implementers can replace the default implementation if there's a faster way than setting
@@ -171,6 +367,97 @@ pub trait DataStore {
         Ok(())
     }
 
+    /// Removes all data keys whose names start with the given prefix, returning the removed
+    /// keys.  Metadata is left alone; use unset_metadata_prefix to clear it too.
+    ///
+    /// Implementers can replace the default implementation if there's a faster way than
+    /// listing and unsetting each key individually.
+    fn unset_prefix<S: AsRef<str>>(
+        &mut self,
+        prefix: S,
+        committed: &Committed,
+    ) -> Result<HashSet<Key>> {
+        let keys = self.list_populated_keys(prefix, committed)?;
+        self.unset_keys(&keys, committed)?;
+        Ok(keys)
+    }
+
+    /// Removes all live settings whose strength metadata marks them as weak, along with their
+    /// strength and expiry metadata.  Other metadata on those keys is left alone, as are strong
+    /// settings and settings whose strength can't be read.  Returns the set of removed data keys.
+    ///
+    /// Weak settings are meant to be removed on reboot, so this should be called early in boot.
+    fn remove_weak_settings(&mut self) -> Result<HashSet<Key>> {
+        let strength_key = Key::new(KeyType::Meta, STRENGTH_METADATA_KEY)?;
+        let strengths = self.get_metadata_prefix("", &Some(STRENGTH_METADATA_KEY))?;
+
+        let mut removed = HashSet::new();
+        for (data_key, metadata) in strengths {
+            match is_weak(metadata.get(&strength_key)) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    warn!("Skipping setting {} with invalid strength: {}", data_key, e);
+                    continue;
+                }
+            }
+
+            trace!("Removing weak setting {}", data_key);
+            unset_weak_setting(self, &data_key)?;
+            removed.insert(data_key);
+        }
+        Ok(removed)
+    }
+
+    /// Removes all live weak settings whose expiry metadata is at or before the given time, along
+    /// with their strength and expiry metadata, like remove_weak_settings.  Weak settings without
+    /// an expiry, and strong settings, are left alone, as are settings whose expiry or strength
+    /// can't be read, so one bad value doesn't stop the rest from expiring.  Returns the set of removed data keys.
+    fn remove_expired_settings(&mut self, now: SystemTime) -> Result<HashSet<Key>> {
+        let strength_key = Key::new(KeyType::Meta, STRENGTH_METADATA_KEY)?;
+        let expires_key = Key::new(KeyType::Meta, EXPIRES_AT_METADATA_KEY)?;
+        let expiries = self.get_metadata_prefix("", &Some(EXPIRES_AT_METADATA_KEY))?;
+
+        let mut removed = HashSet::new();
+        for (data_key, metadata) in expiries {
+            let value = match metadata.get(&expires_key) {
+                Some(value) => value,
+                None => continue,
+            };
+            let expires_at: u64 = match deserialize_scalar::<_, ScalarError>(value) {
+                Ok(expires_at) => expires_at,
+                Err(e) => {
+                    warn!(
+                        "Skipping setting {} with invalid expiry '{}': {}",
+                        data_key, value, e
+                    );
+                    continue;
+                }
+            };
+            if UNIX_EPOCH + Duration::from_secs(expires_at) > now {
+                continue;
+            }
+            // Only weak settings expire; strong settings stay until they're changed.
+            let strength = self.get_metadata_raw(&strength_key, &data_key, &Committed::Live)?;
+            match is_weak(strength.as_ref()) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    warn!(
+                        "Skipping expired setting {} with invalid strength: {}",
+                        data_key, e
+                    );
+                    continue;
+                }
+            }
+
+            trace!("Removing expired setting {}", data_key);
+            unset_weak_setting(self, &data_key)?;
+            removed.insert(data_key);
+        }
+        Ok(removed)
+    }
+
     /// Retrieves all keys starting with the given prefix, returning them in a Key -> value map.
     ///
     /// Can be followed up by a deserialize::from_map call to build a structure.
@@ -198,6 +485,78 @@ pub trait DataStore {
         Ok(result)
     }
 
+    /// Retrieves all keys starting with the given prefix, like get_prefix, but returns them in a
+    /// list sorted by key name, so results are stable across calls.
+    fn get_prefix_sorted<S: AsRef<str>>(
+        &self,
+        find_prefix: S,
+        committed: &Committed,
+    ) -> Result<Vec<(Key, String)>> {
+        let mut result: Vec<_> = self
+            .get_prefix(find_prefix, committed)?
+            .into_iter()
+            .collect();
+        result.sort_by(|(a, _), (b, _)| a.name().cmp(b.name()));
+        Ok(result)
+    }
+
+    /// Iterates over all keys starting with the given prefix, along with their values.  Unlike
+    /// get_prefix, implementations that hold their data in memory can yield borrowed keys and
+    /// values without building a map; by default, this falls back to get_prefix.
+    ///
+    /// Iteration order is unspecified.
+    fn iter_prefix<'a, S: AsRef<str>>(
+        &'a self,
+        find_prefix: S,
+        committed: &Committed,
+    ) -> Result<PrefixIter<'a>> {
+        let data = self.get_prefix(find_prefix, committed)?;
+        Ok(Box::new(
+            data.into_iter()
+                .map(|(key, value)| (Cow::Owned(key), Cow::Owned(value))),
+        ))
+    }
+
+    /// Returns the number of populated data keys whose names start with the given prefix, i.e.
+    /// the length of list_populated_keys.  By default, this lists the keys; implementations that
+    /// can count without building the set should override it.
+    fn count_prefix<S: AsRef<str>>(&self, prefix: S, committed: &Committed) -> Result<usize> {
+        Ok(self.list_populated_keys(prefix, committed)?.len())
+    }
+
+    /// Returns the names of the pending transactions in which the given data key is populated, to
+    /// find transactions that would overwrite each other's changes.  By default, this checks the
+    /// key in each transaction; implementations that can look it up directly should override it.
+    fn transactions_for_key(&self, key: &Key) -> Result<HashSet<String>> {
+        let mut transactions = HashSet::new();
+        for transaction in self.list_transactions()? {
+            let pending = Committed::Pending {
+                tx: transaction.clone(),
+            };
+            if self.key_populated(key, &pending)? {
+                transactions.insert(transaction);
+            }
+        }
+        Ok(transactions)
+    }
+
+    /// Returns the approximate size of the live data in bytes, for capacity planning.  By
+    /// default, this is the total length of the live data and metadata values; implementations
+    /// that can measure their actual storage should override it.
+    fn live_size(&self) -> Result<u64> {
+        let data: usize = self
+            .iter_prefix("", &Committed::Live)?
+            .map(|(_, value)| value.len())
+            .sum();
+        let metadata: usize = self
+            .get_metadata_prefix("", &None::<&str>)?
+            .values()
+            .flat_map(HashMap::values)
+            .map(String::len)
+            .sum();
+        Ok((data + metadata) as u64)
+    }
+
     /// Retrieves all metadata for data keys starting with the given prefix.  If you specify
     /// metadata_key_name, only metadata keys with that name will be returned.  Returns a
     /// mapping of each data key to its metadata, where metadata is a mapping of metadata Key to
@@ -234,12 +593,12 @@ pub trait DataStore {
                     meta_key,
                     &data_key
                 );
-                let value = self.get_metadata(&meta_key, &data_key)?.context(
-                    error::ListedMetaNotPresentSnafu {
+                let value = self
+                    .get_metadata(&meta_key, &data_key, &Committed::Live)?
+                    .context(error::ListedMetaNotPresentSnafu {
                         meta_key: meta_key.name(),
                         data_key: data_key.name(),
-                    },
-                )?;
+                    })?;
 
                 // Insert a top-level map entry for the data key if we've found metadata.
                 let data_entry = result.entry(data_key.clone()).or_insert_with(HashMap::new);
@@ -251,6 +610,28 @@ pub trait DataStore {
     }
 }
 
+/// Returns whether the given value of strength metadata, if any, marks a setting as weak.
+fn is_weak(strength: Option<&String>) -> Result<bool> {
+    match strength {
+        Some(value) => {
+            let strength: String = deserialize_scalar::<_, ScalarError>(value)
+                .context(error::DeserializeScalarSnafu { given: value })?;
+            Ok(strength == WEAK_STRENGTH)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Removes a live weak setting along with its strength and expiry metadata.
+fn unset_weak_setting<D: DataStore + ?Sized>(datastore: &mut D, data_key: &Key) -> Result<()> {
+    datastore.unset_key(data_key, &Committed::Live)?;
+    for meta_name in [STRENGTH_METADATA_KEY, EXPIRES_AT_METADATA_KEY] {
+        let meta_key = Key::new(KeyType::Meta, meta_name)?;
+        datastore.unset_metadata(&meta_key, data_key, &Committed::Live)?;
+    }
+    Ok(())
+}
+
 /////
 
 // This section ties together serialization and deserialization of scalar values, so it's in the
@@ -289,11 +670,76 @@ fn deserializer_for_scalar(scalar: &str) -> ScalarDeserializer<'_> {
 /// anything returned by the deserialization bits above.
 pub type Value = serde_json::Value;
 
+/// A value stored under a single datastore key.  This is either a scalar -- a string, number,
+/// boolean, or null -- or a structured value, meaning a list.  (Maps aren't stored under a single
+/// key; they're represented by the key tree itself.)
+///
+/// This centralizes the decision of whether user input is a scalar or a structure, so callers
+/// that accept loosely typed input, like 'key=value' pairs, all treat it the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatastoreValue(Value);
+
+impl DatastoreValue {
+    /// Interprets loosely typed user input.  Input that parses as a boolean, number, list, or null
+    /// is taken as that type; anything else, including quoted strings and maps, is taken as a
+    /// literal string, so users don't have to quote their strings.
+    pub fn from_input(input: &str) -> Self {
+        match serde_json::from_str(input) {
+            Ok(value @ (Value::Bool(_) | Value::Number(_) | Value::Array(_) | Value::Null)) => {
+                Self(value)
+            }
+            _ => Self(Value::String(input.to_string())),
+        }
+    }
+
+    /// Deserializes a value from the module-standard serialization format.
+    pub fn from_scalar_str(scalar: &str) -> std::result::Result<Self, ScalarError> {
+        deserialize_scalar(scalar).map(Self)
+    }
+
+    /// Serializes the value to the module-standard serialization format.
+    pub fn to_scalar_string(&self) -> std::result::Result<String, ScalarError> {
+        serialize_scalar(&self.0)
+    }
+
+    /// Returns the value if it's a scalar, meaning it's not a list or map.
+    pub fn as_scalar(&self) -> Option<&Value> {
+        match self.0 {
+            Value::Array(_) | Value::Object(_) => None,
+            _ => Some(&self.0),
+        }
+    }
+
+    /// Returns the value if it's structured, meaning it's a list or map.
+    pub fn as_structured(&self) -> Option<&Value> {
+        match self.0 {
+            Value::Array(_) | Value::Object(_) => Some(&self.0),
+            _ => None,
+        }
+    }
+
+    /// Returns the underlying value.
+    pub fn into_inner(self) -> Value {
+        self.0
+    }
+}
+
+impl From<Value> for DatastoreValue {
+    fn from(value: Value) -> Self {
+        Self(value)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::memory::MemoryDataStore;
-    use super::{Committed, DataStore, Key, KeyType};
+    use super::{
+        Committed, DataStore, DatastoreValue, Key, KeyType, EXPIRES_AT_METADATA_KEY,
+        STRENGTH_METADATA_KEY,
+    };
     use maplit::{hashmap, hashset};
+    use serde::{Deserialize, Serialize};
+    use std::time::{Duration, UNIX_EPOCH};
 
     #[test]
     fn set_unset_keys() {
@@ -336,14 +782,142 @@ mod test {
         let grandchild = Key::new(KeyType::Data, "a.b.c").unwrap();
 
         // Set metadata on parent
-        m.set_metadata(&meta, &parent, "value").unwrap();
+        m.set_metadata(&meta, &parent, "value", &Committed::Live)
+            .unwrap();
         // Metadata shows up on grandchild...
         assert_eq!(
-            m.get_metadata(&meta, &grandchild).unwrap(),
+            m.get_metadata(&meta, &grandchild, &Committed::Live)
+                .unwrap(),
             Some("value".to_string())
         );
         // ...but only through inheritance, not directly.
-        assert_eq!(m.get_metadata_raw(&meta, &grandchild).unwrap(), None);
+        assert_eq!(
+            m.get_metadata_raw(&meta, &grandchild, &Committed::Live)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn remove_weak_settings() {
+        let mut m = MemoryDataStore::new();
+        let strength = Key::new(KeyType::Meta, STRENGTH_METADATA_KEY).unwrap();
+        let other_meta = Key::new(KeyType::Meta, "affected-services").unwrap();
+        let weak = Key::new(KeyType::Data, "settings.weak").unwrap();
+        let strong = Key::new(KeyType::Data, "settings.strong").unwrap();
+        let plain = Key::new(KeyType::Data, "settings.plain").unwrap();
+        let bad_strength = Key::new(KeyType::Data, "settings.bad-strength").unwrap();
+
+        for key in &[&weak, &strong, &plain, &bad_strength] {
+            m.set_key(key, "\"value\"", &Committed::Live).unwrap();
+            m.set_metadata(&other_meta, key, "[\"service\"]", &Committed::Live)
+                .unwrap();
+        }
+        m.set_metadata(&strength, &weak, "\"weak\"", &Committed::Live)
+            .unwrap();
+        m.set_metadata(&strength, &strong, "\"strong\"", &Committed::Live)
+            .unwrap();
+        m.set_metadata(&strength, &bad_strength, "weak", &Committed::Live)
+            .unwrap();
+
+        assert_eq!(m.remove_weak_settings().unwrap(), hashset!(weak.clone()));
+
+        // The weak setting and its strength are gone, but its other metadata is untouched.
+        assert_eq!(m.get_key(&weak, &Committed::Live).unwrap(), None);
+        assert_eq!(
+            m.get_metadata_raw(&strength, &weak, &Committed::Live)
+                .unwrap(),
+            None
+        );
+        assert!(m
+            .get_metadata_raw(&other_meta, &weak, &Committed::Live)
+            .unwrap()
+            .is_some());
+
+        // Strong settings, settings without a strength, and settings with an unreadable strength
+        // are untouched.
+        for key in &[&strong, &plain, &bad_strength] {
+            assert!(m.get_key(key, &Committed::Live).unwrap().is_some());
+            assert!(m
+                .get_metadata_raw(&other_meta, key, &Committed::Live)
+                .unwrap()
+                .is_some());
+        }
+        assert_eq!(
+            m.get_metadata_raw(&strength, &strong, &Committed::Live)
+                .unwrap(),
+            Some("\"strong\"".to_string())
+        );
+    }
+
+    #[test]
+    fn remove_expired_settings() {
+        let mut m = MemoryDataStore::new();
+        let strength = Key::new(KeyType::Meta, STRENGTH_METADATA_KEY).unwrap();
+        let expires_at = Key::new(KeyType::Meta, EXPIRES_AT_METADATA_KEY).unwrap();
+        let expired = Key::new(KeyType::Data, "settings.expired").unwrap();
+        let unexpired = Key::new(KeyType::Data, "settings.unexpired").unwrap();
+        let no_expiry = Key::new(KeyType::Data, "settings.no-expiry").unwrap();
+        let bad_expiry = Key::new(KeyType::Data, "settings.bad-expiry").unwrap();
+        let bad_strength = Key::new(KeyType::Data, "settings.bad-strength").unwrap();
+        let strong = Key::new(KeyType::Data, "settings.strong").unwrap();
+
+        for key in &[
+            &expired,
+            &unexpired,
+            &no_expiry,
+            &bad_expiry,
+            &bad_strength,
+            &strong,
+        ] {
+            m.set_key(key, "\"value\"", &Committed::Live).unwrap();
+        }
+        for key in &[&expired, &unexpired, &no_expiry, &bad_expiry] {
+            m.set_metadata(&strength, key, "\"weak\"", &Committed::Live)
+                .unwrap();
+        }
+        m.set_metadata(&strength, &strong, "\"strong\"", &Committed::Live)
+            .unwrap();
+        m.set_metadata(&strength, &bad_strength, "weak", &Committed::Live)
+            .unwrap();
+        for (key, expiry) in [
+            (&expired, "1000"),
+            (&unexpired, "3000"),
+            (&bad_expiry, "\"soon\""),
+            (&bad_strength, "1000"),
+            (&strong, "1000"),
+        ] {
+            m.set_metadata(&expires_at, key, expiry, &Committed::Live)
+                .unwrap();
+        }
+
+        let now = UNIX_EPOCH + Duration::from_secs(2000);
+        assert_eq!(
+            m.remove_expired_settings(now).unwrap(),
+            hashset!(expired.clone())
+        );
+        assert_eq!(m.get_key(&expired, &Committed::Live).unwrap(), None);
+        for meta in [&strength, &expires_at] {
+            assert_eq!(
+                m.get_metadata_raw(meta, &expired, &Committed::Live)
+                    .unwrap(),
+                None
+            );
+        }
+
+        // Settings that haven't expired, weak settings without a valid expiry, settings without a
+        // valid strength, and strong settings are untouched.
+        for key in &[&unexpired, &no_expiry, &bad_expiry, &bad_strength, &strong] {
+            assert!(m.get_key(key, &Committed::Live).unwrap().is_some());
+        }
+
+        // Once it's past their expiry, they go too, as long as they're weak.
+        let later = UNIX_EPOCH + Duration::from_secs(3000);
+        assert_eq!(
+            m.remove_expired_settings(later).unwrap(),
+            hashset!(unexpired.clone())
+        );
+        assert!(m.get_key(&strong, &Committed::Live).unwrap().is_some());
     }
 
     #[test]
@@ -379,9 +953,9 @@ mod test {
         let mk1 = Key::new(KeyType::Meta, "metatest1").unwrap();
         let mk2 = Key::new(KeyType::Meta, "metatest2").unwrap();
         let mk3 = Key::new(KeyType::Meta, "metatest3").unwrap();
-        m.set_metadata(&mk1, &k1, "41").unwrap();
-        m.set_metadata(&mk2, &k2, "42").unwrap();
-        m.set_metadata(&mk3, &k3, "43").unwrap();
+        m.set_metadata(&mk1, &k1, "41", &Committed::Live).unwrap();
+        m.set_metadata(&mk2, &k2, "42", &Committed::Live).unwrap();
+        m.set_metadata(&mk3, &k3, "43", &Committed::Live).unwrap();
 
         // Check all metadata
         assert_eq!(
@@ -396,4 +970,109 @@ mod test {
             hashmap!(k2 => hashmap!(mk2 => "42".to_string()))
         );
     }
+
+    /// Structured setting-generator metadata, with several fields, as a typical example of
+    /// metadata that isn't a single scalar.
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    struct RawSettingsGenerator {
+        command: String,
+        strength: String,
+        skip_if_populated: bool,
+    }
+
+    #[test]
+    fn typed_metadata_round_trip() {
+        let mut m = MemoryDataStore::new();
+        let generator = RawSettingsGenerator {
+            command: "/bin/gen --flag".to_string(),
+            strength: "weak".to_string(),
+            skip_if_populated: true,
+        };
+        let parent = Key::new(KeyType::Data, "settings.a").unwrap();
+        let child = Key::new(KeyType::Data, "settings.a.b").unwrap();
+        let mk = Key::new(KeyType::Meta, "setting-generator").unwrap();
+        m.set_metadata_typed(&mk, &parent, &generator, &Committed::Live)
+            .unwrap();
+
+        // It's stored as a single serialized value.
+        assert_eq!(
+            m.get_metadata_raw(&mk, &parent, &Committed::Live).unwrap(),
+            Some(
+                r#"{"command":"/bin/gen --flag","strength":"weak","skip-if-populated":true}"#
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            m.get_metadata_typed::<RawSettingsGenerator>(&mk, &parent, &Committed::Live)
+                .unwrap(),
+            Some(generator)
+        );
+        // Typed metadata is inherited just like untyped metadata.
+        assert_eq!(
+            m.get_metadata_typed::<RawSettingsGenerator>(&mk, &child, &Committed::Live)
+                .unwrap()
+                .unwrap()
+                .command,
+            "/bin/gen --flag"
+        );
+
+        let missing = Key::new(KeyType::Data, "settings.other").unwrap();
+        assert_eq!(
+            m.get_metadata_typed::<RawSettingsGenerator>(&mk, &missing, &Committed::Live)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn typed_metadata_wrong_type() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let mk = Key::new(KeyType::Meta, "setting-generator").unwrap();
+        m.set_metadata_typed(&mk, &k, &"/bin/gen", &Committed::Live)
+            .unwrap();
+        m.get_metadata_typed::<RawSettingsGenerator>(&mk, &k, &Committed::Live)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn datastore_value_scalars() {
+        for (input, expected) in [
+            ("true", serde_json::json!(true)),
+            ("42", serde_json::json!(42)),
+            ("-5", serde_json::json!(-5)),
+            ("1.5", serde_json::json!(1.5)),
+            ("null", serde_json::json!(null)),
+            ("hi", serde_json::json!("hi")),
+            // Strings are taken literally, quotes and all.
+            ("\"hi\"", serde_json::json!("\"hi\"")),
+            // Maps aren't stored under a single key, so they're just strings.
+            ("{\"a\": 1}", serde_json::json!("{\"a\": 1}")),
+        ] {
+            let value = DatastoreValue::from_input(input);
+            assert_eq!(value.as_scalar(), Some(&expected), "{}", input);
+            assert_eq!(value.as_structured(), None, "{}", input);
+
+            let serialized = value.to_scalar_string().unwrap();
+            assert_eq!(DatastoreValue::from_scalar_str(&serialized).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn datastore_value_structured() {
+        let value = DatastoreValue::from_input("[1, \"two\", [3]]");
+        let expected = serde_json::json!([1, "two", [3]]);
+        assert_eq!(value.as_structured(), Some(&expected));
+        assert_eq!(value.as_scalar(), None);
+
+        let serialized = value.to_scalar_string().unwrap();
+        assert_eq!(serialized, "[1,\"two\",[3]]");
+        let value = DatastoreValue::from_scalar_str(&serialized).unwrap();
+        assert_eq!(value.into_inner(), expected);
+
+        // Things that only look a bit like lists are strings.
+        let value = DatastoreValue::from_input("[1, 2");
+        assert_eq!(value.as_scalar(), Some(&serde_json::json!("[1, 2")));
+    }
 }
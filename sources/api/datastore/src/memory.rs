@@ -1,11 +1,15 @@
 //! In-memory datastore for use in testing other modules.
 //!
-//! Mimics some of the decisions made for FilesystemDataStore, e.g. metadata being committed
-//! immediately.
+//! Mimics some of the decisions made for FilesystemDataStore, e.g. metadata being kept per
+//! transaction and applied to live on commit.
 
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
 
-use super::{Committed, DataStore, Key, Result};
+use snafu::ensure;
+
+use super::{error, Committed, DataStore, Key, KeyType, PrefixIter, Result, TransactionLocks};
 
 #[derive(Debug, Default)]
 pub struct MemoryDataStore {
@@ -16,6 +20,39 @@ pub struct MemoryDataStore {
     // Map of data keys to their metadata, which in turn is a mapping of metadata keys to
     // arbitrary (string/serialized) values.
     metadata: HashMap<Key, HashMap<Key, String>>,
+    // Transaction name -> (data key -> (metadata key -> metadata value))
+    pending_metadata: HashMap<String, HashMap<Key, HashMap<Key, String>>>,
+    // Transaction name -> annotation
+    annotations: HashMap<String, String>,
+    // Transaction name -> when it was last written or touched
+    touched: HashMap<String, SystemTime>,
+    // Maximum number of transactions that can be pending at once, if any.
+    max_transactions: Option<usize>,
+    // Record of data changes, if requested.
+    operation_log: Option<Vec<LoggedOperation>>,
+    // Number of writes to live data and metadata so far.
+    generation: u64,
+    // Advisory locks on transactions.
+    tx_locks: TransactionLocks,
+    // Who writes are made on behalf of, for checking transaction locks.
+    lock_holder: Option<String>,
+}
+
+/// A change to data keys recorded in the operation log of a MemoryDataStore.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Set { key: Key, value: String },
+    Unset { key: Key },
+    Commit { tx: String, changed: HashSet<Key> },
+}
+
+/// An entry in the operation log of a MemoryDataStore: what was done, when, and to which data.
+/// For a commit, `committed` is where the transaction was committed to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoggedOperation {
+    pub time: SystemTime,
+    pub committed: Committed,
+    pub operation: Operation,
 }
 
 impl MemoryDataStore {
@@ -23,6 +60,107 @@ impl MemoryDataStore {
         Default::default()
     }
 
+    /// Limits the number of transactions that can be pending at once; writes that would start a
+    /// new transaction beyond the limit will fail until a transaction is committed or deleted.
+    pub fn with_max_transactions(mut self, max: usize) -> Self {
+        self.max_transactions = Some(max);
+        self
+    }
+
+    /// Keeps a log of every set, unset, and commit of data keys, for tracing how a key got its
+    /// value while debugging.  The log grows without bound, so this is only meant for tests.
+    pub fn with_operation_log(mut self) -> Self {
+        self.operation_log = Some(Vec::new());
+        self
+    }
+
+    /// Returns the operations recorded so far, oldest first, or None if the operation log wasn't
+    /// requested.
+    pub fn operation_log(&self) -> Option<&[LoggedOperation]> {
+        self.operation_log.as_deref()
+    }
+
+    /// Locks the given transaction for the given holder, so writes to it are rejected with a
+    /// TransactionLocked error unless they're made on behalf of that holder; see set_lock_holder.
+    /// The lock is released when the transaction is committed or deleted, or by
+    /// unlock_transaction.
+    pub fn lock_transaction<S1, S2>(&mut self, transaction: S1, holder: S2) -> Result<()>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.tx_locks.acquire(transaction, holder)
+    }
+
+    /// Unlocks the given transaction, returning whether it was locked.  Fails if it's locked by
+    /// someone other than the given holder.
+    pub fn unlock_transaction<S1, S2>(&mut self, transaction: S1, holder: S2) -> Result<bool>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.tx_locks.release(transaction, holder)
+    }
+
+    /// Returns the holder of the lock on the given transaction, if it's locked.
+    pub fn transaction_lock_holder<S: AsRef<str>>(&self, transaction: S) -> Option<&str> {
+        self.tx_locks.holder(transaction)
+    }
+
+    /// Sets who later writes are made on behalf of, for checking transaction locks.  With no
+    /// holder, writes to any locked transaction are rejected.
+    pub fn set_lock_holder(&mut self, holder: Option<String>) {
+        self.lock_holder = holder;
+    }
+
+    /// Confirms that the current lock holder, if any, may write to the given transaction, if any.
+    fn check_transaction_lock(&self, committed: &Committed) -> Result<()> {
+        match committed {
+            Committed::Live => Ok(()),
+            Committed::Pending { tx } => self.tx_locks.check(tx, self.lock_holder.as_deref()),
+        }
+    }
+
+    /// Notes a write to the given data, moving to a new generation if it was live.
+    fn bump_generation(&mut self, committed: &Committed) {
+        if *committed == Committed::Live {
+            self.generation += 1;
+        }
+    }
+
+    /// Adds the given operation to the operation log, if we're keeping one.
+    fn log_operation(&mut self, committed: &Committed, operation: Operation) {
+        if let Some(log) = &mut self.operation_log {
+            log.push(LoggedOperation {
+                time: SystemTime::now(),
+                committed: committed.clone(),
+                operation,
+            });
+        }
+    }
+
+    /// Records that the given transaction, if any, was used now.
+    fn touch(&mut self, committed: &Committed) {
+        if let Committed::Pending { tx } = committed {
+            self.touched.insert(tx.clone(), SystemTime::now());
+        }
+    }
+
+    /// Confirms that writing to the given transaction won't exceed our transaction limit.
+    fn check_transaction_limit(&self, committed: &Committed) -> Result<()> {
+        let (tx, max) = match (committed, self.max_transactions) {
+            (Committed::Pending { tx }, Some(max)) => (tx, max),
+            _ => return Ok(()),
+        };
+        // Writing to an existing transaction is always fine.
+        let transactions = self.list_transactions()?;
+        ensure!(
+            transactions.contains(tx) || transactions.len() < max,
+            error::TooManyTransactionsSnafu { tx, max }
+        );
+        Ok(())
+    }
+
     fn dataset(&self, committed: &Committed) -> Option<&HashMap<Key, String>> {
         match committed {
             Committed::Live => Some(&self.live),
@@ -36,6 +174,23 @@ impl MemoryDataStore {
             Committed::Pending { tx } => self.pending.entry(tx.clone()).or_default(),
         }
     }
+
+    fn metadataset(&self, committed: &Committed) -> Option<&HashMap<Key, HashMap<Key, String>>> {
+        match committed {
+            Committed::Live => Some(&self.metadata),
+            Committed::Pending { tx } => self.pending_metadata.get(tx),
+        }
+    }
+
+    fn metadataset_mut(
+        &mut self,
+        committed: &Committed,
+    ) -> Option<&mut HashMap<Key, HashMap<Key, String>>> {
+        match committed {
+            Committed::Live => Some(&mut self.metadata),
+            Committed::Pending { tx } => self.pending_metadata.get_mut(tx),
+        }
+    }
 }
 
 impl DataStore for MemoryDataStore {
@@ -54,6 +209,85 @@ impl DataStore for MemoryDataStore {
             .collect())
     }
 
+    /// Counts matching keys in place, rather than cloning them into a set.
+    fn count_prefix<S: AsRef<str>>(&self, prefix: S, committed: &Committed) -> Result<usize> {
+        Ok(self.dataset(committed).map_or(0, |dataset| {
+            dataset
+                .keys()
+                .filter(|k| k.name().starts_with(prefix.as_ref()))
+                .count()
+        }))
+    }
+
+    /// Checks each transaction's data directly, rather than going through key_populated.
+    fn transactions_for_key(&self, key: &Key) -> Result<HashSet<String>> {
+        Ok(self
+            .pending
+            .iter()
+            .filter(|(_, dataset)| dataset.contains_key(key))
+            .map(|(transaction, _)| transaction.clone())
+            .collect())
+    }
+
+    fn generation(&self) -> Result<u64> {
+        Ok(self.generation)
+    }
+
+    /// Sums the lengths of our live values in place, rather than collecting them first.
+    fn live_size(&self) -> Result<u64> {
+        let data: usize = self.live.values().map(String::len).sum();
+        let metadata: usize = self
+            .metadata
+            .values()
+            .flat_map(HashMap::values)
+            .map(String::len)
+            .sum();
+        Ok((data + metadata) as u64)
+    }
+
+    fn iter_prefix<'a, S: AsRef<str>>(
+        &'a self,
+        find_prefix: S,
+        committed: &Committed,
+    ) -> Result<PrefixIter<'a>> {
+        let prefix = find_prefix.as_ref().to_string();
+        let iter = self
+            .dataset(committed)
+            .into_iter()
+            .flat_map(|dataset| dataset.iter())
+            // Make sure the data keys start with the given prefix.
+            .filter(move |(key, _)| key.name().starts_with(&prefix))
+            .map(|(key, value)| (Cow::Borrowed(key), Cow::Borrowed(value.as_str())));
+        Ok(Box::new(iter))
+    }
+
+    /// Builds the map in a single pass over our data, rather than listing keys and then looking
+    /// up each one.
+    fn get_prefix<S: AsRef<str>>(
+        &self,
+        find_prefix: S,
+        committed: &Committed,
+    ) -> Result<HashMap<Key, String>> {
+        Ok(self
+            .iter_prefix(find_prefix, committed)?
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect())
+    }
+
+    /// Sorts borrowed entries, so we only clone once when building the result.
+    fn get_prefix_sorted<S: AsRef<str>>(
+        &self,
+        find_prefix: S,
+        committed: &Committed,
+    ) -> Result<Vec<(Key, String)>> {
+        let mut entries: Vec<_> = self.iter_prefix(find_prefix, committed)?.collect();
+        entries.sort_by(|(a, _), (b, _)| a.name().cmp(b.name()));
+        Ok(entries
+            .into_iter()
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect())
+    }
+
     fn list_populated_metadata<S1, S2>(
         &self,
         prefix: S1,
@@ -96,24 +330,67 @@ impl DataStore for MemoryDataStore {
     }
 
     fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed) -> Result<()> {
+        self.check_transaction_lock(committed)?;
+        self.check_transaction_limit(committed)?;
+        self.touch(committed);
         self.dataset_mut(committed)
             .insert(key.clone(), value.as_ref().to_owned());
+        self.bump_generation(committed);
+        self.log_operation(
+            committed,
+            Operation::Set {
+                key: key.clone(),
+                value: value.as_ref().to_owned(),
+            },
+        );
         Ok(())
     }
 
     fn unset_key(&mut self, key: &Key, committed: &Committed) -> Result<()> {
-        self.dataset_mut(committed).remove(key);
+        self.check_transaction_lock(committed)?;
+        if self.dataset_mut(committed).remove(key).is_some() {
+            self.bump_generation(committed);
+            self.log_operation(committed, Operation::Unset { key: key.clone() });
+        }
         Ok(())
     }
 
+    fn unset_prefix<S: AsRef<str>>(
+        &mut self,
+        prefix: S,
+        committed: &Committed,
+    ) -> Result<HashSet<Key>> {
+        self.check_transaction_lock(committed)?;
+        let mut removed = HashSet::new();
+        self.dataset_mut(committed).retain(|key, _| {
+            if key.name().starts_with(prefix.as_ref()) {
+                removed.insert(key.clone());
+                return false;
+            }
+            true
+        });
+        if !removed.is_empty() {
+            self.bump_generation(committed);
+        }
+        for key in &removed {
+            self.log_operation(committed, Operation::Unset { key: key.clone() });
+        }
+        Ok(removed)
+    }
+
     fn key_populated(&self, key: &Key, committed: &Committed) -> Result<bool> {
         let empty = HashMap::new();
         let dataset = self.dataset(committed).unwrap_or(&empty);
         Ok(dataset.contains_key(key))
     }
 
-    fn get_metadata_raw(&self, metadata_key: &Key, data_key: &Key) -> Result<Option<String>> {
-        let metadata_for_data = self.metadata.get(data_key);
+    fn get_metadata_raw(
+        &self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<Option<String>> {
+        let metadata_for_data = self.metadataset(committed).and_then(|m| m.get(data_key));
         // If we have a metadata entry for this data key, then we can try fetching the requested
         // metadata key, otherwise we'll return early with Ok(None).
         let result = metadata_for_data.and_then(|m| m.get(metadata_key));
@@ -125,46 +402,207 @@ impl DataStore for MemoryDataStore {
         metadata_key: &Key,
         data_key: &Key,
         value: S,
+        committed: &Committed,
     ) -> Result<()> {
+        self.check_transaction_lock(committed)?;
+        self.check_transaction_limit(committed)?;
+        self.touch(committed);
+        let metadataset = match committed {
+            Committed::Live => &mut self.metadata,
+            Committed::Pending { tx } => self.pending_metadata.entry(tx.clone()).or_default(),
+        };
         // If we don't already have a metadata entry for this data key, insert one.
-        let metadata_for_data = self
-            .metadata
+        let metadata_for_data = metadataset
             // Clone data key because we want the HashMap key type to be Key, not &Key, and we
             // can't pass ownership because we only have a reference from our parameters.
             .entry(data_key.clone())
             .or_default();
 
         metadata_for_data.insert(metadata_key.clone(), value.as_ref().to_owned());
+        self.bump_generation(committed);
         Ok(())
     }
 
-    fn unset_metadata(&mut self, metadata_key: &Key, data_key: &Key) -> Result<()> {
+    fn unset_metadata(
+        &mut self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<()> {
+        self.check_transaction_lock(committed)?;
         // If we have any metadata for this data key, remove the given metadata key.
-        if let Some(metadata_for_data) = self.metadata.get_mut(data_key) {
-            metadata_for_data.remove(metadata_key);
+        let removed = self
+            .metadataset_mut(committed)
+            .and_then(|m| m.get_mut(data_key))
+            .and_then(|metadata_for_data| metadata_for_data.remove(metadata_key));
+        if removed.is_some() {
+            self.bump_generation(committed);
         }
         Ok(())
     }
 
-    fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    fn unset_metadata_prefix<S1, S2>(
+        &mut self,
+        data_prefix: S1,
+        metadata_key_name: &Option<S2>,
+        committed: &Committed,
+    ) -> Result<HashSet<(Key, Key)>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.check_transaction_lock(committed)?;
+        let mut removed = HashSet::new();
+        let metadataset = match self.metadataset_mut(committed) {
+            Some(metadataset) => metadataset,
+            None => return Ok(removed),
+        };
+
+        for (data_key, meta_map) in metadataset.iter_mut() {
+            // Confirm data key matches requested prefix.
+            if !data_key.name().starts_with(data_prefix.as_ref()) {
+                continue;
+            }
+
+            meta_map.retain(|meta_key, _| {
+                // Keep metadata keys that don't match the requested name, if any.
+                if let Some(name) = metadata_key_name {
+                    if name.as_ref() != meta_key.name() {
+                        return true;
+                    }
+                }
+                removed.insert((data_key.clone(), meta_key.clone()));
+                false
+            });
+        }
+        // Don't leave behind entries for data keys that no longer have metadata.
+        metadataset.retain(|_, meta_map| !meta_map.is_empty());
+
+        if !removed.is_empty() {
+            self.bump_generation(committed);
+        }
+        Ok(removed)
+    }
+
+    fn rename_metadata_key<S1, S2>(
+        &mut self,
+        from_name: S1,
+        to_name: S2,
+        committed: &Committed,
+    ) -> Result<HashSet<Key>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.check_transaction_lock(committed)?;
+        let from = Key::new(KeyType::Meta, from_name)?;
+        let to = Key::new(KeyType::Meta, to_name)?;
+
+        let mut moved = HashSet::new();
+        let metadataset = match self.metadataset_mut(committed) {
+            Some(metadataset) => metadataset,
+            None => return Ok(moved),
+        };
+        if from == to {
+            return Ok(moved);
+        }
+
+        for (data_key, meta_map) in metadataset.iter_mut() {
+            if let Some(value) = meta_map.remove(&from) {
+                meta_map.insert(to.clone(), value);
+                moved.insert(data_key.clone());
+            }
+        }
+        if !moved.is_empty() {
+            self.bump_generation(committed);
+        }
+        Ok(moved)
+    }
+
+    fn commit_transaction_to<S>(
+        &mut self,
+        transaction: S,
+        target: &Committed,
+    ) -> Result<HashSet<Key>>
     where
         S: Into<String> + AsRef<str>,
     {
-        // Remove anything pending for this transaction
+        self.check_transaction_lock(&Committed::Pending {
+            tx: transaction.as_ref().to_string(),
+        })?;
+        self.check_transaction_lock(target)?;
+        let has_changes = self.pending.contains_key(transaction.as_ref())
+            || self
+                .pending_metadata
+                .get(transaction.as_ref())
+                .is_some_and(|metadata| !metadata.is_empty());
+        if has_changes {
+            if let (Committed::Pending { tx }, Some(max)) = (target, self.max_transactions) {
+                // The source transaction goes away, so it doesn't count against the limit.
+                let mut transactions = self.list_transactions()?;
+                transactions.remove(transaction.as_ref());
+                ensure!(
+                    transactions.contains(tx) || transactions.len() < max,
+                    error::TooManyTransactionsSnafu { tx, max }
+                );
+            }
+        }
+
+        // Nothing below can fail, so the source transaction is only removed once we know its
+        // changes can be written to the target.
+        self.tx_locks.clear(transaction.as_ref());
+        self.touched.remove(transaction.as_ref());
+        self.annotations.remove(transaction.as_ref());
+        if has_changes {
+            self.touch(target);
+        }
+
+        // Apply any pending metadata changes to the target
+        let mut changed = HashSet::new();
+        if let Some(pending_metadata) = self.pending_metadata.remove(transaction.as_ref()) {
+            let metadataset = match target {
+                Committed::Live => &mut self.metadata,
+                Committed::Pending { tx } => self.pending_metadata.entry(tx.clone()).or_default(),
+            };
+            for (data_key, meta_map) in pending_metadata {
+                if !meta_map.is_empty() {
+                    changed.insert(data_key.clone());
+                }
+                metadataset.entry(data_key).or_default().extend(meta_map);
+            }
+        }
+
+        // Apply pending data changes to the target.  These are logged as a single commit below,
+        // rather than as sets.
         if let Some(pending) = self.pending.remove(transaction.as_ref()) {
-            // Apply pending changes to live
-            self.set_keys(&pending, &Committed::Live)?;
-            // Return keys that were committed
-            Ok(pending.keys().cloned().collect())
-        } else {
-            Ok(HashSet::new())
+            changed.extend(pending.keys().cloned());
+            self.dataset_mut(target).extend(pending);
+        }
+        if !changed.is_empty() {
+            self.bump_generation(target);
         }
+        self.log_operation(
+            target,
+            Operation::Commit {
+                tx: transaction.into(),
+                changed: changed.clone(),
+            },
+        );
+        Ok(changed)
     }
 
     fn delete_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
     where
         S: Into<String> + AsRef<str>,
     {
+        self.check_transaction_lock(&Committed::Pending {
+            tx: transaction.as_ref().to_string(),
+        })?;
+        self.tx_locks.clear(transaction.as_ref());
+        self.pending_metadata.remove(transaction.as_ref());
+        self.touched.remove(transaction.as_ref());
+        self.annotations.remove(transaction.as_ref());
+
         // Remove anything pending for this transaction
         if let Some(pending) = self.pending.remove(transaction.as_ref()) {
             // Return the old pending keys
@@ -175,15 +613,70 @@ impl DataStore for MemoryDataStore {
     }
 
     fn list_transactions(&self) -> Result<HashSet<String>> {
-        Ok(self.pending.keys().cloned().collect())
+        Ok(self
+            .pending
+            .keys()
+            .chain(self.pending_metadata.keys())
+            .cloned()
+            .collect())
+    }
+
+    fn get_transaction_annotation<S: AsRef<str>>(&self, transaction: S) -> Result<Option<String>> {
+        Ok(self.annotations.get(transaction.as_ref()).cloned())
+    }
+
+    fn set_transaction_annotation<S1, S2>(
+        &mut self,
+        transaction: S1,
+        annotation: S2,
+    ) -> Result<bool>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let transaction = transaction.as_ref();
+        self.tx_locks
+            .check(transaction, self.lock_holder.as_deref())?;
+        if !self.list_transactions()?.contains(transaction) {
+            return Ok(false);
+        }
+        self.annotations
+            .insert(transaction.to_string(), annotation.as_ref().to_string());
+        Ok(true)
+    }
+
+    fn touch_transaction<S: AsRef<str>>(
+        &mut self,
+        transaction: S,
+        now: SystemTime,
+    ) -> Result<bool> {
+        let transaction = transaction.as_ref();
+        self.check_transaction_lock(&Committed::Pending {
+            tx: transaction.to_string(),
+        })?;
+        if !self.list_transactions()?.contains(transaction) {
+            return Ok(false);
+        }
+        self.touched.insert(transaction.to_string(), now);
+        Ok(true)
+    }
+
+    fn transaction_last_touched<S: AsRef<str>>(
+        &self,
+        transaction: S,
+    ) -> Result<Option<SystemTime>> {
+        Ok(self.touched.get(transaction.as_ref()).copied())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::super::{Committed, DataStore, Key, KeyType};
-    use super::MemoryDataStore;
-    use maplit::hashset;
+    use super::super::{Committed, DataStore, Error, Key, KeyType};
+    use super::{MemoryDataStore, Operation};
+    use maplit::{hashmap, hashset};
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime};
 
     #[test]
     fn get_set_unset() {
@@ -198,19 +691,400 @@ mod test {
 
         let mdkey = Key::new(KeyType::Meta, "testmd").unwrap();
         let md = "mdval";
-        m.set_metadata(&mdkey, &k, md).unwrap();
+        m.set_metadata(&mdkey, &k, md, &Committed::Live).unwrap();
         assert_eq!(
-            m.get_metadata_raw(&mdkey, &k).unwrap(),
+            m.get_metadata_raw(&mdkey, &k, &Committed::Live).unwrap(),
             Some(md.to_string())
         );
 
-        m.unset_metadata(&mdkey, &k).unwrap();
-        assert_eq!(m.get_metadata_raw(&mdkey, &k).unwrap(), None);
+        m.unset_metadata(&mdkey, &k, &Committed::Live).unwrap();
+        assert_eq!(
+            m.get_metadata_raw(&mdkey, &k, &Committed::Live).unwrap(),
+            None
+        );
 
         m.unset_key(&k, &Committed::Live).unwrap();
         assert_eq!(m.get_key(&k, &Committed::Live).unwrap(), None);
     }
 
+    #[test]
+    fn transaction_annotations() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let annotation = "{\"author\":\"me\"}";
+
+        // Transactions have to exist to be annotated.
+        assert!(!m.set_transaction_annotation("tx", annotation).unwrap());
+        m.set_key(&k, "1", &Committed::Pending { tx: "tx".into() })
+            .unwrap();
+        assert!(m.set_transaction_annotation("tx", annotation).unwrap());
+        assert_eq!(
+            m.get_transaction_annotation("tx").unwrap(),
+            Some(annotation.to_string())
+        );
+
+        // The annotation persists through more changes, but not through a commit.
+        m.set_key(&k, "2", &Committed::Pending { tx: "tx".into() })
+            .unwrap();
+        assert!(m.get_transaction_annotation("tx").unwrap().is_some());
+        m.commit_transaction("tx").unwrap();
+        assert_eq!(m.get_transaction_annotation("tx").unwrap(), None);
+    }
+
+    #[test]
+    fn touch_prevents_expiry() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        for tx in &["tx1", "tx2"] {
+            m.set_key(&k, "1", &Committed::Pending { tx: tx.to_string() })
+                .unwrap();
+        }
+        // Anything not used in the last minute will expire, as of a few minutes from now.
+        let now = SystemTime::now() + Duration::from_secs(300);
+        let cutoff = now - Duration::from_secs(60);
+
+        assert!(m.touch_transaction("tx1", now).unwrap());
+        assert!(!m.touch_transaction("bogus", now).unwrap());
+
+        assert_eq!(
+            m.expire_transactions(cutoff).unwrap(),
+            hashset!("tx2".into())
+        );
+        assert_eq!(m.list_transactions().unwrap(), hashset!("tx1".into()));
+        assert_eq!(m.transaction_last_touched("tx2").unwrap(), None);
+    }
+
+    #[test]
+    fn max_transactions() {
+        let mut m = MemoryDataStore::new().with_max_transactions(2);
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let tx = |name: &str| Committed::Pending { tx: name.into() };
+
+        // We can create transactions up to the limit, and keep writing to them.
+        m.set_key(&k, "1", &tx("tx1")).unwrap();
+        m.set_key(&k, "2", &tx("tx2")).unwrap();
+        m.set_key(&k, "3", &tx("tx1")).unwrap();
+        // Live writes aren't limited.
+        m.set_key(&k, "4", &Committed::Live).unwrap();
+
+        // One more is rejected, whether it's data or metadata.
+        m.set_key(&k, "5", &tx("tx3")).unwrap_err();
+        let meta = Key::new(KeyType::Meta, "strength").unwrap();
+        m.set_metadata(&meta, &k, "\"weak\"", &tx("tx3"))
+            .unwrap_err();
+        assert_eq!(
+            m.list_transactions().unwrap(),
+            hashset!("tx1".into(), "tx2".into())
+        );
+
+        // Committing or deleting a transaction frees a slot.
+        m.commit_transaction("tx1").unwrap();
+        m.set_key(&k, "6", &tx("tx3")).unwrap();
+        m.set_key(&k, "7", &tx("tx4")).unwrap_err();
+        m.delete_transaction("tx2").unwrap();
+        m.set_key(&k, "8", &tx("tx4")).unwrap();
+    }
+
+    #[test]
+    fn iter_prefix() {
+        let mut m = MemoryDataStore::new();
+        let pending = Committed::Pending { tx: "tx".into() };
+        let keys = ["a.1", "a.2", "a.3", "b.1"];
+        for (i, name) in keys.iter().enumerate() {
+            let key = Key::new(KeyType::Data, name).unwrap();
+            m.set_key(&key, i.to_string(), &Committed::Live).unwrap();
+        }
+        let pending_key = Key::new(KeyType::Data, "a.4").unwrap();
+        m.set_key(&pending_key, "4", &pending).unwrap();
+
+        // Iteration yields every matching key exactly once, regardless of order.
+        let mut names: Vec<String> = m
+            .iter_prefix("a.", &Committed::Live)
+            .unwrap()
+            .map(|(key, _)| key.name().clone())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["a.1", "a.2", "a.3"]);
+
+        // Iteration matches the materialized map.
+        let iterated: HashMap<Key, String> = m
+            .iter_prefix("a.", &Committed::Live)
+            .unwrap()
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+        assert_eq!(iterated, m.get_prefix("a.", &Committed::Live).unwrap());
+
+        // Pending data is separate, and unknown transactions are empty.
+        let pending_items: Vec<_> = m.iter_prefix("a.", &pending).unwrap().collect();
+        assert_eq!(
+            pending_items,
+            vec![(Cow::Borrowed(&pending_key), Cow::Borrowed("4"))]
+        );
+        let unknown = Committed::Pending { tx: "bogus".into() };
+        assert_eq!(m.iter_prefix("", &unknown).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn transactions_for_key() {
+        let mut m = MemoryDataStore::new();
+        let key = Key::new(KeyType::Data, "a.1").unwrap();
+        let other = Key::new(KeyType::Data, "a.2").unwrap();
+        for tx in ["tx1", "tx2"] {
+            m.set_key(&key, "\"x\"", &Committed::Pending { tx: tx.into() })
+                .unwrap();
+        }
+        m.set_key(&other, "\"x\"", &Committed::Pending { tx: "tx3".into() })
+            .unwrap();
+        m.set_key(&key, "\"x\"", &Committed::Live).unwrap();
+
+        assert_eq!(
+            m.transactions_for_key(&key).unwrap(),
+            hashset!("tx1".to_string(), "tx2".to_string())
+        );
+        assert_eq!(
+            m.transactions_for_key(&other).unwrap(),
+            hashset!("tx3".to_string())
+        );
+        let missing = Key::new(KeyType::Data, "b").unwrap();
+        assert!(m.transactions_for_key(&missing).unwrap().is_empty());
+    }
+
+    #[test]
+    fn count_prefix() {
+        let mut m = MemoryDataStore::new();
+        let pending = Committed::Pending { tx: "tx".into() };
+        for name in ["a.1", "a.2", "ab.1", "b.1"] {
+            let key = Key::new(KeyType::Data, name).unwrap();
+            m.set_key(&key, "\"x\"", &Committed::Live).unwrap();
+        }
+        let pending_key = Key::new(KeyType::Data, "a.3").unwrap();
+        m.set_key(&pending_key, "\"x\"", &pending).unwrap();
+        let unknown = Committed::Pending { tx: "bogus".into() };
+
+        for committed in [&Committed::Live, &pending, &unknown] {
+            for prefix in ["", "a", "a.", "b.1", "c"] {
+                assert_eq!(
+                    m.count_prefix(prefix, committed).unwrap(),
+                    m.list_populated_keys(prefix, committed).unwrap().len(),
+                    "prefix '{}' in {:?}",
+                    prefix,
+                    committed
+                );
+            }
+        }
+        assert_eq!(m.count_prefix("a", &Committed::Live).unwrap(), 3);
+        assert_eq!(m.count_prefix("a.", &pending).unwrap(), 1);
+    }
+
+    #[test]
+    fn get_prefix_sorted() {
+        let mut m = MemoryDataStore::new();
+        for name in &["a.c", "a.a", "b.a", "a.b.z", "a.b.a"] {
+            let key = Key::new(KeyType::Data, name).unwrap();
+            m.set_key(&key, format!("\"{}\"", name), &Committed::Live)
+                .unwrap();
+        }
+
+        let sorted = m.get_prefix_sorted("a.", &Committed::Live).unwrap();
+        let names: Vec<&str> = sorted.iter().map(|(key, _)| key.name().as_str()).collect();
+        assert_eq!(names, vec!["a.a", "a.b.a", "a.b.z", "a.c"]);
+        // Values come along with their keys.
+        for (key, value) in &sorted {
+            assert_eq!(value, &format!("\"{}\"", key.name()));
+        }
+        // Nothing is missing compared to the unsorted map.
+        assert_eq!(
+            sorted.into_iter().collect::<HashMap<_, _>>(),
+            m.get_prefix("a.", &Committed::Live).unwrap()
+        );
+    }
+
+    #[test]
+    fn unset_pending_metadata() {
+        let mut m = MemoryDataStore::new();
+        let strength = Key::new(KeyType::Meta, "strength").unwrap();
+        let k = Key::new(KeyType::Data, "settings.a.b.c").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        m.set_metadata(&strength, &k, "\"strong\"", &Committed::Live)
+            .unwrap();
+        m.set_metadata(&strength, &k, "\"weak\"", &pending).unwrap();
+        assert_eq!(
+            m.get_metadata_raw(&strength, &k, &pending).unwrap(),
+            Some("\"weak\"".to_string())
+        );
+
+        m.unset_metadata(&strength, &k, &pending).unwrap();
+        assert_eq!(m.get_metadata_raw(&strength, &k, &pending).unwrap(), None);
+        // Live metadata is untouched
+        assert_eq!(
+            m.get_metadata_raw(&strength, &k, &Committed::Live).unwrap(),
+            Some("\"strong\"".to_string())
+        );
+    }
+
+    #[test]
+    fn commit_pending_metadata() {
+        let mut m = MemoryDataStore::new();
+        let strength = Key::new(KeyType::Meta, "strength").unwrap();
+        let k = Key::new(KeyType::Data, "settings.a.b.c").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        m.set_key(&k, "\"value\"", &pending).unwrap();
+        m.set_metadata(&strength, &k, "\"weak\"", &pending).unwrap();
+        assert_eq!(
+            m.get_metadata_raw(&strength, &k, &Committed::Live).unwrap(),
+            None
+        );
+
+        m.commit_transaction("tx").unwrap();
+        assert_eq!(
+            m.get_metadata_raw(&strength, &k, &Committed::Live).unwrap(),
+            Some("\"weak\"".to_string())
+        );
+        assert_eq!(m.get_metadata_raw(&strength, &k, &pending).unwrap(), None);
+    }
+
+    #[test]
+    fn unset_metadata_prefix() {
+        let mut m = MemoryDataStore::new();
+        let strength = Key::new(KeyType::Meta, "strength").unwrap();
+        let other = Key::new(KeyType::Meta, "affected-services").unwrap();
+        let k1 = Key::new(KeyType::Data, "settings.a.x").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.a.y").unwrap();
+        let k3 = Key::new(KeyType::Data, "settings.b.z").unwrap();
+        for key in &[&k1, &k2, &k3] {
+            m.set_metadata(&strength, key, "\"weak\"", &Committed::Live)
+                .unwrap();
+            m.set_metadata(&other, key, "[\"service\"]", &Committed::Live)
+                .unwrap();
+        }
+
+        let removed = m
+            .unset_metadata_prefix("settings.a.", &Some("strength"), &Committed::Live)
+            .unwrap();
+        assert_eq!(
+            removed,
+            hashset!(
+                (k1.clone(), strength.clone()),
+                (k2.clone(), strength.clone())
+            )
+        );
+
+        // Strength is gone under the prefix, but other metadata remains.
+        for key in &[&k1, &k2] {
+            assert_eq!(
+                m.get_metadata_raw(&strength, key, &Committed::Live)
+                    .unwrap(),
+                None
+            );
+            assert!(m
+                .get_metadata_raw(&other, key, &Committed::Live)
+                .unwrap()
+                .is_some());
+        }
+        // Nothing outside the prefix was touched.
+        assert!(m
+            .get_metadata_raw(&strength, &k3, &Committed::Live)
+            .unwrap()
+            .is_some());
+        assert!(m
+            .get_metadata_raw(&other, &k3, &Committed::Live)
+            .unwrap()
+            .is_some());
+
+        // Without a metadata key name, all metadata under the prefix is removed.
+        let removed = m
+            .unset_metadata_prefix("settings.a.", &None as &Option<&str>, &Committed::Live)
+            .unwrap();
+        assert_eq!(removed, hashset!((k1.clone(), other.clone()), (k2, other)));
+        assert_eq!(
+            m.get_metadata_raw(&strength, &k1, &Committed::Live)
+                .unwrap(),
+            None
+        );
+
+        // There's no metadata in the transaction, so there's nothing to remove.
+        let pending = Committed::Pending { tx: "tx".into() };
+        assert!(m
+            .unset_metadata_prefix("", &None as &Option<&str>, &pending)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn unset_prefix() {
+        let mut m = MemoryDataStore::new();
+        let k1 = Key::new(KeyType::Data, "settings.kubernetes.cluster-name").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.kubernetes.node-labels.a").unwrap();
+        let k3 = Key::new(KeyType::Data, "settings.kubernetes-other").unwrap();
+        let k4 = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let pending = Committed::Pending {
+            tx: "test".to_string(),
+        };
+        for key in &[&k1, &k2, &k3, &k4] {
+            m.set_key(key, "\"value\"", &Committed::Live).unwrap();
+        }
+        m.set_key(&k1, "\"pending\"", &pending).unwrap();
+
+        let removed = m
+            .unset_prefix("settings.kubernetes.", &Committed::Live)
+            .unwrap();
+        assert_eq!(removed, hashset!(k1.clone(), k2.clone()));
+
+        // Only the subtree was removed.
+        for key in &[&k1, &k2] {
+            assert!(!m.key_populated(key, &Committed::Live).unwrap());
+        }
+        for key in &[&k3, &k4] {
+            assert!(m.key_populated(key, &Committed::Live).unwrap());
+        }
+        // Other transactions are untouched.
+        assert!(m.key_populated(&k1, &pending).unwrap());
+    }
+
+    #[test]
+    fn rename_metadata_key() {
+        let mut m = MemoryDataStore::new();
+        let strength = Key::new(KeyType::Meta, "strength").unwrap();
+        let renamed = Key::new(KeyType::Meta, "setting-strength").unwrap();
+        let other = Key::new(KeyType::Meta, "affected-services").unwrap();
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        let k3 = Key::new(KeyType::Data, "settings.c").unwrap();
+        m.set_metadata(&strength, &k1, "\"weak\"", &Committed::Live)
+            .unwrap();
+        m.set_metadata(&strength, &k2, "\"strong\"", &Committed::Live)
+            .unwrap();
+        m.set_metadata(&other, &k3, "[\"service\"]", &Committed::Live)
+            .unwrap();
+
+        let moved = m
+            .rename_metadata_key("strength", "setting-strength", &Committed::Live)
+            .unwrap();
+        assert_eq!(moved, hashset!(k1.clone(), k2.clone()));
+
+        // Values moved to the new name and are gone from the old one.
+        for (key, value) in &[(&k1, "\"weak\""), (&k2, "\"strong\"")] {
+            assert_eq!(
+                m.get_metadata_raw(&renamed, key, &Committed::Live).unwrap(),
+                Some(value.to_string())
+            );
+            assert_eq!(
+                m.get_metadata_raw(&strength, key, &Committed::Live)
+                    .unwrap(),
+                None
+            );
+        }
+        // Other metadata was untouched.
+        assert_eq!(
+            m.get_metadata_raw(&other, &k3, &Committed::Live).unwrap(),
+            Some("[\"service\"]".to_string())
+        );
+        assert_eq!(
+            m.get_metadata_raw(&renamed, &k3, &Committed::Live).unwrap(),
+            None
+        );
+    }
+
     #[test]
     fn populated() {
         let mut m = MemoryDataStore::new();
@@ -247,6 +1121,135 @@ mod test {
         assert!(m.key_populated(&k, &Committed::Live).unwrap());
     }
 
+    #[test]
+    fn operation_log() {
+        let mut m = MemoryDataStore::new().with_operation_log();
+        let k = Key::new(KeyType::Data, "settings.a.b.c").unwrap();
+        let pending = Committed::Pending { tx: "test".into() };
+        let start = SystemTime::now();
+        m.set_key(&k, "memvalue", &pending).unwrap();
+        m.commit_transaction("test").unwrap();
+
+        let log = m.operation_log().unwrap();
+        let operations: Vec<_> = log
+            .iter()
+            .map(|entry| (&entry.committed, &entry.operation))
+            .collect();
+        assert_eq!(
+            operations,
+            vec![
+                (
+                    &pending,
+                    &Operation::Set {
+                        key: k.clone(),
+                        value: "memvalue".to_string()
+                    }
+                ),
+                (
+                    &Committed::Live,
+                    &Operation::Commit {
+                        tx: "test".to_string(),
+                        changed: hashset!(k.clone()),
+                    }
+                ),
+            ]
+        );
+        assert!(log[0].time >= start && log[1].time >= log[0].time);
+
+        // The log is only kept when requested.
+        let mut m = MemoryDataStore::new();
+        m.set_key(&k, "memvalue", &pending).unwrap();
+        assert_eq!(m.operation_log(), None);
+    }
+
+    #[test]
+    fn commit_at_generation() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let pending = Committed::Pending { tx: "test".into() };
+
+        // Pending writes don't change the generation, live writes do.
+        let start = m.generation().unwrap();
+        m.set_key(&k, "\"pending\"", &pending).unwrap();
+        assert_eq!(m.generation().unwrap(), start);
+
+        // Nothing changed since we read, so we can commit, which moves to a new generation.
+        let changed = m.commit_transaction_at_generation("test", start).unwrap();
+        assert_eq!(changed, hashset!(k.clone()));
+        assert!(m.generation().unwrap() > start);
+    }
+
+    #[test]
+    fn commit_at_stale_generation() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let pending = Committed::Pending { tx: "test".into() };
+        m.set_key(&k, "\"pending\"", &pending).unwrap();
+        let read = m.generation().unwrap();
+
+        // Someone else changes live data after we read.
+        m.set_key(&k, "\"other\"", &Committed::Live).unwrap();
+
+        let err = m
+            .commit_transaction_at_generation("test", read)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::StaleGeneration { expected, current } if expected == read && current == read + 1
+        ));
+        // The transaction is left pending and live is untouched.
+        assert_eq!(
+            m.get_key(&k, &pending).unwrap(),
+            Some("\"pending\"".to_string())
+        );
+        assert_eq!(
+            m.get_key(&k, &Committed::Live).unwrap(),
+            Some("\"other\"".to_string())
+        );
+    }
+
+    #[test]
+    fn commit_to_pending() {
+        let mut m = MemoryDataStore::new();
+        let strength = Key::new(KeyType::Meta, "strength").unwrap();
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        let staging = Committed::Pending {
+            tx: "staging".into(),
+        };
+        m.set_key(&k1, "\"one\"", &staging).unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        m.set_key(&k2, "\"two\"", &pending).unwrap();
+        m.set_metadata(&strength, &k2, "\"weak\"", &pending)
+            .unwrap();
+
+        let changed = m.commit_transaction_to("tx", &staging).unwrap();
+        assert_eq!(changed, hashset!(k2.clone()));
+
+        // The staging transaction has both keys and the metadata; live is untouched.
+        assert_eq!(
+            m.get_prefix("settings.", &staging).unwrap(),
+            hashmap!(k1 => "\"one\"".to_string(), k2.clone() => "\"two\"".to_string())
+        );
+        assert_eq!(
+            m.get_metadata_raw(&strength, &k2, &staging).unwrap(),
+            Some("\"weak\"".to_string())
+        );
+        assert!(m
+            .get_prefix("settings.", &Committed::Live)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            m.get_metadata_raw(&strength, &k2, &Committed::Live)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            m.list_transactions().unwrap(),
+            hashset!("staging".to_string())
+        );
+    }
+
     #[test]
     fn delete_transaction() {
         let mut m = MemoryDataStore::new();
@@ -272,4 +1275,100 @@ mod test {
         // Assure other transactions were not deleted
         assert!(m.key_populated(&k2, &pending2).unwrap());
     }
+    #[test]
+    fn transaction_lock_contention() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let strength = Key::new(KeyType::Meta, "strength").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+
+        m.lock_transaction("tx", "tool-a").unwrap();
+        assert_eq!(m.transaction_lock_holder("tx"), Some("tool-a"));
+        // Someone else can't take the lock.
+        assert!(matches!(
+            m.lock_transaction("tx", "tool-b"),
+            Err(Error::TransactionLocked { holder, .. }) if holder == "tool-a"
+        ));
+
+        // Writes not on behalf of the holder are rejected, whoever they're from.
+        for holder in [None, Some("tool-b".to_string())] {
+            m.set_lock_holder(holder);
+            m.set_key(&k, "\"b\"", &pending).unwrap_err();
+            m.set_metadata(&strength, &k, "\"weak\"", &pending)
+                .unwrap_err();
+            m.unset_key(&k, &pending).unwrap_err();
+            m.commit_transaction("tx").unwrap_err();
+            m.delete_transaction("tx").unwrap_err();
+            m.unlock_transaction("tx", "tool-b").unwrap_err();
+        }
+        // Other transactions and live data are unaffected.
+        m.set_key(&k, "\"b\"", &Committed::Pending { tx: "other".into() })
+            .unwrap();
+        m.set_key(&k, "\"live\"", &Committed::Live).unwrap();
+
+        m.set_lock_holder(Some("tool-a".to_string()));
+        m.set_key(&k, "\"a\"", &pending).unwrap();
+        assert!(m.unlock_transaction("tx", "tool-a").unwrap());
+        m.set_lock_holder(None);
+        m.set_key(&k, "\"anyone\"", &pending).unwrap();
+    }
+
+    #[test]
+    fn failed_commit_keeps_transaction() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let strength = Key::new(KeyType::Meta, "strength").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        let target = Committed::Pending {
+            tx: "target".into(),
+        };
+        m.lock_transaction("target", "tool-b").unwrap();
+        m.set_lock_holder(Some("tool-a".to_string()));
+        m.lock_transaction("tx", "tool-a").unwrap();
+        m.set_key(&k, "\"a\"", &pending).unwrap();
+        m.set_metadata(&strength, &k, "\"weak\"", &pending).unwrap();
+        m.set_transaction_annotation("tx", "\"note\"").unwrap();
+
+        // The target is locked by someone else, so the commit fails without changing anything.
+        m.commit_transaction_to("tx", &target).unwrap_err();
+        assert_eq!(m.get_key(&k, &pending).unwrap(), Some("\"a\"".to_string()));
+        assert_eq!(
+            m.get_metadata_raw(&strength, &k, &pending).unwrap(),
+            Some("\"weak\"".to_string())
+        );
+        assert_eq!(
+            m.get_transaction_annotation("tx").unwrap(),
+            Some("\"note\"".to_string())
+        );
+        assert!(m.transaction_last_touched("tx").unwrap().is_some());
+        assert_eq!(m.transaction_lock_holder("tx"), Some("tool-a"));
+
+        // Touching a transaction locked by someone else is rejected too.
+        m.set_lock_holder(None);
+        m.touch_transaction("tx", SystemTime::now()).unwrap_err();
+        m.touch_transaction("target", SystemTime::now())
+            .unwrap_err();
+    }
+
+    #[test]
+    fn transaction_lock_released_on_commit_and_delete() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        m.set_lock_holder(Some("tool-a".to_string()));
+
+        m.lock_transaction("tx", "tool-a").unwrap();
+        m.set_key(&k, "\"a\"", &pending).unwrap();
+        m.commit_transaction("tx").unwrap();
+        assert_eq!(m.transaction_lock_holder("tx"), None);
+
+        m.lock_transaction("tx", "tool-a").unwrap();
+        m.set_key(&k, "\"a\"", &pending).unwrap();
+        m.delete_transaction("tx").unwrap();
+        assert_eq!(m.transaction_lock_holder("tx"), None);
+
+        // With the lock gone, anyone can write to the transaction again.
+        m.set_lock_holder(None);
+        m.set_key(&k, "\"b\"", &pending).unwrap();
+    }
 }
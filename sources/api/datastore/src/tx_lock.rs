@@ -0,0 +1,105 @@
+//! Advisory locks on transactions, so tools that use the same transaction name don't interleave
+//! their writes.  A lock is held by a named holder; writes to a locked transaction are only
+//! allowed on behalf of its holder.  Locks are advisory in that nothing enforces them except the
+//! data store or server that checks them.
+
+use snafu::ensure;
+use std::collections::HashMap;
+
+use super::{error, Result};
+
+/// The holder of the lock on each locked transaction.
+#[derive(Debug, Default, Clone)]
+pub struct TransactionLocks {
+    // Transaction name -> holder
+    holders: HashMap<String, String>,
+}
+
+impl TransactionLocks {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Locks the given transaction for the given holder.  Locking a transaction you already hold
+    /// is fine; if someone else holds it, returns a TransactionLocked error.
+    pub fn acquire<S1, S2>(&mut self, tx: S1, holder: S2) -> Result<()>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let (tx, holder) = (tx.as_ref(), holder.as_ref());
+        self.check(tx, Some(holder))?;
+        self.holders.insert(tx.to_string(), holder.to_string());
+        Ok(())
+    }
+
+    /// Unlocks the given transaction, returning whether it was locked.  If someone other than the
+    /// given holder holds it, returns a TransactionLocked error.
+    pub fn release<S1, S2>(&mut self, tx: S1, holder: S2) -> Result<bool>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let tx = tx.as_ref();
+        self.check(tx, Some(holder.as_ref()))?;
+        Ok(self.holders.remove(tx).is_some())
+    }
+
+    /// Confirms that the given holder, if any, may write to the given transaction: it's either
+    /// unlocked or locked by that holder.
+    pub fn check<S: AsRef<str>>(&self, tx: S, holder: Option<&str>) -> Result<()> {
+        let tx = tx.as_ref();
+        if let Some(current) = self.holders.get(tx) {
+            ensure!(
+                holder == Some(current.as_str()),
+                error::TransactionLockedSnafu {
+                    tx,
+                    holder: current
+                }
+            );
+        }
+        Ok(())
+    }
+
+    /// Removes any lock on the given transaction, regardless of holder; used once a transaction
+    /// has been committed or deleted.
+    pub fn clear<S: AsRef<str>>(&mut self, tx: S) {
+        self.holders.remove(tx.as_ref());
+    }
+
+    /// Returns the holder of the lock on the given transaction, if it's locked.
+    pub fn holder<S: AsRef<str>>(&self, tx: S) -> Option<&str> {
+        self.holders.get(tx.as_ref()).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn acquire_and_release() {
+        let mut locks = TransactionLocks::new();
+        locks.check("tx", None).unwrap();
+
+        locks.acquire("tx", "a").unwrap();
+        assert_eq!(locks.holder("tx"), Some("a"));
+        // Reacquiring is fine for the holder, but not anyone else.
+        locks.acquire("tx", "a").unwrap();
+        locks.acquire("tx", "b").unwrap_err();
+        locks.check("tx", Some("a")).unwrap();
+        locks.check("tx", Some("b")).unwrap_err();
+        locks.check("tx", None).unwrap_err();
+        // Other transactions aren't affected.
+        locks.check("other", None).unwrap();
+
+        locks.release("tx", "b").unwrap_err();
+        assert!(locks.release("tx", "a").unwrap());
+        assert!(!locks.release("tx", "a").unwrap());
+        locks.check("tx", None).unwrap();
+
+        locks.acquire("tx", "b").unwrap();
+        locks.clear("tx");
+        assert_eq!(locks.holder("tx"), None);
+    }
+}
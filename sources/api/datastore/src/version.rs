@@ -0,0 +1,122 @@
+//! Helpers for finding the version of a data store on disk.
+//!
+//! Data stores are versioned using a chain of symlinks in the data store directory, so that
+//! migrations can atomically swap in a new version.  For example:
+//!
+//! current -> v1 -> v1.5 -> v1.5.2 -> v1.5.2_0123456789abcdef
+//!
+//! The patch-level link (v1.5.2 above) contains the full version number.
+
+use semver::Version;
+use snafu::{OptionExt, ResultExt};
+use std::fs;
+use std::path::Path;
+
+use super::{error, Result};
+
+/// Returns the version of the data store that's currently live in the given data store directory,
+/// by following the current -> major -> minor -> patch symlink chain.
+pub fn current_version<P>(datastore_dir: P) -> Result<Version>
+where
+    P: AsRef<Path>,
+{
+    let datastore_dir = datastore_dir.as_ref();
+
+    // Find the current patch version link, which contains our full version number
+    let current = datastore_dir.join("current");
+    let major = datastore_dir
+        .join(fs::read_link(&current).context(error::LinkReadSnafu { link: current })?);
+    let minor =
+        datastore_dir.join(fs::read_link(&major).context(error::LinkReadSnafu { link: major })?);
+    let patch =
+        datastore_dir.join(fs::read_link(&minor).context(error::LinkReadSnafu { link: minor })?);
+
+    version_from_path(patch)
+}
+
+/// Returns the version in the name of the given patch version link or data store directory, for
+/// example v1.5.2 or v1.5.2_0123456789abcdef.
+pub fn version_from_path<P>(path: P) -> Result<Version>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+
+    // Pull out the basename of the path, which contains the version
+    let version_os_str = path.file_name().context(error::LinkToRootSnafu { path })?;
+    let mut version_str = version_os_str
+        .to_str()
+        .context(error::NonUnicodeFileSnafu {
+            file: path.display().to_string(),
+            context: "data store version link",
+        })?;
+
+    // Allow 'v' at the start so the links have clearer names for humans
+    if version_str.starts_with('v') {
+        version_str = &version_str[1..];
+    }
+    // Data store directories have a random ID after the version; semver doesn't use underscores
+    if let Some((version, _id)) = version_str.split_once('_') {
+        version_str = version;
+    }
+
+    Version::parse(version_str).context(error::InvalidVersionSnafu { path })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    /// Builds a data store directory with the standard version symlink chain pointing at a
+    /// directory with the given (already 'v'-prefixed, if desired) patch version name.
+    fn version_tree(major: &str, minor: &str, patch: &str) -> TempDir {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path();
+        let datastore = format!("{}_0123456789abcdef", patch);
+        fs::create_dir(dir.join(&datastore)).unwrap();
+        symlink(&datastore, dir.join(patch)).unwrap();
+        symlink(patch, dir.join(minor)).unwrap();
+        symlink(minor, dir.join(major)).unwrap();
+        symlink(major, dir.join("current")).unwrap();
+        tmp
+    }
+
+    #[test]
+    fn current_version_works() {
+        let tmp = version_tree("v1", "v1.5", "v1.5.2");
+        assert_eq!(current_version(tmp.path()).unwrap(), Version::new(1, 5, 2));
+    }
+
+    #[test]
+    fn current_version_without_v_prefix() {
+        let tmp = version_tree("1", "1.5", "1.5.2");
+        assert_eq!(current_version(tmp.path()).unwrap(), Version::new(1, 5, 2));
+    }
+
+    #[test]
+    fn current_version_invalid() {
+        let tmp = version_tree("v1", "v1.5", "vbogus");
+        current_version(tmp.path()).unwrap_err();
+    }
+
+    #[test]
+    fn version_from_datastore_path() {
+        assert_eq!(
+            version_from_path("/x/v1.5.2_0123456789abcdef").unwrap(),
+            Version::new(1, 5, 2)
+        );
+        assert_eq!(
+            version_from_path("/x/1.5.2").unwrap(),
+            Version::new(1, 5, 2)
+        );
+        version_from_path("/x/current").unwrap_err();
+    }
+
+    #[test]
+    fn current_version_missing_link() {
+        let tmp = TempDir::new().unwrap();
+        current_version(tmp.path()).unwrap_err();
+    }
+}
@@ -19,8 +19,22 @@ fn usage() -> ! {
             --root-path PATH
             --metadata-directory PATH
             (--migrate-to-version x.y | --migrate-to-version-from-os-release)
+            [ --source-datastore PATH ]
+            [ --work-dir PATH ]
+            [ --verify-after ]
+            [ --dry-run-flip ]
             [ --no-color ]
-            [ --log-level trace|debug|info|warn|error ]",
+            [ --log-level trace|debug|info|warn|error ]
+
+    --source-datastore migrates from the given data store directory rather than the one the
+      'current' link points to; its version is taken from its name, e.g. v1.5.2_0123456789abcdef
+    --work-dir builds the migrated data stores in the given directory, which can be on another
+      filesystem, rather than next to the source data store; the final data store is moved back
+      into the data store directory before it's made live
+    --verify-after checks that the migrated data store can be read with the new model after
+      it's made live, and exits with an error if it can't
+    --dry-run-flip logs the symlink swaps that would make the migrated data store live, without
+      making them, to help debug the symlink layout; the migrations still run",
         program_name
     );
     process::exit(2);
@@ -40,6 +54,10 @@ pub(crate) struct Args {
     pub(crate) migrate_to_version: Version,
     pub(crate) root_path: PathBuf,
     pub(crate) metadata_directory: PathBuf,
+    pub(crate) source_datastore: Option<PathBuf>,
+    pub(crate) verify_after: bool,
+    pub(crate) dry_run_flip: bool,
+    pub(crate) work_dir: Option<PathBuf>,
 }
 
 impl Args {
@@ -52,6 +70,10 @@ impl Args {
         let mut migrate_to_version = None;
         let mut root_path = None;
         let mut metadata_path = None;
+        let mut source_datastore = None;
+        let mut verify_after = false;
+        let mut dry_run_flip = false;
+        let mut work_dir = None;
 
         let mut iter = args.skip(1);
         while let Some(arg) = iter.next() {
@@ -131,6 +153,39 @@ impl Args {
                     trace!("Given --metadata-directory: {}", path_str);
                     metadata_path = Some(PathBuf::from(path_str));
                 }
+
+                "--source-datastore" => {
+                    let path_str = iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --source-datastore")
+                    });
+                    trace!("Given --source-datastore: {}", path_str);
+                    let canonical = fs::canonicalize(&path_str).unwrap_or_else(|e| {
+                        usage_msg(format!(
+                            "Could not canonicalize given source data store path '{}': {}",
+                            path_str, e
+                        ))
+                    });
+                    source_datastore = Some(canonical);
+                }
+
+                "--verify-after" => verify_after = true,
+
+                "--dry-run-flip" => dry_run_flip = true,
+
+                "--work-dir" => {
+                    let path_str = iter
+                        .next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --work-dir"));
+                    trace!("Given --work-dir: {}", path_str);
+                    let canonical = fs::canonicalize(&path_str).unwrap_or_else(|e| {
+                        usage_msg(format!(
+                            "Could not canonicalize given work directory '{}': {}",
+                            path_str, e
+                        ))
+                    });
+                    work_dir = Some(canonical);
+                }
+
                 _ => usage_msg(format!("Unable to parse input '{}'", arg)),
             }
         }
@@ -150,6 +205,10 @@ impl Args {
             root_path: root_path.unwrap_or_else(|| usage_msg("--root-path must be specified")),
             metadata_directory: metadata_path
                 .unwrap_or_else(|| usage_msg("--metadata-directory must be specified")),
+            source_datastore,
+            verify_after,
+            dry_run_flip,
+            work_dir,
         }
     }
 }
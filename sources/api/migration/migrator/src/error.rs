@@ -10,8 +10,18 @@ use std::process::Output;
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub(crate)))]
 pub(crate) enum Error {
-    #[snafu(display("Data store path '{}' contains invalid UTF-8", path.display()))]
-    DataStorePathNotUTF8 { path: PathBuf },
+    #[snafu(display("Unable to find current data store version: {}", source))]
+    CurrentVersion { source: datastore::Error },
+
+    #[snafu(display(
+        "Unable to determine version of source data store '{}': {}",
+        path.display(),
+        source
+    ))]
+    SourceVersion {
+        path: PathBuf,
+        source: datastore::Error,
+    },
 
     #[snafu(display("Unable to open data store directory '{}': {}", path.display(), source))]
     DataStoreDirOpen { path: PathBuf, source: nix::Error },
@@ -27,18 +37,19 @@ pub(crate) enum Error {
         source: update_metadata::error::Error,
     },
 
-    #[snafu(display("Data store path '{}' contains invalid version: {}", path.display(), source))]
-    InvalidDataStoreVersion {
-        path: PathBuf,
-        source: semver::Error,
-    },
-
     #[snafu(display("Data store for new version {} already exists at {}", version, path.display()))]
     NewVersionAlreadyExists { version: Version, path: PathBuf },
 
     #[snafu(display("Unable to seal migration command: {}", source))]
     SealMigration { source: std::io::Error },
 
+    #[snafu(display(
+        "Migration {} is larger than the limit of {} bytes after decompression",
+        migration,
+        max
+    ))]
+    MigrationTooLarge { migration: String, max: u64 },
+
     #[snafu(display("Unable to start migration command: {}", source))]
     StartMigration { source: std::io::Error },
 
@@ -55,8 +66,17 @@ pub(crate) enum Error {
     #[snafu(display("Failed to swap symlink at {} to new version: {}", link.display(), source))]
     LinkSwap { link: PathBuf, source: io::Error },
 
-    #[snafu(display("Failed to read symlink at {} to find version: {}", link.display(), source))]
-    LinkRead { link: PathBuf, source: io::Error },
+    #[snafu(display(
+        "Failed to move new data store from {} to {}: {}",
+        from.display(),
+        to.display(),
+        source
+    ))]
+    MoveDataStore {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
 
     #[snafu(display("Invalid target name '{}': {}", target, source))]
     TargetName {
@@ -92,6 +112,18 @@ pub(crate) enum Error {
         source: update_metadata::error::Error,
     },
 
+    #[snafu(display(
+        "Manifest doesn't allow migrating from {} to {}; allowed versions are {}",
+        from,
+        to,
+        allowed
+    ))]
+    UnsupportedVersionJump {
+        from: Version,
+        to: Version,
+        allowed: String,
+    },
+
     #[snafu(display("Migration '{}' not found", migration))]
     MigrationNotFound { migration: String },
 
@@ -101,6 +133,18 @@ pub(crate) enum Error {
         source: std::io::Error,
     },
 
+    #[snafu(display("Unable to read '{}' from migrated data store: {}", prefix, source))]
+    VerifyRead {
+        prefix: String,
+        source: datastore::Error,
+    },
+
+    #[snafu(display("Migrated '{}' data doesn't match the model: {}", prefix, source))]
+    VerifyDeserialize {
+        prefix: String,
+        source: datastore::deserialization::Error,
+    },
+
     #[snafu(display("Failed to load TUF repo: {}", source))]
     RepoLoad {
         #[snafu(source(from(tough::error::Error, Box::new)))]
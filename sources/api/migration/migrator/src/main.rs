@@ -7,13 +7,14 @@
 //!
 //! Given those, it will:
 //! * confirm that the given data store has the appropriate versioned symlink structure
-//! * find the version of the given data store
+//! * find the version of the given data store (or of an explicitly given source data store)
 //! * find migrations between the two versions
 //! * if there are migrations:
 //!   * run the migrations; the transformed data becomes the new data store
 //! * if there are *no* migrations:
 //!   * just symlink to the old data store
 //! * do symlink flips so the new version takes the place of the original
+//! * if requested, check that the new data store can be read with the current model
 //!
 //! To understand motivation and more about the overall process, look at the migration system
 //! documentation, one level up.
@@ -22,17 +23,21 @@
 extern crate log;
 
 use args::Args;
+use datastore::deserialization::from_map_with_prefix;
+use datastore::{Committed, DataStore, FilesystemDataStore};
 use direction::Direction;
 use error::Result;
 use futures::{StreamExt, TryStreamExt};
+use model::{ConfigurationFiles, Services, Settings};
 use nix::{dir::Dir, fcntl::OFlag, sys::stat::Mode, unistd::fsync};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use semver::Version;
+use serde::de::DeserializeOwned;
 use simplelog::{Config as LogConfig, SimpleLogger};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::convert::TryInto;
 use std::env;
-use std::io::ErrorKind;
+use std::io::{ErrorKind, Read};
 use std::os::unix::fs::symlink;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
@@ -68,46 +73,6 @@ async fn main() {
     }
 }
 
-async fn get_current_version<P>(datastore_dir: P) -> Result<Version>
-where
-    P: AsRef<Path>,
-{
-    let datastore_dir = datastore_dir.as_ref();
-
-    // Find the current patch version link, which contains our full version number
-    let current = datastore_dir.join("current");
-    let major = datastore_dir.join(
-        fs::read_link(&current)
-            .await
-            .context(error::LinkReadSnafu { link: current })?,
-    );
-    let minor = datastore_dir.join(
-        fs::read_link(&major)
-            .await
-            .context(error::LinkReadSnafu { link: major })?,
-    );
-    let patch = datastore_dir.join(
-        fs::read_link(&minor)
-            .await
-            .context(error::LinkReadSnafu { link: minor })?,
-    );
-
-    // Pull out the basename of the path, which contains the version
-    let version_os_str = patch
-        .file_name()
-        .context(error::DataStoreLinkToRootSnafu { path: &patch })?;
-    let mut version_str = version_os_str
-        .to_str()
-        .context(error::DataStorePathNotUTF8Snafu { path: &patch })?;
-
-    // Allow 'v' at the start so the links have clearer names for humans
-    if version_str.starts_with('v') {
-        version_str = &version_str[1..];
-    }
-
-    Version::parse(version_str).context(error::InvalidDataStoreVersionSnafu { path: &patch })
-}
-
 pub(crate) async fn run(args: &Args) -> Result<()> {
     // Get the directory we're working in.
     let datastore_dir = args
@@ -117,13 +82,30 @@ pub(crate) async fn run(args: &Args) -> Result<()> {
             path: &args.datastore_path,
         })?;
 
-    let current_version = get_current_version(datastore_dir).await?;
+    // By default we migrate from the data store the 'current' link points to, but we can be given
+    // a specific one, for testing or repair.
+    let (source_datastore, current_version) = match &args.source_datastore {
+        Some(source) => {
+            let version = datastore::version_from_path(source)
+                .context(error::SourceVersionSnafu { path: source })?;
+            info!(
+                "Using given source data store at '{}' with version {}",
+                source.display(),
+                version
+            );
+            (source, version)
+        }
+        None => (
+            &args.datastore_path,
+            datastore::current_version(datastore_dir).context(error::CurrentVersionSnafu)?,
+        ),
+    };
     let direction = Direction::from_versions(&current_version, &args.migrate_to_version)
         .unwrap_or_else(|| {
             info!(
                 "Requested version {} matches version of given datastore at '{}'; nothing to do",
                 args.migrate_to_version,
-                args.datastore_path.display()
+                source_datastore.display()
             );
             process::exit(0);
         });
@@ -174,26 +156,71 @@ pub(crate) async fn run(args: &Args) -> Result<()> {
         .await
         .context(error::RepoLoadSnafu)?;
     let manifest = load_manifest(repo.clone()).await?;
+    // Refuse version jumps the manifest doesn't support before changing anything.
+    ensure!(
+        manifest.allows_migration_to(&args.migrate_to_version),
+        error::UnsupportedVersionJumpSnafu {
+            from: current_version.clone(),
+            to: args.migrate_to_version.clone(),
+            allowed: format!(
+                "{} to {}",
+                version_or_any(&manifest.min_migratable_version),
+                version_or_any(&manifest.max_migratable_version)
+            ),
+        }
+    );
     let migrations =
         update_metadata::find_migrations(&current_version, &args.migrate_to_version, &manifest)
             .context(error::FindMigrationsSnafu)?;
 
-    if migrations.is_empty() {
+    let new_datastore = if migrations.is_empty() {
         // Not all new OS versions need to change the data store format.  If there's been no
         // change, we can just link to the last version rather than making a copy.
-        // (Note: we link to the fully resolved directory, args.datastore_path,  so we don't
+        // (Note: we link to the fully resolved directory, source_datastore,  so we don't
         // have a chain of symlinks that could go past the maximum depth.)
-        flip_to_new_version(&args.migrate_to_version, &args.datastore_path).await?;
+        source_datastore.clone()
     } else {
-        let copy_path = run_migrations(
+        // Migrated data stores are built in the work directory, if we were given one, and moved
+        // back next to the others once they're done.
+        let work_dir = args.work_dir.as_deref().unwrap_or(datastore_dir);
+        let migrated = run_migrations(
             &repo,
             direction,
             &migrations,
-            &args.datastore_path,
+            source_datastore,
+            work_dir,
             &args.migrate_to_version,
         )
         .await?;
-        flip_to_new_version(&args.migrate_to_version, copy_path).await?;
+        if work_dir == datastore_dir {
+            migrated
+        } else {
+            move_into_datastore_dir(&migrated, datastore_dir)?
+        }
+    };
+    flip_to_new_version(
+        &args.migrate_to_version,
+        datastore_dir,
+        &new_datastore,
+        args.dry_run_flip,
+    )
+    .await?;
+
+    if args.verify_after {
+        // We don't flip back if verification fails; the previous version's data store is kept, so
+        // a rollback is still possible, and flipping back here would hide the problem.
+        verify_datastore(&new_datastore).map_err(|e| {
+            error!(
+                "Migrated data store at '{}' failed verification: {}",
+                new_datastore.display(),
+                e
+            );
+            e
+        })?;
+        info!(
+            "Migrated data store at '{}' verified",
+            new_datastore.display()
+        );
     }
     Ok(())
 }
@@ -210,15 +237,22 @@ fn rando() -> String {
         .collect()
 }
 
-/// Generates a path for a new data store, given the path of the existing data store,
-/// the new version number, and a random "copy id" to append.
-fn new_datastore_location<P>(from: P, new_version: &Version) -> Result<PathBuf>
+/// Describes an optional manifest version bound for error messages.
+fn version_or_any(version: &Option<Version>) -> String {
+    version
+        .as_ref()
+        .map_or_else(|| "any".to_string(), Version::to_string)
+}
+
+/// Generates a path for a new data store, given the directory to build it in, the new version
+/// number, and a random "copy id" to append.
+fn new_datastore_location<P>(work_dir: P, new_version: &Version) -> Result<PathBuf>
 where
     P: AsRef<Path>,
 {
-    let to = from
+    let to = work_dir
         .as_ref()
-        .with_file_name(format!("v{}_{}", new_version, rando()));
+        .join(format!("v{}_{}", new_version, rando()));
     ensure!(
         !to.exists(),
         error::NewVersionAlreadyExistsSnafu {
@@ -234,16 +268,59 @@ where
     Ok(to)
 }
 
+/// The largest migration we'll run, in bytes after decompression.  Migrations are decompressed
+/// into a sealed in-memory file and run from there, so each migration is held in memory in full
+/// while it runs; this keeps an oversized migration from using too much memory at boot.
+const MAX_MIGRATION_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Wraps a reader, failing reads once more than `max` bytes have been read from it.
+struct SizeLimitedReader<R> {
+    inner: R,
+    max: u64,
+    read: u64,
+}
+
+impl<R: Read> SizeLimitedReader<R> {
+    fn new(inner: R, max: u64) -> Self {
+        Self {
+            inner,
+            max,
+            read: 0,
+        }
+    }
+
+    /// Returns true if reads failed because the inner reader had more than `max` bytes.
+    fn exceeded(&self) -> bool {
+        self.read > self.max
+    }
+}
+
+impl<R: Read> Read for SizeLimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.read += count as u64;
+        if self.exceeded() {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("more than {} bytes", self.max),
+            ));
+        }
+        Ok(count)
+    }
+}
+
 /// Runs the given migrations in their given order.  The given direction is passed to each
 /// migration so it knows which direction we're migrating.
 ///
 /// The given data store is used as a starting point; each migration is given the output of the
-/// previous migration, and the final output becomes the new data store.
+/// previous migration, and the final output becomes the new data store.  Each migration's output
+/// is created in the given work directory.
 async fn run_migrations<P, S>(
     repository: &tough::Repository,
     direction: Direction,
     migrations: &[S],
     source_datastore: P,
+    work_dir: &Path,
     new_version: &Version,
 ) -> Result<PathBuf>
 where
@@ -286,10 +363,13 @@ where
         let lz4_async_read = lz4_byte_stream.into_async_read().compat();
         let lz4_bytes = SyncIoBridge::new(lz4_async_read);
 
-        // Add an LZ4 decoder so the bytes will be deflated on read
-        let mut reader = lz4::Decoder::new(lz4_bytes).context(error::Lz4DecodeSnafu {
+        // Add an LZ4 decoder so the bytes will be deflated on read, and stop reading if the
+        // migration turns out to be too large.
+        let decoder = lz4::Decoder::new(lz4_bytes).context(error::Lz4DecodeSnafu {
             migration: migration.raw(),
         })?;
+        let mut reader = SizeLimitedReader::new(decoder, MAX_MIGRATION_SIZE);
+        let migration_name = migration.raw().to_string();
 
         let mut command_args = vec![
             direction.to_string(),
@@ -298,7 +378,7 @@ where
         ];
 
         // Create a new output location for this migration.
-        target_datastore = new_datastore_location(source_datastore, new_version)?;
+        target_datastore = new_datastore_location(work_dir, new_version)?;
 
         command_args.push("--target-datastore".to_string());
         command_args.push(target_datastore.display().to_string());
@@ -308,9 +388,17 @@ where
         // Run this blocking IO in a thread so it doesn't block the scheduler.
         let rt = Handle::current();
         let task = rt.spawn_blocking(move || {
-            // Create a sealed command with pentacle, so we can run the verified bytes from memory
-            let mut command =
-                pentacle::SealedCommand::new(&mut reader).context(error::SealMigrationSnafu)?;
+            // Create a sealed command with pentacle, so we can run the verified bytes from memory.
+            // pentacle copies the decoded bytes into the sealed file a buffer at a time.
+            let sealed = pentacle::SealedCommand::new(&mut reader);
+            ensure!(
+                !reader.exceeded(),
+                error::MigrationTooLargeSnafu {
+                    migration: migration_name,
+                    max: MAX_MIGRATION_SIZE,
+                }
+            );
+            let mut command = sealed.context(error::SealMigrationSnafu)?;
             command.args(command_args);
 
             debug!("Migration command: {:?}", command);
@@ -369,37 +457,120 @@ async fn delete_intermediate_datastore(path: &PathBuf) {
     }
 }
 
-/// Atomically flips version symlinks to point to the given "to" datastore so that it becomes live.
-///
-/// This includes:
+/// Moves a data store that was built in a separate work directory into the data store directory,
+/// so the version links can point to it, and returns its new path.  The work directory is often
+/// on another filesystem, where we can't simply rename, so in that case we copy the data store to
+/// a temporary name in the data store directory, rename it into place, and remove the original.
+fn move_into_datastore_dir(from: &Path, datastore_dir: &Path) -> Result<PathBuf> {
+    move_into_datastore_dir_with(from, datastore_dir, |from, to| std::fs::rename(from, to))
+}
+
+/// Does the work of move_into_datastore_dir, trying the given function first to rename the data
+/// store into place, so tests can stand in for a rename across filesystems.
+fn move_into_datastore_dir_with<F>(from: &Path, datastore_dir: &Path, rename: F) -> Result<PathBuf>
+where
+    F: FnOnce(&Path, &Path) -> std::io::Result<()>,
+{
+    let name = from
+        .file_name()
+        .context(error::DataStoreLinkToRootSnafu { path: from })?;
+    let to = datastore_dir.join(name);
+    debug!(
+        "Moving new data store from {} to {}",
+        from.display(),
+        to.display()
+    );
+
+    match rename(from, &to) {
+        Ok(()) => return Ok(to),
+        Err(e) if e.raw_os_error() == Some(nix::errno::Errno::EXDEV as i32) => {
+            debug!("Data store is on another filesystem, copying it instead");
+        }
+        Err(e) => return Err(e).context(error::MoveDataStoreSnafu { from, to }),
+    }
+
+    let mut partial_name = name.to_os_string();
+    partial_name.push(".partial");
+    let partial = datastore_dir.join(partial_name);
+    // Clear out a partial copy left by an earlier failed attempt, and don't leave one behind if
+    // this attempt fails.
+    remove_partial_copy(&partial);
+    if let Err(e) = copy_dir_all(from, &partial).and_then(|()| std::fs::rename(&partial, &to)) {
+        remove_partial_copy(&partial);
+        return Err(e).context(error::MoveDataStoreSnafu { from, to: &to });
+    }
+
+    // The copy is in place, so failing to clean up the original shouldn't fail the migration.
+    if let Err(e) = std::fs::remove_dir_all(from) {
+        error!(
+            "Failed to remove data store from work directory at '{}': {}",
+            from.display(),
+            e
+        );
+    }
+    Ok(to)
+}
+
+/// Removes a partial copy of a data store, if there is one, logging any failure; the copy isn't
+/// linked from anywhere, so it's only wasted space.
+fn remove_partial_copy(partial: &Path) {
+    match std::fs::remove_dir_all(partial) {
+        Ok(()) => debug!("Removed partial data store copy at {}", partial.display()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => error!(
+            "Failed to remove partial data store copy at '{}': {}",
+            partial.display(),
+            e
+        ),
+    }
+}
+
+/// Recursively copies the directory `from` to the new path `to`, including any symlinks as links.
+/// Files and directories keep their permissions.
+fn copy_dir_all(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let source = entry.path();
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_dir_all(&source, &target)?;
+        } else if file_type.is_symlink() {
+            symlink(std::fs::read_link(&source)?, &target)?;
+        } else {
+            std::fs::copy(&source, &target)?;
+        }
+    }
+    // Set the directory's permissions last, in case they don't let us write to it.
+    std::fs::set_permissions(to, std::fs::metadata(from)?.permissions())
+}
+
+/// A version symlink, and the relative target it's flipped to point at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LinkSwap {
+    link: PathBuf,
+    target: PathBuf,
+}
+
+/// Returns the symlink swaps in `to_dir`, the data store directory, that make the given "to"
+/// datastore live, in the order they should be made, so that each link's new target is in place
+/// before anything points to it:
 /// * pointing the new patch version to the given `to_datastore`
 /// * pointing the minor version to the patch version
 /// * pointing the major version to the minor version
 /// * pointing the 'current' link to the major version
-/// * fsyncing the directory to disk
-async fn flip_to_new_version<P>(version: &Version, to_datastore: P) -> Result<()>
+///
+/// The "to" datastore is usually in `to_dir`, but when there are no migrations it's the source
+/// data store, which may have been given from elsewhere.
+fn flip_plan<P1, P2>(version: &Version, to_dir: P1, to_datastore: P2) -> Result<Vec<LinkSwap>>
 where
-    P: AsRef<Path>,
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
 {
-    // Get the directory we're working in.
-    let to_dir = to_datastore
-        .as_ref()
-        .parent()
-        .context(error::DataStoreLinkToRootSnafu {
-            path: to_datastore.as_ref(),
-        })?;
-    // We need a file descriptor for the directory so we can fsync after the symlink swap.
-    let raw_dir = Dir::open(
-        to_dir,
-        // Confirm it's a directory
-        OFlag::O_DIRECTORY,
-        // (mode doesn't matter for opening a directory)
-        Mode::empty(),
-    )
-    .context(error::DataStoreDirOpenSnafu { path: &to_dir })?;
+    let to_dir = to_dir.as_ref();
+    let to_datastore = to_datastore.as_ref();
 
-    // Get a unique temporary path in the directory; we need this to atomically swap.
-    let temp_link = to_dir.join(rando());
     // Build the path to the 'current' link; this is what we're atomically swapping from
     // pointing at the old major version to pointing at the new major version.
     // Example: /path/to/datastore/current
@@ -421,105 +592,101 @@ where
         version.major, version.minor, version.patch
     ));
 
-    // Get the final component of the paths we're linking to, so we can use relative links instead
-    // of absolute, for understandability.
-    let to_target = to_datastore
-        .as_ref()
-        .file_name()
-        .context(error::DataStoreLinkToRootSnafu {
-            path: to_datastore.as_ref(),
-        })?;
-    let patch_target = patch_version_link
-        .file_name()
-        .context(error::DataStoreLinkToRootSnafu {
-            path: to_datastore.as_ref(),
-        })?;
-    let minor_target = minor_version_link
-        .file_name()
-        .context(error::DataStoreLinkToRootSnafu {
-            path: to_datastore.as_ref(),
-        })?;
-    let major_target = major_version_link
-        .file_name()
-        .context(error::DataStoreLinkToRootSnafu {
-            path: to_datastore.as_ref(),
-        })?;
-
-    // =^..^=   =^..^=   =^..^=   =^..^=
-
-    debug!(
-        "Flipping {} to point to {}",
-        patch_version_link.display(),
-        to_target.to_string_lossy(),
-    );
-
-    // Create a symlink from the patch version to the new data store.  We create it at a temporary
-    // path so we can atomically swap it into the real path with a rename call.
-    // This will point at, for example, /path/to/datastore/v1.5.2_0123456789abcdef
-    symlink(to_target, &temp_link).context(error::LinkCreateSnafu { path: &temp_link })?;
-    // Atomically swap the link into place, so that the patch version link points to the new data
-    // store copy.
-    fs::rename(&temp_link, &patch_version_link)
-        .await
-        .context(error::LinkSwapSnafu {
-            link: &patch_version_link,
-        })?;
-
-    // =^..^=   =^..^=   =^..^=   =^..^=
-
-    debug!(
-        "Flipping {} to point to {}",
-        minor_version_link.display(),
-        patch_target.to_string_lossy(),
-    );
-
-    // Create a symlink from the minor version to the new patch version.
-    // This will point at, for example, /path/to/datastore/v1.5.2
-    symlink(patch_target, &temp_link).context(error::LinkCreateSnafu { path: &temp_link })?;
-    // Atomically swap the link into place, so that the minor version link points to the new patch
-    // version.
-    fs::rename(&temp_link, &minor_version_link)
-        .await
-        .context(error::LinkSwapSnafu {
-            link: &minor_version_link,
-        })?;
+    // Each link points to the final component of the path below it, so we use relative links
+    // instead of absolute, for understandability.
+    let file_name = |path: &Path| -> Result<PathBuf> {
+        path.file_name()
+            .map(PathBuf::from)
+            .context(error::DataStoreLinkToRootSnafu { path: to_datastore })
+    };
+    // A data store outside the directory can't be reached by name, so we link to its full path.
+    let datastore_target = if to_datastore.parent() == Some(to_dir) {
+        file_name(to_datastore)?
+    } else {
+        to_datastore.to_path_buf()
+    };
+    Ok(vec![
+        // This will point at, for example, /path/to/datastore/v1.5.2_0123456789abcdef
+        LinkSwap {
+            target: datastore_target,
+            link: patch_version_link.clone(),
+        },
+        // This will point at, for example, /path/to/datastore/v1.5.2
+        LinkSwap {
+            target: file_name(&patch_version_link)?,
+            link: minor_version_link.clone(),
+        },
+        // This will point at, for example, /path/to/datastore/v1.5
+        LinkSwap {
+            target: file_name(&minor_version_link)?,
+            link: major_version_link.clone(),
+        },
+        // This will point at, for example, /path/to/datastore/v1
+        LinkSwap {
+            target: file_name(&major_version_link)?,
+            link: current_version_link,
+        },
+    ])
+}
 
-    // =^..^=   =^..^=   =^..^=   =^..^=
+/// Atomically flips the version symlinks in `to_dir`, the data store directory, to point to the
+/// given "to" datastore so that it becomes live, making the swaps from flip_plan and then fsyncing
+/// the directory to disk.
+///
+/// If `dry_run` is true, the swaps are only logged, for debugging; nothing is changed.
+async fn flip_to_new_version<P1, P2>(
+    version: &Version,
+    to_dir: P1,
+    to_datastore: P2,
+    dry_run: bool,
+) -> Result<()>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let to_dir = to_dir.as_ref();
+    let plan = flip_plan(version, to_dir, &to_datastore)?;
+    if dry_run {
+        for swap in &plan {
+            info!(
+                "Dry run: would flip {} to point to {}",
+                swap.link.display(),
+                swap.target.display(),
+            );
+        }
+        return Ok(());
+    }
 
-    debug!(
-        "Flipping {} to point to {}",
-        major_version_link.display(),
-        minor_target.to_string_lossy(),
-    );
+    // We need a file descriptor for the directory so we can fsync after the symlink swap.
+    let raw_dir = Dir::open(
+        to_dir,
+        // Confirm it's a directory
+        OFlag::O_DIRECTORY,
+        // (mode doesn't matter for opening a directory)
+        Mode::empty(),
+    )
+    .context(error::DataStoreDirOpenSnafu { path: &to_dir })?;
 
-    // Create a symlink from the major version to the new minor version.
-    // This will point at, for example, /path/to/datastore/v1.5
-    symlink(minor_target, &temp_link).context(error::LinkCreateSnafu { path: &temp_link })?;
-    // Atomically swap the link into place, so that the major version link points to the new minor
-    // version.
-    fs::rename(&temp_link, &major_version_link)
-        .await
-        .context(error::LinkSwapSnafu {
-            link: &major_version_link,
-        })?;
+    // Get a unique temporary path in the directory; we need this to atomically swap.
+    let temp_link = to_dir.join(rando());
 
-    // =^..^=   =^..^=   =^..^=   =^..^=
+    for swap in &plan {
+        // =^..^=   =^..^=   =^..^=   =^..^=
 
-    debug!(
-        "Flipping {} to point to {}",
-        current_version_link.display(),
-        major_target.to_string_lossy(),
-    );
+        debug!(
+            "Flipping {} to point to {}",
+            swap.link.display(),
+            swap.target.display(),
+        );
 
-    // Create a symlink from 'current' to the new major version.
-    // This will point at, for example, /path/to/datastore/v1
-    symlink(major_target, &temp_link).context(error::LinkCreateSnafu { path: &temp_link })?;
-    // Atomically swap the link into place, so that 'current' points to the new major version.
-    fs::rename(&temp_link, &current_version_link)
-        .await
-        .context(error::LinkSwapSnafu {
-            link: &current_version_link,
-        })?;
+        // Create the new link at a temporary path so we can atomically swap it into the real
+        // path with a rename call.
+        symlink(&swap.target, &temp_link).context(error::LinkCreateSnafu { path: &temp_link })?;
+        // Atomically swap the link into place, so that it points to its new target.
+        fs::rename(&temp_link, &swap.link)
+            .await
+            .context(error::LinkSwapSnafu { link: &swap.link })?;
+    }
 
     // =^..^=   =^..^=   =^..^=   =^..^=
 
@@ -537,6 +704,49 @@ where
     Ok(())
 }
 
+/// Confirms that the live data in the given data store can be deserialized into the model, so we
+/// know migrations left it in a state this version can use.
+fn verify_datastore<P>(datastore_path: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let datastore = FilesystemDataStore::new(datastore_path);
+    verify_prefix::<Settings>(&datastore, "settings.", None)?;
+    verify_prefix::<Services>(&datastore, "services.", Some("services"))?;
+    verify_prefix::<ConfigurationFiles>(
+        &datastore,
+        "configuration-files.",
+        Some("configuration-files"),
+    )?;
+    Ok(())
+}
+
+/// Deserializes the live data under the given prefix into the given model type, if there is any.
+fn verify_prefix<T>(
+    datastore: &FilesystemDataStore,
+    find_prefix: &str,
+    map_prefix: Option<&str>,
+) -> Result<()>
+where
+    T: DeserializeOwned,
+{
+    debug!("Verifying migrated '{}' data", find_prefix);
+    let data = datastore
+        .get_prefix(find_prefix, &Committed::Live)
+        .context(error::VerifyReadSnafu {
+            prefix: find_prefix,
+        })?;
+    if data.is_empty() {
+        return Ok(());
+    }
+    let _: T = from_map_with_prefix(map_prefix.map(str::to_string), &data).context(
+        error::VerifyDeserializeSnafu {
+            prefix: find_prefix,
+        },
+    )?;
+    Ok(())
+}
+
 async fn load_manifest(repository: tough::Repository) -> Result<Manifest> {
     let target = "manifest.json";
     let target = target
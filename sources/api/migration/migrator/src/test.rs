@@ -1,6 +1,7 @@
 //! Provides an end-to-end test of `migrator` via the `run` function. This module is conditionally
 //! compiled for cfg(test) only.
 use crate::args::Args;
+use crate::error::Error;
 use crate::run;
 use chrono::{DateTime, Utc};
 use semver::Version;
@@ -32,6 +33,12 @@ enum TestType {
     BackwardFailure,
     /// The test is not expected to raise an error in migrator.
     Success,
+    /// The last migration succeeds, but leaves data that doesn't match the model.
+    InvalidResult,
+    /// The manifest doesn't allow migrating past the starting version, so nothing should run.
+    Bounded,
+    /// The migrations succeed, but are large, like a real migration binary.
+    Large,
 }
 
 impl TestType {
@@ -39,7 +46,10 @@ impl TestType {
         match self {
             TestType::ForwardFailure => [FIRST_MIGRATION, SECOND_MIGRATION, FAILING_MIGRATION],
             TestType::BackwardFailure => [FAILING_MIGRATION, SECOND_MIGRATION, THIRD_MIGRATION],
-            TestType::Success => [FIRST_MIGRATION, SECOND_MIGRATION, THIRD_MIGRATION],
+            TestType::Success | TestType::Bounded | TestType::Large => {
+                [FIRST_MIGRATION, SECOND_MIGRATION, THIRD_MIGRATION]
+            }
+            TestType::InvalidResult => [FIRST_MIGRATION, SECOND_MIGRATION, INVALID_MIGRATION],
         }
         .iter()
         .map(|s| s.to_string())
@@ -64,6 +74,16 @@ const THIRD_MIGRATION: &str = "third-migration";
 /// A migration that will fail and exit with a non-zero code.
 const FAILING_MIGRATION: &str = "failing-migration";
 
+/// A migration that succeeds, but writes a setting that doesn't match the model.
+const INVALID_MIGRATION: &str = "invalid-migration";
+
+/// A comment line used to pad out large test migrations.
+const LARGE_MIGRATION_PADDING: &str =
+    "# padding to make this migration about as large as a real migration binary\n";
+
+/// How many padding lines to add to large test migrations; about 20 MiB.
+const LARGE_MIGRATION_LINES: usize = 256 * 1024;
+
 /// Creates a script that will serve as a migration during testing. The script writes its migrations
 /// name to a file named `result.txt` in the parent directory of the datastore. `pentacle` does not
 /// retain the name of the executing binary or script, so we take the `migration_name` as input,
@@ -78,10 +98,17 @@ target_datastore="$5"
 outfile="${{datastore_parent_dir}}/result.txt"
 echo "${{migration_name}}:" "${{@}}" >> "${{outfile}}"
 mkdir -p $5
+# Real migrations always leave live data, which --verify-after reads.
+mkdir -p "${{target_datastore}}/live"
 if [[ "${{migration_name}}" = "failing-migration" ]]; then
   >&2 echo "this migration is supposed to fail: exit 1"
   exit 1
 fi
+if [[ "${{migration_name}}" = "invalid-migration" ]]; then
+  # ntp is a structure in the model, not a string.
+  mkdir -p "${{target_datastore}}/live/settings"
+  echo '"not a structure"' > "${{target_datastore}}/live/settings/ntp"
+fi
 "#,
         migration_name.as_ref()
     )
@@ -150,6 +177,13 @@ async fn create_test_repo(test_type: TestType) -> TestRepo {
         (Version::new(0, 99, 0), Version::new(0, 99, 1)),
         migration_names.clone(),
     );
+    // A version that doesn't change the data store, so there's nothing to run.
+    manifest
+        .migrations
+        .insert((Version::new(0, 99, 1), Version::new(0, 99, 2)), Vec::new());
+    if let TestType::Bounded = test_type {
+        manifest.max_migratable_version = Some(Version::new(0, 99, 0));
+    }
     update_metadata::write_file(tuf_indir.join("manifest.json").as_path(), &manifest).unwrap();
 
     // Create an script that we can use as the 'migration' that migrator will run. This script will
@@ -160,7 +194,10 @@ async fn create_test_repo(test_type: TestType) -> TestRepo {
     // will need to be updated.
     for migration_name in &migration_names {
         // Create a script to use as a migration.
-        let data = create_test_migration(migration_name);
+        let mut data = create_test_migration(migration_name);
+        if let TestType::Large = test_type {
+            data.push_str(&LARGE_MIGRATION_PADDING.repeat(LARGE_MIGRATION_LINES));
+        }
         // Save an lz4 compressed copy of the migration script into the tuftool_indir.
         compress(data.as_bytes(), &tuf_indir.join(migration_name))
     }
@@ -348,6 +385,10 @@ async fn migrate_forward() {
         migrate_to_version: to_version,
         root_path: root(),
         metadata_directory: test_repo.metadata_path.clone(),
+        source_datastore: None,
+        verify_after: false,
+        dry_run_flip: false,
+        work_dir: None,
     };
     run(&args).await.unwrap();
     // the migrations should write to a file named result.txt.
@@ -396,6 +437,10 @@ async fn migrate_backward() {
         migrate_to_version: to_version,
         root_path: root(),
         metadata_directory: test_repo.metadata_path.clone(),
+        source_datastore: None,
+        verify_after: false,
+        dry_run_flip: false,
+        work_dir: None,
     };
     run(&args).await.unwrap();
     let output_file = test_datastore.tmp.path().join("result.txt");
@@ -441,6 +486,10 @@ async fn migrate_forward_with_failed_migration() {
         migrate_to_version: to_version.clone(),
         root_path: root(),
         metadata_directory: test_repo.metadata_path.clone(),
+        source_datastore: None,
+        verify_after: false,
+        dry_run_flip: false,
+        work_dir: None,
     };
     let result = run(&args).await;
     assert!(result.is_err());
@@ -494,6 +543,10 @@ async fn migrate_backward_with_failed_migration() {
         migrate_to_version: to_version.clone(),
         root_path: root(),
         metadata_directory: test_repo.metadata_path.clone(),
+        source_datastore: None,
+        verify_after: false,
+        dry_run_flip: false,
+        work_dir: None,
     };
     let result = run(&args).await;
     assert!(result.is_err());
@@ -532,3 +585,416 @@ async fn migrate_backward_with_failed_migration() {
         .unwrap()
         .starts_with("v0.99.1"));
 }
+
+/// This test ensures that --verify-after catches a migrated data store that doesn't match the
+/// model, and that the new version is left in place since the old one is kept for rollback.
+#[tokio::test]
+async fn migrate_forward_with_failed_verification() {
+    let from_version = Version::parse("0.99.0").unwrap();
+    let to_version = Version::parse("0.99.1").unwrap();
+    let test_datastore = TestDatastore::new(from_version);
+    let test_repo = create_test_repo(TestType::InvalidResult).await;
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version,
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path.clone(),
+        source_datastore: None,
+        verify_after: true,
+        dry_run_flip: false,
+        work_dir: None,
+    };
+    let result = run(&args).await;
+    assert!(
+        matches!(result, Err(Error::VerifyDeserialize { ref prefix, .. }) if prefix == "settings."),
+        "{:?}",
+        result
+    );
+
+    // The migrations ran and the links were flipped, so current points to the new version.
+    let current = assert_directory_structure(test_datastore.tmp.path()).await;
+    assert!(current
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("v0.99.1"));
+}
+
+/// This test ensures that --verify-after accepts a migrated data store that matches the model.
+#[tokio::test]
+async fn migrate_forward_with_verification() {
+    let from_version = Version::parse("0.99.0").unwrap();
+    let to_version = Version::parse("0.99.1").unwrap();
+    let test_datastore = TestDatastore::new(from_version);
+    let test_repo = create_test_repo(TestType::Success).await;
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version,
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path.clone(),
+        source_datastore: None,
+        verify_after: true,
+        dry_run_flip: false,
+        work_dir: None,
+    };
+    run(&args).await.unwrap();
+}
+
+/// This test ensures that migrator refuses a target version outside the manifest's bounds before
+/// running any migrations.
+#[tokio::test]
+async fn migrate_forward_past_manifest_bounds() {
+    let from_version = Version::parse("0.99.0").unwrap();
+    let to_version = Version::parse("0.99.1").unwrap();
+    let test_datastore = TestDatastore::new(from_version);
+    let test_repo = create_test_repo(TestType::Bounded).await;
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version,
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path.clone(),
+        source_datastore: None,
+        verify_after: false,
+        dry_run_flip: false,
+        work_dir: None,
+    };
+    let result = run(&args).await;
+    assert!(
+        matches!(result, Err(Error::UnsupportedVersionJump { .. })),
+        "{:?}",
+        result
+    );
+
+    // No migrations ran, and we're still on the old version.
+    assert!(!test_datastore.tmp.path().join("result.txt").exists());
+    let current = test_datastore
+        .tmp
+        .path()
+        .join("current")
+        .canonicalize()
+        .unwrap();
+    assert!(current
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("v0.99.0"));
+}
+
+/// This test ensures that migrator runs migrations from an explicitly given source data store,
+/// taking its version from its name rather than from the `current` link.
+#[tokio::test]
+async fn migrate_forward_from_source_datastore() {
+    let from_version = Version::parse("0.99.0").unwrap();
+    let to_version = Version::parse("0.99.1").unwrap();
+    // The `current` link says we're already on the target version, so migrator would have nothing
+    // to do without the given source.
+    let test_datastore = TestDatastore::new(to_version.clone());
+    let source = test_datastore
+        .tmp
+        .path()
+        .join(format!("v{}_repair", from_version));
+    std::fs::create_dir_all(source.join("live")).unwrap();
+    let source = source.canonicalize().unwrap();
+    let test_repo = create_test_repo(TestType::Success).await;
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version,
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path.clone(),
+        source_datastore: Some(source.clone()),
+        verify_after: false,
+        dry_run_flip: false,
+        work_dir: None,
+    };
+    run(&args).await.unwrap();
+
+    // The forward migrations ran, starting from the given source.
+    let output_file = test_datastore.tmp.path().join("result.txt");
+    let contents = std::fs::read_to_string(&output_file).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with(&format!("{}: --forward", FIRST_MIGRATION)));
+    assert!(lines[0].contains(&format!("--source-datastore {}", source.display())));
+
+    // The migrated data store is now current.
+    let current = test_datastore
+        .tmp
+        .path()
+        .join("current")
+        .canonicalize()
+        .unwrap();
+    let current_name = current.file_name().unwrap().to_str().unwrap();
+    assert!(current_name.starts_with("v0.99.1_"));
+    assert_ne!(current, test_datastore.datastore);
+}
+
+/// This test ensures that when there are no migrations, a source data store from outside the
+/// data store directory is made live by the links in the data store directory, and no links are
+/// made beside the source.
+#[tokio::test]
+async fn migrate_forward_without_migrations_from_outside_source() {
+    let from_version = Version::parse("0.99.1").unwrap();
+    let to_version = Version::parse("0.99.2").unwrap();
+    let test_datastore = TestDatastore::new(from_version.clone());
+    let outside = TempDir::new().unwrap();
+    let source = outside.path().join(format!("v{}_repair", from_version));
+    std::fs::create_dir_all(source.join("live")).unwrap();
+    let source = source.canonicalize().unwrap();
+    let test_repo = create_test_repo(TestType::Success).await;
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version,
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path.clone(),
+        source_datastore: Some(source.clone()),
+        verify_after: false,
+        dry_run_flip: false,
+        work_dir: None,
+    };
+    run(&args).await.unwrap();
+
+    // No migrations ran.
+    assert!(!test_datastore.tmp.path().join("result.txt").exists());
+
+    // The links in the data store directory now lead to the given source.
+    let dir = test_datastore.tmp.path();
+    assert_eq!(dir.join("current").canonicalize().unwrap(), source);
+    assert_eq!(dir.join("v0.99.2").canonicalize().unwrap(), source);
+
+    // Nothing was added beside the source.
+    let outside_entries = list_dir_entries(outside.path()).await;
+    assert_eq!(outside_entries, vec![source]);
+}
+
+/// Migrations are held in memory while they run, so there's a limit to their size, but it's well
+/// above the size of real migrations.
+#[tokio::test]
+async fn migrate_forward_large_migrations() {
+    let from_version = Version::parse("0.99.0").unwrap();
+    let to_version = Version::parse("0.99.1").unwrap();
+    let test_datastore = TestDatastore::new(from_version);
+    let test_repo = create_test_repo(TestType::Large).await;
+    let migration_size = create_test_migration(FIRST_MIGRATION).len()
+        + LARGE_MIGRATION_PADDING.len() * LARGE_MIGRATION_LINES;
+    assert!(migration_size > 16 * 1024 * 1024);
+    assert!((migration_size as u64) < crate::MAX_MIGRATION_SIZE);
+
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version,
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path.clone(),
+        source_datastore: None,
+        verify_after: false,
+        dry_run_flip: false,
+        work_dir: None,
+    };
+    run(&args).await.unwrap();
+
+    // All three migrations ran.
+    let output_file = test_datastore.tmp.path().join("result.txt");
+    let contents = std::fs::read_to_string(&output_file).unwrap();
+    assert_eq!(contents.lines().count(), 3);
+}
+
+#[test]
+fn size_limited_reader() {
+    use std::io::Read;
+    let data = vec![7u8; 100];
+
+    let mut out = Vec::new();
+    let mut reader = crate::SizeLimitedReader::new(data.as_slice(), 100);
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, data);
+    assert!(!reader.exceeded());
+
+    let mut reader = crate::SizeLimitedReader::new(data.as_slice(), 99);
+    reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert!(reader.exceeded());
+}
+
+/// Migrations can be built in a separate work directory; the result is moved back into the data
+/// store directory before the links are flipped to it.
+#[tokio::test]
+async fn migrate_forward_with_work_dir() {
+    let from_version = Version::parse("0.99.0").unwrap();
+    let to_version = Version::parse("0.99.1").unwrap();
+    let test_datastore = TestDatastore::new(from_version);
+    let test_repo = create_test_repo(TestType::Success).await;
+    let work_dir = TempDir::new().unwrap();
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version,
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path.clone(),
+        source_datastore: None,
+        verify_after: true,
+        dry_run_flip: false,
+        work_dir: Some(work_dir.path().canonicalize().unwrap()),
+    };
+    run(&args).await.unwrap();
+
+    // The test migrations write result.txt next to their source data store, so the first one
+    // writes it in the data store directory, and the rest in the work directory.
+    let work_path = work_dir.path().canonicalize().unwrap();
+    let first = std::fs::read_to_string(test_datastore.tmp.path().join("result.txt")).unwrap();
+    assert_eq!(first.lines().count(), 1);
+    assert!(first.contains(&format!("--target-datastore {}", work_path.display())));
+    let rest = std::fs::read_to_string(work_path.join("result.txt")).unwrap();
+    assert_eq!(rest.lines().count(), 2);
+
+    // The final data store is live, in the data store directory, and no data stores are left
+    // behind in the work directory.
+    let current = assert_directory_structure(test_datastore.tmp.path()).await;
+    assert!(current
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("v0.99.1"));
+    assert_eq!(
+        current.parent().unwrap(),
+        test_datastore.tmp.path().canonicalize().unwrap()
+    );
+    assert_eq!(
+        list_dir_entries(&work_path).await,
+        vec![work_path.join("result.txt")]
+    );
+}
+
+#[test]
+fn copy_datastore_dir() {
+    let tmp = TempDir::new().unwrap();
+    let from = tmp.path().join("from");
+    std::fs::create_dir_all(from.join("live/settings")).unwrap();
+    std::fs::write(from.join("live/settings/motd"), "\"hi\"").unwrap();
+    std::os::unix::fs::symlink("live", from.join("link")).unwrap();
+
+    let to = tmp.path().join("to");
+    crate::copy_dir_all(&from, &to).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(to.join("live/settings/motd")).unwrap(),
+        "\"hi\""
+    );
+    assert_eq!(
+        std::fs::read_link(to.join("link")).unwrap(),
+        PathBuf::from("live")
+    );
+}
+
+#[test]
+fn move_datastore_across_filesystems() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new().unwrap();
+    let datastore_dir = tmp.path().join("datastore");
+    std::fs::create_dir(&datastore_dir).unwrap();
+    let from = tmp.path().join("work").join("v1.5.2_0123456789abcdef");
+    std::fs::create_dir_all(from.join("live/settings")).unwrap();
+    std::fs::write(from.join("live/settings/motd"), "\"hi\"").unwrap();
+    std::fs::set_permissions(from.join("live"), std::fs::Permissions::from_mode(0o750)).unwrap();
+    // A partial copy left behind by an earlier attempt doesn't get in the way.
+    std::fs::create_dir(datastore_dir.join("v1.5.2_0123456789abcdef.partial")).unwrap();
+
+    let cross_device = |_: &Path, _: &Path| {
+        Err(std::io::Error::from_raw_os_error(
+            nix::errno::Errno::EXDEV as i32,
+        ))
+    };
+    let to = crate::move_into_datastore_dir_with(&from, &datastore_dir, cross_device).unwrap();
+    assert_eq!(to, datastore_dir.join("v1.5.2_0123456789abcdef"));
+    assert_eq!(
+        std::fs::read_to_string(to.join("live/settings/motd")).unwrap(),
+        "\"hi\""
+    );
+    let mode = std::fs::metadata(to.join("live"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o750);
+    assert!(!from.exists());
+    assert!(!datastore_dir
+        .join("v1.5.2_0123456789abcdef.partial")
+        .exists());
+
+    // If the copy fails, the partial copy is removed.
+    let missing = tmp.path().join("work").join("v1.5.3_0123456789abcdef");
+    crate::move_into_datastore_dir_with(&missing, &datastore_dir, cross_device).unwrap_err();
+    assert!(!datastore_dir
+        .join("v1.5.3_0123456789abcdef.partial")
+        .exists());
+}
+
+#[tokio::test]
+async fn dry_run_flip_matches_real_flip() {
+    let tmp = TempDir::new().unwrap();
+    let version = Version::new(1, 5, 2);
+    let to_datastore = tmp.path().join("v1.5.2_0123456789abcdef");
+    std::fs::create_dir(&to_datastore).unwrap();
+    // Start from an older version, like a real upgrade.
+    std::fs::create_dir(tmp.path().join("v1.4.0_fedcba9876543210")).unwrap();
+    for (link, target) in [
+        ("v1.4.0", "v1.4.0_fedcba9876543210"),
+        ("v1.4", "v1.4.0"),
+        ("v1", "v1.4"),
+        ("current", "v1"),
+    ] {
+        std::os::unix::fs::symlink(target, tmp.path().join(link)).unwrap();
+    }
+
+    let plan = crate::flip_plan(&version, tmp.path(), &to_datastore).unwrap();
+    let planned: Vec<_> = plan
+        .iter()
+        .map(|swap| (swap.link.clone(), swap.target.clone()))
+        .collect();
+    assert_eq!(
+        planned,
+        [
+            ("v1.5.2", "v1.5.2_0123456789abcdef"),
+            ("v1.5", "v1.5.2"),
+            ("v1", "v1.5"),
+            ("current", "v1"),
+        ]
+        .iter()
+        .map(|(link, target)| (tmp.path().join(link), PathBuf::from(target)))
+        .collect::<Vec<_>>()
+    );
+
+    // A dry run doesn't touch the links.
+    crate::flip_to_new_version(&version, tmp.path(), &to_datastore, true)
+        .await
+        .unwrap();
+    assert!(!tmp.path().join("v1.5.2").exists());
+    assert_eq!(
+        std::fs::read_link(tmp.path().join("v1")).unwrap(),
+        PathBuf::from("v1.4")
+    );
+
+    // The real flip makes exactly the planned swaps.
+    crate::flip_to_new_version(&version, tmp.path(), &to_datastore, false)
+        .await
+        .unwrap();
+    for (link, target) in planned {
+        assert_eq!(std::fs::read_link(&link).unwrap(), target);
+    }
+    assert_eq!(
+        std::fs::canonicalize(tmp.path().join("current")).unwrap(),
+        std::fs::canonicalize(&to_datastore).unwrap()
+    );
+}
@@ -52,6 +52,18 @@ impl BottlerocketTemplateImporter {
             ..Default::default()
         }
     }
+
+    /// Creates an importer that renders templates with the settings as they would be if the given
+    /// transaction were committed.
+    pub fn for_transaction(api_socket: PathBuf, transaction: String) -> Self {
+        Self {
+            settings_resolver: BottlerocketSettingsResolver::for_transaction(
+                api_socket,
+                transaction,
+            ),
+            ..Default::default()
+        }
+    }
 }
 
 impl_template_importer!(
@@ -5,7 +5,6 @@ use super::as_std_err;
 use crate::v2::ExtensionRequirement;
 use async_trait::async_trait;
 use cached::proc_macro::once;
-use constants::API_SETTINGS_URI;
 use serde_json::{json, Map, Value};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::path::{Path, PathBuf};
@@ -24,22 +23,36 @@ pub trait SettingsResolver {
 }
 
 /// `SettingsResolver` implementation that fetches settings from the Bottlerocket API.
+///
+/// Live settings are used unless a transaction is given, in which case settings are fetched as
+/// they would be if that transaction were committed.
 #[derive(Debug, Clone)]
 pub struct BottlerocketSettingsResolver {
     pub api_socket: PathBuf,
+    transaction: Option<String>,
 }
 
 impl BottlerocketSettingsResolver {
     pub fn new(api_socket: PathBuf) -> Self {
-        Self { api_socket }
+        Self {
+            api_socket,
+            transaction: None,
+        }
+    }
+
+    /// Creates a resolver that fetches settings as they would be if the given transaction were
+    /// committed.
+    pub fn for_transaction(api_socket: PathBuf, transaction: String) -> Self {
+        Self {
+            api_socket,
+            transaction: Some(transaction),
+        }
     }
 }
 
 impl Default for BottlerocketSettingsResolver {
     fn default() -> Self {
-        Self {
-            api_socket: constants::API_SOCKET.into(),
-        }
+        Self::new(constants::API_SOCKET.into())
     }
 }
 
@@ -54,14 +67,18 @@ impl SettingsResolver for BottlerocketSettingsResolver {
         I: Iterator<Item = ExtensionRequirement> + Send,
     {
         // TODO: Modify this to use per-setting requests in the future.
-        let all_settings = get_settings_json(&self.api_socket)
-            .await?
-            .as_object()
-            .cloned()
-            .context(error::NonJSONObjectSnafu {
-                key: "settings".to_string(),
-            })
-            .map_err(as_std_err)?;
+        let all_settings = match &self.transaction {
+            Some(transaction) => {
+                get_transaction_settings_json(&self.api_socket, transaction).await?
+            }
+            None => get_settings_json(&self.api_socket).await?,
+        }
+        .as_object()
+        .cloned()
+        .context(error::NonJSONObjectSnafu {
+            key: "settings".to_string(),
+        })
+        .map_err(as_std_err)?;
 
         let settings = Self::minimize_settings(
             &Self::extract_key_from_api_response("settings", &all_settings).map_err(as_std_err)?,
@@ -127,29 +144,39 @@ impl BottlerocketSettingsResolver {
 /// Results are cached, only calling the API on the first function execution.
 #[once(result = true, sync_writes = true)]
 pub async fn get_settings_json(socket_path: &Path) -> Result<Value> {
+    fetch_settings_json(socket_path, SETTINGS_API_URI).await
+}
+
+/// Fetches a JSON object like `get_settings_json`, but with the settings as they would be if the
+/// given transaction were committed.
+///
+/// Results are cached, only calling the API on the first function execution.
+#[once(result = true, sync_writes = true)]
+pub async fn get_transaction_settings_json(socket_path: &Path, transaction: &str) -> Result<Value> {
+    let transaction: String =
+        url::form_urlencoded::byte_serialize(transaction.as_bytes()).collect();
+    let uri = format!("{}?tx={}", SETTINGS_API_URI, transaction);
+    fetch_settings_json(socket_path, &uri).await
+}
+
+async fn fetch_settings_json(socket_path: &Path, uri: &str) -> Result<Value> {
     let method = "GET";
-    trace!("{}ing from {}", method, SETTINGS_API_URI);
-    let (code, response_body) = apiclient::raw_request(socket_path, SETTINGS_API_URI, method, None)
+    trace!("{}ing from {}", method, uri);
+    let (code, response_body) = apiclient::raw_request(socket_path, uri, method, None)
         .await
-        .context(error::APIRequestSnafu {
-            method,
-            uri: SETTINGS_API_URI,
-        })?;
+        .context(error::APIRequestSnafu { method, uri })?;
 
     ensure!(
         code.is_success(),
         error::APIResponseSnafu {
             method,
-            uri: SETTINGS_API_URI.to_string(),
+            uri,
             code,
             response_body,
         }
     );
     trace!("JSON response: {}", response_body);
-    serde_json::from_str(&response_body).context(error::ResponseJsonSnafu {
-        method,
-        uri: API_SETTINGS_URI,
-    })
+    serde_json::from_str(&response_body).context(error::ResponseJsonSnafu { method, uri })
 }
 
 pub mod error {
@@ -385,7 +385,7 @@ fn populate_default_datastore<P: AsRef<Path>>(
         for metadata in metadata_to_write {
             let (md, key, val) = metadata;
             datastore
-                .set_metadata(&md, &key, val)
+                .set_metadata(&md, &key, val, &datastore::Committed::Live)
                 .context(error::WriteMetadataSnafu)?;
         }
     }
@@ -36,9 +36,11 @@ enum RunMode {
 /// Store the args we receive on the command line
 struct Args {
     daemon: bool,
+    dry_run: bool,
     log_level: LevelFilter,
     mode: RunMode,
     socket_path: String,
+    transaction: Option<String>,
 }
 
 /// Print a usage message in the event a bad arg is passed
@@ -48,6 +50,7 @@ fn usage() -> ! {
         r"Usage: {}
             [ --all ]
             [ --daemon ]
+            [ --dry-run [ --transaction NAME ] ]
             [ --socket-path PATH ]
             [ --log-level trace|debug|info|warn|error ]
 
@@ -59,6 +62,13 @@ fn usage() -> ! {
     If --daemon is given, thar-be-settings will fork and do its work in a new
     process; this is useful to prevent blocking an API call.
 
+    If --dry-run is given, configuration files are rendered but not written,
+    and no services are restarted; any rendering failure is printed and
+    thar-be-settings exits nonzero.  It can't be used with --daemon, since the
+    caller needs the result.  If --transaction is also given, files are
+    rendered with the settings as they would be if that transaction were
+    committed, rather than the live settings.
+
     Socket path defaults to {}",
         program_name,
         constants::API_SOCKET,
@@ -75,9 +85,11 @@ fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
 /// Parse the args to the program and return an Args struct
 fn parse_args(args: env::Args) -> Args {
     let mut daemon = false;
+    let mut dry_run = false;
     let mut log_level = None;
     let mut mode = RunMode::SpecificKeys;
     let mut socket_path = None;
+    let mut transaction = None;
 
     let mut iter = args.skip(1);
     while let Some(arg) = iter.next() {
@@ -86,6 +98,8 @@ fn parse_args(args: env::Args) -> Args {
 
             "--daemon" => daemon = true,
 
+            "--dry-run" => dry_run = true,
+
             "--log-level" => {
                 let log_level_str = iter
                     .next()
@@ -102,15 +116,31 @@ fn parse_args(args: env::Args) -> Args {
                 )
             }
 
+            "--transaction" => {
+                transaction = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --transaction")),
+                )
+            }
+
             _ => usage(),
         }
     }
 
+    if daemon && dry_run {
+        usage_msg("--dry-run can't be used with --daemon");
+    }
+    if transaction.is_some() && !dry_run {
+        usage_msg("--transaction can only be used with --dry-run");
+    }
+
     Args {
         daemon,
+        dry_run,
         mode,
         log_level: log_level.unwrap_or(LevelFilter::Info),
         socket_path: socket_path.unwrap_or_else(|| constants::API_SOCKET.to_string()),
+        transaction,
     }
 }
 
@@ -125,16 +155,28 @@ async fn write_config_files(
     let config_files = config::get_affected_config_files(&args.socket_path, files_limit).await?;
     trace!("Found config files: {:?}", config_files);
 
-    let template_importer = BottlerocketTemplateImporter::new((&args.socket_path).into());
+    let template_importer = match &args.transaction {
+        Some(transaction) => BottlerocketTemplateImporter::for_transaction(
+            (&args.socket_path).into(),
+            transaction.clone(),
+        ),
+        None => BottlerocketTemplateImporter::new((&args.socket_path).into()),
+    };
 
-    // Ensure all files render properly
+    // Ensure all files render properly; a dry run is only useful if it reports every failure
     info!("Rendering config files...");
-    let strict = match &args.mode {
-        RunMode::SpecificKeys => true,
-        RunMode::All => false,
-    };
+    let strict = args.dry_run
+        || match &args.mode {
+            RunMode::SpecificKeys => true,
+            RunMode::All => false,
+        };
     let rendered = config::render_config_files(&template_importer, config_files, strict).await?;
 
+    if args.dry_run {
+        info!("Rendered {} config files, not writing them", rendered.len());
+        return Ok(());
+    }
+
     // If all the config renders properly, write it to disk
     info!("Writing config files to disk...");
     config::write_config_files(&rendered)?;
@@ -180,12 +222,19 @@ async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
                 write_config_files(&args, Some(config_file_names)).await?;
             }
 
+            if args.dry_run {
+                return Ok(());
+            }
+
             // Now go bounce the affected services
             info!("Restarting affected services...");
             service::restart_services(services)?;
         }
         RunMode::All => {
             write_config_files(&args, None).await?;
+            if args.dry_run {
+                return Ok(());
+            }
 
             info!("Restarting all services...");
             let services = service::get_affected_services(&args.socket_path, None).await?;
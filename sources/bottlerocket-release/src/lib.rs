@@ -31,6 +31,36 @@ pub struct BottlerocketRelease {
     pub arch: String,
 }
 
+/// PartialBottlerocketRelease holds some of the fields of a BottlerocketRelease, for callers that
+/// only want to show a subset of them, like API queries for a prefix of the field names.  Fields
+/// that aren't included are left out when serializing.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct PartialBottlerocketRelease {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pretty_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_id: Option<Version>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+}
+
+impl From<BottlerocketRelease> for PartialBottlerocketRelease {
+    fn from(release: BottlerocketRelease) -> Self {
+        Self {
+            pretty_name: Some(release.pretty_name),
+            variant_id: Some(release.variant_id),
+            version_id: Some(release.version_id),
+            build_id: Some(release.build_id),
+            arch: Some(release.arch),
+        }
+    }
+}
+
 mod error {
     use snafu::Snafu;
     use std::io;
@@ -98,4 +128,18 @@ impl BottlerocketRelease {
 
         envy::from_iter(pairs).context(error::LoadReleaseDataSnafu { path })
     }
+
+    /// Returns a PartialBottlerocketRelease with only the fields whose names start with the given
+    /// prefix, for example "version_id" for "ver".
+    pub fn with_field_prefix<S: AsRef<str>>(self, prefix: S) -> PartialBottlerocketRelease {
+        let prefix = prefix.as_ref();
+        let keep = |name: &str| name.starts_with(prefix);
+        PartialBottlerocketRelease {
+            pretty_name: Some(self.pretty_name).filter(|_| keep("pretty_name")),
+            variant_id: Some(self.variant_id).filter(|_| keep("variant_id")),
+            version_id: Some(self.version_id).filter(|_| keep("version_id")),
+            build_id: Some(self.build_id).filter(|_| keep("build_id")),
+            arch: Some(self.arch).filter(|_| keep("arch")),
+        }
+    }
 }
@@ -1,22 +1,125 @@
+use async_trait::async_trait;
 use aws_config::BehaviorVersion;
+use aws_sdk_cloudformation::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_cloudformation::operation::signal_resource::SignalResourceError;
+use aws_sdk_cloudformation::operation::RequestId;
+use std::env;
 use std::str::FromStr;
+use std::time::Duration;
 
 use crate::error::{self, Result};
 use aws_types::region::Region;
 use imdsclient::ImdsClient;
-use log::info;
+use log::{info, warn};
 use snafu::{OptionExt, ResultExt};
+use tokio_retry::{strategy::FibonacciBackoff, Retry};
 
-/// Signals Cloudformation stack resource
+// IMDS is reachable at a well-known IPv4 address by default, and at a well-known IPv6 address on
+// instances with the IPv6 endpoint enabled.
+const IMDS_IPV4_ENDPOINT: &str = "http://169.254.169.254";
+const IMDS_IPV6_ENDPOINT: &str = "http://[fd00:ec2::254]";
+// Environment variable used to select the IMDS endpoint, matching the one used by the AWS SDKs.
+const IMDS_ENDPOINT_MODE_ENV_VAR: &str = "AWS_EC2_METADATA_SERVICE_ENDPOINT_MODE";
+
+// Environment variables consulted for the region if it isn't given explicitly, in order.
+const REGION_ENV_VARS: &[&str] = &["AWS_REGION", "AWS_DEFAULT_REGION"];
+
+// How many times to retry sending a signal after the first attempt fails.
+const SIGNAL_RETRIES: usize = 5;
+
+fn retry_strategy() -> impl Iterator<Item = Duration> {
+    // Retry attempts at 0.25s, 0.5s, 1s, 1.75s, and 3s.
+    FibonacciBackoff::from_millis(250).take(SIGNAL_RETRIES)
+}
+
+/// A status to send if the primary status can't be delivered, and the SignalResource error codes
+/// it's sent for.
+#[derive(Debug)]
+pub struct Fallback {
+    pub status: String,
+    pub error_codes: Vec<String>,
+}
+
+impl Fallback {
+    /// Returns whether the given error from sending the primary status should be covered by
+    /// sending the fallback status.  Only errors returned by CloudFormation have a code; if we
+    /// couldn't reach it at all, the fallback wouldn't get through either.
+    fn covers(&self, err: &error::Error) -> bool {
+        match err {
+            error::Error::SignalResource { code, .. } => self.error_codes.contains(code),
+            _ => false,
+        }
+    }
+}
+
+/// Sends signals with a given status to a CloudFormation resource.
+#[async_trait]
+pub(crate) trait SignalSender {
+    async fn send_signal(&self, status: &str) -> Result<()>;
+}
+
+/// Sends signals for this instance using the CloudFormation API.
+struct CloudFormationSignaler {
+    client: aws_sdk_cloudformation::Client,
+    stack_name: String,
+    logical_resource_id: String,
+    instance_id: String,
+}
+
+#[async_trait]
+impl SignalSender for CloudFormationSignaler {
+    async fn send_signal(&self, status: &str) -> Result<()> {
+        let output = self
+            .client
+            .signal_resource()
+            .stack_name(&self.stack_name)
+            .logical_resource_id(&self.logical_resource_id)
+            .status(
+                aws_sdk_cloudformation::types::ResourceSignalStatus::from_str(status)
+                    .expect("infallible"),
+            )
+            .unique_id(&self.instance_id)
+            .send()
+            .await
+            .with_context(signal_error_context)?;
+
+        info!(
+            "Sent {} signal - RequestID: {}",
+            status,
+            output.request_id().unwrap_or("unknown")
+        );
+        Ok(())
+    }
+}
+
+/// Pulls the error code and request ID out of a failed SignalResource request, so they're
+/// included in our error for troubleshooting.
+fn signal_error_context(
+    err: &mut SdkError<SignalResourceError>,
+) -> error::SignalResourceSnafu<String, String> {
+    error::SignalResourceSnafu {
+        code: err.code().unwrap_or("unknown").to_string(),
+        request_id: err.request_id().unwrap_or("unknown").to_string(),
+    }
+}
+
+/// Signals Cloudformation stack resource.  The region and instance ID are fetched from IMDS
+/// unless they're given explicitly, or the region is set in the environment.
+///
+/// If a fallback is given, its status is sent once if the primary status was still rejected with
+/// one of its error codes after retries.
 pub async fn signal_resource(
     stack_name: String,
     logical_resource_id: String,
     status: String,
+    fallback: Option<Fallback>,
+    region: Option<String>,
+    instance_id: Option<String>,
 ) -> Result<()> {
-    info!("Connecting to IMDS");
-    let mut client = ImdsClient::new();
-    let instance_id = get_instance_id(&mut client).await?;
-    let region = get_region(&mut client).await?;
+    let endpoint = imds_endpoint(env::var(IMDS_ENDPOINT_MODE_ENV_VAR).ok().as_deref());
+    let mut client = ImdsClient::new_impl(endpoint.to_string());
+    let instance_id = get_instance_id(instance_id, &mut client).await?;
+    let region = get_region(region.or_else(region_from_env), &mut client).await?;
 
     info!(
         "Region: {:?} - InstanceID: {:?} - Signal: {:?}",
@@ -26,26 +129,70 @@ pub async fn signal_resource(
         .region(Region::new(region.to_owned()))
         .load()
         .await;
-    let client = aws_sdk_cloudformation::Client::new(&config);
+    let signaler = CloudFormationSignaler {
+        client: aws_sdk_cloudformation::Client::new(&config),
+        stack_name,
+        logical_resource_id,
+        instance_id,
+    };
 
-    client
-        .signal_resource()
-        .stack_name(stack_name)
-        .logical_resource_id(logical_resource_id)
-        .status(
-            aws_sdk_cloudformation::types::ResourceSignalStatus::from_str(&status)
-                .expect("infallible"),
-        )
-        .unique_id(instance_id)
-        .send()
-        .await
-        .context(error::SignalResourceSnafu)?;
+    match fallback {
+        Some(fallback) => {
+            signal_with_fallback(&signaler, &status, &fallback, retry_strategy()).await
+        }
+        None => Retry::spawn(retry_strategy(), || signaler.send_signal(&status)).await,
+    }
+}
 
-    Ok(())
+/// Sends the primary status, retrying according to the given strategy.  If it's still rejected
+/// with an error the fallback covers, sends the fallback status once, so the stack doesn't wait
+/// for a signal that will never arrive.  Other errors are returned as they are.
+pub(crate) async fn signal_with_fallback<S, I>(
+    sender: &S,
+    primary_status: &str,
+    fallback: &Fallback,
+    retry_strategy: I,
+) -> Result<()>
+where
+    S: SignalSender + Sync,
+    I: IntoIterator<Item = Duration>,
+{
+    let err = match Retry::spawn(retry_strategy, || sender.send_signal(primary_status)).await {
+        Ok(()) => return Ok(()),
+        Err(e) if fallback.covers(&e) => e,
+        Err(e) => return Err(e),
+    };
+
+    warn!(
+        "Unable to send {} signal, sending {} instead: {}",
+        primary_status, fallback.status, err
+    );
+    sender.send_signal(&fallback.status).await
+}
+
+/// Returns the IMDS endpoint for the given endpoint mode; IPv4 is used unless "IPv6" is requested.
+fn imds_endpoint(mode: Option<&str>) -> &'static str {
+    match mode {
+        Some(mode) if mode.eq_ignore_ascii_case("ipv6") => IMDS_IPV6_ENDPOINT,
+        _ => IMDS_IPV4_ENDPOINT,
+    }
 }
 
-/// Returns the instanceId
-async fn get_instance_id(client: &mut ImdsClient) -> Result<String> {
+/// Returns the region set in the environment, if any.
+fn region_from_env() -> Option<String> {
+    REGION_ENV_VARS
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|region| !region.is_empty())
+}
+
+/// Returns the instanceId, using IMDS only if no override is given
+async fn get_instance_id(instance_id: Option<String>, client: &mut ImdsClient) -> Result<String> {
+    if let Some(instance_id) = instance_id {
+        return Ok(instance_id);
+    }
+
+    info!("Fetching instance ID from IMDS");
     client
         .fetch_instance_id()
         .await
@@ -55,11 +202,214 @@ async fn get_instance_id(client: &mut ImdsClient) -> Result<String> {
         })
 }
 
-/// Returns the region
-async fn get_region(client: &mut ImdsClient) -> Result<String> {
+/// Returns the region, using IMDS only if no override is given
+async fn get_region(region: Option<String>, client: &mut ImdsClient) -> Result<String> {
+    if let Some(region) = region {
+        return Ok(region);
+    }
+
+    info!("Fetching region from IMDS");
     client
         .fetch_region()
         .await
         .context(error::ImdsRequestSnafu)?
         .context(error::ImdsNoneSnafu { what: "region" })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Records the statuses it's asked to send, rejecting the given status with the given
+    /// SignalResource error code, or failing to reach CloudFormation if there's no code.
+    struct FakeSignaler {
+        failing_status: &'static str,
+        error_code: Option<&'static str>,
+        sent: Mutex<Vec<String>>,
+    }
+
+    impl FakeSignaler {
+        fn new(failing_status: &'static str, error_code: Option<&'static str>) -> Self {
+            Self {
+                failing_status,
+                error_code,
+                sent: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl SignalSender for FakeSignaler {
+        async fn send_signal(&self, status: &str) -> Result<()> {
+            self.sent.lock().unwrap().push(status.to_string());
+            if status != self.failing_status {
+                return Ok(());
+            }
+            match self.error_code {
+                Some(code) => Err(rejected(code)).with_context(signal_error_context),
+                None => error::ImdsNoneSnafu { what: "signal" }.fail(),
+            }
+        }
+    }
+
+    /// Returns a SignalResource service error with the given code and a fixed request ID.
+    fn rejected(code: &str) -> SdkError<SignalResourceError> {
+        use aws_sdk_cloudformation::error::ErrorMetadata;
+        use aws_smithy_runtime_api::http::{Response, StatusCode};
+        use aws_smithy_types::body::SdkBody;
+
+        let mut response = Response::new(StatusCode::try_from(400).unwrap(), SdkBody::empty());
+        response
+            .headers_mut()
+            .insert("x-amzn-requestid", "0123-abcd");
+        let service_error =
+            SignalResourceError::generic(ErrorMetadata::builder().code(code).build());
+        SdkError::service_error(service_error, response)
+    }
+
+    fn fallback_on(code: &str) -> Fallback {
+        Fallback {
+            status: "FAILURE".to_string(),
+            error_codes: vec![code.to_string()],
+        }
+    }
+
+    fn no_delay() -> Vec<Duration> {
+        vec![Duration::ZERO; SIGNAL_RETRIES]
+    }
+
+    // An IMDS client that can't succeed, so tests fail if it's used.
+    fn unusable_client() -> ImdsClient {
+        ImdsClient::new_impl("http://localhost:0".to_string()).with_timeout(Duration::ZERO)
+    }
+
+    #[tokio::test]
+    async fn failing_primary_sends_one_fallback() {
+        let signaler = FakeSignaler::new("SUCCESS", Some("ValidationError"));
+        signal_with_fallback(
+            &signaler,
+            "SUCCESS",
+            &fallback_on("ValidationError"),
+            no_delay(),
+        )
+        .await
+        .unwrap();
+
+        // The primary status is retried before falling back.
+        let sent = signaler.sent.into_inner().unwrap();
+        let mut expected = vec!["SUCCESS".to_string(); SIGNAL_RETRIES + 1];
+        expected.push("FAILURE".to_string());
+        assert_eq!(sent, expected);
+    }
+
+    #[tokio::test]
+    async fn uncovered_errors_skip_fallback() {
+        // Rejected with a code the fallback doesn't cover.
+        let signaler = FakeSignaler::new("SUCCESS", Some("Throttling"));
+        signal_with_fallback(
+            &signaler,
+            "SUCCESS",
+            &fallback_on("ValidationError"),
+            no_delay(),
+        )
+        .await
+        .unwrap_err();
+        assert!(!signaler
+            .sent
+            .into_inner()
+            .unwrap()
+            .contains(&"FAILURE".to_string()));
+
+        // Couldn't reach CloudFormation at all.
+        let signaler = FakeSignaler::new("SUCCESS", None);
+        signal_with_fallback(
+            &signaler,
+            "SUCCESS",
+            &fallback_on("ValidationError"),
+            no_delay(),
+        )
+        .await
+        .unwrap_err();
+        assert!(!signaler
+            .sent
+            .into_inner()
+            .unwrap()
+            .contains(&"FAILURE".to_string()));
+    }
+
+    #[tokio::test]
+    async fn successful_primary_skips_fallback() {
+        let signaler = FakeSignaler::new("FAILURE", Some("ValidationError"));
+        signal_with_fallback(
+            &signaler,
+            "SUCCESS",
+            &fallback_on("ValidationError"),
+            no_delay(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(signaler.sent.into_inner().unwrap(), vec!["SUCCESS"]);
+    }
+
+    #[tokio::test]
+    async fn failing_fallback_is_returned() {
+        let signaler = FakeSignaler::new("FAILURE", Some("ValidationError"));
+        signal_with_fallback(
+            &signaler,
+            "FAILURE",
+            &fallback_on("ValidationError"),
+            no_delay(),
+        )
+        .await
+        .unwrap_err();
+    }
+
+    #[test]
+    fn signal_error_has_sdk_detail() {
+        let result: Result<()> = Err(rejected("Throttling")).with_context(signal_error_context);
+        match result.unwrap_err() {
+            error::Error::SignalResource {
+                code, request_id, ..
+            } => {
+                assert_eq!(code, "Throttling");
+                assert_eq!(request_id, "0123-abcd");
+            }
+            e => panic!("Unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn imds_endpoint_selection() {
+        assert_eq!(imds_endpoint(None), IMDS_IPV4_ENDPOINT);
+        assert_eq!(imds_endpoint(Some("IPv4")), IMDS_IPV4_ENDPOINT);
+        assert_eq!(imds_endpoint(Some("bogus")), IMDS_IPV4_ENDPOINT);
+        assert_eq!(imds_endpoint(Some("IPv6")), IMDS_IPV6_ENDPOINT);
+        assert_eq!(imds_endpoint(Some("ipv6")), IMDS_IPV6_ENDPOINT);
+    }
+
+    #[tokio::test]
+    async fn explicit_region_skips_imds() {
+        let mut client = unusable_client();
+        let region = get_region(Some("us-west-2".to_string()), &mut client)
+            .await
+            .unwrap();
+        assert_eq!(region, "us-west-2");
+    }
+
+    #[tokio::test]
+    async fn explicit_instance_id_skips_imds() {
+        let mut client = unusable_client();
+        let instance_id = get_instance_id(Some("i-0123456789abcdef0".to_string()), &mut client)
+            .await
+            .unwrap();
+        assert_eq!(instance_id, "i-0123456789abcdef0");
+    }
+
+    #[tokio::test]
+    async fn missing_region_uses_imds() {
+        let mut client = unusable_client();
+        get_region(None, &mut client).await.unwrap_err();
+    }
+}
@@ -9,6 +9,10 @@ pub(crate) struct Config {
     pub(crate) should_signal: bool,
     pub(crate) stack_name: String,
     pub(crate) logical_resource_id: String,
+    #[serde(default)]
+    pub(crate) fallback_to_failure_on: Vec<String>,
+    pub(crate) region: Option<String>,
+    pub(crate) instance_id: Option<String>,
 }
 
 impl Config {
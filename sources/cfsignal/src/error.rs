@@ -1,5 +1,7 @@
 //! Provides the list of errors for `cfsignal`.
 
+use aws_sdk_cloudformation::error::SdkError;
+use aws_sdk_cloudformation::operation::signal_resource::SignalResourceError;
 use snafu::Snafu;
 use std::path::PathBuf;
 
@@ -33,10 +35,16 @@ pub enum Error {
     #[snafu(display("IMDS request failed: No '{}' found", what))]
     ImdsNone { what: String },
 
-    #[snafu(display("SignalResource request failed: {}", source))]
+    #[snafu(display(
+        "SignalResource request failed (code: {}, request ID: {}): {}",
+        code,
+        request_id,
+        source
+    ))]
     SignalResource {
-        source: aws_sdk_cloudformation::error::SdkError<
-            aws_sdk_cloudformation::operation::signal_resource::SignalResourceError,
-        >,
+        code: String,
+        request_id: String,
+        #[snafu(source(from(SdkError<SignalResourceError>, Box::new)))]
+        source: Box<SdkError<SignalResourceError>>,
     },
 }
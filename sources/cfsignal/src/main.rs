@@ -15,6 +15,11 @@ Configuration is read from a TOML file, which is generated from Bottlerocket set
 * `should_signal`: Whether to check system status and send signal.
 * `stack_name`: Name of the CFN stack to signal.
 * `logical_resource_id`: The logical ID of the AutoScalingGroup resource that you want to signal.
+* `fallback_to_failure_on` (optional): SignalResource error codes for which a FAILURE signal is sent if a SUCCESS signal is still rejected with that code after retries, so the stack fails fast rather than timing out.  Other errors, like network failures, don't trigger a FAILURE signal.  Defaults to none.
+* `region` (optional): The region of the CFN stack.  If unset, `AWS_REGION` or `AWS_DEFAULT_REGION` from the environment is used, and otherwise the region is fetched from IMDS.
+* `instance_id` (optional): The instance ID to send as the signal's unique ID.  If unset, it's fetched from IMDS.
+
+IMDS is reached over IPv4 by default.  On IPv6-only hosts, set `AWS_EC2_METADATA_SERVICE_ENDPOINT_MODE=IPv6` in the environment to use the IPv6 endpoint instead.
 */
 
 mod cloudformation;
@@ -25,7 +30,7 @@ mod system_check;
 use crate::config::Config;
 use crate::error::Result;
 use crate::system_check::SystemCheck;
-use cloudformation::signal_resource;
+use cloudformation::{signal_resource, Fallback};
 use log::LevelFilter;
 use log::{error, info, warn};
 use simplelog::{Config as LogConfig, SimpleLogger};
@@ -59,11 +64,21 @@ async fn run() -> Result<()> {
         if system_status.is_healthy {
             signal_status = "SUCCESS";
         }
+        // If CloudFormation rejects our SUCCESS signal with one of the configured errors,
+        // tell it we failed so the stack doesn't have to time out waiting for us.
+        let fallback = (!config.fallback_to_failure_on.is_empty() && signal_status != "FAILURE")
+            .then(|| Fallback {
+                status: "FAILURE".to_string(),
+                error_codes: config.fallback_to_failure_on,
+            });
 
         if let Err(err) = signal_resource(
             config.stack_name,
             config.logical_resource_id,
             signal_status.to_owned(),
+            fallback,
+            config.region,
+            config.instance_id,
         )
         .await
         {
@@ -2,6 +2,7 @@
 //! 'apiclient exec'.
 use libc::winsize as WinSize;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::ffi::OsString;
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
@@ -44,6 +45,9 @@ pub struct Initialize {
     pub target: String,
     /// Whether the user wants a TTY.
     pub tty: Option<TtyInit>,
+    /// Environment variables to set for the command, by name; the server only accepts names in
+    /// its allowlist.
+    pub env: Option<BTreeMap<String, String>>,
 }
 
 /// If the user wants a TTY, these are the initial parameters the TTY should be set up with.
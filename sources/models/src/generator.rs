@@ -0,0 +1,136 @@
+//! The 'generator' module holds types for setting generators, the programs that sundog runs to
+//! produce values for settings, and the rules for the order they should run in.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A setting generator as stored in the 'setting-generator' metadata of a setting.  It's either
+/// just the command to run, or a structure with more detail about how to run it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RawSettingsGenerator {
+    Command(String),
+    Structured(SettingsGenerator),
+}
+
+/// The structured form of a setting generator.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SettingsGenerator {
+    pub command: String,
+    /// The strength of the generated setting, like "weak" or "strong"; strong if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strength: Option<String>,
+    /// Don't run the generator if the setting already has a value.
+    #[serde(default)]
+    pub skip_if_populated: bool,
+}
+
+impl RawSettingsGenerator {
+    /// Returns the command to run to generate the setting.
+    pub fn command(&self) -> &str {
+        match self {
+            RawSettingsGenerator::Command(command) => command,
+            RawSettingsGenerator::Structured(generator) => &generator.command,
+        }
+    }
+}
+
+/// Returns the setting keys of the given generators in the order the generators should run.
+///
+/// The order is:
+/// * by depth, meaning the number of segments in the setting key, shallowest first, so that a
+///   generator for a parent like "settings.a" runs before one for a child like "settings.a.b"
+/// * by key name, for generators at the same depth, so the order is the same on every run
+pub fn order_generators(generators: &HashMap<String, RawSettingsGenerator>) -> Vec<String> {
+    let mut keys: Vec<&String> = generators.keys().collect();
+    keys.sort_by(|a, b| key_depth(a).cmp(&key_depth(b)).then_with(|| a.cmp(b)));
+    keys.into_iter().cloned().collect()
+}
+
+/// Counts the segments of a dotted setting key.  Dots inside quoted segments, like the label name
+/// in settings.kubernetes.node-labels."example.com/label", don't separate segments.
+fn key_depth(key: &str) -> usize {
+    let mut in_quotes = false;
+    let mut depth = 1;
+    for c in key.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '.' if !in_quotes => depth += 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn generators(keys: &[&str]) -> HashMap<String, RawSettingsGenerator> {
+        keys.iter()
+            .map(|k| {
+                (
+                    k.to_string(),
+                    RawSettingsGenerator::Command(format!("/bin/gen-{}", k)),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn order_by_depth_then_name() {
+        let gens = generators(&[
+            "settings.b.c.d",
+            "settings.b",
+            "settings.a.z",
+            "settings.c",
+            "settings.a",
+            "settings.a.b",
+        ]);
+        assert_eq!(
+            order_generators(&gens),
+            vec![
+                "settings.a",
+                "settings.b",
+                "settings.c",
+                "settings.a.b",
+                "settings.a.z",
+                "settings.b.c.d",
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_segments_count_once() {
+        let gens = generators(&[
+            "settings.kubernetes.node-labels.\"example.com.deep.label\"",
+            "settings.kubernetes.cluster-name.x",
+            "settings.kubernetes.a",
+        ]);
+        assert_eq!(
+            order_generators(&gens),
+            vec![
+                "settings.kubernetes.a",
+                "settings.kubernetes.cluster-name.x",
+                "settings.kubernetes.node-labels.\"example.com.deep.label\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_generator_forms() {
+        let command: RawSettingsGenerator = serde_json::from_str("\"/bin/gen\"").unwrap();
+        assert_eq!(command.command(), "/bin/gen");
+
+        let structured: RawSettingsGenerator =
+            serde_json::from_str(r#"{"command": "/bin/gen", "skip-if-populated": true}"#).unwrap();
+        assert_eq!(
+            structured,
+            RawSettingsGenerator::Structured(SettingsGenerator {
+                command: "/bin/gen".to_string(),
+                strength: None,
+                skip_if_populated: true,
+            })
+        );
+    }
+}
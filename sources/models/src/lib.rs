@@ -24,6 +24,18 @@ pub mod exec;
 // Types used to communicate between client and server for 'apiclient ephemeral-storage'.
 pub mod ephemeral_storage;
 
+// Types used to communicate between client and server for 'apiclient report'.
+pub mod report;
+
+// Types for setting generators, and the order they run in.
+pub mod generator;
+
+// A JSON Schema of the API model, for tooling and editors.
+pub mod schema;
+
+// Identifies the revision of the API model, so clients can tell when it changes.
+pub mod revision;
+
 use bottlerocket_release::BottlerocketRelease;
 use bottlerocket_settings_models::model_derive::model;
 use bottlerocket_settings_plugin::BottlerocketSettings;
@@ -89,4 +101,5 @@ struct Metadata {
 struct Report {
     name: String,
     description: String,
+    formats: Vec<String>,
 }
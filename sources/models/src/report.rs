@@ -0,0 +1,57 @@
+//! The 'report' module holds types used to communicate between client and server for
+//! 'apiclient report', matching the JSON output of bloodhound.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The overall result of a compliance report, or of one of its checks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum CheckStatus {
+    /// Successfully verified to be in the expected state.
+    Pass,
+    /// Found to not be in the expected state.
+    Fail,
+    /// Unable to verify state, manual verification required.
+    Skip,
+}
+
+/// Whether a check was performed automatically or needs manual verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CheckMode {
+    Automatic,
+    Manual,
+}
+
+/// The result of an individual check in a report.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub id: String,
+    pub level: u8,
+    pub title: String,
+    pub mode: CheckMode,
+    pub status: CheckStatus,
+    /// Details of why the check failed or was skipped; empty if it passed.
+    pub error: String,
+}
+
+/// A compliance report containing the results of all checks that were run, such as the CIS
+/// benchmark report.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CisReport {
+    pub level: u8,
+    pub total: usize,
+    pub passed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub status: CheckStatus,
+    pub timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Check results, keyed by check name.
+    pub results: BTreeMap<String, CheckResult>,
+}
@@ -0,0 +1,85 @@
+//! The 'revision' module identifies the revision of the API model, so clients that cache
+//! settings or the schema can tell when the model changes, for example because the variant's
+//! settings plugin was updated.
+//!
+//! The settings plugin doesn't report a version of its own, but it's built into the OS image, so
+//! it can only change along with the OS release.  We fingerprint the release that provides the
+//! plugin, along with the schema built by this crate.  The fingerprint is only meant to be
+//! compared for equality; it doesn't order revisions.
+use crate::schema::model_schema;
+use bottlerocket_release::BottlerocketRelease;
+use serde::{Deserialize, Serialize};
+
+/// The revision of the API model served by this host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ModelRevision {
+    /// The version of the models crate, which defines everything outside of settings.
+    pub models_version: String,
+    /// A fingerprint of the settings plugin and schema, as a hex string.
+    pub settings_revision: String,
+}
+
+impl ModelRevision {
+    /// Returns the revision of the model served by the given OS release.
+    pub fn for_release(release: &BottlerocketRelease) -> Self {
+        let fingerprint = format!(
+            "{}\n{}\n{}\n{}",
+            release.variant_id,
+            release.version_id,
+            release.build_id,
+            model_schema()
+        );
+        Self {
+            models_version: env!("CARGO_PKG_VERSION").to_string(),
+            settings_revision: format!("{:016x}", fnv1a(fingerprint.as_bytes())),
+        }
+    }
+}
+
+/// Hashes the given bytes with 64-bit FNV-1a.  We use our own hash rather than the standard
+/// library's, which isn't guaranteed to give the same result from one Rust release to the next.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn release(build_id: &str) -> BottlerocketRelease {
+        BottlerocketRelease {
+            pretty_name: "Bottlerocket OS 1.2.3 (aws-dev)".to_string(),
+            variant_id: "aws-dev".to_string(),
+            version_id: "1.2.3".parse().unwrap(),
+            build_id: build_id.to_string(),
+            arch: "x86_64".to_string(),
+        }
+    }
+
+    #[test]
+    fn revision_is_stable() {
+        let revision = ModelRevision::for_release(&release("abcdef01"));
+        assert!(!revision.models_version.is_empty());
+        assert_eq!(revision.settings_revision.len(), 16);
+        assert_eq!(revision, ModelRevision::for_release(&release("abcdef01")));
+    }
+
+    #[test]
+    fn revision_changes_with_release() {
+        assert_ne!(
+            ModelRevision::for_release(&release("abcdef01")).settings_revision,
+            ModelRevision::for_release(&release("abcdef02")).settings_revision
+        );
+    }
+
+    #[test]
+    fn fnv1a_known_values() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+}
@@ -0,0 +1,214 @@
+//! The 'schema' module builds a JSON Schema of the API model, for tooling and editors that want to
+//! validate or complete documents before sending them to the API.
+//!
+//! The schema is built by hand from the types in this crate, since they're plain Rust types
+//! without reflection.
+//!
+//! The schema doesn't describe the contents of `settings`.  They're defined by the variant's
+//! settings plugin, which only exchanges JSON with us and doesn't expose its types, so there's
+//! nothing to generate a schema from.  `settings` is described as an open object, and its contents
+//! are checked by the API when they're set; see `POST /settings/validate-value`.
+use serde_json::{json, Value};
+
+/// The JSON Schema dialect of the generated schema.
+pub const SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// A regular expression matching strings accepted by the `SingleLineString` modeled type, which
+/// rejects all line terminators.
+pub const SINGLE_LINE_PATTERN: &str = r"^[^\n\r\u000B\u000C\u0085\u2028\u2029]*$";
+
+/// Returns the schema of a `SingleLineString`.
+pub fn single_line_string() -> Value {
+    json!({
+        "type": "string",
+        "pattern": SINGLE_LINE_PATTERN,
+    })
+}
+
+/// Returns the schema of the settings section of the model.  This only says that settings are an
+/// object; see the module docs for why their contents aren't described.
+pub fn settings_schema() -> Value {
+    json!({
+        "type": "object",
+        "description": "Settings defined by the variant's settings plugin; their contents aren't described by this schema",
+        "additionalProperties": true,
+    })
+}
+
+/// Returns the schema of a `Service`.
+fn service_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "configuration-files": {
+                "type": "array",
+                "items": single_line_string(),
+            },
+            "restart-commands": {
+                "type": "array",
+                "items": { "type": "string" },
+            },
+        },
+        "required": ["configuration-files", "restart-commands"],
+        "additionalProperties": false,
+    })
+}
+
+/// Returns the schema of a `ConfigurationFile`.
+fn configuration_file_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "path": single_line_string(),
+            "template-path": single_line_string(),
+            "mode": { "type": "string" },
+        },
+        "required": ["path", "template-path"],
+        "additionalProperties": false,
+    })
+}
+
+/// Returns the JSON Schema of the full API model, as returned by a GET of "/".
+pub fn model_schema() -> Value {
+    json!({
+        "$schema": SCHEMA_DIALECT,
+        "title": "Bottlerocket API model",
+        "type": "object",
+        "properties": {
+            "settings": settings_schema(),
+            "services": {
+                "type": "object",
+                "additionalProperties": service_schema(),
+            },
+            "configuration-files": {
+                "type": "object",
+                "additionalProperties": configuration_file_schema(),
+            },
+            "os": {
+                "type": "object",
+                "description": "Read-only information about the running OS",
+            },
+        },
+        "additionalProperties": false,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use regex::Regex;
+
+    /// Checks a document against the subset of JSON Schema used by this module.
+    fn validates(schema: &Value, doc: &Value) -> bool {
+        let type_ok = match schema.get("type").and_then(Value::as_str) {
+            Some("object") => doc.is_object(),
+            Some("array") => doc.is_array(),
+            Some("string") => doc.is_string(),
+            Some(other) => panic!("type {} not handled by test validator", other),
+            None => true,
+        };
+        if !type_ok {
+            return false;
+        }
+
+        if let (Some(pattern), Some(s)) = (schema.get("pattern"), doc.as_str()) {
+            if !Regex::new(pattern.as_str().unwrap()).unwrap().is_match(s) {
+                return false;
+            }
+        }
+        if let (Some(items), Some(list)) = (schema.get("items"), doc.as_array()) {
+            if !list.iter().all(|item| validates(items, item)) {
+                return false;
+            }
+        }
+        if let Some(map) = doc.as_object() {
+            let empty = serde_json::Map::new();
+            let properties = schema
+                .get("properties")
+                .and_then(Value::as_object)
+                .unwrap_or(&empty);
+            let required = schema
+                .get("required")
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default();
+            if !required
+                .iter()
+                .all(|r| map.contains_key(r.as_str().unwrap()))
+            {
+                return false;
+            }
+            for (name, value) in map {
+                let ok = match (properties.get(name), schema.get("additionalProperties")) {
+                    (Some(property), _) => validates(property, value),
+                    (None, Some(Value::Bool(allowed))) => *allowed,
+                    (None, Some(additional)) => validates(additional, value),
+                    (None, None) => true,
+                };
+                if !ok {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn good_model() -> Value {
+        json!({
+            "settings": { "motd": "hi" },
+            "services": {
+                "ntp": {
+                    "configuration-files": ["chrony-conf"],
+                    "restart-commands": ["/bin/systemctl try-restart chronyd"],
+                },
+            },
+            "configuration-files": {
+                "chrony-conf": {
+                    "path": "/etc/chrony.conf",
+                    "template-path": "/usr/share/templates/chrony-conf",
+                    "mode": "0644",
+                },
+            },
+            "os": { "variant-id": "aws-dev" },
+        })
+    }
+
+    #[test]
+    fn good_model_validates() {
+        assert!(validates(&model_schema(), &good_model()));
+    }
+
+    #[test]
+    fn bad_models_rejected() {
+        let schema = model_schema();
+
+        let mut multi_line = good_model();
+        multi_line["configuration-files"]["chrony-conf"]["path"] = json!("/etc/chrony\n.conf");
+        assert!(!validates(&schema, &multi_line));
+
+        let mut missing_field = good_model();
+        missing_field["services"]["ntp"]
+            .as_object_mut()
+            .unwrap()
+            .remove("restart-commands");
+        assert!(!validates(&schema, &missing_field));
+
+        let mut unknown_section = good_model();
+        unknown_section["bogus"] = json!({});
+        assert!(!validates(&schema, &unknown_section));
+
+        let mut bad_settings = good_model();
+        bad_settings["settings"] = json!(["not", "an", "object"]);
+        assert!(!validates(&schema, &bad_settings));
+    }
+
+    #[test]
+    fn single_line_pattern() {
+        let pattern = Regex::new(SINGLE_LINE_PATTERN).unwrap();
+        assert!(pattern.is_match("a single line"));
+        assert!(pattern.is_match(""));
+        for bad in ["a\nb", "a\rb", "a\u{2028}b", "a\u{85}b"] {
+            assert!(!pattern.is_match(bad), "{:?}", bad);
+        }
+    }
+}
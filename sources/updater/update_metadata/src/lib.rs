@@ -101,6 +101,12 @@ pub struct Manifest {
     #[serde(deserialize_with = "de::deserialize_migration")]
     #[serde(serialize_with = "se::serialize_migration")]
     pub migrations: BTreeMap<(Version, Version), Vec<String>>,
+    /// The lowest version the data store may be migrated to, if there's a limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_migratable_version: Option<Version>,
+    /// The highest version the data store may be migrated to, if there's a limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_migratable_version: Option<Version>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -143,6 +149,20 @@ impl Manifest {
         serde_json::from_reader(r).context(error::ManifestParseSnafu)
     }
 
+    /// Returns true if the manifest allows migrating the data store to the given version, meaning
+    /// it's within the manifest's migratable version bounds, if any are declared.
+    pub fn allows_migration_to(&self, version: &Version) -> bool {
+        let above_min = self
+            .min_migratable_version
+            .as_ref()
+            .is_none_or(|min| version >= min);
+        let below_max = self
+            .max_migratable_version
+            .as_ref()
+            .is_none_or(|max| version <= max);
+        above_min && below_max
+    }
+
     pub fn add_update(
         &mut self,
         image_version: Version,
@@ -704,4 +724,26 @@ mod tests {
         assert_eq!(i.next().unwrap(), "migration_1.1.0_b");
         assert_eq!(i.next().unwrap(), "migration_1.1.0_a");
     }
+
+    #[test]
+    fn test_migratable_version_bounds() {
+        let v = |s| Version::parse(s).unwrap();
+        let mut manifest = Manifest::default();
+        // With no bounds, any version is fine.
+        assert!(manifest.allows_migration_to(&v("0.1.0")));
+        assert!(manifest.allows_migration_to(&v("99.0.0")));
+
+        manifest.min_migratable_version = Some(v("1.1.0"));
+        manifest.max_migratable_version = Some(v("1.3.0"));
+        assert!(!manifest.allows_migration_to(&v("1.0.0")));
+        assert!(manifest.allows_migration_to(&v("1.1.0")));
+        assert!(manifest.allows_migration_to(&v("1.3.0")));
+        assert!(!manifest.allows_migration_to(&v("1.4.0")));
+
+        // Bounds are optional in serialized manifests.
+        let path = "./tests/data/migrations.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        assert_eq!(manifest.min_migratable_version, None);
+        assert_eq!(manifest.max_migratable_version, None);
+    }
 }